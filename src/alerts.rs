@@ -0,0 +1,307 @@
+//! Threshold alerts derived from the polled values
+//!
+//! Alerts are boolean topics so consumers (and Home Assistant binary sensors)
+//! don't have to repeat the threshold logic. They are debounced over a number
+//! of consecutive polls to ignore single outliers.
+
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+
+use crate::events::{CellImbalanceEvent, EnergyBalanceEvent, TemperatureAlertEvent};
+use crate::mqtt::BatteryData;
+
+/// Boolean state that flips after `polls` consecutive polls disagreeing with it
+#[derive(Debug, Clone)]
+pub struct Debounce {
+    polls: u32,
+    count: u32,
+    active: bool,
+}
+
+impl Debounce {
+    pub fn new(polls: u32) -> Self {
+        Self {
+            polls: polls.max(1),
+            count: 0,
+            active: false,
+        }
+    }
+
+    /// Feed the current condition, returns the debounced state
+    pub fn update(&mut self, condition: bool) -> bool {
+        if condition == self.active {
+            self.count = 0;
+        } else {
+            self.count += 1;
+            if self.count >= self.polls {
+                self.active = condition;
+                self.count = 0;
+            }
+        }
+        self.active
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+}
+
+/// Alert topic of a battery (`dcb` None) or one of its DCBs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BatteryAlert {
+    pub battery: u64,
+    pub dcb: Option<u64>,
+    /// Topic name, e.g. `cell_imbalance_alert`
+    pub name: &'static str,
+    pub active: bool,
+}
+
+/// Raises `cell_imbalance_alert` when the imbalance exceeds the threshold
+pub struct CellImbalanceMonitor {
+    threshold_mv: f64,
+    polls: u32,
+    states: BTreeMap<(u64, Option<u64>), Debounce>,
+}
+
+impl CellImbalanceMonitor {
+    pub fn new(threshold_mv: f64, polls: u32) -> Self {
+        Self {
+            threshold_mv,
+            polls,
+            states: BTreeMap::new(),
+        }
+    }
+
+    /// Alert state of every battery and DCB after this poll and the alerts that changed
+    pub fn update(
+        &mut self,
+        batteries: &[BatteryData],
+        time: DateTime<Utc>,
+    ) -> (Vec<BatteryAlert>, Vec<CellImbalanceEvent>) {
+        let mut alerts = Vec::new();
+        let mut events = Vec::new();
+        for battery in batteries {
+            let imbalances = battery
+                .dcbs
+                .iter()
+                .map(|dcb| (Some(dcb.index), dcb.cell_imbalance))
+                .chain([(None, battery.cell_imbalance)]);
+            for (dcb, imbalance) in imbalances {
+                let state = self
+                    .states
+                    .entry((battery.index, dcb))
+                    .or_insert_with(|| Debounce::new(self.polls));
+                let was_active = state.is_active();
+                let active = state.update(imbalance > self.threshold_mv);
+                if active != was_active {
+                    events.push(CellImbalanceEvent {
+                        time,
+                        battery: battery.index,
+                        dcb,
+                        active,
+                        imbalance,
+                        threshold: self.threshold_mv,
+                    });
+                }
+                alerts.push(BatteryAlert {
+                    battery: battery.index,
+                    dcb,
+                    name: "cell_imbalance_alert",
+                    active,
+                });
+            }
+        }
+        (alerts, events)
+    }
+}
+
+/// Cell temperature limit checked by [`TemperatureMonitor`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum TemperatureLimit {
+    High,
+    Low,
+}
+
+impl TemperatureLimit {
+    fn as_str(self) -> &'static str {
+        match self {
+            TemperatureLimit::High => "high",
+            TemperatureLimit::Low => "low",
+        }
+    }
+
+    fn topic(self) -> &'static str {
+        match self {
+            TemperatureLimit::High => "temperature_high_alert",
+            TemperatureLimit::Low => "temperature_low_alert",
+        }
+    }
+}
+
+/// Raises `temperature_high_alert`/`temperature_low_alert` per battery from
+/// `max_dcb_cell_temp` above the high and `min_dcb_cell_temp` below the low threshold
+pub struct TemperatureMonitor {
+    high_c: Option<f64>,
+    low_c: Option<f64>,
+    polls: u32,
+    states: BTreeMap<(u64, TemperatureLimit), Debounce>,
+}
+
+impl TemperatureMonitor {
+    pub fn new(high_c: Option<f64>, low_c: Option<f64>, polls: u32) -> Self {
+        Self {
+            high_c,
+            low_c,
+            polls,
+            states: BTreeMap::new(),
+        }
+    }
+
+    /// Alert state of every battery after this poll and the alerts that changed
+    pub fn update(
+        &mut self,
+        batteries: &[BatteryData],
+        time: DateTime<Utc>,
+    ) -> (Vec<BatteryAlert>, Vec<TemperatureAlertEvent>) {
+        let mut alerts = Vec::new();
+        let mut events = Vec::new();
+        for battery in batteries {
+            let limits = [
+                (
+                    TemperatureLimit::High,
+                    self.high_c,
+                    battery.max_dcb_cell_temp,
+                ),
+                (TemperatureLimit::Low, self.low_c, battery.min_dcb_cell_temp),
+            ];
+            for (limit, threshold, temperature) in limits {
+                let Some(threshold) = threshold else {
+                    continue;
+                };
+                let field = match limit {
+                    TemperatureLimit::High => "max_dcb_cell_temp",
+                    TemperatureLimit::Low => "min_dcb_cell_temp",
+                };
+                if !battery.is_known(field) {
+                    continue;
+                }
+                let exceeded = match limit {
+                    TemperatureLimit::High => temperature > threshold,
+                    TemperatureLimit::Low => temperature < threshold,
+                };
+                let state = self
+                    .states
+                    .entry((battery.index, limit))
+                    .or_insert_with(|| Debounce::new(self.polls));
+                let was_active = state.is_active();
+                let active = state.update(exceeded);
+                if active != was_active {
+                    events.push(TemperatureAlertEvent {
+                        time,
+                        battery: battery.index,
+                        limit: limit.as_str(),
+                        active,
+                        temperature,
+                        threshold,
+                    });
+                }
+                alerts.push(BatteryAlert {
+                    battery: battery.index,
+                    dcb: None,
+                    name: limit.topic(),
+                    active,
+                });
+            }
+        }
+        (alerts, events)
+    }
+}
+
+/// Raises `events/energy_balance` when the absolute residual of the energy
+/// balance exceeds the threshold, hinting at meter misconfiguration or decoding bugs
+pub struct EnergyBalanceMonitor {
+    threshold_w: f64,
+    state: Debounce,
+}
+
+impl EnergyBalanceMonitor {
+    pub fn new(threshold_w: f64, polls: u32) -> Self {
+        Self {
+            threshold_w,
+            state: Debounce::new(polls),
+        }
+    }
+
+    /// Feed the residual in W, returns an event when the alert changes
+    pub fn update(&mut self, residual: f64, time: DateTime<Utc>) -> Option<EnergyBalanceEvent> {
+        let was_active = self.state.is_active();
+        let active = self.state.update(residual.abs() > self.threshold_w);
+        (active != was_active).then_some(EnergyBalanceEvent {
+            time,
+            active,
+            residual,
+            threshold: self.threshold_w,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_debounce() {
+        let mut state = Debounce::new(3);
+        assert!(!state.update(true));
+        assert!(!state.update(true));
+        // An outlier resets the count
+        assert!(!state.update(false));
+        assert!(!state.update(true));
+        assert!(!state.update(true));
+        assert!(state.update(true));
+        // Clearing is debounced as well
+        assert!(state.update(false));
+        assert!(state.update(false));
+        assert!(!state.update(false));
+    }
+
+    #[test]
+    fn test_energy_balance_monitor() {
+        let time = Utc::now();
+        let mut monitor = EnergyBalanceMonitor::new(100.0, 2);
+        assert_eq!(monitor.update(-150.0, time), None);
+        let event = monitor.update(-180.0, time).unwrap();
+        assert!(event.active);
+        assert_eq!(event.residual, -180.0);
+        assert_eq!(monitor.update(20.0, time), None);
+        assert!(!monitor.update(10.0, time).unwrap().active);
+    }
+
+    #[test]
+    fn test_temperature_alerts() {
+        let time = Utc::now();
+        let battery = |max_dcb_cell_temp| {
+            BatteryData::from_e3dc(&crate::e3dc::BatteryData {
+                max_dcb_cell_temp: Some(max_dcb_cell_temp),
+                min_dcb_cell_temp: Some(20.0),
+                ..Default::default()
+            })
+        };
+        let mut monitor = TemperatureMonitor::new(Some(45.0), None, 2);
+        let (alerts, events) = monitor.update(&[battery(46.0)], time);
+        assert_eq!(alerts.len(), 1);
+        assert!(!alerts[0].active);
+        assert!(events.is_empty());
+
+        let (alerts, events) = monitor.update(&[battery(46.5)], time);
+        assert_eq!(alerts[0].name, "temperature_high_alert");
+        assert!(alerts[0].active);
+        assert_eq!(events[0].limit, "high");
+        assert_eq!(events[0].temperature, 46.5);
+
+        // No repeated event while the alert stays active
+        let (_, events) = monitor.update(&[battery(47.0)], time);
+        assert!(events.is_empty());
+    }
+}
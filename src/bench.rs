@@ -0,0 +1,102 @@
+//! Query latency benchmark (`bench` subcommand)
+//!
+//! Runs one query type a number of times and summarizes the round trip times
+//! as percentiles, to pick intervals that fit the hardware and to quantify
+//! the effect of request batching.
+
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use tracing::debug;
+
+/// Round trip times of one query type
+#[derive(Debug, Clone)]
+pub struct Latencies {
+    pub name: &'static str,
+    /// Successful runs, sorted ascending
+    samples: Vec<Duration>,
+    pub errors: u32,
+}
+
+impl Latencies {
+    /// Run `query` `runs` times; failed runs are counted, not timed
+    pub fn measure<T, E: fmt::Display>(
+        name: &'static str,
+        runs: u32,
+        mut query: impl FnMut() -> Result<T, E>,
+    ) -> Self {
+        let mut samples = Vec::with_capacity(runs as usize);
+        let mut errors = 0;
+        for _ in 0..runs {
+            let start = Instant::now();
+            match query() {
+                Ok(_) => samples.push(start.elapsed()),
+                Err(e) => {
+                    debug!("{} query failed: {}", name, e);
+                    errors += 1;
+                }
+            }
+        }
+        Self::from_samples(name, samples, errors)
+    }
+
+    pub fn from_samples(name: &'static str, mut samples: Vec<Duration>, errors: u32) -> Self {
+        samples.sort();
+        Self {
+            name,
+            samples,
+            errors,
+        }
+    }
+
+    /// Nearest-rank percentile (`p` in 0..=100), None without samples
+    pub fn percentile(&self, p: f64) -> Option<Duration> {
+        if self.samples.is_empty() {
+            return None;
+        }
+        let rank = (p / 100.0 * self.samples.len() as f64).ceil() as usize;
+        Some(self.samples[rank.clamp(1, self.samples.len()) - 1])
+    }
+}
+
+impl fmt::Display for Latencies {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ms = |p: f64| match self.percentile(p) {
+            Some(d) => format!("{:>6.1}", d.as_secs_f64() * 1000.0),
+            None => format!("{:>6}", "-"),
+        };
+        write!(
+            f,
+            "{:<16} {:>4} ok {:>3} err  min {}  p50 {}  p90 {}  p99 {}  max {} ms",
+            self.name,
+            self.samples.len(),
+            self.errors,
+            ms(0.0),
+            ms(50.0),
+            ms(90.0),
+            ms(99.0),
+            ms(100.0)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_percentiles() {
+        let samples = (1..=10).rev().map(Duration::from_millis).collect();
+        let latencies = Latencies::from_samples("status", samples, 1);
+        assert_eq!(latencies.percentile(0.0), Some(Duration::from_millis(1)));
+        assert_eq!(latencies.percentile(50.0), Some(Duration::from_millis(5)));
+        assert_eq!(latencies.percentile(90.0), Some(Duration::from_millis(9)));
+        assert_eq!(latencies.percentile(99.0), Some(Duration::from_millis(10)));
+        assert_eq!(latencies.percentile(100.0), Some(Duration::from_millis(10)));
+
+        let failed = Latencies::measure("status", 3, || Err::<(), _>("timeout"));
+        assert_eq!(failed.errors, 3);
+        assert_eq!(failed.percentile(50.0), None);
+        assert!(failed.to_string().contains("p50      -"));
+    }
+}
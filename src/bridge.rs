@@ -0,0 +1,2286 @@
+//! The bridge main loop, embeddable in other applications
+//!
+//! [`run`] connects to the E3DC and the broker and publishes until its
+//! [`Shutdown`] is requested. The binary requests it on SIGTERM/SIGINT; an
+//! application embedding the bridge keeps a clone of the handle, several
+//! bridges (one per E3DC) can run on threads of one process.
+//!
+//! ```ignore
+//! let shutdown = Shutdown::new();
+//! let bridge = std::thread::spawn({
+//!     let shutdown = shutdown.clone();
+//!     move || e3dc_mqtt_rs::bridge::run(config, shutdown)
+//! });
+//! // ...
+//! shutdown.request();
+//! bridge.join().unwrap()?;
+//! ```
+//!
+//! The request is checked between poll cycles, setup (connecting, backfilling
+//! the history) finishes first. Logging is up to the application, `run`
+//! only emits `tracing` events.
+
+use std::cmp::max;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex, PoisonError};
+use std::time::Instant;
+
+use anyhow::Context;
+use chrono::{DateTime, Duration, Local, NaiveDate, Utc};
+use tracing::{debug, error, info, warn};
+
+use crate::alerts::{BatteryAlert, CellImbalanceMonitor, EnergyBalanceMonitor, TemperatureMonitor};
+use crate::config::{Config, ErrorPolicy, OnErrorConfig, StatisticsWindowConfig};
+use crate::cycles::CycleHistory;
+use crate::e3dc::debug::{DebugLog, TagFilter};
+use crate::e3dc::worker::{BatteryPoll, BatteryWorker};
+use crate::e3dc::{
+    self, Degraded, E3dcClient, EmergencyPowerState, PeriodTotals, StatisticsPeriod, Status,
+    SystemInfo,
+};
+use crate::energy::EnergyIntegrator;
+use crate::errors::{BridgeError, E3dcError, MqttError};
+use crate::events::{
+    BatteryErrorTracker, DcbCountChangedEvent, DcbModules, EmergencyPowerTestTracker,
+    EmergencyPowerTracker, EmergencyPowerUsage, ExportLimitTracker, GridOutageTracker, SocTracker,
+    WallboxSessionTracker,
+};
+use crate::forecast::{self, ForecastWorker, PvForecast};
+#[cfg(feature = "history")]
+use crate::history::{self, BackfillSpan, HistorySink, HistoryStore, Snapshot};
+use crate::lifecycle::{Lifecycle, LifecycleEvent};
+use crate::mqtt::commands::{
+    ActionCommands, ClockCommands, EmergencyPowerTestCommands, IncomingCommand, SettingsCommands,
+    WallboxCommands,
+};
+use crate::mqtt::diff::Heartbeat;
+use crate::mqtt::discovery::Discovery;
+use crate::mqtt::homie::HomiePublisher;
+use crate::mqtt::link::ConnectionEvent;
+use crate::mqtt::subscriber::Subscriber;
+use crate::mqtt::suppression::ZeroPvSuppression;
+use crate::mqtt::{self, DailyStatistics, MqttPublisher, Publisher};
+use crate::pipeline::{Message, Pipeline, Sink};
+use crate::rates::{SmoothedSoc, SocRate};
+use crate::schedule::Scheduler;
+use crate::state::{Counters, StateDir, StateFile};
+use crate::tasks::{random_below, Cadence, CatchUp, TaskId, Tasks, Trigger};
+use crate::version::BuildInfo;
+
+/// Poll messages queued for the sinks before new ones are dropped
+const SINK_QUEUE: usize = 100;
+
+/// Full status republish interval without `[payload] heartbeat` while status isn't retained
+const UNRETAINED_STATUS_HEARTBEAT: Duration = Duration::minutes(5);
+
+/// Shutdown check interval while waiting for the next poll
+const SHUTDOWN_CHECK: std::time::Duration = std::time::Duration::from_millis(200);
+
+/// Time for the MQTT event loop to write the last messages
+const SHUTDOWN_FLUSH: std::time::Duration = std::time::Duration::from_millis(500);
+
+/// Cloneable cancellation handle of [`run`]
+#[derive(Debug, Clone, Default)]
+pub struct Shutdown {
+    requested: Arc<(Mutex<bool>, Condvar)>,
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Ask the bridge to stop, `run` returns after the running cycle
+    pub fn request(&self) {
+        let (requested, changed) = &*self.requested;
+        *requested.lock().unwrap_or_else(PoisonError::into_inner) = true;
+        changed.notify_all();
+    }
+
+    pub fn is_requested(&self) -> bool {
+        *self
+            .requested
+            .0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Sleep for `timeout` or until requested, returns whether it was requested
+    pub fn wait(&self, timeout: std::time::Duration) -> bool {
+        let (requested, changed) = &*self.requested;
+        let guard = requested.lock().unwrap_or_else(PoisonError::into_inner);
+        let (guard, _) = changed
+            .wait_timeout_while(guard, timeout, |requested| !*requested)
+            .unwrap_or_else(PoisonError::into_inner);
+        *guard
+    }
+}
+
+/// Wait up to `timeout` for a command, None on timeout or a shutdown request
+fn wait_for_command(
+    mqtt_publisher: &MqttPublisher,
+    shutdown: &Shutdown,
+    timeout: std::time::Duration,
+) -> Option<IncomingCommand> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() || shutdown.is_requested() {
+            return None;
+        }
+        if let Some(command) = mqtt_publisher.wait_for_command(remaining.min(SHUTDOWN_CHECK)) {
+            return Some(command);
+        }
+    }
+}
+
+/// Read back the effective idle period schedule and publish it
+pub fn publish_idle_periods(
+    e3dc_client: &mut E3dcClient,
+    mqtt_publisher: &MqttPublisher,
+) -> anyhow::Result<()> {
+    let periods: Vec<mqtt::IdlePeriod> = e3dc_client
+        .get_idle_periods()?
+        .iter()
+        .map(mqtt::IdlePeriod::from_e3dc)
+        .collect();
+    mqtt_publisher.publish_idle_periods(&periods)?;
+    Ok(())
+}
+
+/// Query the pending backfill `spans` from the E3DC's DB history into the
+/// store, returns the records written
+///
+/// Stops at the first failed query or a shutdown request, the records
+/// written until then are kept and the rest is queried at the next start.
+#[cfg(feature = "history")]
+fn backfill_history(
+    e3dc_client: &mut E3dcClient,
+    store: &mut HistoryStore,
+    spans: &[BackfillSpan],
+    shutdown: &Shutdown,
+) -> anyhow::Result<usize> {
+    info!("Backfilling history with {} DB queries", spans.len());
+    for (written, span) in spans.iter().enumerate() {
+        if shutdown.is_requested() {
+            info!(
+                "Backfill stopped after {} records, the rest follows at the next start",
+                written
+            );
+            return Ok(written);
+        }
+        let stats = e3dc_client
+            .get_db_data_timestamp(span.start, span.span)
+            .with_context(|| {
+                format!("Query for {} failed after {} records", span.start, written)
+            })?;
+        let stats = DailyStatistics::from_e3dc(&stats);
+        store.append_backfill(
+            span,
+            &Snapshot::from_changes(span.kind, None, span.time(), &stats.diff(None)),
+        )?;
+    }
+    Ok(spans.len())
+}
+
+/// Load a state file, the default value without a state directory
+fn load_state<T: StateFile>(state_dir: &Option<StateDir>) -> anyhow::Result<T> {
+    let Some(state_dir) = state_dir else {
+        return Ok(T::default());
+    };
+    state_dir.load().with_context(|| {
+        format!(
+            "Failed to read state file {}",
+            state_dir.path::<T>().display()
+        )
+    })
+}
+
+/// Write a state file, a failure only loses the state of this run
+fn save_state<T: StateFile>(state_dir: &Option<StateDir>, state: &T) {
+    if let Some(state_dir) = state_dir {
+        if let Err(e) = state_dir.save(state) {
+            warn!(
+                "Failed to write state file {}: {}",
+                state_dir.path::<T>().display(),
+                e
+            );
+        }
+    }
+}
+
+/// Cycle skipped per `[on_error]`
+struct SkippedCycle {
+    policy: ErrorPolicy,
+    /// Publishing failed, not an E3DC query
+    mqtt: bool,
+}
+
+/// Run the E3DC query a cycle depends on, once more with
+/// `[on_error] e3dc = "retry_then_skip"`
+///
+/// Only the query is repeated: what the cycle published before stays
+/// published once and its trackers advance once.
+fn query<C, T>(
+    on_error: &OnErrorConfig,
+    e3dc_client: &mut C,
+    name: &str,
+    mut query: impl FnMut(&mut C) -> Result<T, E3dcError>,
+) -> Result<T, E3dcError> {
+    match query(e3dc_client) {
+        Err(e) if on_error.e3dc == ErrorPolicy::RetryThenSkip => {
+            warn!("{} query failed, retrying: {}", name, e);
+            query(e3dc_client)
+        }
+        result => result,
+    }
+}
+
+/// Apply the `[on_error] mqtt` policy to broker connections the event loops
+/// lost, `bridge/state` follows the outage
+///
+/// Returns the lifecycle event of the changes, if there were any.
+/// rumqttc reconnects by itself, unless the policy crashes the bridge keeps
+/// polling and its publishes fail until the broker is back.
+fn check_connections(
+    on_error: &OnErrorConfig,
+    lifecycle: &mut Lifecycle,
+    mqtt_publisher: &MqttPublisher,
+    homie: Option<&HomiePublisher>,
+) -> anyhow::Result<Option<LifecycleEvent>> {
+    let events: Vec<ConnectionEvent> = mqtt_publisher
+        .connection_events()
+        .into_iter()
+        .chain(
+            homie
+                .into_iter()
+                .flat_map(HomiePublisher::connection_events),
+        )
+        .collect();
+    let lost = mqtt_publisher.connection_lost() || homie.is_some_and(|h| h.connection_lost());
+    let Some(event) = broker_event(on_error, events, lost)? else {
+        return Ok(None);
+    };
+    if let Some(state) = lifecycle.handle(event) {
+        info!("Bridge state: {}", state);
+        // Queued until the broker is back, failures are counted for the next cycle
+        let _ = mqtt_publisher.publish_bridge_state(state);
+    }
+    Ok(Some(event))
+}
+
+/// Lifecycle event for the connection changes of the event loops, `lost` if
+/// any of them is still without a broker
+///
+/// Errors if the policy crashes the bridge on a lost connection.
+fn broker_event(
+    on_error: &OnErrorConfig,
+    events: Vec<ConnectionEvent>,
+    lost: bool,
+) -> Result<Option<LifecycleEvent>, BridgeError> {
+    if events.is_empty() {
+        return Ok(None);
+    }
+    for event in events {
+        let ConnectionEvent::Lost(reason) = event else {
+            continue;
+        };
+        let e = BridgeError::Mqtt(MqttError::ConnectionLost(reason));
+        match on_error.policy(&e) {
+            ErrorPolicy::Crash => {
+                error!("{}", e);
+                return Err(e);
+            }
+            _ => warn!("{}, reconnecting", e),
+        }
+    }
+    Ok(Some(if lost {
+        LifecycleEvent::MqttLost
+    } else {
+        LifecycleEvent::MqttRecovered
+    }))
+}
+
+/// Connect to the E3DC and apply the `[e3dc]` client settings (model, retry
+/// policy, DCB polling)
+pub fn connect_e3dc(config: &Config) -> anyhow::Result<E3dcClient> {
+    info!("Creating E3DC client...");
+    let mut e3dc_client = E3dcClient::new(
+        config.e3dc.host.clone(),
+        config.e3dc.key.clone(),
+        config.e3dc.username.clone(),
+        config.e3dc.password.clone(),
+        config.e3dc.connect_timeout,
+    )?;
+
+    e3dc_client.set_model(config.e3dc.model.as_deref(), &config.e3dc.models);
+    e3dc_client.set_retry_policy(config.retry);
+    e3dc_client.set_poll_dcbs(config.e3dc.poll_dcbs);
+    if !config.e3dc.poll_batteries {
+        info!("Battery polling disabled, publishing status and statistics only");
+    } else if !config.e3dc.poll_dcbs {
+        info!("DCB polling disabled, publishing pack-level battery data only");
+    }
+    Ok(e3dc_client)
+}
+
+/// Command handlers of `command/#`, the refresh flag and the emergency power
+/// test the handlers share with the main loop
+fn register_commands(
+    config: &Config,
+) -> (
+    Subscriber<E3dcClient>,
+    Arc<AtomicBool>,
+    Arc<Mutex<EmergencyPowerTestTracker>>,
+) {
+    let mut subscriber = Subscriber::new(config.commands.clone());
+    subscriber.register(Box::new(SettingsCommands));
+    subscriber.register(Box::new(ClockCommands::new(&config.commands)));
+    let emergency_power_test = Arc::new(Mutex::new(EmergencyPowerTestTracker::new()));
+    subscriber.register(Box::new(EmergencyPowerTestCommands::new(
+        emergency_power_test.clone(),
+    )));
+    let refresh = Arc::new(AtomicBool::new(false));
+    subscriber.register(Box::new(ActionCommands::new(refresh.clone())));
+    if config.e3dc.poll_wb_phases {
+        subscriber.register(Box::new(WallboxCommands::new(config.e3dc.wallbox_index)));
+    }
+    if config.commands.enabled {
+        info!("Commands enabled, accepting writes on command/#");
+    } else {
+        info!("Commands disabled, running read-only");
+    }
+    (subscriber, refresh, emergency_power_test)
+}
+
+/// Publish the Home Assistant discovery configs of this configuration
+fn publish_discovery(
+    config: &Config,
+    mqtt_publisher: &MqttPublisher,
+    device_id: &str,
+    system_info: &mqtt::SystemInfo,
+    batteries: &[u64],
+) -> anyhow::Result<()> {
+    let discovery = Discovery::new(
+        mqtt_publisher.root_topic(),
+        device_id,
+        system_info,
+        &config.topics,
+    )
+    .with_expire_after(config.discovery_expire_after())
+    .with_renames(&config.mqtt.rename);
+    let mut entities = Vec::new();
+    // The settings entities are writable, so they need commands
+    if config.commands.enabled {
+        entities.extend(
+            discovery
+                .number_entities(system_info)
+                .into_iter()
+                .chain(discovery.mode_entities())
+                .filter(|entity| config.commands.is_allowed(&entity.object_id)),
+        );
+        entities.extend(
+            discovery.button_entities(config.homeassistant.manual_charge_wh, |command| {
+                config.commands.is_allowed(command)
+            }),
+        );
+    } else {
+        info!("Home Assistant settings and button entities skipped, they require commands.enabled");
+    }
+    let alerts = [
+        (
+            config
+                .alerts
+                .cell_imbalance_mv
+                .filter(|_| config.e3dc.poll_dcbs),
+            "cell_imbalance_alert",
+            "cell imbalance",
+            "problem",
+        ),
+        (
+            config.alerts.temperature_high_c,
+            "temperature_high_alert",
+            "temperature high",
+            "heat",
+        ),
+        (
+            config.alerts.temperature_low_c,
+            "temperature_low_alert",
+            "temperature low",
+            "cold",
+        ),
+    ];
+    for (threshold, alert, name, device_class) in alerts {
+        if threshold.is_some() {
+            entities.extend(discovery.battery_alert_entities(batteries, alert, name, device_class));
+        }
+    }
+    entities
+        .push(discovery.firmware_update_entity(config.homeassistant.latest_firmware.as_deref()));
+    // Device triggers for the events published with this configuration
+    entities.extend(discovery.grid_outage_triggers());
+    if config.alerts.export_limit_w.is_some() || config.alerts.export_limit_percent.is_some() {
+        entities.extend(discovery.derating_triggers());
+    }
+    entities.extend(discovery.battery_triggers(batteries, "battery_error"));
+    if config.alerts.cell_imbalance_mv.is_some() && config.e3dc.poll_dcbs {
+        entities.extend(discovery.battery_triggers(batteries, "cell_imbalance"));
+    }
+    for entity in &entities {
+        mqtt_publisher.publish_discovery(&config.homeassistant.discovery_prefix, entity)?;
+    }
+    info!(
+        "✓ Published Home Assistant discovery ({} entities)",
+        entities.len()
+    );
+    Ok(())
+}
+
+/// Open `[default] state_dir`, importing a legacy `state_file` once
+fn open_state_dir(config: &Config) -> anyhow::Result<Option<StateDir>> {
+    let Some(dir) = config.default.state_dir() else {
+        return Ok(None);
+    };
+    let state_dir = StateDir::open(&dir)
+        .with_context(|| format!("Failed to open state directory {}", dir.display()))?;
+    if let Some(file) = &config.default.state_file {
+        warn!("[default] state_file is deprecated, use state_dir");
+        if state_dir
+            .import_legacy(file)
+            .with_context(|| format!("Failed to import state file {}", file.display()))?
+        {
+            info!(
+                "Imported state file {} into {}",
+                file.display(),
+                dir.display()
+            );
+        }
+    }
+    Ok(Some(state_dir))
+}
+
+/// Run the history backfill on its own thread and E3DC connection
+///
+/// The DB queries take minutes for a year of history, the bridge polls and
+/// records live readings meanwhile. The store is opened a second time, the
+/// live snapshots are written through the pipeline's connection.
+#[cfg(feature = "history")]
+fn spawn_backfill(
+    config: &Config,
+    spans: Vec<BackfillSpan>,
+    shutdown: &Shutdown,
+) -> std::io::Result<()> {
+    let e3dc = config.e3dc.clone();
+    let retry = config.retry;
+    let dir = config.history.as_ref().map(|history| history.dir.clone());
+    let shutdown = shutdown.clone();
+    std::thread::Builder::new()
+        .name("history-backfill".to_string())
+        .spawn(move || {
+            let result = dir.context("No history store").and_then(|dir| {
+                let mut store = HistoryStore::open(&dir)?;
+                let mut client = crate::e3dc::worker::connect(&e3dc, retry)?;
+                backfill_history(&mut client, &mut store, &spans, &shutdown)
+            });
+            match result {
+                Ok(0) => {}
+                Ok(records) => info!("✓ Backfilled history with {} records", records),
+                Err(e) => warn!(
+                    "Failed to backfill history, the rest follows at the next start: {:#}",
+                    e
+                ),
+            }
+        })?;
+    Ok(())
+}
+
+/// Sinks of the poll readings (the history store)
+fn spawn_pipeline(config: &Config, shutdown: &Shutdown) -> anyhow::Result<Option<Pipeline>> {
+    let mut sinks: Vec<Box<dyn Sink>> = Vec::new();
+    sinks.extend(history_sink(config, shutdown)?);
+    if sinks.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(Pipeline::spawn(sinks, SINK_QUEUE)?))
+}
+
+/// The `[history]` store as sink, its pending backfill started next to it
+#[cfg(feature = "history")]
+fn history_sink(config: &Config, shutdown: &Shutdown) -> anyhow::Result<Option<Box<dyn Sink>>> {
+    let Some(history) = &config.history else {
+        return Ok(None);
+    };
+    let mut store = HistoryStore::open(&history.dir)
+        .with_context(|| format!("Failed to open {}", history.dir.display()))?;
+    info!("Recording history to {}", history.dir.display());
+    // Planned before the first live snapshot, a new store is still empty
+    match store.pending_backfill(history::backfill_spans(
+        Utc::now(),
+        history.backfill_days,
+        history.backfill_detail_days,
+    )) {
+        Ok(spans) if spans.is_empty() => {}
+        Ok(spans) => spawn_backfill(config, spans, shutdown)?,
+        Err(e) => warn!("Failed to plan the history backfill: {:#}", e),
+    }
+    Ok(Some(Box::new(HistorySink::new(store, history))))
+}
+
+#[cfg(not(feature = "history"))]
+fn history_sink(config: &Config, _shutdown: &Shutdown) -> anyhow::Result<Option<Box<dyn Sink>>> {
+    if config.history.is_some() {
+        warn!("[history] is ignored, this build has no history feature");
+    }
+    Ok(None)
+}
+
+/// Trackers and last published values of the status cycle
+struct StatusCycle {
+    last: Option<mqtt::Status>,
+    heartbeat: Heartbeat,
+    zero_pv: Option<ZeroPvSuppression>,
+    last_pvi_data: Vec<mqtt::PviData>,
+    soc_alerts: SocTracker,
+    last_charge_limits: Option<mqtt::ChargeLimits>,
+    last_phase_balance: Option<mqtt::PhaseBalance>,
+    last_dcdc_data: Vec<mqtt::DcdcData>,
+    last_wb_phases: Option<u8>,
+    wallbox_sessions: WallboxSessionTracker,
+    last_session_energy: Option<f64>,
+    export_limit: Option<ExportLimitTracker>,
+    last_export_usage: Option<mqtt::ExportLimitUsage>,
+    last_residual: Option<f64>,
+    energy_balance: Option<EnergyBalanceMonitor>,
+    soc_rate: Option<SocRate>,
+    last_battery_rates: Option<mqtt::BatteryRates>,
+    smoothed_soc: Option<SmoothedSoc>,
+    last_smoothed_soc: Option<f64>,
+    emergency_power: EmergencyPowerTracker,
+    emergency_power_usage: EmergencyPowerUsage,
+    last_emergency_power_usage: Option<mqtt::EmergencyPowerUsage>,
+    grid_outage: GridOutageTracker,
+    scheduler: Option<Scheduler>,
+    energy: Option<EnergyIntegrator>,
+}
+
+impl StatusCycle {
+    fn new(
+        config: &Config,
+        system_info: &SystemInfo,
+        battery_capacity: f64,
+        state_dir: &Option<StateDir>,
+    ) -> anyhow::Result<Self> {
+        let heartbeat = config
+            .payload
+            .heartbeat
+            .map(Duration::from_std)
+            .transpose()?;
+        // Non-retained status values reach late subscribers through the heartbeat only
+        let heartbeat_age = match heartbeat {
+            None if !config.topics.retain.status => Some(UNRETAINED_STATUS_HEARTBEAT),
+            heartbeat => heartbeat,
+        };
+        let soc_rate = match config.e3dc.soc_rate_window {
+            Some(window) => Some(SocRate::new(Duration::from_std(window)?)),
+            None => None,
+        };
+        if soc_rate.is_some() && battery_capacity == 0.0 {
+            warn!("Installed battery capacity unknown, time_to_full/time_to_empty stay 0");
+        }
+        let smoothed_soc = match config.e3dc.soc_smoothing_window {
+            Some(window) => Some(SmoothedSoc::new(
+                Duration::from_std(window)?,
+                config.e3dc.soc_smoothing_hysteresis,
+            )),
+            None => None,
+        };
+        let scheduler = (!config.schedule.windows.is_empty())
+            .then(|| Scheduler::new(config.schedule.windows.clone()));
+        if scheduler.is_some() {
+            info!(
+                "Schedule with {} window(s) enabled",
+                config.schedule.windows.len()
+            );
+            if battery_capacity == 0.0 {
+                warn!("Installed battery capacity unknown, grid charge windows do nothing");
+            }
+        }
+        Ok(Self {
+            last: None,
+            heartbeat: Heartbeat::new(heartbeat_age),
+            zero_pv: config
+                .e3dc
+                .suppress_zero_pv_after
+                .map(|after| Duration::from_std(after).map(ZeroPvSuppression::new))
+                .transpose()?,
+            last_pvi_data: Vec::new(),
+            soc_alerts: SocTracker::new(
+                config.alerts.soc_low,
+                config.alerts.soc_high,
+                config.alerts.soc_hysteresis,
+            ),
+            last_charge_limits: None,
+            last_phase_balance: None,
+            last_dcdc_data: Vec::new(),
+            last_wb_phases: None,
+            wallbox_sessions: WallboxSessionTracker::new(),
+            last_session_energy: None,
+            export_limit: config
+                .alerts
+                .export_limit(system_info.installed_peak_power)
+                .map(ExportLimitTracker::new),
+            last_export_usage: None,
+            last_residual: None,
+            energy_balance: config
+                .alerts
+                .energy_balance_w
+                .map(|threshold| EnergyBalanceMonitor::new(threshold, config.alerts.polls)),
+            soc_rate,
+            last_battery_rates: None,
+            smoothed_soc,
+            last_smoothed_soc: None,
+            emergency_power: EmergencyPowerTracker::new(),
+            emergency_power_usage: load_state(state_dir)?,
+            last_emergency_power_usage: None,
+            grid_outage: GridOutageTracker::new(config.alerts.polls),
+            scheduler,
+            energy: if config.e3dc.integrate_energy {
+                Some(load_state(state_dir)?)
+            } else {
+                None
+            },
+        })
+    }
+}
+
+/// Last published values of the statistics cycle
+struct StatisticsCycle {
+    heartbeat: Heartbeat,
+    last_daily_stats: Option<DailyStatistics>,
+    last_integrated_energy: Option<mqtt::IntegratedEnergy>,
+    lifetime_day: Option<NaiveDate>,
+    windows: Vec<(StatisticsWindowConfig, Option<mqtt::PeriodStatistics>)>,
+    period_totals: Vec<(PeriodTotals, Option<mqtt::PeriodStatistics>)>,
+    last_day_curve: Option<mqtt::DayCurve>,
+    portal_online: Option<bool>,
+    last_ep_capabilities: Option<mqtt::EmergencyPowerCapabilities>,
+    last_network: Option<mqtt::NetworkConfig>,
+    forecast_worker: Option<ForecastWorker>,
+    pv_forecast: Option<PvForecast>,
+    /// Next forecast fetch, None for right away
+    forecast_due: Option<Instant>,
+    last_forecast: Option<mqtt::PvForecast>,
+    grid_charge_skipped: Option<bool>,
+    last_device_clock: Option<mqtt::DeviceClock>,
+}
+
+impl StatisticsCycle {
+    fn new(config: &Config) -> anyhow::Result<Self> {
+        Ok(Self {
+            heartbeat: Heartbeat::new(
+                config
+                    .payload
+                    .heartbeat
+                    .map(Duration::from_std)
+                    .transpose()?,
+            ),
+            last_daily_stats: None,
+            last_integrated_energy: None,
+            lifetime_day: None,
+            windows: config
+                .statistics
+                .windows
+                .iter()
+                .map(|window| (window.clone(), None))
+                .collect(),
+            period_totals: vec![
+                (PeriodTotals::new(StatisticsPeriod::Month), None),
+                (PeriodTotals::new(StatisticsPeriod::Year), None),
+            ],
+            last_day_curve: None,
+            portal_online: None,
+            last_ep_capabilities: None,
+            last_network: None,
+            forecast_worker: config
+                .forecast
+                .as_ref()
+                .map(ForecastWorker::spawn)
+                .transpose()?,
+            pv_forecast: None,
+            forecast_due: None,
+            last_forecast: None,
+            grid_charge_skipped: None,
+            last_device_clock: None,
+        })
+    }
+}
+
+/// Trackers and last published values of the battery cycle
+struct BatteryCycle {
+    /// Battery data polled by the statistics cycle without a worker
+    pending: Option<BatteryPoll>,
+    /// A full republish was due when the poll was requested
+    republish: bool,
+    last: Vec<mqtt::BatteryData>,
+    dcb_modules: Option<DcbModules>,
+    errors: BatteryErrorTracker,
+    cycles: CycleHistory,
+    last_cycle_trends: Vec<mqtt::CycleTrend>,
+    cell_imbalance: Option<CellImbalanceMonitor>,
+    temperature: TemperatureMonitor,
+    last_alerts: Vec<BatteryAlert>,
+}
+
+impl BatteryCycle {
+    fn new(config: &Config, state_dir: &Option<StateDir>) -> anyhow::Result<Self> {
+        if config.alerts.cell_imbalance_mv.is_some() && !config.e3dc.poll_dcbs {
+            warn!("alerts.cell_imbalance_mv needs the DCB cell voltages, ignored with e3dc.poll_dcbs = false");
+        }
+        Ok(Self {
+            pending: None,
+            republish: false,
+            last: Vec::new(),
+            // Without the DCB queries there are no serials to compare
+            dcb_modules: config
+                .e3dc
+                .poll_dcbs
+                .then(|| load_state(state_dir))
+                .transpose()?,
+            errors: BatteryErrorTracker::new(),
+            cycles: load_state(state_dir)?,
+            last_cycle_trends: Vec::new(),
+            cell_imbalance: config
+                .alerts
+                .cell_imbalance_mv
+                .filter(|_| config.e3dc.poll_dcbs)
+                .map(|threshold| CellImbalanceMonitor::new(threshold, config.alerts.polls)),
+            temperature: TemperatureMonitor::new(
+                config.alerts.temperature_high_c,
+                config.alerts.temperature_low_c,
+                config.alerts.polls,
+            ),
+            last_alerts: Vec::new(),
+        })
+    }
+}
+
+/// A connected bridge: clients, schedule and the state of its cycles
+struct Bridge {
+    config: Config,
+    shutdown: Shutdown,
+    e3dc_client: E3dcClient,
+    mqtt_publisher: MqttPublisher,
+    homie: Option<HomiePublisher>,
+    subscriber: Subscriber<E3dcClient>,
+    /// Set by `command/refresh`, polls everything right away
+    refresh: Arc<AtomicBool>,
+    emergency_power_test: Arc<Mutex<EmergencyPowerTestTracker>>,
+    battery_worker: Option<BatteryWorker>,
+    pipeline: Option<Pipeline>,
+    state_dir: Option<StateDir>,
+    lifecycle: Lifecycle,
+    degraded: Degraded,
+    tasks: Tasks,
+    status_task: TaskId,
+    statistics_task: TaskId,
+    interval: Duration,
+    statistic_interval: Duration,
+    curve_slot: Option<Duration>,
+    /// A few missed polls are bridged by the energy integration, a reconnect isn't
+    max_energy_gap: Duration,
+    battery_capacity: f64,
+    started: Instant,
+    status: StatusCycle,
+    statistics: StatisticsCycle,
+    battery: BatteryCycle,
+}
+
+impl Bridge {
+    /// Connect to the E3DC and the broker and publish the startup topics
+    ///
+    /// None if `shutdown` was requested during the startup delay.
+    fn start(config: Config, shutdown: Shutdown) -> anyhow::Result<Option<Self>> {
+        let interval = Duration::from_std(config.e3dc.interval)?;
+        let statistic_interval = Duration::from_std(config.e3dc.statistic_update_interval)?;
+        let align_offset = Duration::from_std(config.e3dc.align_offset)?;
+        let curve_slot = config
+            .statistics
+            .curve_slot
+            .map(Duration::from_std)
+            .transpose()?;
+        let build = BuildInfo::current();
+        info!("Log level: {}", config.default.log_level);
+        debug!("Debug logging is enabled");
+
+        info!("Configuration loaded successfully!");
+        info!("  E3DC Host: {}", config.e3dc.host);
+        info!("  MQTT Root: {}", config.mqtt.root);
+        info!("  Interval: {:?}", interval);
+        info!("  Statistics Interval: {:?}", statistic_interval);
+
+        // Spread the start of a fleet of bridges
+        if let Some(startup_delay) = config.e3dc.startup_delay {
+            let delay = random_below(Duration::from_std(startup_delay)?);
+            info!("Waiting {:.1}s before connecting", delay.as_seconds_f64());
+            if shutdown.wait(delay.to_std()?) {
+                return Ok(None);
+            }
+        }
+
+        let mut e3dc_client = connect_e3dc(&config)?;
+        let batteries = e3dc_client.batteries().clone();
+
+        let system_info = e3dc_client.get_system_info()?;
+        let device_id = format!("{}-{}", system_info.model, system_info.serial_number);
+        info!("Device ID: {}", device_id);
+
+        // Query batteries at startup to know how many we have and their DCB counts
+        info!("Querying batteries...");
+        info!("Found {} battery/batteries", batteries.len());
+        for battery in batteries.iter() {
+            info!(
+                "  Battery {}: {} DCB modules",
+                battery.index, battery.dcb_count
+            );
+        }
+
+        // Create MQTT publisher (blocking)
+        info!("Creating MQTT publisher...");
+        let mqtt_publisher = MqttPublisher::new(&config, device_id.clone())?;
+        info!("✓ MQTT publisher created successfully!");
+
+        if config.debug.rscp {
+            let mut debug_log = DebugLog::new(TagFilter::new(config.debug.tags.clone()));
+            if config.debug.mqtt {
+                debug_log = debug_log.with_sink(mqtt_publisher.debug_sink());
+            }
+            e3dc_client.add_observer(Box::new(debug_log));
+            info!("RSCP debug log enabled");
+        }
+
+        // Give MQTT a moment to connect
+        std::thread::sleep(Duration::milliseconds(500).to_std()?);
+        let mut lifecycle = Lifecycle::new();
+        check_connections(&config.on_error, &mut lifecycle, &mqtt_publisher, None)?;
+
+        // Publish online status
+        mqtt_publisher.publish_online_status(true)?;
+        mqtt_publisher.publish_bridge_state(lifecycle.state())?;
+        info!("✓ Published online status");
+
+        // Publish initial system info
+        let mqtt_system_info = mqtt::SystemInfo::from_e3dc(&system_info);
+        mqtt_publisher.publish_system_info(&mqtt_system_info)?;
+        info!("✓ Published system info");
+
+        let mut statistics = StatisticsCycle::new(&config)?;
+        // Emergency power capabilities and reserve, refreshed (or discovered
+        // after a failure here) every statistics cycle
+        let battery_capacity = system_info.installed_battery_capacity.map(|c| c as f64);
+        match e3dc_client.get_emergency_power_capabilities(battery_capacity) {
+            Ok(capabilities) => {
+                let capabilities = mqtt::EmergencyPowerCapabilities::from_e3dc(&capabilities);
+                mqtt_publisher.publish_emergency_power_capabilities(&capabilities, None)?;
+                statistics.last_ep_capabilities = Some(capabilities);
+            }
+            Err(e) => warn!("Emergency power capabilities not available: {}", e),
+        }
+
+        let (subscriber, refresh, emergency_power_test) = register_commands(&config);
+
+        if config.homeassistant.enabled {
+            let batteries: Vec<u64> = e3dc_client
+                .batteries()
+                .iter()
+                .filter(|_| config.e3dc.poll_batteries)
+                .map(|b| b.index)
+                .collect();
+            publish_discovery(
+                &config,
+                &mqtt_publisher,
+                &device_id,
+                &mqtt_system_info,
+                &batteries,
+            )?;
+        }
+
+        publish_idle_periods(&mut e3dc_client, &mqtt_publisher)?;
+        info!("✓ Published idle periods");
+
+        if config.statistics.backfill_days > 0 {
+            match e3dc_client.get_past_days(config.statistics.backfill_days) {
+                Ok(days) => {
+                    let history: Vec<mqtt::DayStatistics> =
+                        days.iter().map(mqtt::DayStatistics::from_e3dc).collect();
+                    mqtt_publisher.publish_statistics_history(&history)?;
+                    info!("✓ Published statistics of the last {} days", history.len());
+                }
+                // History is a convenience, don't refuse to start without it
+                Err(e) => warn!("Failed to backfill daily statistics: {}", e),
+            }
+        }
+
+        // Homie layout on a second connection, it needs its own last will ($state)
+        let homie = if config.homie.enabled {
+            let name = format!("E3DC {}", system_info.model);
+            info!("Publishing Homie 4 layout below {}", config.homie.prefix);
+            Some(HomiePublisher::new(&config, &device_id, name)?)
+        } else {
+            None
+        };
+
+        // Python-style timing: polls at the interval boundaries, both right away at start
+        let mut tasks = Tasks::new();
+        let started_at = Utc::now();
+        let cadence = |interval| {
+            Trigger::Cadence(Cadence {
+                interval,
+                alignment: config.e3dc.align,
+                offset: align_offset,
+                started: started_at,
+            })
+        };
+        let status_task = tasks.register("status", cadence(interval), CatchUp::Once, started_at);
+        let statistics_trigger = match &config.e3dc.statistic_schedule {
+            Some(cron) => {
+                info!("Statistics scheduled at \"{}\"", cron);
+                Trigger::Cron(cron.clone())
+            }
+            None => cadence(statistic_interval),
+        };
+        let statistics_task =
+            tasks.register("statistics", statistics_trigger, CatchUp::Once, started_at);
+        if let Some(jitter) = config.e3dc.jitter {
+            let jitter = Duration::from_std(jitter)?;
+            tasks.set_jitter(status_task, jitter);
+            tasks.set_jitter(statistics_task, jitter);
+        }
+        tasks.run_all_now(started_at);
+
+        let battery_worker = if config.e3dc.battery_worker && config.e3dc.poll_batteries {
+            info!("Polling batteries on a second E3DC connection");
+            Some(BatteryWorker::spawn(&config.e3dc, config.retry)?)
+        } else {
+            None
+        };
+        let pipeline = spawn_pipeline(&config, &shutdown)?;
+        let battery_capacity = system_info.installed_battery_capacity.unwrap_or_default() as f64;
+        let state_dir = open_state_dir(&config)?;
+        let status = StatusCycle::new(&config, &system_info, battery_capacity, &state_dir)?;
+        let battery = BatteryCycle::new(&config, &state_dir)?;
+
+        let mut counters: Counters = load_state(&state_dir)?;
+        let restarts = counters.record_start();
+        save_state(&state_dir, &counters);
+        info!("Restarts so far: {}", restarts);
+        mqtt_publisher.publish_restarts(restarts)?;
+        mqtt_publisher.publish_version(&build)?;
+
+        Ok(Some(Self {
+            max_energy_gap: max(interval * 3, Duration::minutes(1)),
+            config,
+            shutdown,
+            e3dc_client,
+            mqtt_publisher,
+            homie,
+            subscriber,
+            refresh,
+            emergency_power_test,
+            battery_worker,
+            pipeline,
+            state_dir,
+            lifecycle,
+            degraded: Degraded::new(),
+            tasks,
+            status_task,
+            statistics_task,
+            interval,
+            statistic_interval,
+            curve_slot,
+            battery_capacity,
+            started: Instant::now(),
+            status,
+            statistics,
+            battery,
+        }))
+    }
+
+    /// Run the cycles that are due, then wait for the next one
+    fn poll(&mut self) -> anyhow::Result<()> {
+        let now = Utc::now();
+        let connection = check_connections(
+            &self.config.on_error,
+            &mut self.lifecycle,
+            &self.mqtt_publisher,
+            self.homie.as_ref(),
+        )?;
+        if connection == Some(LifecycleEvent::MqttRecovered) {
+            self.republish_all(now)?;
+        }
+        self.skip_while_offline(now);
+
+        if self.tasks.due(self.status_task, now).is_some() {
+            let skipped = self.run_cycle("Status", |bridge| bridge.status_cycle(now))?;
+            if skipped.is_none() {
+                // Failures here are counted for the next cycle
+                let _ = self.mqtt_publisher.publish_last_poll("status", Utc::now());
+            }
+            self.update_degraded(skipped)?;
+            self.publish_metrics();
+        }
+
+        // Get and publish statistics (only when interval has elapsed)
+        if self.tasks.due(self.statistics_task, now).is_some() {
+            let skipped = self.run_cycle("Statistics", |bridge| bridge.statistics_cycle(now))?;
+            if skipped.is_none() {
+                let _ = self
+                    .mqtt_publisher
+                    .publish_last_poll("statistics", Utc::now());
+            }
+            self.update_degraded(skipped)?;
+        }
+
+        if let Some(poll) = self.take_battery_poll()? {
+            let full_republish = std::mem::take(&mut self.battery.republish);
+            let skipped = self.run_cycle("Battery", |bridge| {
+                bridge.battery_cycle(&poll, full_republish)
+            })?;
+            if skipped.is_none() {
+                let _ = self.mqtt_publisher.publish_last_poll("battery", Utc::now());
+            }
+        }
+
+        self.wait_for_next_poll(now)
+    }
+
+    /// Degraded mode: while offline, only poll right after a reconnect
+    fn skip_while_offline(&mut self, now: DateTime<Utc>) {
+        if self.degraded.is_offline() && self.tasks.is_due(self.status_task, now) {
+            let reconnected = self.degraded.reconnect_due(Instant::now())
+                && match self.e3dc_client.reconnect() {
+                    Ok(()) => true,
+                    Err(e) => {
+                        warn!("E3DC still unreachable: {}", e);
+                        false
+                    }
+                };
+            if !reconnected {
+                self.tasks.due(self.status_task, now);
+            }
+        }
+        if self.degraded.is_offline() {
+            self.tasks.due(self.statistics_task, now);
+        }
+    }
+
+    /// Run one poll cycle and apply the `[on_error]` policy of its failure class
+    ///
+    /// Returns how the cycle was skipped, None if it succeeded. The cycle
+    /// isn't run again, `retry_then_skip` only repeats its E3DC queries.
+    fn run_cycle(
+        &mut self,
+        name: &str,
+        cycle: impl FnOnce(&mut Self) -> Result<(), BridgeError>,
+    ) -> anyhow::Result<Option<SkippedCycle>> {
+        let Err(e) = cycle(self) else {
+            return Ok(None);
+        };
+        match self.config.on_error.policy(&e) {
+            ErrorPolicy::Crash => {
+                error!("{} cycle failed: {}", name, e);
+                Err(e.into())
+            }
+            policy @ (ErrorPolicy::SkipCycle
+            | ErrorPolicy::RetryThenSkip
+            | ErrorPolicy::Degrade) => {
+                warn!("{} cycle failed, skipped: {}", name, e);
+                Ok(Some(SkippedCycle {
+                    policy,
+                    mqtt: matches!(e, BridgeError::Mqtt(_)),
+                }))
+            }
+        }
+    }
+
+    /// Track the degraded mode and lifecycle after a cycle, `online` and
+    /// `bridge/state` follow their changes
+    fn update_degraded(&mut self, skipped: Option<SkippedCycle>) -> anyhow::Result<()> {
+        let mut events = Vec::new();
+        // A cycle that only failed to publish still got its answers from the E3DC
+        let answered = skipped.as_ref().is_none_or(|skipped| skipped.mqtt);
+        if answered && self.degraded.recover() {
+            info!("E3DC answers again, back online");
+            events.push(LifecycleEvent::E3dcRecovered);
+        }
+        match skipped {
+            None => events.push(LifecycleEvent::CycleSucceeded),
+            Some(SkippedCycle {
+                policy: ErrorPolicy::Degrade,
+                ..
+            }) if self.degraded.fail(Instant::now()) => {
+                warn!("E3DC unreachable, running offline until it answers again");
+                events.push(LifecycleEvent::E3dcLost);
+            }
+            Some(SkippedCycle { mqtt: true, .. }) => events.push(LifecycleEvent::MqttFailed),
+            Some(_) => {}
+        }
+        if events.iter().any(|event| {
+            matches!(
+                event,
+                LifecycleEvent::E3dcLost | LifecycleEvent::E3dcRecovered
+            )
+        }) {
+            self.during_outage(self.publish_online())?;
+        }
+        for event in events {
+            if let Some(state) = self.lifecycle.handle(event) {
+                info!("Bridge state: {}", state);
+                self.during_outage(self.mqtt_publisher.publish_bridge_state(state))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// `online` and the Homie `$state`: offline while the E3DC is unreachable
+    fn publish_online(&self) -> Result<(), MqttError> {
+        let offline = self.degraded.is_offline();
+        self.mqtt_publisher.publish_online_status(!offline)?;
+        if let Some(homie) = &self.homie {
+            homie.set_alert(offline)?;
+        }
+        Ok(())
+    }
+
+    /// Ignore a failed publish while a broker connection is lost, the
+    /// reconnect publishes everything again
+    fn during_outage(&self, result: Result<(), MqttError>) -> Result<(), MqttError> {
+        let lost = self.mqtt_publisher.connection_lost()
+            || self.homie.as_ref().is_some_and(|h| h.connection_lost());
+        match result {
+            Err(e) if lost => {
+                debug!("{}, publishing again after the reconnect", e);
+                Ok(())
+            }
+            result => result,
+        }
+    }
+
+    /// The broker is back: the last will replaced `online` and publishes
+    /// failed during the outage, so publish everything again right away
+    fn republish_all(&mut self, now: DateTime<Utc>) -> anyhow::Result<()> {
+        info!("Broker connection back, publishing everything again");
+        self.during_outage(self.publish_online())?;
+        self.during_outage(
+            self.mqtt_publisher
+                .publish_bridge_state(self.lifecycle.state()),
+        )?;
+        self.status.heartbeat.force();
+        self.statistics.heartbeat.force();
+        self.battery.republish = true;
+        self.tasks.run_all_now(now);
+        Ok(())
+    }
+
+    /// One warning per cycle instead of one per failed field
+    fn publish_metrics(&self) {
+        let publish_stats = self.mqtt_publisher.take_publish_stats();
+        if publish_stats.failures > 0 {
+            warn!(
+                "{} of {} publishes failed ({} retried, {} queued), first: {}",
+                publish_stats.failures,
+                publish_stats.attempts,
+                publish_stats.retries,
+                publish_stats.queue_depth,
+                publish_stats.first_error.as_deref().unwrap_or("unknown")
+            );
+        }
+        // Failures here are counted for the next cycle
+        let _ = self.mqtt_publisher.publish_metrics(&publish_stats);
+    }
+
+    /// Sleep until the next poll, waking up early for incoming commands
+    fn wait_for_next_poll(&mut self, now: DateTime<Utc>) -> anyhow::Result<()> {
+        // Python-style sleep: compensate for execution time
+        let sleep_duration = max(
+            self.tasks.next_run().unwrap_or(now + self.interval) - Utc::now(),
+            Duration::milliseconds(100),
+        );
+        if let Some(command) = wait_for_command(
+            &self.mqtt_publisher,
+            &self.shutdown,
+            sleep_duration
+                .to_std()
+                .expect("Sleep duration invalid - this is a bug in timing calculation"),
+        ) {
+            self.subscriber
+                .dispatch(command, &mut self.e3dc_client, &self.mqtt_publisher)?;
+            if self.refresh.swap(false, Ordering::Relaxed) {
+                self.tasks.run_all_now(Utc::now());
+            }
+        }
+        Ok(())
+    }
+
+    /// Publish `shutting_down` and give the event loop a moment to write it,
+    /// `online` follows when the publisher is dropped
+    fn stop(mut self) -> anyhow::Result<()> {
+        if self.status.emergency_power_usage.is_island() {
+            save_state(&self.state_dir, &self.status.emergency_power_usage);
+        }
+        if let Some(state) = self.lifecycle.handle(LifecycleEvent::Shutdown) {
+            info!("Bridge state: {}", state);
+            self.mqtt_publisher.publish_bridge_state(state)?;
+        }
+        std::thread::sleep(SHUTDOWN_FLUSH);
+        Ok(())
+    }
+
+    /// Status cycle: the real-time values and everything derived from them
+    fn status_cycle(&mut self, now: DateTime<Utc>) -> Result<(), BridgeError> {
+        let mut status = query(
+            &self.config.on_error,
+            &mut self.e3dc_client,
+            "Status",
+            E3dcClient::get_status,
+        )?;
+        let pvi_data = self.query_pvi_data(&mut status);
+        // Publish to MQTT (per-field change detection inside publish_status)
+        let mut mqtt_status = mqtt::Status::from_e3dc(&status);
+        if let Some(zero_pv) = &mut self.status.zero_pv {
+            zero_pv.apply(&mut mqtt_status, self.status.last.as_ref());
+        }
+        let previous_status = self
+            .status
+            .heartbeat
+            .previous(now, self.status.last.as_ref());
+        self.mqtt_publisher
+            .publish_status(&mqtt_status, previous_status)?;
+        if let Some(homie) = &self.homie {
+            homie.publish_status(&mqtt_status, previous_status)?;
+            homie.announce()?;
+        }
+        // The values below are published in full with the status
+        let republish = previous_status.is_none();
+        if let Some(pipeline) = &self.pipeline {
+            pipeline.send(Message::Status(mqtt_status.clone()));
+        }
+        if let Some(energy) = &mut self.status.energy {
+            energy.update(&mqtt_status, self.max_energy_gap);
+        }
+        if let Some(pvis) = pvi_data {
+            self.publish_pvi_data(pvis, republish)?;
+        }
+
+        let soc_events = match status.battery_soc {
+            Some(soc) => {
+                self.status
+                    .soc_alerts
+                    .update(soc, status.emergency_power_state, status.time_stamp)
+            }
+            None => Vec::new(),
+        };
+        for event in soc_events {
+            info!(
+                "SOC {} alert {}: {:.1}% (threshold {:.1}%)",
+                event.limit,
+                if event.active { "raised" } else { "cleared" },
+                event.state_of_charge,
+                event.threshold
+            );
+            self.mqtt_publisher.publish_event("soc_alert", &event)?;
+        }
+
+        self.limits_cycle(republish)?;
+        self.wallbox_cycle(&status, republish)?;
+        self.derived_status_cycle(&status, &mqtt_status, republish)?;
+        self.emergency_power_cycle(&status, republish)?;
+        self.run_schedule(&status);
+
+        debug!(
+            "Status: Solar={:.0}W Battery={:.0}W Grid={:.0}W Home={:.0}W SOC={:.1}%",
+            mqtt_status.solar_production,
+            mqtt_status.battery_consumption,
+            mqtt_status.grid_production,
+            mqtt_status.house_consumption,
+            mqtt_status.state_of_charge
+        );
+        self.status.last = Some(mqtt_status);
+        Ok(())
+    }
+
+    /// Systems with several inverters (Quattroporte): per-inverter topics,
+    /// solar production is the sum of all of them. Skipped while the PV topics
+    /// are suppressed at night.
+    fn query_pvi_data(&mut self, status: &mut Status) -> Option<Vec<mqtt::PviData>> {
+        let suppressed = self
+            .status
+            .zero_pv
+            .as_ref()
+            .is_some_and(|z| z.is_suppressed());
+        if self.e3dc_client.pvis().len() <= 1 || suppressed {
+            return None;
+        }
+        match self.e3dc_client.get_pvi_data() {
+            Ok(pvis) => {
+                status.power_pv = Some(pvis.iter().map(|pvi| pvi.dc_power).sum());
+                Some(pvis.iter().map(mqtt::PviData::from_e3dc).collect())
+            }
+            Err(e) => {
+                warn!("Failed to get PVI data: {}", e);
+                None
+            }
+        }
+    }
+
+    fn publish_pvi_data(
+        &mut self,
+        pvis: Vec<mqtt::PviData>,
+        republish: bool,
+    ) -> Result<(), BridgeError> {
+        let previous: &[mqtt::PviData] = if republish {
+            &[]
+        } else {
+            &self.status.last_pvi_data
+        };
+        self.mqtt_publisher.publish_pvi_data(&pvis, previous)?;
+        if let Some(homie) = &self.homie {
+            homie.publish_pvi_data(&pvis, previous)?;
+        }
+        self.status.last_pvi_data = pvis;
+        Ok(())
+    }
+
+    /// Optional status queries: charge limits, phase balance and DCDC converters
+    fn limits_cycle(&mut self, republish: bool) -> Result<(), BridgeError> {
+        if self.config.e3dc.poll_charge_limits {
+            match self.e3dc_client.get_charge_limits() {
+                Ok(limits) => {
+                    let limits = mqtt::ChargeLimits::from_e3dc(&limits);
+                    let previous = self
+                        .status
+                        .last_charge_limits
+                        .as_ref()
+                        .filter(|_| !republish);
+                    self.mqtt_publisher
+                        .publish_charge_limits(&limits, previous)?;
+                    if let Some(homie) = &self.homie {
+                        homie.publish_charge_limits(&limits, previous)?;
+                    }
+                    self.status.last_charge_limits = Some(limits);
+                }
+                Err(e) => warn!("Failed to get charge limits: {}", e),
+            }
+        }
+
+        if self.config.e3dc.poll_phases {
+            match self.e3dc_client.get_phase_balance() {
+                Ok(balance) => {
+                    let balance = mqtt::PhaseBalance::from_e3dc(&balance);
+                    let previous = self
+                        .status
+                        .last_phase_balance
+                        .as_ref()
+                        .filter(|_| !republish);
+                    self.mqtt_publisher
+                        .publish_phase_balance(&balance, previous)?;
+                    if let Some(homie) = &self.homie {
+                        homie.publish_phase_balance(&balance, previous)?;
+                    }
+                    self.status.last_phase_balance = Some(balance);
+                }
+                Err(e) => warn!("Failed to get phase balance: {}", e),
+            }
+        }
+
+        if self.config.e3dc.poll_dcdc {
+            match self.e3dc_client.get_dcdc_data() {
+                Ok(dcdcs) => {
+                    let dcdcs: Vec<mqtt::DcdcData> =
+                        dcdcs.iter().map(mqtt::DcdcData::from_e3dc).collect();
+                    let previous: &[mqtt::DcdcData] = if republish {
+                        &[]
+                    } else {
+                        &self.status.last_dcdc_data
+                    };
+                    self.mqtt_publisher.publish_dcdc_data(&dcdcs, previous)?;
+                    self.status.last_dcdc_data = dcdcs;
+                }
+                Err(e) => warn!("Failed to get DCDC data: {}", e),
+            }
+        }
+        Ok(())
+    }
+
+    /// Wallbox phases, charging sessions and the energy of the running session
+    fn wallbox_cycle(&mut self, status: &Status, republish: bool) -> Result<(), BridgeError> {
+        if self.config.e3dc.poll_wb_phases {
+            match self
+                .e3dc_client
+                .get_wallbox_phases(self.config.e3dc.wallbox_index)
+            {
+                Ok(Some(phases)) => {
+                    if republish || self.status.last_wb_phases != Some(phases) {
+                        self.mqtt_publisher.publish_wallbox_phases(phases)?;
+                    }
+                    self.status.last_wb_phases = Some(phases);
+                }
+                // No phase switching, nothing to publish
+                Ok(None) => self.status.last_wb_phases = None,
+                Err(e) => warn!("Failed to get wallbox phases: {}", e),
+            }
+        }
+
+        let session_event = status.power_wb.and_then(|power| {
+            // Without the solar share the session counts as grid powered
+            let solar = status.power_wb_solar.unwrap_or_default();
+            self.status
+                .wallbox_sessions
+                .update(power, solar, status.time_stamp)
+        });
+        if let Some(event) = session_event {
+            info!(
+                "Wallbox session ended: {:.0}Wh in {}s, {:.1}% solar",
+                event.energy, event.duration_seconds, event.solar_share
+            );
+            self.mqtt_publisher
+                .publish_event("wallbox_session", &event)?;
+        }
+        let session_energy = self
+            .status
+            .wallbox_sessions
+            .session_energy()
+            .map(f64::round);
+        if let Some(energy) = session_energy {
+            if republish || self.status.last_session_energy != session_energy {
+                self.mqtt_publisher.publish_wallbox_session_energy(energy)?;
+            }
+        }
+        self.status.last_session_energy = session_energy;
+        Ok(())
+    }
+
+    /// Energy balance residual and its alert
+    fn energy_balance_cycle(
+        &mut self,
+        residual: f64,
+        time: DateTime<Utc>,
+        republish: bool,
+    ) -> Result<(), BridgeError> {
+        if republish || self.status.last_residual != Some(residual) {
+            self.mqtt_publisher.publish_energy_balance(residual)?;
+        }
+        self.status.last_residual = Some(residual);
+        if let Some(event) = self
+            .status
+            .energy_balance
+            .as_mut()
+            .and_then(|monitor| monitor.update(residual, time))
+        {
+            if event.active {
+                warn!(
+                    "Energy balance residual {:.0}W exceeds {:.0}W, check the meter configuration",
+                    event.residual, event.threshold
+                );
+            } else {
+                info!("Energy balance residual back to {:.0}W", event.residual);
+            }
+            self.mqtt_publisher
+                .publish_event("energy_balance", &event)?;
+        }
+        Ok(())
+    }
+
+    /// Values derived from the status: export limit, energy balance and SOC rates
+    fn derived_status_cycle(
+        &mut self,
+        status: &Status,
+        mqtt_status: &mqtt::Status,
+        republish: bool,
+    ) -> Result<(), BridgeError> {
+        if let Some(tracker) = self
+            .status
+            .export_limit
+            .as_mut()
+            .filter(|_| mqtt_status.is_known("export_to_grid"))
+        {
+            let usage = mqtt::ExportLimitUsage::new(mqtt_status.export_to_grid, tracker.limit());
+            let previous = self
+                .status
+                .last_export_usage
+                .as_ref()
+                .filter(|_| !republish);
+            self.mqtt_publisher.publish_export_limit(&usage, previous)?;
+            if let Some(event) = tracker.update(mqtt_status.export_to_grid, status.time_stamp) {
+                info!(
+                    "Grid export limit {}: {:.0}W of {:.0}W",
+                    if event.active { "reached" } else { "left" },
+                    event.export,
+                    event.limit
+                );
+                self.mqtt_publisher.publish_event("export_limit", &event)?;
+            }
+            self.status.last_export_usage = Some(usage);
+        }
+
+        if let Some(residual) = status.energy_balance_residual().map(f64::round) {
+            self.energy_balance_cycle(residual, status.time_stamp, republish)?;
+        }
+
+        if let Some(rates) = self
+            .status
+            .soc_rate
+            .as_mut()
+            .zip(status.battery_soc)
+            .and_then(|(soc_rate, soc)| soc_rate.update(status.time_stamp, soc))
+            .and_then(|rate| mqtt::BatteryRates::new(rate, status, self.battery_capacity))
+        {
+            let previous = self
+                .status
+                .last_battery_rates
+                .as_ref()
+                .filter(|_| !republish);
+            self.mqtt_publisher
+                .publish_battery_rates(&rates, previous)?;
+            if let Some(homie) = &self.homie {
+                homie.publish_battery_rates(&rates, previous)?;
+            }
+            self.status.last_battery_rates = Some(rates);
+        }
+
+        if let (Some(smoothed_soc), Some(battery_soc)) =
+            (&mut self.status.smoothed_soc, status.battery_soc)
+        {
+            let soc = smoothed_soc.update(status.time_stamp, battery_soc);
+            if republish || self.status.last_smoothed_soc != Some(soc) {
+                self.mqtt_publisher.publish_smoothed_soc(soc)?;
+            }
+            self.status.last_smoothed_soc = Some(soc);
+        }
+        Ok(())
+    }
+
+    /// Emergency power: state changes, usage, grid outages and a running test
+    fn emergency_power_cycle(
+        &mut self,
+        status: &Status,
+        republish: bool,
+    ) -> Result<(), BridgeError> {
+        if let Some(event) = self
+            .status
+            .emergency_power
+            .update(status.emergency_power_state, status.time_stamp)
+        {
+            info!(
+                "Emergency power state changed: {} -> {}",
+                event.from, event.to
+            );
+            self.mqtt_publisher
+                .publish_event("emergency_power", &event)?;
+        }
+        // Systems without emergency power hardware have nothing to count
+        if status.emergency_power_state != EmergencyPowerState::NotPossible {
+            if self
+                .status
+                .emergency_power_usage
+                .update(status, self.max_energy_gap)
+            {
+                save_state(&self.state_dir, &self.status.emergency_power_usage);
+            }
+            let usage = mqtt::EmergencyPowerUsage::new(&self.status.emergency_power_usage);
+            let previous = self
+                .status
+                .last_emergency_power_usage
+                .as_ref()
+                .filter(|_| !republish);
+            self.mqtt_publisher
+                .publish_emergency_power_usage(&usage, previous)?;
+            self.status.last_emergency_power_usage = Some(usage);
+        }
+        if let Some(event) = self.status.grid_outage.update(status) {
+            match event.duration_seconds {
+                None => warn!(
+                    "Grid outage detected ({}) since {}",
+                    event.cause, event.start
+                ),
+                Some(duration) => info!(
+                    "Grid outage ended after {}s, battery supplied {}Wh",
+                    duration,
+                    event.battery_energy.unwrap_or_default()
+                ),
+            }
+            self.mqtt_publisher.publish_event("grid_outage", &event)?;
+        }
+
+        // Follow an emergency power test started by command
+        let mut test = self
+            .emergency_power_test
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        if test.is_running() {
+            match self.e3dc_client.get_emergency_power_test() {
+                Ok((test_status, result)) => {
+                    if let Some(event) = test.update(test_status, result, Utc::now()) {
+                        info!(
+                            "Emergency power test {}: status {:?}, result {:?}",
+                            event.phase, event.status, event.result
+                        );
+                        self.mqtt_publisher.publish_emergency_power_test(&event)?;
+                    }
+                }
+                Err(e) => warn!("Failed to get emergency power test status: {}", e),
+            }
+        }
+        Ok(())
+    }
+
+    /// Execute the commands of the `[schedule]` windows, failures are logged only
+    ///
+    /// Without a SOC there is nothing to plan with.
+    fn run_schedule(&mut self, status: &Status) {
+        let (Some(scheduler), Some(soc)) = (&mut self.status.scheduler, status.battery_soc) else {
+            return;
+        };
+        let now = Local::now().time();
+        if !scheduler.is_active(now) {
+            return;
+        }
+        match self.e3dc_client.get_ems_control_state() {
+            Ok(ems) => {
+                for command in scheduler.plan(now, soc, self.battery_capacity, &ems) {
+                    info!("Schedule: {:?}", command);
+                    if let Err(e) = command.execute(&mut self.e3dc_client) {
+                        warn!("Schedule: {:?} failed: {}", command, e);
+                    }
+                }
+            }
+            Err(e) => warn!("Failed to get EMS state for the schedule: {}", e),
+        }
+    }
+
+    /// Statistics cycle: daily statistics, totals, the slower optional queries
+    /// and the battery poll
+    fn statistics_cycle(&mut self, now: DateTime<Utc>) -> Result<(), BridgeError> {
+        self.mqtt_publisher
+            .publish_uptime(self.started.elapsed().as_secs())?;
+
+        // Publish daily statistics
+        let e3dc_stats = query(
+            &self.config.on_error,
+            &mut self.e3dc_client,
+            "Statistics",
+            |client| client.get_daily_statistics(self.statistic_interval),
+        )?;
+        let stats = mqtt::DailyStatistics::from_e3dc(&e3dc_stats);
+        // Heartbeat: compare against nothing so unchanged values go out again
+        let full_republish = self.statistics.heartbeat.due(now);
+        let previous_stats = self
+            .statistics
+            .last_daily_stats
+            .as_ref()
+            .filter(|_| !full_republish);
+        self.mqtt_publisher
+            .publish_daily_statistics(&stats, previous_stats)?;
+        if let Some(homie) = &self.homie {
+            homie.publish_daily_statistics(&stats, previous_stats)?;
+        }
+        if let Some(energy) = &self.status.energy {
+            if let Some(totals) = energy.totals() {
+                let integrated = mqtt::IntegratedEnergy::new(totals);
+                let previous = self
+                    .statistics
+                    .last_integrated_energy
+                    .as_ref()
+                    .filter(|_| !full_republish);
+                self.mqtt_publisher
+                    .publish_integrated_energy(&integrated, previous)?;
+                self.statistics.last_integrated_energy = Some(integrated);
+            }
+            save_state(&self.state_dir, energy);
+        }
+        // Switchovers are saved right away, the running island totals here
+        if self.status.emergency_power_usage.is_island() {
+            save_state(&self.state_dir, &self.status.emergency_power_usage);
+        }
+        self.totals_cycle(&e3dc_stats, full_republish)?;
+        info!(
+            "Statistics: Autarky={:.1}% SelfCons={:.1}% Solar={}Wh Consumption={}Wh",
+            e3dc_stats.autarky,
+            e3dc_stats.consumed_production,
+            e3dc_stats.solar_production,
+            e3dc_stats.consumption
+        );
+
+        if let Some(pipeline) = &self.pipeline {
+            pipeline.send(Message::Statistics(stats.clone()));
+        }
+        self.statistics.last_daily_stats = Some(stats);
+
+        if let Some(slot) = self.curve_slot {
+            match self.e3dc_client.get_day_curve(slot) {
+                Ok(curve) => {
+                    let curve = mqtt::DayCurve::from_e3dc(&curve);
+                    if full_republish || self.statistics.last_day_curve.as_ref() != Some(&curve) {
+                        self.mqtt_publisher.publish_day_curve(&curve)?;
+                        self.statistics.last_day_curve = Some(curve);
+                    }
+                }
+                Err(e) => warn!("Failed to get the day curve: {}", e),
+            }
+        }
+
+        self.device_cycle(full_republish)?;
+        self.emergency_power_capabilities_cycle(full_republish)?;
+        self.forecast_cycle(full_republish)?;
+
+        // Published by the battery cycle once the poll finished
+        self.battery.republish |= full_republish;
+        match &self.battery_worker {
+            Some(worker) => worker.request(),
+            None => {
+                self.battery.pending = Some(query(
+                    &self.config.on_error,
+                    &mut self.e3dc_client,
+                    "Battery",
+                    |client| BatteryPoll::query(client, &self.config.e3dc),
+                )?);
+            }
+        }
+        Ok(())
+    }
+
+    /// Lifetime totals, `[[statistics.windows]]` and the month/year totals
+    fn totals_cycle(
+        &mut self,
+        e3dc_stats: &e3dc::DailyStatistics,
+        full_republish: bool,
+    ) -> Result<(), BridgeError> {
+        // Lifetime totals only change noticeably per day
+        let day = e3dc_stats.start.date_naive();
+        if self.statistics.lifetime_day != Some(day) {
+            match self.e3dc_client.get_lifetime_totals() {
+                Ok(totals) => {
+                    let totals = mqtt::PeriodStatistics::from_e3dc(&totals);
+                    self.mqtt_publisher
+                        .publish_lifetime_statistics(&totals, None)?;
+                    if let Some(homie) = &self.homie {
+                        homie.publish_lifetime_statistics(&totals, None)?;
+                    }
+                    self.statistics.lifetime_day = Some(day);
+                }
+                Err(e) => warn!("Failed to get lifetime totals: {}", e),
+            }
+        }
+        for (window, last) in self.statistics.windows.iter_mut() {
+            match self
+                .e3dc_client
+                .get_window_statistics(window.window, self.statistic_interval)
+            {
+                Ok(sums) => {
+                    let sums = mqtt::PeriodStatistics::from_e3dc(&sums);
+                    let previous = last.as_ref().filter(|_| !full_republish);
+                    self.mqtt_publisher
+                        .publish_window_statistics(&window.name, &sums, previous)?;
+                    if let Some(homie) = &self.homie {
+                        homie.publish_window_statistics(&window.name, &sums, previous)?;
+                    }
+                    *last = Some(sums);
+                }
+                Err(e) => warn!("Failed to get {} statistics: {}", window.name, e),
+            }
+        }
+        for (totals, last) in self.statistics.period_totals.iter_mut() {
+            let period = totals.period();
+            match totals.update(&mut self.e3dc_client, e3dc_stats) {
+                Ok(sums) => {
+                    let sums = mqtt::PeriodStatistics::from_e3dc(&sums);
+                    let previous = last.as_ref().filter(|_| !full_republish);
+                    self.mqtt_publisher
+                        .publish_period_statistics(period, &sums, previous)?;
+                    if let Some(homie) = &self.homie {
+                        homie.publish_period_statistics(period, &sums, previous)?;
+                    }
+                    *last = Some(sums);
+                }
+                Err(e) => warn!("Failed to get {} totals: {}", period.name(), e),
+            }
+        }
+        Ok(())
+    }
+
+    /// Portal connection, network settings and clock of the E3DC
+    fn device_cycle(&mut self, full_republish: bool) -> Result<(), BridgeError> {
+        if self.config.e3dc.poll_portal {
+            match self.e3dc_client.get_portal_online() {
+                Ok(online) => {
+                    if self
+                        .statistics
+                        .portal_online
+                        .is_some_and(|was| was != online)
+                    {
+                        if online {
+                            info!("E3DC portal connection restored");
+                        } else {
+                            warn!("E3DC lost its portal connection");
+                        }
+                    }
+                    self.mqtt_publisher
+                        .publish_portal_status(online, Utc::now())?;
+                    self.statistics.portal_online = Some(online);
+                }
+                Err(e) => warn!("Failed to get portal status: {}", e),
+            }
+        }
+
+        if self.config.e3dc.poll_network {
+            match self.e3dc_client.get_network_config() {
+                Ok(network) => {
+                    let network = mqtt::NetworkConfig::from_e3dc(&network);
+                    if let Some(old) = self
+                        .statistics
+                        .last_network
+                        .as_ref()
+                        .filter(|old| **old != network)
+                    {
+                        warn!(
+                            "E3DC network settings changed: dhcp {} -> {}, ip {} -> {}",
+                            old.dhcp, network.dhcp, old.ip_address, network.ip_address
+                        );
+                    }
+                    let previous = self
+                        .statistics
+                        .last_network
+                        .as_ref()
+                        .filter(|_| !full_republish);
+                    self.mqtt_publisher
+                        .publish_network_config(&network, previous)?;
+                    self.statistics.last_network = Some(network);
+                }
+                Err(e) => warn!("Failed to get network settings: {}", e),
+            }
+        }
+
+        if self.config.e3dc.poll_clock {
+            match self.e3dc_client.get_device_clock() {
+                Ok(clock) => {
+                    let clock = mqtt::DeviceClock::from_e3dc(&clock);
+                    debug!(
+                        "E3DC clock: {} {} (offset {}s)",
+                        clock.local_time, clock.time_zone, clock.offset
+                    );
+                    let previous = self
+                        .statistics
+                        .last_device_clock
+                        .as_ref()
+                        .filter(|_| !full_republish);
+                    self.mqtt_publisher.publish_device_clock(&clock, previous)?;
+                    self.statistics.last_device_clock = Some(clock);
+                }
+                Err(e) => warn!("Failed to get E3DC clock: {}", e),
+            }
+        }
+        Ok(())
+    }
+
+    /// Emergency power capabilities and reserve, if the startup query found them
+    fn emergency_power_capabilities_cycle(
+        &mut self,
+        full_republish: bool,
+    ) -> Result<(), BridgeError> {
+        let battery_capacity = (self.battery_capacity > 0.0).then_some(self.battery_capacity);
+        match self
+            .e3dc_client
+            .get_emergency_power_capabilities(battery_capacity)
+        {
+            Ok(capabilities) => {
+                let capabilities = mqtt::EmergencyPowerCapabilities::from_e3dc(&capabilities);
+                let statistics = &mut self.statistics;
+                let previous = statistics
+                    .last_ep_capabilities
+                    .as_ref()
+                    .filter(|_| !full_republish);
+                self.mqtt_publisher
+                    .publish_emergency_power_capabilities(&capabilities, previous)?;
+                statistics.last_ep_capabilities = Some(capabilities);
+            }
+            // Not available since the start: don't warn every cycle
+            Err(e) if self.statistics.last_ep_capabilities.is_none() => {
+                debug!("Emergency power capabilities still not available: {}", e)
+            }
+            Err(e) => warn!("Failed to get emergency power capabilities: {}", e),
+        }
+        Ok(())
+    }
+
+    /// PV forecast of `[forecast]` and the grid charge windows it skips
+    fn forecast_cycle(&mut self, full_republish: bool) -> Result<(), BridgeError> {
+        let Some(forecast_config) = &self.config.forecast else {
+            return Ok(());
+        };
+        let statistics = &mut self.statistics;
+        let Some(worker) = &statistics.forecast_worker else {
+            return Ok(());
+        };
+        // The fetch requested by an earlier cycle
+        let fetched = match worker.try_result() {
+            Some(Ok(forecast)) => {
+                statistics.forecast_due = Some(Instant::now() + forecast_config.interval);
+                self.mqtt_publisher
+                    .publish_forecast_power(&forecast.watts)?;
+                statistics.pv_forecast = Some(forecast);
+                true
+            }
+            Some(Err(e)) => {
+                let retry = forecast_config.interval.min(forecast::RETRY_DELAY);
+                warn!(
+                    "Failed to fetch PV forecast, retrying in {}s: {}",
+                    retry.as_secs(),
+                    e
+                );
+                statistics.forecast_due = Some(Instant::now() + retry);
+                false
+            }
+            None => false,
+        };
+        if statistics
+            .forecast_due
+            .is_none_or(|due| Instant::now() >= due)
+        {
+            worker.request();
+        }
+        if full_republish && !fetched {
+            if let Some(forecast) = &statistics.pv_forecast {
+                self.mqtt_publisher
+                    .publish_forecast_power(&forecast.watts)?;
+            }
+        }
+        let Some(forecast) = &statistics.pv_forecast else {
+            return Ok(());
+        };
+        let now = Local::now().naive_local();
+        let energy = mqtt::PvForecast::new(forecast, now.date());
+        let previous = statistics
+            .last_forecast
+            .as_ref()
+            .filter(|_| !full_republish);
+        self.mqtt_publisher.publish_forecast(&energy, previous)?;
+        statistics.last_forecast = Some(energy);
+
+        if let Some(threshold) = forecast_config.skip_grid_charge_above {
+            let day = forecast::upcoming_day(now);
+            let expected = forecast.energy(day);
+            let skipped = expected.is_some_and(|energy| energy >= threshold);
+            if statistics.grid_charge_skipped != Some(skipped) {
+                info!(
+                    "PV forecast for {}: {:.0}Wh, grid charge windows {}",
+                    day,
+                    expected.unwrap_or_default(),
+                    if skipped { "skipped" } else { "enabled" }
+                );
+            }
+            if statistics.grid_charge_skipped != Some(skipped) || full_republish {
+                self.mqtt_publisher.publish_grid_charge_skipped(skipped)?;
+            }
+            if let Some(scheduler) = &mut self.status.scheduler {
+                scheduler.set_grid_charge_skipped(skipped);
+            }
+            statistics.grid_charge_skipped = Some(skipped);
+        }
+        Ok(())
+    }
+
+    /// Battery data of the statistics cycle, or of the worker once its poll finished
+    ///
+    /// A failed worker poll is skipped unless the `[on_error] e3dc` policy
+    /// crashes, the status cycle tracks whether the E3DC is reachable.
+    fn take_battery_poll(&mut self) -> anyhow::Result<Option<BatteryPoll>> {
+        let Some(worker) = &self.battery_worker else {
+            return Ok(self.battery.pending.take());
+        };
+        match worker.try_result() {
+            Some(Ok(poll)) => Ok(Some(poll)),
+            Some(Err(e)) => {
+                let e = BridgeError::E3dc(e);
+                match self.config.on_error.policy(&e) {
+                    ErrorPolicy::Crash => {
+                        error!("Battery poll failed: {}", e);
+                        Err(e.into())
+                    }
+                    _ => {
+                        warn!("Battery poll failed, skipped: {}", e);
+                        Ok(None)
+                    }
+                }
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Battery cycle: battery and DCB data with the events and alerts derived from them
+    fn battery_cycle(
+        &mut self,
+        poll: &BatteryPoll,
+        full_republish: bool,
+    ) -> Result<(), BridgeError> {
+        for change in &poll.dcb_changes {
+            info!(
+                "Battery {} DCB count changed: {} -> {}",
+                change.battery, change.from, change.to
+            );
+            let event = DcbCountChangedEvent::new(Utc::now(), *change);
+            self.mqtt_publisher
+                .publish_event("dcb_count_changed", &event)?;
+        }
+        if let Some(modules) = &mut self.battery.dcb_modules {
+            let mut recorded = false;
+            for battery in &poll.batteries {
+                let Some(events) = modules.update(Utc::now(), battery) else {
+                    continue;
+                };
+                recorded = true;
+                for event in events {
+                    warn!(
+                        "Battery {} DCB {} {}: {} -> {}",
+                        event.battery,
+                        event.dcb,
+                        event.change,
+                        event.old_serial.as_deref().unwrap_or("-"),
+                        event.new_serial.as_deref().unwrap_or("-")
+                    );
+                    self.mqtt_publisher
+                        .publish_event("dcb_module_changed", &event)?;
+                }
+            }
+            if recorded {
+                save_state(&self.state_dir, modules);
+            }
+        }
+
+        // Publish battery data for all known batteries with change detection
+        // Battery data now includes DCBs, much simpler!
+        let bat_data: Vec<mqtt::BatteryData> = poll
+            .batteries
+            .iter()
+            .map(mqtt::BatteryData::from_e3dc)
+            .collect();
+        let previous_batteries: &[mqtt::BatteryData] = if full_republish {
+            &[]
+        } else {
+            &self.battery.last
+        };
+        self.mqtt_publisher
+            .publish_battery_data(&bat_data, previous_batteries)?;
+        for battery in bat_data.iter().filter(|b| b.is_known("error_code")) {
+            let Some(event) =
+                self.battery
+                    .errors
+                    .update(battery.index, battery.error_code, battery.time)
+            else {
+                continue;
+            };
+            warn!(
+                "Battery {} error code {} (was {})",
+                event.battery, event.error_code, event.previous_code
+            );
+            self.mqtt_publisher.publish_event("battery_error", &event)?;
+        }
+        for removed in self
+            .mqtt_publisher
+            .clear_removed_batteries(&bat_data, &self.battery.last)?
+        {
+            info!(
+                "Battery hardware removed, cleared retained topics of {}",
+                removed
+            );
+        }
+
+        // Charge cycle trend from the first reading of every day
+        let mut new_sample = false;
+        for battery in bat_data.iter().filter(|b| b.is_known("charge_cycles")) {
+            new_sample |= self.battery.cycles.record(
+                battery.index,
+                battery.time.date_naive(),
+                battery.charge_cycles,
+            );
+        }
+        if new_sample {
+            save_state(&self.state_dir, &self.battery.cycles);
+        }
+        let trends: Vec<mqtt::CycleTrend> = bat_data
+            .iter()
+            .filter_map(|battery| {
+                let trend = self.battery.cycles.trend(battery.index)?;
+                Some(mqtt::CycleTrend::new(battery.index, trend))
+            })
+            .collect();
+        let previous_trends: &[mqtt::CycleTrend] = if full_republish {
+            &[]
+        } else {
+            &self.battery.last_cycle_trends
+        };
+        self.mqtt_publisher
+            .publish_cycle_trends(&trends, previous_trends)?;
+
+        let (mut battery_alerts, imbalance_events) = match &mut self.battery.cell_imbalance {
+            Some(monitor) => monitor.update(&bat_data, Utc::now()),
+            None => (Vec::new(), Vec::new()),
+        };
+        for event in imbalance_events {
+            self.mqtt_publisher
+                .publish_event("cell_imbalance", &event)?;
+        }
+        let (temperature_alerts, temperature_events) =
+            self.battery.temperature.update(&bat_data, Utc::now());
+        battery_alerts.extend(temperature_alerts);
+        let previous_alerts: &[BatteryAlert] = if full_republish {
+            &[]
+        } else {
+            &self.battery.last_alerts
+        };
+        self.mqtt_publisher
+            .publish_battery_alerts(&battery_alerts, previous_alerts)?;
+        for event in temperature_events {
+            warn!(
+                "Battery {} temperature {} alert {}: {:.1}°C (threshold {:.1}°C)",
+                event.battery,
+                event.limit,
+                if event.active { "raised" } else { "cleared" },
+                event.temperature,
+                event.threshold
+            );
+            self.mqtt_publisher
+                .publish_event("temperature_alert", &event)?;
+        }
+
+        if let Some(homie) = &self.homie {
+            homie.publish_battery_data(&bat_data, previous_batteries)?;
+            homie.publish_cycle_trends(&trends, previous_trends)?;
+            homie.publish_battery_alerts(&battery_alerts, previous_alerts)?;
+            homie.clear_removed_batteries(&bat_data, &self.battery.last)?;
+            homie.announce()?;
+        }
+        self.battery.last_cycle_trends = trends;
+        self.battery.last_alerts = battery_alerts;
+
+        for battery in &bat_data {
+            debug!(
+                "Battery {}: SOC={:.1}%, {} DCBs with {} cells each",
+                battery.index,
+                battery.rsoc_real,
+                battery.dcb_count,
+                battery.dcbs.first().map(|d| d.voltages.len()).unwrap_or(0)
+            );
+        }
+
+        if let Some(pipeline) = &self.pipeline {
+            pipeline.send(Message::Batteries(bat_data.clone()));
+        }
+
+        self.battery.last = bat_data;
+        Ok(())
+    }
+}
+
+/// Run the bridge until `shutdown` is requested
+///
+/// Returns Ok after a requested shutdown, an error for a failure the
+/// `[on_error]` policy doesn't skip.
+pub fn run(config: Config, shutdown: Shutdown) -> anyhow::Result<()> {
+    let Some(mut bridge) = Bridge::start(config, shutdown)? else {
+        return Ok(());
+    };
+    info!("Starting main loop...");
+    while !bridge.shutdown.is_requested() {
+        bridge.poll()?;
+    }
+    bridge.stop()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lifecycle::BridgeState;
+    use crate::mqtt::link::{self, Link};
+    use rumqttc::{ConnAck, ConnectReturnCode, ConnectionError, Event, Packet};
+
+    fn on_error(mqtt: ErrorPolicy) -> OnErrorConfig {
+        OnErrorConfig {
+            e3dc: ErrorPolicy::Crash,
+            mqtt,
+        }
+    }
+
+    fn connack() -> Event {
+        Event::Incoming(Packet::ConnAck(ConnAck::new(
+            ConnectReturnCode::Success,
+            false,
+        )))
+    }
+
+    #[test]
+    fn test_broker_outage_degrades_and_recovers() {
+        let on_error = on_error(ErrorPolicy::SkipCycle);
+        let (reporter, link) = link::link("MQTT");
+        let mut lifecycle = Lifecycle::new();
+        let mut apply = |link: &Link| {
+            broker_event(&on_error, link.events(), link.is_lost())
+                .unwrap()
+                .and_then(|event| lifecycle.handle(event))
+        };
+        let dropped = Err(ConnectionError::NetworkTimeout);
+
+        // The first connection doesn't end `connecting`, a cycle does
+        reporter.observe(&Ok(connack()));
+        assert_eq!(apply(&link), None);
+        assert_eq!(apply(&link), None);
+
+        reporter.observe(&dropped);
+        assert_eq!(apply(&link), Some(BridgeState::DegradedMqtt));
+        // Failed reconnects are no change
+        reporter.observe(&dropped);
+        assert_eq!(apply(&link), None);
+
+        reporter.observe(&Ok(connack()));
+        assert_eq!(apply(&link), Some(BridgeState::Running));
+    }
+
+    #[test]
+    fn test_retry_then_skip_repeats_the_query_only() {
+        let failing = |calls: &mut u32| -> Result<(), E3dcError> {
+            *calls += 1;
+            Err(E3dcError::QueryFailed("timeout".to_string()))
+        };
+        let mut calls = 0;
+        assert!(query(&on_error(ErrorPolicy::Crash), &mut calls, "Status", failing).is_err());
+        assert_eq!(calls, 1);
+
+        let retry = OnErrorConfig {
+            e3dc: ErrorPolicy::RetryThenSkip,
+            mqtt: ErrorPolicy::Crash,
+        };
+        let mut calls = 0;
+        assert!(query(&retry, &mut calls, "Status", failing).is_err());
+        assert_eq!(calls, 2);
+
+        let mut calls = 0;
+        let answer = query(&retry, &mut calls, "Status", |calls| {
+            *calls += 1;
+            if *calls == 1 {
+                Err(E3dcError::QueryFailed("timeout".to_string()))
+            } else {
+                Ok(*calls)
+            }
+        });
+        assert_eq!(answer.unwrap(), 2);
+    }
+
+    #[test]
+    fn test_broker_outage_crashes() {
+        let (reporter, link) = link::link("MQTT");
+        reporter.observe(&Err(ConnectionError::NetworkTimeout));
+        let result = broker_event(&on_error(ErrorPolicy::Crash), link.events(), link.is_lost());
+        assert!(matches!(
+            result,
+            Err(BridgeError::Mqtt(MqttError::ConnectionLost(_)))
+        ));
+    }
+}
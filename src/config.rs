@@ -1,13 +1,28 @@
 //! Configuration module for E3DC-MQTT bridge
 //!
 //! Loads configuration from TOML file with structure matching the Python version:
-//! - [default] - General settings (log_level)
+//! - [default] - General settings (log_level, state_file)
 //! - [e3dc] - E3DC connection settings
 //! - [mqtt] - MQTT broker settings
+//! - [retry] - RSCP request retries per query class
+//! - [payload] - MQTT payload formatting
+//! - [topics] - MQTT topic names per metric group
+//! - [commands] - MQTT commands (writes to the E3DC)
+//! - [homeassistant] - Home Assistant MQTT discovery
+//! - [homie] - Homie 4 convention output
+//! - [statistics] - Daily statistics history
+//! - [alerts] - Threshold alerts
+//! - [on_error] - Error policy per failure class
+//! - [debug] - RSCP request/response debug log
 
-use serde::Deserialize;
+use crate::e3dc::{RetryPolicy, StatisticsWindow};
+use crate::errors::BridgeError;
+use crate::tasks::{Alignment, Cron};
+use chrono::NaiveTime;
+use serde::{Deserialize, Deserializer};
+use std::collections::BTreeMap;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 
 /// Log level for the application
@@ -58,6 +73,32 @@ pub struct Config {
     pub default: DefaultConfig,
     pub e3dc: E3dcConfig,
     pub mqtt: MqttConfig,
+    #[serde(default)]
+    pub retry: RetryPolicy,
+    #[serde(default)]
+    pub payload: PayloadConfig,
+    #[serde(default)]
+    pub topics: TopicsConfig,
+    #[serde(default)]
+    pub commands: CommandsConfig,
+    #[serde(default)]
+    pub homeassistant: HomeAssistantConfig,
+    #[serde(default)]
+    pub homie: HomieConfig,
+    #[serde(default)]
+    pub statistics: StatisticsConfig,
+    #[serde(default)]
+    pub alerts: AlertsConfig,
+    #[serde(default)]
+    pub schedule: ScheduleConfig,
+    #[serde(default)]
+    pub forecast: Option<ForecastConfig>,
+    #[serde(default)]
+    pub history: Option<HistoryConfig>,
+    #[serde(default)]
+    pub on_error: OnErrorConfig,
+    #[serde(default)]
+    pub debug: DebugConfig,
 }
 
 /// General application settings
@@ -66,14 +107,42 @@ pub struct DefaultConfig {
     /// Log level: TRACE, DEBUG, INFO, WARN, ERROR
     #[serde(default)]
     pub log_level: LogLevel,
+
+    /// Directory persisting data across restarts (charge cycle trend, restart counter),
+    /// kept in memory only if unset
+    #[serde(default)]
+    pub state_dir: Option<PathBuf>,
+
+    /// Deprecated single state file, imported into `state_dir` (its parent
+    /// directory if `state_dir` is unset)
+    #[serde(default)]
+    pub state_file: Option<PathBuf>,
+}
+
+impl DefaultConfig {
+    /// The state directory, falling back to the directory of `state_file`
+    pub fn state_dir(&self) -> Option<PathBuf> {
+        self.state_dir.clone().or_else(|| {
+            let parent = self.state_file.as_ref()?.parent()?;
+            Some(if parent.as_os_str().is_empty() {
+                PathBuf::from(".")
+            } else {
+                parent.to_path_buf()
+            })
+        })
+    }
 }
 
 /// E3DC connection configuration
 #[derive(Deserialize, Clone)]
 pub struct E3dcConfig {
-    /// E3DC hostname or IP address (required)
+    /// E3DC hostname or IP address (required), IPv6 with or without brackets
     pub host: String,
 
+    /// Connect timeout per resolved address (default 5s)
+    #[serde(default = "default_connect_timeout", with = "humantime_serde")]
+    pub connect_timeout: Duration,
+
     /// E3DC portal username (required, usually email)
     pub username: String,
 
@@ -90,12 +159,131 @@ pub struct E3dcConfig {
     /// Statistics update interval (e.g., "5m", "300s")
     #[serde(default = "default_statistic_interval", with = "humantime_serde")]
     pub statistic_update_interval: Duration,
+
+    /// Cron expression for the statistics cycle in local time, replaces the
+    /// `statistic_update_interval` timing (e.g., "*/15 * * * *")
+    #[serde(default)]
+    pub statistic_schedule: Option<Cron>,
+
+    /// What the status and statistics intervals are aligned to: "epoch"
+    /// (default), "local_clock" or "start" (free-running)
+    #[serde(default)]
+    pub align: Alignment,
+
+    /// Poll this long after every aligned boundary (e.g., "2s", default 0),
+    /// so several bridges on one site don't poll at the same second
+    #[serde(default, with = "humantime_serde")]
+    pub align_offset: Duration,
+
+    /// Wait a random time below this before connecting (e.g., "30s", default none)
+    #[serde(default, with = "humantime_serde")]
+    pub startup_delay: Option<Duration>,
+
+    /// Delay every status and statistics poll by a random time below this
+    /// (e.g., "1s", default none), must stay below `interval`
+    #[serde(default, with = "humantime_serde")]
+    pub jitter: Option<Duration>,
+
+    /// Scan for batteries and DCB modules again in every statistics cycle (default false)
+    /// Vanished batteries/DCBs get their retained topics cleared
+    #[serde(default)]
+    pub rediscover_batteries: bool,
+
+    /// Stop publishing PV topics after PV production was exactly zero this long
+    /// (e.g., "30m", disabled by default)
+    #[serde(default, with = "humantime_serde")]
+    pub suppress_zero_pv_after: Option<Duration>,
+
+    /// Query the currently applied EMS charge/discharge limits every status cycle (default false)
+    #[serde(default)]
+    pub poll_charge_limits: bool,
+
+    /// Query the phase balancing state and per-phase grid power every status cycle (default false)
+    #[serde(default)]
+    pub poll_phases: bool,
+
+    /// Publish the SOC change per hour over this window and the time to
+    /// full/empty every status cycle (e.g., "15m", disabled by default)
+    #[serde(default, with = "humantime_serde")]
+    pub soc_rate_window: Option<Duration>,
+
+    /// Publish the median SOC over this window as `status/state_of_charge_smoothed`
+    /// (e.g., "5m", disabled by default)
+    #[serde(default, with = "humantime_serde")]
+    pub soc_smoothing_window: Option<Duration>,
+
+    /// Change in % of the median before the smoothed SOC follows it (default 1)
+    #[serde(default = "default_soc_smoothing_hysteresis")]
+    pub soc_smoothing_hysteresis: f64,
+
+    /// Query every DCB module with the battery data (default true)
+    /// Disabling skips the DCB topics, the DCB count refresh and the cell imbalance
+    #[serde(default = "default_true")]
+    pub poll_dcbs: bool,
+
+    /// Model name instead of the one derived from the serial number, part of
+    /// the device id and thereby the topics (e.g., "S10X_Pro")
+    #[serde(default)]
+    pub model: Option<String>,
+
+    /// Models of serial number prefixes missing from the built-in table
+    #[serde(default)]
+    pub models: BTreeMap<String, String>,
+
+    /// Query the DCDC converters every status cycle and publish them below
+    /// `status/dcdc:{i}` (default false)
+    #[serde(default)]
+    pub poll_dcdc: bool,
+
+    /// Query the wallbox phases every status cycle, publish them to
+    /// `status/wb_phases` and accept `command/wb_phases` (default false)
+    #[serde(default)]
+    pub poll_wb_phases: bool,
+
+    /// Wallbox the phase switching applies to (default 0)
+    #[serde(default)]
+    pub wallbox_index: u8,
+
+    /// Integrate the status power readings into Wh counters published as
+    /// `*_energy_integrated` with the daily statistics (default false)
+    #[serde(default)]
+    pub integrate_energy: bool,
+
+    /// Query the battery data every statistics cycle (default true)
+    /// Disabling publishes status and statistics only, for PV-only installations
+    #[serde(default = "default_true")]
+    pub poll_batteries: bool,
+
+    /// Poll the batteries on a second RSCP connection in a worker thread (default false)
+    /// A slow battery cycle then never delays the status poll
+    #[serde(default)]
+    pub battery_worker: bool,
+
+    /// Query the unit's clock and time zone every statistics cycle (default false)
+    #[serde(default)]
+    pub poll_clock: bool,
+
+    /// Query the E3DC portal connection every statistics cycle (default false)
+    #[serde(default)]
+    pub poll_portal: bool,
+
+    /// Query the unit's network settings every statistics cycle (default false)
+    #[serde(default)]
+    pub poll_network: bool,
+}
+
+fn default_connect_timeout() -> Duration {
+    Duration::from_secs(5)
 }
 
 fn default_interval() -> Duration {
     Duration::from_secs(5)
 }
 
+fn default_soc_smoothing_hysteresis() -> f64 {
+    1.0
+}
+
 fn default_statistic_interval() -> Duration {
     Duration::from_secs(300)
 }
@@ -107,8 +295,33 @@ impl std::fmt::Debug for E3dcConfig {
             .field("username", &self.username)
             .field("password", &"***REDACTED***")
             .field("key", &"***REDACTED***")
+            .field("connect_timeout", &self.connect_timeout)
             .field("interval", &self.interval)
             .field("statistic_update_interval", &self.statistic_update_interval)
+            .field("statistic_schedule", &self.statistic_schedule)
+            .field("align", &self.align)
+            .field("align_offset", &self.align_offset)
+            .field("startup_delay", &self.startup_delay)
+            .field("jitter", &self.jitter)
+            .field("rediscover_batteries", &self.rediscover_batteries)
+            .field("suppress_zero_pv_after", &self.suppress_zero_pv_after)
+            .field("poll_charge_limits", &self.poll_charge_limits)
+            .field("poll_phases", &self.poll_phases)
+            .field("soc_rate_window", &self.soc_rate_window)
+            .field("soc_smoothing_window", &self.soc_smoothing_window)
+            .field("soc_smoothing_hysteresis", &self.soc_smoothing_hysteresis)
+            .field("poll_dcbs", &self.poll_dcbs)
+            .field("model", &self.model)
+            .field("models", &self.models)
+            .field("poll_dcdc", &self.poll_dcdc)
+            .field("poll_wb_phases", &self.poll_wb_phases)
+            .field("wallbox_index", &self.wallbox_index)
+            .field("integrate_energy", &self.integrate_energy)
+            .field("poll_batteries", &self.poll_batteries)
+            .field("battery_worker", &self.battery_worker)
+            .field("poll_clock", &self.poll_clock)
+            .field("poll_portal", &self.poll_portal)
+            .field("poll_network", &self.poll_network)
             .finish()
     }
 }
@@ -120,13 +333,29 @@ pub struct MqttConfig {
     #[serde(default = "default_mqtt_root")]
     pub root: String,
 
-    /// MQTT broker hostname
+    /// MQTT broker hostname or IP address, IPv6 with or without brackets
     pub host: String,
 
     /// MQTT broker port (default 1883)
     #[serde(default = "default_mqtt_port")]
     pub port: u16,
 
+    /// Connect timeout (default 5s), for all addresses of the broker together
+    #[serde(default = "default_connect_timeout", with = "humantime_serde")]
+    pub connect_timeout: Duration,
+
+    /// Network interface the broker connection is bound to (e.g. "eth1", Linux only)
+    #[serde(default)]
+    pub bind_interface: Option<String>,
+
+    /// SOCKS5 proxy the broker connection goes through (`[mqtt.socks5]`)
+    #[serde(default)]
+    pub socks5: Option<Socks5Config>,
+
+    /// Custom topic leaf names by field name (`[mqtt.rename]`, e.g. house_consumption = "load_power")
+    #[serde(default)]
+    pub rename: BTreeMap<String, String>,
+
     /// MQTT client ID (optional, defaults to "e3dc-mqtt-rs-{device-id}")
     /// Set different IDs to run multiple instances against the same E3DC
     pub client_id: Option<String>,
@@ -138,6 +367,33 @@ pub struct MqttConfig {
     pub password: String,
 }
 
+impl MqttConfig {
+    fn validate(&self) -> Result<(), ConfigError> {
+        let mut leaves = BTreeMap::new();
+        for (field, leaf) in &self.rename {
+            // The last will is set on `online` when connecting
+            if field == "online" {
+                return Err(ConfigError::ValidationError(
+                    "mqtt.rename can't rename 'online', the last will topic".to_string(),
+                ));
+            }
+            if leaf.is_empty() || leaf.contains(['/', '+', '#']) {
+                return Err(ConfigError::ValidationError(format!(
+                    "mqtt.rename.{} '{}' must be a non-empty topic level without wildcards",
+                    field, leaf
+                )));
+            }
+            if let Some(other) = leaves.insert(leaf, field) {
+                return Err(ConfigError::ValidationError(format!(
+                    "mqtt.rename maps both '{}' and '{}' to '{}'",
+                    other, field, leaf
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
 fn default_mqtt_root() -> String {
     "e3dc".to_string()
 }
@@ -151,6 +407,10 @@ impl std::fmt::Debug for MqttConfig {
         f.debug_struct("MqttConfig")
             .field("host", &self.host)
             .field("port", &self.port)
+            .field("connect_timeout", &self.connect_timeout)
+            .field("bind_interface", &self.bind_interface)
+            .field("socks5", &self.socks5)
+            .field("rename", &self.rename)
             .field("client_id", &self.client_id)
             .field("username", &self.username)
             .field("password", &"***REDACTED***")
@@ -159,6 +419,678 @@ impl std::fmt::Debug for MqttConfig {
     }
 }
 
+/// SOCKS5 proxy for the broker connection
+#[derive(Deserialize, Clone)]
+pub struct Socks5Config {
+    /// Proxy hostname or IP address
+    pub host: String,
+
+    /// Proxy port (default 1080)
+    #[serde(default = "default_socks5_port")]
+    pub port: u16,
+
+    /// Username for username/password authentication (optional)
+    #[serde(default)]
+    pub username: Option<String>,
+
+    /// Password for username/password authentication (optional)
+    #[serde(default)]
+    pub password: Option<String>,
+}
+
+fn default_socks5_port() -> u16 {
+    1080
+}
+
+impl Socks5Config {
+    /// Username and password, None without authentication
+    pub fn credentials(&self) -> Option<(&str, &str)> {
+        Some((self.username.as_deref()?, self.password.as_deref()?))
+    }
+}
+
+impl std::fmt::Debug for Socks5Config {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Socks5Config")
+            .field("host", &self.host)
+            .field("port", &self.port)
+            .field("username", &self.username)
+            .field(
+                "password",
+                &self.password.as_ref().map(|_| "***REDACTED***"),
+            )
+            .finish()
+    }
+}
+
+/// MQTT payload formatting
+#[derive(Debug, Deserialize, Clone)]
+pub struct PayloadConfig {
+    /// Decimals for float payloads (default 3)
+    #[serde(default = "default_float_decimals")]
+    pub float_decimals: usize,
+
+    /// Strip trailing zeros ("12.500" -> "12.5", default true)
+    #[serde(default = "default_true")]
+    pub trim_trailing_zeros: bool,
+
+    /// Per-field decimals by topic name, e.g. `voltages = 3`
+    #[serde(default)]
+    pub decimals: BTreeMap<String, usize>,
+
+    /// Timezone of timestamp topics: "UTC" (default), "local" or a name like "Europe/Berlin"
+    #[serde(default)]
+    pub timezone: Timezone,
+
+    /// Encoding of the aggregate data documents and cell arrays: "json" (default) or "cbor"
+    #[serde(default)]
+    pub encoding: PayloadEncoding,
+
+    /// Republish every field after this age even if unchanged (e.g., "15m", disabled by default)
+    #[serde(default, with = "humantime_serde")]
+    pub heartbeat: Option<Duration>,
+}
+
+/// Encoding of aggregate payloads (JSON documents, DCB cell arrays)
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PayloadEncoding {
+    #[default]
+    Json,
+    /// Binary CBOR (RFC 8949), cell arrays as 32-bit floats
+    Cbor,
+}
+
+/// Timezone timestamps are converted to before publishing
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(try_from = "String")]
+pub enum Timezone {
+    #[default]
+    Utc,
+    /// Timezone of the host running the bridge
+    Local,
+    Named(chrono_tz::Tz),
+}
+
+impl std::str::FromStr for Timezone {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "utc" => Ok(Timezone::Utc),
+            "local" => Ok(Timezone::Local),
+            _ => value
+                .parse::<chrono_tz::Tz>()
+                .map(Timezone::Named)
+                .map_err(|_| format!("unknown timezone '{}'", value)),
+        }
+    }
+}
+
+impl TryFrom<String> for Timezone {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+fn default_float_decimals() -> usize {
+    3
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl Default for PayloadConfig {
+    fn default() -> Self {
+        Self {
+            float_decimals: default_float_decimals(),
+            trim_trailing_zeros: default_true(),
+            decimals: BTreeMap::new(),
+            timezone: Timezone::Utc,
+            encoding: PayloadEncoding::Json,
+            heartbeat: None,
+        }
+    }
+}
+
+/// Topic path segments per metric group, below `{root}/{device-id}`
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
+pub struct TopicsConfig {
+    /// Real-time status (default "status")
+    #[serde(default = "default_topic_status")]
+    pub status: String,
+
+    /// Daily statistics (default "status_sums")
+    #[serde(default = "default_topic_status_sums")]
+    pub status_sums: String,
+
+    /// System info JSON (default "info")
+    #[serde(default = "default_topic_info")]
+    pub info: String,
+
+    /// Battery prefix below the status group, `{status}/{battery}:{index}` (default "battery")
+    #[serde(default = "default_topic_battery")]
+    pub battery: String,
+
+    /// How battery/DCB indexes appear in topics
+    #[serde(default)]
+    pub layout: TopicLayout,
+
+    /// Which groups are published retained
+    #[serde(default)]
+    pub retain: RetainConfig,
+}
+
+/// Retain flag per metric group (`[topics.retain]`)
+///
+/// Real-time values are not retained by default: a consumer (re)connecting
+/// would take a retained power reading for the current one, however old it is.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub struct RetainConfig {
+    /// Real-time status, PVI and derived values below `{status}` (default false)
+    #[serde(default)]
+    pub status: bool,
+
+    /// Daily, period and lifetime statistics (default true)
+    #[serde(default = "default_true")]
+    pub status_sums: bool,
+
+    /// System info document (default true)
+    #[serde(default = "default_true")]
+    pub info: bool,
+
+    /// Battery and DCB values (default true)
+    #[serde(default = "default_true")]
+    pub battery: bool,
+}
+
+impl Default for RetainConfig {
+    fn default() -> Self {
+        Self {
+            status: false,
+            status_sums: true,
+            info: true,
+            battery: true,
+        }
+    }
+}
+
+/// Index segments of battery and DCB topics
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TopicLayout {
+    /// `battery:0/dcb:1`
+    #[default]
+    #[serde(rename = "default")]
+    Standard,
+    /// `battery/0/dcb/1`, for consumers that mishandle `:` in segments (e.g. ioBroker)
+    Flat,
+}
+
+impl TopicLayout {
+    fn indexed(&self, name: &str, index: impl std::fmt::Display) -> String {
+        match self {
+            TopicLayout::Standard => format!("{}:{}", name, index),
+            TopicLayout::Flat => format!("{}/{}", name, index),
+        }
+    }
+}
+
+fn default_topic_status() -> String {
+    "status".to_string()
+}
+
+fn default_topic_status_sums() -> String {
+    "status_sums".to_string()
+}
+
+fn default_topic_info() -> String {
+    "info".to_string()
+}
+
+fn default_topic_battery() -> String {
+    "battery".to_string()
+}
+
+impl Default for TopicsConfig {
+    fn default() -> Self {
+        Self {
+            status: default_topic_status(),
+            status_sums: default_topic_status_sums(),
+            info: default_topic_info(),
+            battery: default_topic_battery(),
+            layout: TopicLayout::default(),
+            retain: RetainConfig::default(),
+        }
+    }
+}
+
+impl TopicsConfig {
+    /// Topic of battery `index`, relative to the device root
+    pub fn battery(&self, index: impl std::fmt::Display) -> String {
+        format!(
+            "{}/{}",
+            self.status,
+            self.layout.indexed(&self.battery, index)
+        )
+    }
+
+    /// Topic of PVI (inverter) `index`, relative to the device root
+    pub fn pvi(&self, index: impl std::fmt::Display) -> String {
+        format!("{}/{}", self.status, self.layout.indexed("pvi", index))
+    }
+
+    /// Topic of DCDC converter `index`
+    pub fn dcdc(&self, index: impl std::fmt::Display) -> String {
+        format!("{}/{}", self.status, self.layout.indexed("dcdc", index))
+    }
+
+    /// Topic of PV string `index` below `pvi_topic`
+    pub fn pvi_string(&self, pvi_topic: &str, index: impl std::fmt::Display) -> String {
+        format!("{}/{}", pvi_topic, self.layout.indexed("string", index))
+    }
+
+    /// Topic of DCB `index` below `battery_topic`
+    pub fn dcb(&self, battery_topic: &str, index: impl std::fmt::Display) -> String {
+        format!("{}/{}", battery_topic, self.layout.indexed("dcb", index))
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        for (name, segment) in [
+            ("status", &self.status),
+            ("status_sums", &self.status_sums),
+            ("info", &self.info),
+            ("battery", &self.battery),
+        ] {
+            if segment.is_empty()
+                || segment.starts_with('/')
+                || segment.ends_with('/')
+                || segment.contains(['+', '#'])
+            {
+                return Err(ConfigError::ValidationError(format!(
+                    "topics.{} '{}' must be a non-empty topic path without wildcards",
+                    name, segment
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// MQTT command settings
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct CommandsConfig {
+    /// Accept commands on `command/#` (default false = read-only bridge)
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// Command names that are accepted (empty = all commands)
+    #[serde(default)]
+    pub allow: Vec<String>,
+
+    /// Command names that are always rejected, takes precedence over `allow`
+    #[serde(default)]
+    pub deny: Vec<String>,
+}
+
+impl CommandsConfig {
+    /// Check a command name (topic suffix after `command/`) against the ACL
+    pub fn is_allowed(&self, name: &str) -> bool {
+        if self.deny.iter().any(|denied| denied == name) {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(|allowed| allowed == name)
+    }
+}
+
+/// Home Assistant MQTT discovery settings
+#[derive(Debug, Deserialize, Clone)]
+pub struct HomeAssistantConfig {
+    /// Publish discovery configs (default false)
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// Discovery topic prefix configured in Home Assistant
+    #[serde(default = "default_discovery_prefix")]
+    pub discovery_prefix: String,
+
+    /// Energy (Wh) the "Start manual charge" button requests
+    #[serde(default = "default_manual_charge_wh")]
+    pub manual_charge_wh: u64,
+
+    /// Latest E3DC firmware release known to the user, e.g. "S10_2024_04";
+    /// the firmware `update` entity reports the installed one as latest if unset
+    #[serde(default)]
+    pub latest_firmware: Option<String>,
+
+    /// Sensor entities go unavailable after this many republish periods
+    /// without an update (`expire_after`, default 0 = never); needs `[payload] heartbeat`
+    #[serde(default)]
+    pub expire_after_intervals: u32,
+}
+
+fn default_discovery_prefix() -> String {
+    "homeassistant".to_string()
+}
+
+fn default_manual_charge_wh() -> u64 {
+    5000
+}
+
+impl Default for HomeAssistantConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            discovery_prefix: default_discovery_prefix(),
+            manual_charge_wh: default_manual_charge_wh(),
+            latest_firmware: None,
+            expire_after_intervals: 0,
+        }
+    }
+}
+
+/// Homie 4 convention output
+#[derive(Debug, Deserialize, Clone)]
+pub struct HomieConfig {
+    /// Additionally publish the Homie layout (default false)
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// Homie base topic
+    #[serde(default = "default_homie_prefix")]
+    pub prefix: String,
+}
+
+fn default_homie_prefix() -> String {
+    "homie".to_string()
+}
+
+impl Default for HomieConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            prefix: default_homie_prefix(),
+        }
+    }
+}
+
+/// Daily statistics history
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct StatisticsConfig {
+    /// Number of past days published to `{status_sums}/history` at startup (default 0, off)
+    #[serde(default)]
+    pub backfill_days: u32,
+
+    /// Slot length of today's curve published to `{status_sums}/curve`, e.g. "15m" (default off)
+    #[serde(default, with = "humantime_serde")]
+    pub curve_slot: Option<Duration>,
+
+    /// Further windows published below `{status_sums}_{name}` (`[[statistics.windows]]`)
+    #[serde(default)]
+    pub windows: Vec<StatisticsWindowConfig>,
+}
+
+/// One statistics window, e.g. `name = "last_hour"`, `window = "rolling"`, `span = "1h"`
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct StatisticsWindowConfig {
+    /// Topic suffix, `last_hour` publishes to `status_sums_last_hour`
+    pub name: String,
+    #[serde(flatten)]
+    pub window: StatisticsWindow,
+}
+
+/// RSCP debugging
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct DebugConfig {
+    /// Log every RSCP request/response item tree to the `e3dc_mqtt_rs::rscp`
+    /// target, independent of `log_level` (default false)
+    #[serde(default)]
+    pub rscp: bool,
+
+    /// Only log items of these namespaces, tags or hex ids, e.g.
+    /// `["BAT", "EMS::POWER_PV"]` (default: all)
+    #[serde(default)]
+    pub tags: Vec<String>,
+
+    /// Also publish the log to `bridge/debug` (not retained, default false)
+    #[serde(default)]
+    pub mqtt: bool,
+}
+
+/// Reaction to a failed poll cycle
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorPolicy {
+    /// Exit, a supervisor (systemd, Docker) restarts the bridge
+    #[default]
+    Crash,
+    /// Log the error and continue with the next cycle
+    SkipCycle,
+    /// Repeat the failed E3DC query once, skip the cycle if that fails as
+    /// well (publishes aren't repeated)
+    RetryThenSkip,
+    /// Keep running offline (`online = false`), reconnect to the E3DC with a
+    /// backoff and go back online once a status cycle succeeds (E3DC only)
+    Degrade,
+}
+
+/// Error policy per failure class
+#[derive(Debug, Deserialize, Clone, Copy, Default)]
+pub struct OnErrorConfig {
+    /// Failed E3DC queries (default crash)
+    #[serde(default)]
+    pub e3dc: ErrorPolicy,
+
+    /// Failed MQTT publishes (default crash)
+    #[serde(default)]
+    pub mqtt: ErrorPolicy,
+}
+
+impl OnErrorConfig {
+    /// Policy for the failure class of `error`
+    pub fn policy(&self, error: &BridgeError) -> ErrorPolicy {
+        match error {
+            BridgeError::E3dc(_) => self.e3dc,
+            BridgeError::Mqtt(_) => self.mqtt,
+        }
+    }
+}
+
+/// Threshold alerts, each one is off unless its threshold is set
+#[derive(Debug, Deserialize, Clone)]
+pub struct AlertsConfig {
+    /// Highest minus lowest cell voltage of a DCB in mV that raises `cell_imbalance_alert`
+    #[serde(default)]
+    pub cell_imbalance_mv: Option<f64>,
+
+    /// `max_dcb_cell_temp` in °C above which `temperature_high_alert` is raised
+    #[serde(default)]
+    pub temperature_high_c: Option<f64>,
+
+    /// `min_dcb_cell_temp` in °C below which `temperature_low_alert` is raised
+    #[serde(default)]
+    pub temperature_low_c: Option<f64>,
+
+    /// State of charge in % at or below which `events/soc_alert` reports "low"
+    #[serde(default)]
+    pub soc_low: Option<f64>,
+
+    /// State of charge in % at or above which `events/soc_alert` reports "high"
+    #[serde(default)]
+    pub soc_high: Option<f64>,
+
+    /// SOC distance in % from the threshold before a SOC alert clears (default 2)
+    #[serde(default = "default_soc_hysteresis")]
+    pub soc_hysteresis: f64,
+
+    /// Grid export limit in W, monitored in `status/export_limit_usage`
+    #[serde(default)]
+    pub export_limit_w: Option<f64>,
+
+    /// Grid export limit in % of `installed_peak_power` (e.g. 70)
+    #[serde(default)]
+    pub export_limit_percent: Option<f64>,
+
+    /// Energy balance residual in W (absolute) above which `events/energy_balance` is raised
+    #[serde(default)]
+    pub energy_balance_w: Option<f64>,
+
+    /// Consecutive polls a condition must hold before an alert changes state (default 3)
+    #[serde(default = "default_alert_polls")]
+    pub polls: u32,
+}
+
+fn default_alert_polls() -> u32 {
+    3
+}
+
+fn default_soc_hysteresis() -> f64 {
+    2.0
+}
+
+impl Default for AlertsConfig {
+    fn default() -> Self {
+        Self {
+            cell_imbalance_mv: None,
+            temperature_high_c: None,
+            temperature_low_c: None,
+            soc_low: None,
+            soc_high: None,
+            soc_hysteresis: default_soc_hysteresis(),
+            export_limit_w: None,
+            export_limit_percent: None,
+            energy_balance_w: None,
+            polls: default_alert_polls(),
+        }
+    }
+}
+
+impl AlertsConfig {
+    /// Configured grid export limit in W
+    pub fn export_limit(&self, installed_peak_power: u64) -> Option<f64> {
+        self.export_limit_w.or_else(|| {
+            self.export_limit_percent
+                .map(|percent| installed_peak_power as f64 * percent / 100.0)
+        })
+    }
+}
+
+/// Time windows the bridge enforces on the EMS (`[[schedule.windows]]`)
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct ScheduleConfig {
+    #[serde(default)]
+    pub windows: Vec<ScheduleWindow>,
+}
+
+/// One schedule window, `start`/`end` in the host's local time ("02:00")
+///
+/// A window with `end` before `start` runs past midnight.
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct ScheduleWindow {
+    #[serde(deserialize_with = "deserialize_clock_time")]
+    pub start: NaiveTime,
+    #[serde(deserialize_with = "deserialize_clock_time")]
+    pub end: NaiveTime,
+    #[serde(flatten)]
+    pub action: ScheduleAction,
+}
+
+/// What a schedule window makes the EMS do
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum ScheduleAction {
+    /// Charge from the grid until the SOC reaches `target_soc` %
+    GridCharge { target_soc: f64 },
+    /// Limit the battery discharge power to 0 W
+    LockDischarge,
+}
+
+impl ScheduleWindow {
+    /// Whether `time` lies within the window (start inclusive, end exclusive)
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        if self.start <= self.end {
+            self.start <= time && time < self.end
+        } else {
+            time >= self.start || time < self.end
+        }
+    }
+}
+
+/// PV forecast from Forecast.Solar for one plane (`[forecast]`)
+#[derive(Debug, Deserialize, Clone)]
+pub struct ForecastConfig {
+    pub latitude: f64,
+    pub longitude: f64,
+    /// Module tilt in degrees (0 = horizontal, 90 = vertical)
+    pub declination: f64,
+    /// Module orientation in degrees (-90 = east, 0 = south, 90 = west)
+    pub azimuth: f64,
+    /// Installed module power in kWp
+    pub kwp: f64,
+
+    /// Fetch interval (default 1h, the public API allows 12 requests per hour)
+    #[serde(default = "default_forecast_interval", with = "humantime_serde")]
+    pub interval: Duration,
+
+    /// Skip the schedule's grid charge windows when the upcoming day's
+    /// forecast reaches this energy in Wh (disabled by default)
+    #[serde(default)]
+    pub skip_grid_charge_above: Option<f64>,
+
+    /// Network interface the API request is bound to (e.g. "eth0", Linux only)
+    #[serde(default)]
+    pub bind_interface: Option<String>,
+}
+
+fn default_forecast_interval() -> Duration {
+    Duration::from_secs(3600)
+}
+
+/// Local history store (`[history]`)
+#[derive(Debug, Deserialize, Clone)]
+pub struct HistoryConfig {
+    /// Directory of the SQLite database (`history.sqlite`)
+    pub dir: PathBuf,
+
+    /// Record the status at most this often (default 1m), statistics and
+    /// battery snapshots are recorded every statistics cycle
+    #[serde(default = "default_history_status_interval", with = "humantime_serde")]
+    pub status_interval: Duration,
+
+    /// Delete days older than this (e.g. "365d", default: keep everything)
+    #[serde(default, with = "humantime_serde")]
+    pub retention: Option<Duration>,
+
+    /// Export every complete day as Parquet files below this directory
+    #[serde(default)]
+    pub parquet_dir: Option<PathBuf>,
+
+    /// Fill a new store with the daily sums of this many past days from the
+    /// E3DC's DB history at startup, continued at the next start if
+    /// interrupted (default 0 = off)
+    #[serde(default)]
+    pub backfill_days: u32,
+
+    /// Also fill in 15 minute sums for this many past days (default 0 = off)
+    #[serde(default)]
+    pub backfill_detail_days: u32,
+}
+
+fn default_history_status_interval() -> Duration {
+    Duration::from_secs(60)
+}
+
+fn deserialize_clock_time<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<NaiveTime, D::Error> {
+    let value = String::deserialize(deserializer)?;
+    NaiveTime::parse_from_str(&value, "%H:%M")
+        .map_err(|_| serde::de::Error::custom(format!("'{}' is not a time (HH:MM)", value)))
+}
+
 impl Config {
     /// Load configuration from TOML file
     ///
@@ -187,6 +1119,16 @@ impl Config {
         Ok(config)
     }
 
+    /// `expire_after` of the Home Assistant sensor entities, None if disabled
+    ///
+    /// An unchanged value is republished by the heartbeat, checked in every
+    /// statistics cycle, so the longer of both is one republish period.
+    pub fn discovery_expire_after(&self) -> Option<Duration> {
+        let intervals = self.homeassistant.expire_after_intervals;
+        let heartbeat = self.payload.heartbeat?;
+        (intervals > 0).then(|| heartbeat.max(self.e3dc.statistic_update_interval) * intervals)
+    }
+
     /// Validate configuration logic (semantic validation beyond type checks)
     fn validate(&self) -> Result<(), ConfigError> {
         // Duration is always positive by type, no need to validate intervals
@@ -198,6 +1140,158 @@ impl Config {
             ));
         }
 
+        self.topics.validate()?;
+        self.mqtt.validate()?;
+
+        // A jitter of a whole interval would skip runs
+        if self
+            .e3dc
+            .jitter
+            .is_some_and(|jitter| jitter >= self.e3dc.interval)
+        {
+            return Err(ConfigError::ValidationError(
+                "e3dc.jitter must be shorter than e3dc.interval".to_string(),
+            ));
+        }
+
+        if self.e3dc.soc_smoothing_hysteresis.is_nan() || self.e3dc.soc_smoothing_hysteresis < 0.0 {
+            return Err(ConfigError::ValidationError(
+                "e3dc.soc_smoothing_hysteresis must not be negative".to_string(),
+            ));
+        }
+
+        // Discovery value templates read `info` as JSON
+        if self.homeassistant.enabled && self.payload.encoding == PayloadEncoding::Cbor {
+            return Err(ConfigError::ValidationError(
+                "payload.encoding = \"cbor\" cannot be combined with homeassistant.enabled"
+                    .to_string(),
+            ));
+        }
+
+        if self.homeassistant.manual_charge_wh == 0 {
+            return Err(ConfigError::ValidationError(
+                "homeassistant.manual_charge_wh must be greater than 0".to_string(),
+            ));
+        }
+
+        // Unchanged values are only republished by the heartbeat of the statistics cycle
+        if self.homeassistant.expire_after_intervals > 0
+            && (self.payload.heartbeat.is_none() || self.e3dc.statistic_schedule.is_some())
+        {
+            return Err(ConfigError::ValidationError(
+                "homeassistant.expire_after_intervals needs payload.heartbeat and no e3dc.statistic_schedule"
+                    .to_string(),
+            ));
+        }
+
+        if self.alerts.export_limit_w.is_some() && self.alerts.export_limit_percent.is_some() {
+            return Err(ConfigError::ValidationError(
+                "alerts.export_limit_w and alerts.export_limit_percent are mutually exclusive"
+                    .to_string(),
+            ));
+        }
+
+        if cfg!(not(target_os = "linux")) && self.mqtt.bind_interface.is_some() {
+            return Err(ConfigError::ValidationError(
+                "mqtt.bind_interface is only supported on Linux".to_string(),
+            ));
+        }
+
+        if cfg!(not(target_os = "linux"))
+            && self
+                .forecast
+                .as_ref()
+                .is_some_and(|forecast| forecast.bind_interface.is_some())
+        {
+            return Err(ConfigError::ValidationError(
+                "forecast.bind_interface is only supported on Linux".to_string(),
+            ));
+        }
+
+        if let Some(socks5) = &self.mqtt.socks5 {
+            if socks5.username.is_some() != socks5.password.is_some() {
+                return Err(ConfigError::ValidationError(
+                    "mqtt.socks5 needs both username and password, or neither".to_string(),
+                ));
+            }
+            let too_long = [socks5.username.as_deref(), socks5.password.as_deref()]
+                .into_iter()
+                .flatten()
+                .chain([self.mqtt.host.as_str()])
+                .any(|value| value.len() > 255);
+            if too_long {
+                return Err(ConfigError::ValidationError(
+                    "mqtt.socks5 username, password and mqtt.host are limited to 255 bytes"
+                        .to_string(),
+                ));
+            }
+        }
+
+        if self.on_error.mqtt == ErrorPolicy::Degrade {
+            return Err(ConfigError::ValidationError(
+                "on_error.mqtt = \"degrade\" is not supported, degraded mode needs MQTT"
+                    .to_string(),
+            ));
+        }
+
+        // The DB history keeps 15 minute values
+        if let Some(slot) = self.statistics.curve_slot {
+            if slot.as_secs() < 900 || slot.as_secs() % 900 != 0 {
+                return Err(ConfigError::ValidationError(
+                    "statistics.curve_slot must be a multiple of 15 minutes".to_string(),
+                ));
+            }
+        }
+
+        let mut window_names = std::collections::BTreeSet::new();
+        for window in &self.statistics.windows {
+            let name = window.name.as_str();
+            if name.is_empty()
+                || !name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+            {
+                return Err(ConfigError::ValidationError(format!(
+                    "statistics window name '{}' must consist of letters, digits, '_' and '-'",
+                    name
+                )));
+            }
+            // Taken by the month/year/lifetime totals
+            if ["month", "year", "lifetime"].contains(&name) || !window_names.insert(name) {
+                return Err(ConfigError::ValidationError(format!(
+                    "statistics window name '{}' is already used",
+                    name
+                )));
+            }
+            if window.window
+                == (StatisticsWindow::Rolling {
+                    span: Duration::ZERO,
+                })
+            {
+                return Err(ConfigError::ValidationError(format!(
+                    "statistics window '{}' needs a span greater than 0",
+                    name
+                )));
+            }
+        }
+
+        for window in &self.schedule.windows {
+            if window.start == window.end {
+                return Err(ConfigError::ValidationError(format!(
+                    "schedule window {} has the same start and end",
+                    window.start.format("%H:%M")
+                )));
+            }
+            if let ScheduleAction::GridCharge { target_soc } = window.action {
+                if !(0.0..=100.0).contains(&target_soc) {
+                    return Err(ConfigError::ValidationError(format!(
+                        "schedule target_soc {} is not a percentage",
+                        target_soc
+                    )));
+                }
+            }
+        }
+
         Ok(())
     }
 }
@@ -249,5 +1343,241 @@ mod tests {
 
         let config: Config = toml::from_str(toml_str).unwrap();
         assert_eq!(config.default.log_level, LogLevel::Debug);
+        assert_eq!(config.retry, RetryPolicy::default());
+        assert_eq!(config.payload.float_decimals, 3);
+        assert!(config.payload.trim_trailing_zeros);
+        assert_eq!(config.payload.timezone, Timezone::Utc);
+        assert_eq!(config.payload.encoding, PayloadEncoding::Json);
+        assert!(!config.commands.enabled);
+        assert_eq!(config.topics, TopicsConfig::default());
+        assert!(!config.homeassistant.enabled);
+        assert_eq!(config.homeassistant.discovery_prefix, "homeassistant");
+        assert_eq!(config.homeassistant.manual_charge_wh, 5000);
+        assert!(!config.homie.enabled);
+        assert_eq!(config.homie.prefix, "homie");
+    }
+
+    #[test]
+    fn test_retry_parsing() {
+        let toml_str = r#"
+            [e3dc]
+            host = "test"
+            username = "test"
+            password = "test"
+            key = "test"
+
+            [mqtt]
+            host = "test"
+            username = "test"
+            password = "test"
+
+            [retry]
+            statistics = { attempts = 5, backoff = "2s" }
+            battery = { attempts = 1 }
+        "#;
+
+        let config: Config = toml::from_str(toml_str).unwrap();
+        assert_eq!(config.retry.statistics.attempts, 5);
+        assert_eq!(config.retry.statistics.backoff, Duration::from_secs(2));
+        assert_eq!(config.retry.battery.attempts, 1);
+        assert_eq!(config.retry.status, RetryPolicy::default().status);
+    }
+
+    #[test]
+    fn test_on_error_parsing() {
+        let on_error: OnErrorConfig = toml::from_str(r#"mqtt = "retry_then_skip""#).unwrap();
+        assert_eq!(on_error.e3dc, ErrorPolicy::Crash);
+        assert_eq!(on_error.mqtt, ErrorPolicy::RetryThenSkip);
+
+        let error = BridgeError::E3dc(crate::errors::E3dcError::QueryFailed("timeout".into()));
+        assert_eq!(on_error.policy(&error), ErrorPolicy::Crash);
+        assert!(toml::from_str::<OnErrorConfig>(r#"e3dc = "ignore""#).is_err());
+        let on_error: OnErrorConfig = toml::from_str(r#"e3dc = "degrade""#).unwrap();
+        assert_eq!(on_error.policy(&error), ErrorPolicy::Degrade);
+    }
+
+    #[test]
+    fn test_commands_acl() {
+        let open = CommandsConfig {
+            enabled: true,
+            ..Default::default()
+        };
+        assert!(open.is_allowed("max_charge_power"));
+
+        let restricted = CommandsConfig {
+            enabled: true,
+            allow: vec!["max_charge_power".to_string(), "idle_periods".to_string()],
+            deny: vec!["idle_periods".to_string()],
+        };
+        assert!(restricted.is_allowed("max_charge_power"));
+        assert!(!restricted.is_allowed("max_discharge_power"));
+        // Deny wins over allow
+        assert!(!restricted.is_allowed("idle_periods"));
+    }
+
+    #[test]
+    fn test_topics_parsing() {
+        let toml_str = r#"
+            [e3dc]
+            host = "test"
+            username = "test"
+            password = "test"
+            key = "test"
+
+            [mqtt]
+            host = "test"
+            username = "test"
+            password = "test"
+
+            [topics]
+            status = "live"
+            status_sums = "daily"
+        "#;
+
+        let config: Config = toml::from_str(toml_str).unwrap();
+        assert_eq!(config.topics.status, "live");
+        assert_eq!(config.topics.status_sums, "daily");
+        assert_eq!(config.topics.info, "info");
+        assert_eq!(config.topics.battery(0), "live/battery:0");
+        assert_eq!(
+            config.topics.dcb("live/battery:0", 1),
+            "live/battery:0/dcb:1"
+        );
+        assert!(config.validate().is_ok());
+
+        let flat = TopicsConfig {
+            layout: TopicLayout::Flat,
+            ..Default::default()
+        };
+        assert_eq!(flat.battery(0), "status/battery/0");
+        assert_eq!(flat.dcb(&flat.battery(0), 1), "status/battery/0/dcb/1");
+        let parsed: TopicsConfig = toml::from_str(r#"layout = "flat""#).unwrap();
+        assert_eq!(parsed.layout, TopicLayout::Flat);
+        assert!(!parsed.retain.status && parsed.retain.status_sums);
+        let parsed: TopicsConfig = toml::from_str("[retain]\nstatus = true\ninfo = false").unwrap();
+        assert!(parsed.retain.status && !parsed.retain.info && parsed.retain.battery);
+
+        let mut invalid = config.clone();
+        invalid.topics.info = "system/#".to_string();
+        assert!(invalid.validate().is_err());
+        invalid.topics.info = String::new();
+        assert!(invalid.validate().is_err());
+
+        let mut cbor = config.clone();
+        cbor.payload.encoding = PayloadEncoding::Cbor;
+        assert!(cbor.validate().is_ok());
+        cbor.homeassistant.enabled = true;
+        assert!(cbor.validate().is_err());
+
+        let mut renamed: Config = toml::from_str(&format!(
+            "{}\n[mqtt.rename]\nhouse_consumption = \"load_power\"",
+            toml_str
+        ))
+        .unwrap();
+        assert_eq!(renamed.mqtt.rename["house_consumption"], "load_power");
+        assert!(renamed.validate().is_ok());
+        renamed
+            .mqtt
+            .rename
+            .insert("solar_production".to_string(), "load_power".to_string());
+        assert!(renamed.validate().is_err());
+        renamed.mqtt.rename.remove("solar_production");
+        renamed
+            .mqtt
+            .rename
+            .insert("online".to_string(), "alive".to_string());
+        assert!(renamed.validate().is_err());
+
+        let mut expiring = config.clone();
+        expiring.homeassistant.expire_after_intervals = 3;
+        assert!(expiring.validate().is_err());
+        expiring.payload.heartbeat = Some(Duration::from_secs(600));
+        assert!(expiring.validate().is_ok());
+        assert_eq!(
+            expiring.discovery_expire_after(),
+            Some(Duration::from_secs(1800))
+        );
+        expiring.e3dc.statistic_update_interval = Duration::from_secs(900);
+        assert_eq!(
+            expiring.discovery_expire_after(),
+            Some(Duration::from_secs(2700))
+        );
+    }
+
+    #[test]
+    fn test_timezone_parsing() {
+        assert_eq!("UTC".parse(), Ok(Timezone::Utc));
+        assert_eq!("local".parse(), Ok(Timezone::Local));
+        assert_eq!(
+            "Europe/Berlin".parse(),
+            Ok(Timezone::Named(chrono_tz::Europe::Berlin))
+        );
+        assert!("Mars/Olympus".parse::<Timezone>().is_err());
+
+        let payload: PayloadConfig = toml::from_str(r#"timezone = "Europe/Vienna""#).unwrap();
+        assert_eq!(payload.timezone, Timezone::Named(chrono_tz::Europe::Vienna));
+        assert!(toml::from_str::<PayloadConfig>(r#"timezone = "nowhere""#).is_err());
+    }
+
+    #[test]
+    fn test_schedule_parsing() {
+        let schedule: ScheduleConfig = toml::from_str(
+            r#"
+            [[windows]]
+            start = "02:00"
+            end = "05:00"
+            action = "grid_charge"
+            target_soc = 80
+
+            [[windows]]
+            start = "17:00"
+            end = "20:00"
+            action = "lock_discharge"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            schedule.windows[0].action,
+            ScheduleAction::GridCharge { target_soc: 80.0 }
+        );
+        assert_eq!(schedule.windows[1].action, ScheduleAction::LockDischarge);
+        let night = NaiveTime::from_hms_opt(3, 30, 0).unwrap();
+        assert!(schedule.windows[0].contains(night));
+        assert!(!schedule.windows[1].contains(night));
+        assert!(toml::from_str::<ScheduleWindow>(
+            r#"start = "2 am"
+            end = "05:00"
+            action = "lock_discharge""#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_statistics_windows_parsing() {
+        let statistics: StatisticsConfig = toml::from_str(
+            r#"
+            [[windows]]
+            name = "local_today"
+            window = "local_day"
+
+            [[windows]]
+            name = "last_hour"
+            window = "rolling"
+            span = "1h"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(statistics.windows[0].window, StatisticsWindow::LocalDay);
+        assert_eq!(
+            statistics.windows[1].window,
+            StatisticsWindow::Rolling {
+                span: Duration::from_secs(3600)
+            }
+        );
+        assert!(toml::from_str::<StatisticsWindowConfig>(
+            r#"name = "last_hour"
+            window = "rolling""#
+        )
+        .is_err());
     }
 }
@@ -0,0 +1,128 @@
+//! Battery charge cycle trend
+//!
+//! Keeps the first `charge_cycles` reading of every day per battery and
+//! derives cycles per day and per month from it, which says more about wear
+//! than the raw counter. The history is persisted in the state directory
+//! (`cycles.json`) so the trend survives restarts.
+
+use std::collections::BTreeMap;
+
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+
+use crate::state::StateFile;
+
+/// Days of history kept per battery
+const MAX_SAMPLES: usize = 400;
+
+/// Window of the cycles per day average
+const DAY_WINDOW: i64 = 7;
+
+/// Window of the cycles per month average
+const MONTH_WINDOW: i64 = 30;
+
+/// Charge cycle counter at the first reading of a day
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct CycleSample {
+    pub date: NaiveDate,
+    pub cycles: f64,
+}
+
+/// Daily cycle samples by battery index
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct CycleHistory {
+    batteries: BTreeMap<u64, Vec<CycleSample>>,
+}
+
+impl StateFile for CycleHistory {
+    const NAME: &'static str = "cycles";
+    const VERSION: u32 = 1;
+}
+
+impl CycleHistory {
+    /// Record a reading, returns true if it is the first one of `date`
+    pub fn record(&mut self, battery: u64, date: NaiveDate, cycles: f64) -> bool {
+        let samples = self.batteries.entry(battery).or_default();
+        if samples.last().is_some_and(|last| last.date >= date) {
+            return false;
+        }
+        samples.push(CycleSample { date, cycles });
+        if samples.len() > MAX_SAMPLES {
+            samples.drain(..samples.len() - MAX_SAMPLES);
+        }
+        true
+    }
+
+    /// Average cycles per day over the last `days` (or all samples if fewer)
+    ///
+    /// None until samples of at least two different days exist.
+    pub fn rate(&self, battery: u64, days: i64) -> Option<f64> {
+        let samples = self.batteries.get(&battery)?;
+        let latest = samples.last()?;
+        let since = latest.date - chrono::Duration::days(days);
+        let base = samples
+            .iter()
+            .rev()
+            .find(|sample| sample.date <= since)
+            .or(samples.first())?;
+        let elapsed = (latest.date - base.date).num_days();
+        (elapsed > 0).then(|| (latest.cycles - base.cycles) / elapsed as f64)
+    }
+
+    /// Cycles per day (7 day average) and per month (30 day average)
+    pub fn trend(&self, battery: u64) -> Option<(f64, f64)> {
+        let per_day = self.rate(battery, DAY_WINDOW)?;
+        let per_month = self.rate(battery, MONTH_WINDOW)? * MONTH_WINDOW as f64;
+        Some((per_day, per_month))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::StateDir;
+    use std::fs;
+
+    fn day(day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2024, 3, day).unwrap()
+    }
+
+    #[test]
+    fn test_cycle_trend() {
+        let mut history = CycleHistory::default();
+        assert!(history.record(0, day(1), 100.0));
+        // Only the first reading of a day counts
+        assert!(!history.record(0, day(1), 100.4));
+        assert_eq!(history.trend(0), None);
+
+        for d in 2..=15 {
+            history.record(0, day(d), 100.0 + f64::from(d - 1) * 0.5);
+        }
+        let (per_day, per_month) = history.trend(0).unwrap();
+        assert_eq!(per_day, 0.5);
+        // Fewer than 30 days of history, averaged over what is there
+        assert_eq!(per_month, 15.0);
+        assert_eq!(history.trend(1), None);
+    }
+
+    #[test]
+    fn test_cycle_history_persistence() {
+        let dir = std::env::temp_dir().join(format!("e3dc-cycles-{}", std::process::id()));
+        let state = StateDir::open(&dir).unwrap();
+        let mut history = CycleHistory::default();
+        history.record(0, day(1), 100.0);
+        state.save(&history).unwrap();
+
+        let loaded: CycleHistory = state.load().unwrap();
+        assert_eq!(
+            loaded.batteries[&0],
+            vec![CycleSample {
+                date: day(1),
+                cycles: 100.0
+            }]
+        );
+        fs::remove_file(state.path::<CycleHistory>()).unwrap();
+        assert!(state.load::<CycleHistory>().unwrap().batteries.is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
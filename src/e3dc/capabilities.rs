@@ -0,0 +1,178 @@
+//! Startup probing of the tags the firmware answers
+//!
+//! Not every model or firmware knows every tag the bridge polls; unknown tags
+//! are answered with an error item instead of a value. The recurring requests
+//! are probed once at startup and unsupported tags are left out of them
+//! afterwards, their fields are None (and not published) instead of failing
+//! every cycle with `MissingTag`.
+//!
+//! Tags that are supported but occasionally missing from a response are read
+//! with a [`FieldReader`], which falls back to the last good value and
+//! collects the missing tags instead of failing the whole response.
+
+use std::collections::BTreeSet;
+
+use rscp::Item;
+
+use super::items::{empty_item, get_tag, FromValue, Value};
+use super::tags::TagName;
+use crate::errors::E3dcError;
+
+/// Tags found unsupported by the startup probe
+#[derive(Debug, Clone, Default)]
+pub struct TagSupport {
+    unsupported: BTreeSet<u32>,
+}
+
+impl TagSupport {
+    pub fn is_supported(&self, tag: u32) -> bool {
+        !self.unsupported.contains(&tag)
+    }
+
+    /// Unsupported tags, sorted by tag id
+    pub fn unsupported(&self) -> impl Iterator<Item = u32> + '_ {
+        self.unsupported.iter().copied()
+    }
+
+    /// Record every tag of `requested` that `response` has no usable value for
+    pub fn record(&mut self, requested: &[u32], response: &[&Item]) {
+        for &tag in requested {
+            let answered = response
+                .iter()
+                .find(|item| item.tag == tag)
+                .and_then(|item| item.data.as_ref())
+                .is_some_and(|data| Value::from_any(data).is_ok());
+            if !answered {
+                self.unsupported.insert(tag);
+            }
+        }
+    }
+
+    /// Empty request items of the supported tags of `tags`
+    pub fn request_items(&self, tags: &[u32]) -> Vec<Item> {
+        tags.iter()
+            .filter(|&&tag| self.is_supported(tag))
+            .map(|&tag| empty_item(tag))
+            .collect()
+    }
+
+    /// Value of `tag`, None if the tag is unsupported
+    pub fn get<T: FromValue>(&self, items: &[&Item], tag: u32) -> Result<Option<T>, E3dcError> {
+        self.is_supported(tag)
+            .then(|| get_tag(items, tag))
+            .transpose()
+    }
+
+    /// Names of the unsupported tags for the startup log
+    pub fn summary(&self) -> String {
+        tag_list(self.unsupported.iter().copied())
+    }
+}
+
+/// Comma separated tag names for logs
+pub fn tag_list(tags: impl IntoIterator<Item = u32>) -> String {
+    tags.into_iter()
+        .map(|tag| TagName(tag).to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Reads the fields of one response, collecting missing tags instead of failing
+pub struct FieldReader<'a> {
+    items: &'a [&'a Item],
+    tags: &'a TagSupport,
+    missing: Vec<u32>,
+}
+
+impl<'a> FieldReader<'a> {
+    pub fn new(items: &'a [&'a Item], tags: &'a TagSupport) -> Self {
+        Self {
+            items,
+            tags,
+            missing: Vec::new(),
+        }
+    }
+
+    /// Value of `tag`, None if it is unsupported or missing (recorded as missing)
+    pub fn try_get<T: FromValue>(&mut self, tag: u32) -> Option<T> {
+        if !self.tags.is_supported(tag) {
+            return None;
+        }
+        match get_tag(self.items, tag) {
+            Ok(value) => Some(value),
+            Err(_) => {
+                self.missing.push(tag);
+                None
+            }
+        }
+    }
+
+    /// Value of `tag`, else `last` (the value of the previous response)
+    ///
+    /// None if the tag is unsupported or hasn't been answered yet.
+    pub fn get<T: FromValue>(&mut self, tag: u32, last: Option<T>) -> Option<T> {
+        self.try_get(tag).or(last)
+    }
+
+    /// Tags that were expected but missing or unreadable
+    pub fn into_missing(self) -> Vec<u32> {
+        self.missing
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tag_support() {
+        let owned = [
+            Item {
+                tag: 1,
+                data: Some(Box::new(42_u16)),
+            },
+            // Error answer of an unknown tag (no value the bridge can read)
+            Item {
+                tag: 2,
+                data: Some(Box::new(())),
+            },
+        ];
+        let response: Vec<&Item> = owned.iter().collect();
+        let mut support = TagSupport::default();
+        support.record(&[1, 2, 3], &response);
+
+        assert_eq!(support.unsupported().collect::<Vec<_>>(), [2, 3]);
+        assert_eq!(
+            support
+                .request_items(&[1, 2, 3])
+                .iter()
+                .map(|item| item.tag)
+                .collect::<Vec<_>>(),
+            [1]
+        );
+        assert_eq!(support.get::<u64>(&response, 1).unwrap(), Some(42));
+        assert_eq!(support.get::<f64>(&response, 3).unwrap(), None);
+        assert_eq!(support.get::<bool>(&response, 2).unwrap(), None);
+    }
+
+    #[test]
+    fn test_field_reader() {
+        let owned = [Item {
+            tag: 1,
+            data: Some(Box::new(42_u16)),
+        }];
+        let response: Vec<&Item> = owned.iter().collect();
+        let mut support = TagSupport::default();
+        support.record(&[3], &[]);
+        let mut fields = FieldReader::new(&response, &support);
+
+        assert_eq!(fields.get::<u64>(1, Some(7)), Some(42));
+        // Missing: the last value is kept
+        assert_eq!(fields.get::<u64>(2, Some(7)), Some(7));
+        // Missing since the start: no value instead of a made up 0
+        assert_eq!(fields.get::<f64>(2, None), None);
+        // Unsupported tags are not reported as missing
+        assert_eq!(fields.get::<u64>(3, Some(7)), Some(7));
+        assert_eq!(fields.into_missing(), [2, 2]);
+    }
+}
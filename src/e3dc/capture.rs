@@ -0,0 +1,261 @@
+//! Anonymized frame capture for bug reports
+//!
+//! [`FrameRecorder`] is a [`FrameObserver`] that keeps every request and
+//! response as a decoded item tree. `e3dc-mqtt-rs capture` runs one full poll
+//! with it and writes one JSON file per exchange, with serial numbers and MAC
+//! addresses replaced, so a report from an unfamiliar model can be turned into
+//! a test case:
+//!
+//! ```json
+//! {
+//!   "class": "Status",
+//!   "request": [{ "tag": 16777217, "name": "EMS::POWER_PV" }],
+//!   "response": [{ "tag": 16777217, "name": "EMS::POWER_PV", "value": { "Int": 2315 } }],
+//!   "elapsed_ms": 23
+//! }
+//! ```
+//!
+//! Values keep their [`Value`] variant, containers list their `children` and
+//! items without a readable value (error answers) carry `error` instead.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use rscp::tags::{BAT, INFO};
+use rscp::{Frame, Item};
+use serde::{Deserialize, Serialize};
+
+use super::items::Value;
+use super::observer::FrameObserver;
+use super::retry::QueryClass;
+use super::tags::tag_name;
+use crate::errors::E3dcError;
+
+/// One decoded item
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CapturedItem {
+    pub tag: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<CapturedItem>,
+}
+
+impl CapturedItem {
+    pub fn from_item(item: &Item) -> Self {
+        let mut captured = CapturedItem {
+            tag: item.tag,
+            name: tag_name(item.tag).map(str::to_string),
+            value: None,
+            error: None,
+            children: Vec::new(),
+        };
+        if let Some(data) = &item.data {
+            if let Some(children) = data.downcast_ref::<Vec<Item>>() {
+                captured.children = children.iter().map(Self::from_item).collect();
+            } else {
+                match Value::from_any(data) {
+                    Ok(value) => captured.value = Some(value),
+                    Err(e) => captured.error = Some(e.to_string()),
+                }
+            }
+        }
+        captured
+    }
+}
+
+/// Top-level items of a frame
+pub fn capture_frame(frame: &Frame) -> Vec<CapturedItem> {
+    frame
+        .items
+        .as_ref()
+        .and_then(|i| i.downcast_ref::<Vec<Item>>())
+        .map(|items| items.iter().map(CapturedItem::from_item).collect())
+        .unwrap_or_default()
+}
+
+/// One request and its response (or the error of the attempt)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CapturedExchange {
+    pub class: String,
+    pub request: Vec<CapturedItem>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response: Option<Vec<CapturedItem>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub elapsed_ms: u64,
+}
+
+/// Tags whose values identify the system
+const ANONYMIZED_TAGS: [u32; 6] = [
+    INFO::SERIAL_NUMBER as u32,
+    INFO::ASSEMBLY_SERIAL_NUMBER as u32,
+    INFO::MAC_ADDRESS as u32,
+    BAT::SERIALNO as u32,
+    BAT::DCB_SERIALNO as u32,
+    BAT::DCB_SERIALCODE as u32,
+];
+
+/// Replace serial numbers and MAC addresses, keeping the value type and format
+///
+/// Digits become `0` and letters `X` (MAC addresses become all zero), so
+/// parsing code still sees a plausible value.
+pub fn anonymize(items: &mut [CapturedItem]) {
+    for item in items {
+        if ANONYMIZED_TAGS.contains(&item.tag) {
+            let mac = item.tag == INFO::MAC_ADDRESS as u32;
+            item.value = item.value.take().map(|value| match value {
+                Value::String(s) => Value::String(
+                    s.chars()
+                        .map(|c| match c {
+                            c if c.is_ascii_digit() || (mac && c.is_ascii_hexdigit()) => '0',
+                            c if c.is_ascii_alphabetic() => 'X',
+                            c => c,
+                        })
+                        .collect(),
+                ),
+                Value::Int(_) => Value::Int(0),
+                Value::UInt(_) => Value::UInt(0),
+                other => other,
+            });
+        }
+        anonymize(&mut item.children);
+    }
+}
+
+/// Records every exchange, see the module docs
+#[derive(Debug, Clone, Default)]
+pub struct FrameRecorder {
+    exchanges: Arc<Mutex<Vec<CapturedExchange>>>,
+}
+
+impl FrameRecorder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Recorded exchanges, anonymized
+    pub fn exchanges(&self) -> Vec<CapturedExchange> {
+        let mut exchanges = self.exchanges.lock().expect("recorder poisoned").clone();
+        for exchange in &mut exchanges {
+            anonymize(&mut exchange.request);
+            if let Some(response) = &mut exchange.response {
+                anonymize(response);
+            }
+        }
+        exchanges
+    }
+
+    /// Write the anonymized exchanges to `dir` as `001-status.json`, ...
+    ///
+    /// Returns the number of files written.
+    pub fn write(&self, dir: &Path) -> io::Result<usize> {
+        fs::create_dir_all(dir)?;
+        let exchanges = self.exchanges();
+        for (i, exchange) in exchanges.iter().enumerate() {
+            let file = dir.join(format!(
+                "{:03}-{}.json",
+                i + 1,
+                exchange.class.to_lowercase()
+            ));
+            fs::write(file, serde_json::to_string_pretty(exchange)?)?;
+        }
+        Ok(exchanges.len())
+    }
+}
+
+impl FrameObserver for FrameRecorder {
+    fn on_request(&self, class: QueryClass, frame: &Frame) {
+        self.exchanges
+            .lock()
+            .expect("recorder poisoned")
+            .push(CapturedExchange {
+                class: format!("{:?}", class),
+                request: capture_frame(frame),
+                response: None,
+                error: None,
+                elapsed_ms: 0,
+            });
+    }
+
+    fn on_response(
+        &self,
+        _class: QueryClass,
+        result: Result<&Frame, &E3dcError>,
+        elapsed: Duration,
+    ) {
+        let mut exchanges = self.exchanges.lock().expect("recorder poisoned");
+        if let Some(exchange) = exchanges.last_mut() {
+            match result {
+                Ok(frame) => exchange.response = Some(capture_frame(frame)),
+                Err(e) => exchange.error = Some(e.to_string()),
+            }
+            exchange.elapsed_ms = elapsed.as_millis() as u64;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rscp::tags::EMS;
+
+    #[test]
+    fn test_capture_anonymized() {
+        let recorder = FrameRecorder::new();
+        let mut response = Frame::new();
+        response.push_item(Item {
+            tag: INFO::SERIAL_NUMBER.into(),
+            data: Some(Box::new("S10-744123456".to_string())),
+        });
+        response.push_item(Item {
+            tag: INFO::MAC_ADDRESS.into(),
+            data: Some(Box::new("00:1a:2b:3c:4d:5e".to_string())),
+        });
+        response.push_item(Item::new(
+            BAT::DATA.into(),
+            vec![Item {
+                tag: BAT::SERIALNO.into(),
+                data: Some(Box::new("A1B2".to_string())),
+            }],
+        ));
+        response.push_item(Item {
+            tag: EMS::POWER_PV.into(),
+            data: Some(Box::new(2315_i32)),
+        });
+
+        recorder.on_request(QueryClass::Other, &Frame::new());
+        recorder.on_response(QueryClass::Other, Ok(&response), Duration::from_millis(23));
+
+        let exchanges = recorder.exchanges();
+        assert_eq!(exchanges.len(), 1);
+        assert_eq!(exchanges[0].elapsed_ms, 23);
+        let items = exchanges[0].response.as_ref().unwrap();
+        assert_eq!(
+            items[0].value,
+            Some(Value::String("X00-000000000".to_string()))
+        );
+        assert_eq!(
+            items[1].value,
+            Some(Value::String("00:00:00:00:00:00".to_string()))
+        );
+        assert_eq!(
+            items[2].children[0].value,
+            Some(Value::String("X0X0".to_string()))
+        );
+        assert_eq!(items[3].value, Some(Value::Int(2315)));
+        assert_eq!(items[3].name.as_deref(), Some("EMS::POWER_PV"));
+
+        // Files round-trip
+        let json = serde_json::to_string(&exchanges[0]).unwrap();
+        let parsed: CapturedExchange = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, exchanges[0]);
+    }
+}
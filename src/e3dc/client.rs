@@ -2,270 +2,141 @@
 //!
 //! High-level interface to E3DC RSCP protocol
 
-use std::{any::Any, collections::HashMap};
+use std::{
+    collections::{BTreeMap, HashMap},
+    time::Instant,
+};
 
+use super::capabilities::{tag_list, FieldReader, TagSupport};
+use super::items::{any_to, any_to_items, empty_item, frame_size, get_items, get_tag};
+use super::model::model_for_serial;
+use super::observer::FrameObserver;
+use super::retry::{QueryClass, RetryPolicy};
+use super::stream::StatusStream;
 use super::types::*;
+use super::windows::StatisticsWindow;
 use crate::errors::E3dcError;
-use chrono::{DateTime, Duration, Timelike, Utc};
+use crate::net;
+use chrono::{DateTime, Datelike, Duration, NaiveTime, TimeZone, Timelike, Utc, Weekday};
 use rscp::{
-    tags::{BAT, DB, EMS, INFO},
+    tags::{BAT, DB, DCDC, EMS, EP, INFO, PM, PVI, SRV, WB},
     Client, Frame, GetItem, Item,
 };
-use tracing::info;
+use tracing::{debug, info, warn};
 
 /// Minimum valid cell temperature in Celsius.
 /// E3DC firmware returns 0.0 for missing/invalid sensors.
 const MIN_VALID_CELL_TEMP_C: f64 = 10.0;
 
-fn any_to_items(data: &Option<Box<dyn Any>>) -> Result<Vec<&Item>, E3dcError> {
-    if let Some(value) = data {
-        return match value.downcast_ref::<Vec<Item>>() {
-            Some(v) => Ok(v.iter().collect()),
-            None => Ok(Vec::new()),
-        };
-    }
-    Ok(Vec::new())
-}
-
-fn any_to_string(value: &Box<dyn Any>) -> Result<String, E3dcError> {
-    if let Some(v) = value.downcast_ref::<String>().cloned() {
-        return Ok(v);
-    }
-    if let Some(&v) = value.downcast_ref::<bool>() {
-        return Ok(if v {
-            "true".to_string()
-        } else {
-            "false".to_string()
-        });
-    }
-    if let Some(&v) = value.downcast_ref::<i8>() {
-        return Ok(v.to_string());
-    }
-    if let Some(&v) = value.downcast_ref::<u8>() {
-        return Ok(v.to_string());
-    }
-    if let Some(&v) = value.downcast_ref::<i16>() {
-        return Ok(v.to_string());
-    }
-    if let Some(&v) = value.downcast_ref::<u16>() {
-        return Ok(v.to_string());
-    }
-    if let Some(&v) = value.downcast_ref::<i32>() {
-        return Ok(v.to_string());
-    }
-    if let Some(&v) = value.downcast_ref::<u32>() {
-        return Ok(v.to_string());
-    }
-    if let Some(&v) = value.downcast_ref::<i64>() {
-        return Ok(v.to_string());
-    }
-    if let Some(&v) = value.downcast_ref::<u64>() {
-        return Ok(v.to_string());
-    }
-    if let Some(&v) = value.downcast_ref::<f32>() {
-        return Ok(v.to_string());
-    }
-    if let Some(&v) = value.downcast_ref::<f64>() {
-        return Ok(v.to_string());
-    }
-    Err(E3dcError::Type(format!(
-        "Cannot convert {:?} to string",
-        (**value).type_id()
-    )))
-}
-
-fn any_to_f64(value: &Box<dyn Any>) -> Result<f64, E3dcError> {
-    if let Some(&v) = value.downcast_ref::<bool>() {
-        return Ok(if v { 1.0 } else { 0.0 });
-    }
-    if let Some(&v) = value.downcast_ref::<i8>() {
-        return Ok(v as f64);
-    }
-    if let Some(&v) = value.downcast_ref::<u8>() {
-        return Ok(v as f64);
-    }
-    if let Some(&v) = value.downcast_ref::<i16>() {
-        return Ok(v as f64);
-    }
-    if let Some(&v) = value.downcast_ref::<u16>() {
-        return Ok(v as f64);
-    }
-    if let Some(&v) = value.downcast_ref::<i32>() {
-        return Ok(v as f64);
-    }
-    if let Some(&v) = value.downcast_ref::<u32>() {
-        return Ok(v as f64);
-    }
-    if let Some(&v) = value.downcast_ref::<i64>() {
-        return Ok(v as f64);
-    }
-    if let Some(&v) = value.downcast_ref::<u64>() {
-        return Ok(v as f64);
-    }
-    if let Some(&v) = value.downcast_ref::<f32>() {
-        return Ok(v as f64);
-    }
-    if let Some(&v) = value.downcast_ref::<f64>() {
-        return Ok(v);
-    }
-    Err(E3dcError::Type(format!(
-        "Cannot convert {:?} to f64",
-        (**value).type_id()
-    )))
-}
-
-fn any_to_u64(value: &Box<dyn Any>) -> Result<u64, E3dcError> {
-    if let Some(&v) = value.downcast_ref::<bool>() {
-        return Ok(if v { 1 } else { 0 });
-    }
-    if let Some(&v) = value.downcast_ref::<i8>() {
-        return v
-            .try_into()
-            .map_err(|_| E3dcError::Type(format!("Negative i8 {} cannot convert to u64", v)));
-    }
-    if let Some(&v) = value.downcast_ref::<u8>() {
-        return Ok(v as u64);
-    }
-    if let Some(&v) = value.downcast_ref::<i16>() {
-        return v
-            .try_into()
-            .map_err(|_| E3dcError::Type(format!("Negative i16 {} cannot convert to u64", v)));
-    }
-    if let Some(&v) = value.downcast_ref::<u16>() {
-        return Ok(v as u64);
-    }
-    if let Some(&v) = value.downcast_ref::<i32>() {
-        return v
-            .try_into()
-            .map_err(|_| E3dcError::Type(format!("Negative i32 {} cannot convert to u64", v)));
-    }
-    if let Some(&v) = value.downcast_ref::<u32>() {
-        return Ok(v as u64);
-    }
-    if let Some(&v) = value.downcast_ref::<i64>() {
-        return v
-            .try_into()
-            .map_err(|_| E3dcError::Type(format!("Negative i64 {} cannot convert to u64", v)));
-    }
-    if let Some(&v) = value.downcast_ref::<u64>() {
-        return Ok(v);
-    }
-    if let Some(&v) = value.downcast_ref::<f32>() {
-        if !v.is_finite() || v < 0.0 || v > u64::MAX as f32 {
-            return Err(E3dcError::Type(format!("Invalid f32 {} for u64", v)));
-        }
-        return Ok(v as u64);
-    }
-    if let Some(&v) = value.downcast_ref::<f64>() {
-        if !v.is_finite() || v < 0.0 || v > u64::MAX as f64 {
-            return Err(E3dcError::Type(format!("Invalid f64 {} for u64", v)));
-        }
-        return Ok(v as u64);
-    }
-    Err(E3dcError::Type(format!(
-        "Cannot convert {:?} to u64",
-        (**value).type_id()
-    )))
-}
-
-fn any_to_bool(value: &Box<dyn Any>) -> Result<bool, E3dcError> {
-    const EPSILON32: f32 = 1e-10;
-    const EPSILON64: f64 = 1e-10;
-
-    if let Some(&v) = value.downcast_ref::<bool>() {
-        return Ok(v);
-    }
-    if let Some(&v) = value.downcast_ref::<i8>() {
-        return Ok(v != 0);
-    }
-    if let Some(&v) = value.downcast_ref::<u8>() {
-        return Ok(v != 0);
-    }
-    if let Some(&v) = value.downcast_ref::<i16>() {
-        return Ok(v != 0);
-    }
-    if let Some(&v) = value.downcast_ref::<u16>() {
-        return Ok(v != 0);
-    }
-    if let Some(&v) = value.downcast_ref::<i32>() {
-        return Ok(v != 0);
-    }
-    if let Some(&v) = value.downcast_ref::<u32>() {
-        return Ok(v != 0);
-    }
-    if let Some(&v) = value.downcast_ref::<i64>() {
-        return Ok(v != 0);
-    }
-    if let Some(&v) = value.downcast_ref::<u64>() {
-        return Ok(v != 0);
-    }
-    if let Some(&v) = value.downcast_ref::<f32>() {
-        return Ok(v.abs() >= EPSILON32);
-    }
-    if let Some(&v) = value.downcast_ref::<f64>() {
-        return Ok(v.abs() >= EPSILON64);
-    }
-    Err(E3dcError::Type(format!(
-        "Cannot convert {:?} to bool",
-        (**value).type_id()
-    )))
-}
+/// RSCP port, the one rscp connects to
+const RSCP_PORT: u16 = 5033;
+
+/// Oldest year queried for lifetime totals, E3DC systems exist since 2010
+const FIRST_HISTORY_YEAR: i32 = 2010;
+
+/// Tags of the status request
+const STATUS_TAGS: [u32; 11] = [
+    EMS::POWER_PV as u32,
+    EMS::POWER_BAT as u32,
+    EMS::POWER_GRID as u32,
+    EMS::POWER_HOME as u32,
+    EMS::BAT_SOC as u32,
+    EMS::AUTARKY as u32,
+    EMS::SELF_CONSUMPTION as u32,
+    EMS::POWER_WB_ALL as u32,
+    EMS::POWER_WB_SOLAR as u32,
+    EMS::POWER_ADD as u32,
+    EMS::EMERGENCY_POWER_STATUS as u32,
+];
+
+/// Tags of the charge limit request
+const CHARGE_LIMIT_TAGS: [u32; 10] = [
+    EMS::USED_CHARGE_LIMIT as u32,
+    EMS::BAT_CHARGE_LIMIT as u32,
+    EMS::DCDC_CHARGE_LIMIT as u32,
+    EMS::USER_CHARGE_LIMIT as u32,
+    EMS::USED_DISCHARGE_LIMIT as u32,
+    EMS::BAT_DISCHARGE_LIMIT as u32,
+    EMS::DCDC_DISCHARGE_LIMIT as u32,
+    EMS::USER_DISCHARGE_LIMIT as u32,
+    EMS::REMAINING_BAT_CHARGE_POWER as u32,
+    EMS::REMAINING_BAT_DISCHARGE_POWER as u32,
+];
+
+/// Tags of the battery data request (inside the `BAT::DATA` container)
+const BATTERY_TAGS: [u32; 25] = [
+    // State of Charge
+    BAT::RSOC as u32,
+    BAT::RSOC_REAL as u32,
+    BAT::ASOC as u32,
+    // Electrical measurements
+    BAT::CURRENT as u32,
+    BAT::MODULE_VOLTAGE as u32,
+    BAT::TERMINAL_VOLTAGE as u32,
+    BAT::MAX_BAT_VOLTAGE as u32,
+    BAT::EOD_VOLTAGE as u32,
+    // Capacity
+    BAT::FCC as u32,
+    BAT::RC as u32,
+    BAT::DESIGN_CAPACITY as u32,
+    BAT::USABLE_CAPACITY as u32,
+    BAT::USABLE_REMAINING_CAPACITY as u32,
+    // Current limits
+    BAT::MAX_CHARGE_CURRENT as u32,
+    BAT::MAX_DISCHARGE_CURRENT as u32,
+    // Temperature
+    BAT::MAX_DCB_CELL_TEMPERATURE as u32,
+    BAT::MIN_DCB_CELL_TEMPERATURE as u32,
+    // Status and errors
+    BAT::STATUS_CODE as u32,
+    BAT::ERROR_CODE as u32,
+    // Cycles and usage
+    BAT::CHARGE_CYCLES as u32,
+    BAT::TOTAL_USE_TIME as u32,
+    BAT::TOTAL_DISCHARGE_TIME as u32,
+    // DCB info
+    BAT::DCB_COUNT as u32,
+    // Operational state
+    BAT::READY_FOR_SHUTDOWN as u32,
+    BAT::TRAINING_MODE as u32,
+];
+
+/// PVI indexes scanned at startup (Quattroporte systems have up to four inverters)
+const MAX_PVIS: u64 = 4;
+
+/// DCDC converter indexes queried (one per battery string, rarely more than two)
+const MAX_DCDCS: u64 = 4;
 
 /// E3DC client wrapper
 pub struct E3dcClient {
     client: Client,
+    host: String,
+    connect_timeout: std::time::Duration,
     pub batteries: Vec<BatteryInfo>,
+    pvis: Vec<PviInfo>,
+    tags: TagSupport,
+    /// Last responses, missing fields fall back to them
+    last_status: Option<Status>,
+    last_batteries: HashMap<u64, BatteryData>,
     info: SystemInfoStatic,
+    retry: RetryPolicy,
+    /// Query the DCB modules with the battery data
+    poll_dcbs: bool,
+    observers: Vec<Box<dyn FrameObserver>>,
+    /// Traffic of the query running in the current span
+    traffic: Traffic,
 }
 
-pub fn empty_item(tag: u32) -> Item {
-    Item { tag, data: None }
-}
-
-fn find_item<'a>(items: &'a [&'a Item], tag: u32) -> Result<&'a Item, E3dcError> {
-    items
-        .iter()
-        .find(|item| item.tag == tag)
-        .copied()
-        .ok_or(E3dcError::MissingTag(tag))
-}
-
-fn find_item_data<'a>(items: &'a [&'a Item], tag: u32) -> Result<&'a Box<dyn Any>, E3dcError> {
-    let item = items
-        .iter()
-        .find(|item| item.tag == tag)
-        .ok_or(E3dcError::MissingTag(tag))?;
-
-    item.data.as_ref().ok_or(E3dcError::MissingData(tag))
-}
-fn get_items<'a>(items: &'a [&'a Item], tag: u32) -> Result<Vec<&'a Item>, E3dcError> {
-    let item = find_item(items, tag)?;
-    any_to_items(&item.data)
-}
-
-fn get_bool(items: &[&Item], tag: u32) -> Result<bool, E3dcError> {
-    let data = find_item_data(items, tag)?;
-    any_to_bool(data)
-}
-
-fn get_number(items: &[&Item], tag: u32) -> Result<f64, E3dcError> {
-    let data = find_item_data(items, tag)?;
-    any_to_f64(data)
-}
-
-fn get_integer(items: &[&Item], tag: u32) -> Result<u64, E3dcError> {
-    let data = find_item_data(items, tag)?;
-    any_to_u64(data)
-}
-
-fn get_string(items: &[&Item], tag: u32) -> Result<String, E3dcError> {
-    let data = find_item_data(items, tag)?;
-    any_to_string(data)
+/// Tags requested and bytes exchanged by a query
+#[derive(Debug, Default, Clone, Copy)]
+struct Traffic {
+    tags: usize,
+    bytes: usize,
 }
 
-pub fn send_request(client: &mut Client, frame: Frame) -> Result<Frame, E3dcError> {
+pub fn send_request(client: &mut Client, frame: &Frame) -> Result<Frame, E3dcError> {
     let response = client
-        .send_receive_frame(&frame)
+        .send_receive_frame(frame)
         .map_err(|e| E3dcError::QueryFailed(format!("{:?}", e)))?;
 
     if response.items.is_none() {
@@ -277,36 +148,176 @@ pub fn send_request(client: &mut Client, frame: Frame) -> Result<Frame, E3dcErro
 
 impl E3dcClient {
     /// Create a new E3DC client
+    ///
+    /// `connect_timeout` limits the connect to each address of `host`.
     pub fn new(
         host: String,
         key: String,
         username: String,
         password: String,
+        connect_timeout: std::time::Duration,
     ) -> Result<Self, E3dcError> {
         let mut client = Client::new(&key, username, password);
         info!("Connecting to E3DC at {}...", host);
-        client
-            .connect(&host, None)
-            .map_err(|e| E3dcError::ConnectionFailed {
-                host: host.clone(),
-                reason: format!("{:?}", e),
-            })?;
+        Self::connect(&mut client, &host, connect_timeout)?;
         info!("✓ Connected to E3DC successfully!");
         let batteries = Self::get_batteries(&mut client)?;
+        let tags = Self::probe_tags(&mut client, &batteries).unwrap_or_else(|e| {
+            warn!("Tag probe failed, polling all tags: {}", e);
+            TagSupport::default()
+        });
+        if tags.unsupported().next().is_some() {
+            warn!(
+                "Firmware does not answer these tags, their fields are not published: {}",
+                tags.summary()
+            );
+        } else {
+            info!("All polled tags are supported");
+        }
+        let pvis = Self::get_pvis(&mut client).unwrap_or_else(|e| {
+            warn!("PVI scan failed, per-inverter topics disabled: {}", e);
+            Vec::new()
+        });
         let info = Self::get_system_info_static(&mut client)?;
         let device_id = format!("{}-{}", &info.model, &info.serial_number);
         info!("Device ID: {}", device_id);
 
         Ok(Self {
             client,
+            host,
+            connect_timeout,
             batteries,
+            pvis,
+            tags,
+            last_status: None,
+            last_batteries: HashMap::new(),
             info,
+            retry: RetryPolicy::default(),
+            poll_dcbs: true,
+            observers: Vec::new(),
+            traffic: Traffic::default(),
         })
     }
 
+    /// Drop the connection and connect again, e.g. after the E3DC restarted
+    pub fn reconnect(&mut self) -> Result<(), E3dcError> {
+        if let Err(e) = self.client.disconnect() {
+            debug!("Error disconnecting E3DC before reconnect: {:?}", e);
+        }
+        Self::connect(&mut self.client, &self.host, self.connect_timeout)?;
+        info!("✓ Reconnected to E3DC at {}", self.host);
+        Ok(())
+    }
+
+    /// Connect to the first address of `host` that accepts a connection
+    fn connect(
+        client: &mut Client,
+        host: &str,
+        timeout: std::time::Duration,
+    ) -> Result<(), E3dcError> {
+        let failed = |reason: String| E3dcError::ConnectionFailed {
+            host: host.to_string(),
+            reason,
+        };
+        let address = net::reachable_host(host, RSCP_PORT, timeout, None)
+            .map_err(|e| failed(e.to_string()))?;
+        client
+            .connect(&address, None)
+            .map_err(|e| failed(format!("{:?}", e)))
+    }
+
+    /// Register an observer that sees every request frame and its response
+    pub fn add_observer(&mut self, observer: Box<dyn FrameObserver>) {
+        self.observers.push(observer);
+    }
+
+    /// Query the DCB modules with the battery data (default true)
+    ///
+    /// Without, [`BatteryData::dcbs`] stays empty and every battery is one request.
+    pub fn set_poll_dcbs(&mut self, poll_dcbs: bool) {
+        self.poll_dcbs = poll_dcbs;
+    }
+
+    /// Apply `[e3dc] model` or the `[e3dc.models]` table to the model name
+    ///
+    /// The model is part of the device id, call this before reading the system info.
+    pub fn set_model(&mut self, model: Option<&str>, models: &BTreeMap<String, String>) {
+        let model = match model {
+            Some(model) => model.to_string(),
+            None => model_for_serial(&self.info.serial_number, &self.info.serial_prefix, models),
+        };
+        if model != self.info.model {
+            info!("Model {} instead of {}", model, self.info.model);
+            self.info.model = model;
+        }
+    }
+
+    /// Replace the retry policy (defaults to `RetryPolicy::default()`)
+    pub fn set_retry_policy(&mut self, policy: RetryPolicy) {
+        self.retry = policy;
+    }
+
     pub fn send_request(&mut self, frame: Frame) -> Result<Frame, E3dcError> {
         //Result<(Vec<Item>, DateTime<Utc>), E3dcError> {
-        send_request(&mut self.client, frame)
+        self.send_request_as(QueryClass::Other, frame)
+    }
+
+    /// Send a request, retrying according to the policy of its query class
+    pub fn send_request_as(&mut self, class: QueryClass, frame: Frame) -> Result<Frame, E3dcError> {
+        let client = &mut self.client;
+        let observers = &self.observers;
+        let traffic = &mut self.traffic;
+        self.retry.for_class(class).run(class, || {
+            for observer in observers {
+                observer.on_request(class, &frame);
+            }
+            let started = Instant::now();
+            let result = send_request(client, &frame);
+            let elapsed = started.elapsed();
+            let (tags, bytes) = frame_size(&frame);
+            traffic.tags += tags;
+            traffic.bytes += bytes;
+            if let Ok(response) = &result {
+                traffic.bytes += frame_size(response).1;
+            }
+            for observer in observers {
+                observer.on_response(class, result.as_ref(), elapsed);
+            }
+            result
+        })
+    }
+
+    /// Run `query` in a debug span `query{name}` recording the tags requested,
+    /// the bytes sent and received (retries included) and the duration
+    ///
+    /// Nested queries count towards the outer one as well.
+    fn traced<T>(
+        &mut self,
+        name: &'static str,
+        query: impl FnOnce(&mut Self) -> Result<T, E3dcError>,
+    ) -> Result<T, E3dcError> {
+        let span = tracing::debug_span!(
+            "query",
+            name,
+            tags = tracing::field::Empty,
+            bytes = tracing::field::Empty,
+            duration_ms = tracing::field::Empty,
+        );
+        let _entered = span.enter();
+        let outer = std::mem::take(&mut self.traffic);
+        let started = Instant::now();
+        let result = query(self);
+        let duration_ms = started.elapsed().as_millis() as u64;
+        let traffic = self.traffic;
+        self.traffic = Traffic {
+            tags: outer.tags + traffic.tags,
+            bytes: outer.bytes + traffic.bytes,
+        };
+        span.record("tags", traffic.tags);
+        span.record("bytes", traffic.bytes);
+        span.record("duration_ms", duration_ms);
+        debug!("{} took {} ms", name, duration_ms);
+        result
     }
 
     /// Polls the static system info via rscp protocol.
@@ -320,53 +331,70 @@ impl E3dcClient {
         frame.push_item(empty_item(INFO::SERIAL_NUMBER.into()));
         frame.push_item(empty_item(INFO::MAC_ADDRESS.into()));
 
-        let result = send_request(client, frame)?;
+        let result = send_request(client, &frame)?;
 
         let all_items = any_to_items(&result.items)?;
 
-        let derate_at_percent_value = get_number(&all_items, EMS::DERATE_AT_PERCENT_VALUE.into())?;
-        let derate_at_power_value = get_integer(&all_items, EMS::DERATE_AT_POWER_VALUE.into())?;
-        let installed_peak_power = get_integer(&all_items, EMS::INSTALLED_PEAK_POWER.into())?;
-        let ext_source_available = get_bool(&all_items, EMS::EXT_SRC_AVAILABLE.into())?;
-        let mac_address: String = get_string(&all_items, INFO::MAC_ADDRESS.into())?;
-        let serial: String = get_string(&all_items, INFO::SERIAL_NUMBER.into())?;
-        let serial_number = if serial.chars().count() > 4 {
-            serial.chars().skip(4).collect()
-        } else {
-            serial.to_string()
-        };
-        let model = if serial_number.starts_with("4") || serial_number.starts_with("72") {
-            "S10E"
-        } else if serial_number.starts_with("74") {
-            "S10E_Compact"
-        } else if serial_number.starts_with("5") {
-            "S10_Mini"
-        } else if serial_number.starts_with("6") {
-            "Quattroporte"
-        } else if serial_number.starts_with("70") {
-            "S10E_Pro"
-        } else if serial_number.starts_with("75") {
-            "S10E_Pro_Compact"
-        } else if serial_number.starts_with("8") {
-            "S10X"
+        let derate_at_percent_value =
+            get_tag::<f64>(&all_items, EMS::DERATE_AT_PERCENT_VALUE.into())?;
+        let derate_at_power_value = get_tag::<u64>(&all_items, EMS::DERATE_AT_POWER_VALUE.into())?;
+        let installed_peak_power = get_tag::<u64>(&all_items, EMS::INSTALLED_PEAK_POWER.into())?;
+        let ext_source_available = get_tag::<bool>(&all_items, EMS::EXT_SRC_AVAILABLE.into())?;
+        let mac_address: String = get_tag::<String>(&all_items, INFO::MAC_ADDRESS.into())?;
+        let serial: String = get_tag::<String>(&all_items, INFO::SERIAL_NUMBER.into())?;
+        let (serial_prefix, serial_number): (String, String) = if serial.chars().count() > 4 {
+            (
+                serial.chars().take(4).collect(),
+                serial.chars().skip(4).collect(),
+            )
         } else {
-            "N/A"
+            (String::new(), serial.to_string())
         };
+        let model = model_for_serial(&serial_number, &serial_prefix, &BTreeMap::new());
+
+        let hardware = Self::get_hardware_info(client).unwrap_or_else(|e| {
+            warn!("INFO metadata query failed, publishing without it: {}", e);
+            HardwareInfo::default()
+        });
 
         Ok(SystemInfoStatic {
             serial_number,
+            serial_prefix,
             model,
             mac_address,
             installed_peak_power,
             derate_at_percent_value,
             derate_at_power_value,
             ext_source_available,
+            hardware,
+        })
+    }
+
+    /// Production date, platform type and assembly serial number
+    ///
+    /// Separate request, so firmware that rejects one of them doesn't fail the startup.
+    fn get_hardware_info(client: &mut Client) -> Result<HardwareInfo, E3dcError> {
+        let mut frame = Frame::new();
+        frame.push_item(empty_item(INFO::PRODUCTION_DATE.into()));
+        frame.push_item(empty_item(INFO::PLATFORM_TYPE.into()));
+        frame.push_item(empty_item(INFO::ASSEMBLY_SERIAL_NUMBER.into()));
+
+        let result = send_request(client, &frame)?;
+        let all_items = any_to_items(&result.items)?;
+        Ok(HardwareInfo {
+            production_date: get_tag::<String>(&all_items, INFO::PRODUCTION_DATE.into()).ok(),
+            platform_type: get_tag::<u64>(&all_items, INFO::PLATFORM_TYPE.into()).ok(),
+            assembly_serial_number: get_tag::<String>(
+                &all_items,
+                INFO::ASSEMBLY_SERIAL_NUMBER.into(),
+            )
+            .ok(),
         })
     }
 
     /// Get system information (called once at startup)
     /// Only queries tags that are known to work
-    pub fn get_system_info(&'_ mut self) -> Result<SystemInfo<'_>, E3dcError> {
+    pub fn get_system_info(&mut self) -> Result<SystemInfo, E3dcError> {
         let mut frame = Frame::new();
 
         // INFO tags
@@ -394,8 +422,8 @@ impl E3dcClient {
             .filter(|item| item.tag == EMS::SYS_SPEC as u32)
             .filter_map(|item| {
                 let items = any_to_items(&item.data).ok()?;
-                let name = get_string(&items, EMS::SYS_SPEC_NAME.into()).ok()?;
-                let value = get_integer(&items, EMS::SYS_SPEC_VALUE_INT.into());
+                let name = get_tag::<String>(&items, EMS::SYS_SPEC_NAME.into()).ok()?;
+                let value = get_tag::<u64>(&items, EMS::SYS_SPEC_VALUE_INT.into());
                 match value {
                     Ok(v) => Some((name, v)),
                     Err(_) => None,
@@ -403,33 +431,35 @@ impl E3dcClient {
             })
             .collect();
 
-        let ip_address: String = get_string(&all_items, INFO::IP_ADDRESS.into())?;
-        let software_release = get_string(&all_items, INFO::SW_RELEASE.into())?;
+        let ip_address: String = get_tag::<String>(&all_items, INFO::IP_ADDRESS.into())?;
+        let software_release = get_tag::<String>(&all_items, INFO::SW_RELEASE.into())?;
         // Extract from SYS_SPECS container
         let installed_battery_capacity = specs_map.get("installedBatteryCapacity").copied();
         let max_ac_power = specs_map.get("maxAcPower").copied();
         let max_battery_charge_power = specs_map.get("maxBatChargePower").copied();
         let max_battery_discharge_power = specs_map.get("maxBatDischargPower").copied();
         // Extract from GET_POWER_SETTINGS container
-        let max_charge_power = get_integer(&power_settings_items, EMS::MAX_CHARGE_POWER.into())?;
+        let max_charge_power = get_tag::<u64>(&power_settings_items, EMS::MAX_CHARGE_POWER.into())?;
         let max_discharge_power =
-            get_integer(&power_settings_items, EMS::MAX_DISCHARGE_POWER.into())?;
+            get_tag::<u64>(&power_settings_items, EMS::MAX_DISCHARGE_POWER.into())?;
         let discharge_start_power =
-            get_integer(&power_settings_items, EMS::DISCHARGE_START_POWER.into())?;
-        let power_limits_used = get_bool(&power_settings_items, EMS::POWER_LIMITS_USED.into())?;
-        let power_save_enabled = get_bool(&power_settings_items, EMS::POWERSAVE_ENABLED.into())?;
+            get_tag::<u64>(&power_settings_items, EMS::DISCHARGE_START_POWER.into())?;
+        let power_limits_used =
+            get_tag::<bool>(&power_settings_items, EMS::POWER_LIMITS_USED.into())?;
+        let power_save_enabled =
+            get_tag::<bool>(&power_settings_items, EMS::POWERSAVE_ENABLED.into())?;
         let weather_forecast_mode =
-            get_integer(&power_settings_items, EMS::WEATHER_FORECAST_MODE.into())?;
-        let weather_regulated_charge_enabled = get_bool(
+            get_tag::<u64>(&power_settings_items, EMS::WEATHER_FORECAST_MODE.into())?;
+        let weather_regulated_charge_enabled = get_tag::<bool>(
             &power_settings_items,
             EMS::WEATHER_REGULATED_CHARGE_ENABLED.into(),
         )?;
 
         Ok(SystemInfo {
             time_stamp,
-            serial_number: &self.info.serial_number,
-            model: self.info.model,
-            mac_address: &self.info.mac_address,
+            serial_number: self.info.serial_number.clone(),
+            model: self.info.model.clone(),
+            mac_address: self.info.mac_address.clone(),
             ip_address,
             software_release,
             installed_peak_power: self.info.installed_peak_power,
@@ -447,46 +477,362 @@ impl E3dcClient {
             weather_forecast_mode,
             weather_regulated_charge_enabled,
             external_source_available: self.info.ext_source_available,
+            hardware: self.info.hardware.clone(),
+        })
+    }
+
+    /// Write EMS power settings via `EMS::SET_POWER_SETTINGS`
+    /// Only the fields set in `update` are sent; the result code of each is checked
+    pub fn set_power_settings(&mut self, update: &PowerSettingsUpdate) -> Result<(), E3dcError> {
+        let mut settings = Vec::new();
+        let mut result_tags: Vec<u32> = Vec::new();
+
+        if let Some(enabled) = update.power_limits_used {
+            settings.push(Item {
+                tag: EMS::POWER_LIMITS_USED.into(),
+                data: Some(Box::new(enabled)),
+            });
+            result_tags.push(EMS::RES_POWER_LIMITS.into());
+        }
+
+        let power_settings = [
+            (
+                update.max_charge_power,
+                EMS::MAX_CHARGE_POWER,
+                EMS::RES_MAX_CHARGE_POWER,
+            ),
+            (
+                update.max_discharge_power,
+                EMS::MAX_DISCHARGE_POWER,
+                EMS::RES_MAX_DISCHARGE_POWER,
+            ),
+            (
+                update.discharge_start_power,
+                EMS::DISCHARGE_START_POWER,
+                EMS::RES_DISCHARGE_START_POWER,
+            ),
+        ];
+        for (value, tag, result_tag) in power_settings {
+            let Some(value) = value else {
+                continue;
+            };
+            let value = u32::try_from(value).map_err(|_| {
+                E3dcError::ParseError(format!("Power value {} out of range", value))
+            })?;
+            settings.push(Item {
+                tag: tag.into(),
+                data: Some(Box::new(value)),
+            });
+            result_tags.push(result_tag.into());
+        }
+
+        let flags = [
+            (
+                update.power_save_enabled,
+                EMS::POWERSAVE_ENABLED,
+                EMS::RES_POWERSAVE_ENABLED,
+            ),
+            (
+                update.weather_regulated_charge_enabled,
+                EMS::WEATHER_REGULATED_CHARGE_ENABLED,
+                EMS::RES_WEATHER_REGULATED_CHARGE_ENABLED,
+            ),
+        ];
+        for (enabled, tag, result_tag) in flags {
+            let Some(enabled) = enabled else {
+                continue;
+            };
+            settings.push(Item {
+                tag: tag.into(),
+                data: Some(Box::new(u8::from(enabled))),
+            });
+            result_tags.push(result_tag.into());
+        }
+
+        if let Some(mode) = update.weather_forecast_mode {
+            settings.push(Item {
+                tag: EMS::WEATHER_FORECAST_MODE.into(),
+                data: Some(Box::new(mode.as_raw() as i32)),
+            });
+            result_tags.push(EMS::RES_WEATHER_FORECAST_MODE.into());
+        }
+
+        if settings.is_empty() {
+            return Ok(());
+        }
+
+        let mut frame = Frame::new();
+        frame.push_item(Item::new(EMS::SET_POWER_SETTINGS.into(), settings));
+
+        let response = self.send_request_as(QueryClass::Write, frame)?;
+        let all_items = any_to_items(&response.items)?;
+        let result_items = get_items(&all_items, EMS::SET_POWER_SETTINGS.into())?;
+
+        // Result codes: 0 = ok, 1 = ok but adjusted, negative = rejected
+        for tag in result_tags {
+            let code = get_tag::<i64>(&result_items, tag)?;
+            if code < 0 {
+                return Err(E3dcError::WriteRejected { tag, code });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Read the weekly idle period schedule
+    pub fn get_idle_periods(&mut self) -> Result<Vec<IdlePeriod>, E3dcError> {
+        let mut frame = Frame::new();
+        frame.push_item(empty_item(EMS::GET_IDLE_PERIODS.into()));
+
+        let response = self.send_request(frame)?;
+        let all_items = any_to_items(&response.items)?;
+        let periods = get_items(&all_items, EMS::GET_IDLE_PERIODS.into())?;
+
+        periods
+            .iter()
+            .filter(|item| item.tag == u32::from(EMS::IDLE_PERIOD))
+            .map(|item| {
+                let period = any_to_items(&item.data)?;
+                let raw_type = get_tag::<u64>(&period, EMS::IDLE_PERIOD_TYPE.into())?;
+                let period_type = IdlePeriodType::from_raw(raw_type).ok_or_else(|| {
+                    E3dcError::ParseError(format!("Unknown idle period type {}", raw_type))
+                })?;
+                let raw_day = get_tag::<u64>(&period, EMS::IDLE_PERIOD_DAY.into())?;
+                let day = u8::try_from(raw_day)
+                    .ok()
+                    .and_then(|day| Weekday::try_from(day).ok())
+                    .ok_or_else(|| {
+                        E3dcError::ParseError(format!("Invalid idle period day {}", raw_day))
+                    })?;
+                Ok(IdlePeriod {
+                    period_type,
+                    day,
+                    start: Self::get_idle_period_time(&period, EMS::IDLE_PERIOD_START.into())?,
+                    end: Self::get_idle_period_time(&period, EMS::IDLE_PERIOD_END.into())?,
+                    active: get_tag::<bool>(&period, EMS::IDLE_PERIOD_ACTIVE.into())?,
+                })
+            })
+            .collect()
+    }
+
+    fn get_idle_period_time(items: &[&Item], tag: u32) -> Result<NaiveTime, E3dcError> {
+        let time = get_items(items, tag)?;
+        let hour = get_tag::<u64>(&time, EMS::IDLE_PERIOD_HOUR.into())?;
+        let minute = get_tag::<u64>(&time, EMS::IDLE_PERIOD_MINUTE.into())?;
+        NaiveTime::from_hms_opt(hour as u32, minute as u32, 0).ok_or_else(|| {
+            E3dcError::ParseError(format!("Invalid idle period time {}:{}", hour, minute))
         })
     }
 
+    /// Write the weekly idle period schedule
+    ///
+    /// Every type/day combination missing from `periods` is written as inactive,
+    /// so the given schedule fully replaces the existing one.
+    pub fn set_idle_periods(&mut self, periods: &[IdlePeriod]) -> Result<(), E3dcError> {
+        IdlePeriod::validate_schedule(periods).map_err(E3dcError::ParseError)?;
+
+        let midnight = NaiveTime::MIN;
+        let mut schedule = periods.to_vec();
+        for period_type in [IdlePeriodType::Charge, IdlePeriodType::Discharge] {
+            for day in 0..7u8 {
+                let day = Weekday::try_from(day).expect("day index is always below 7");
+                if !periods
+                    .iter()
+                    .any(|p| p.period_type == period_type && p.day == day)
+                {
+                    schedule.push(IdlePeriod {
+                        period_type,
+                        day,
+                        start: midnight,
+                        end: midnight,
+                        active: false,
+                    });
+                }
+            }
+        }
+
+        let time_item = |tag: EMS, time: NaiveTime| {
+            Item::new(
+                tag.into(),
+                vec![
+                    Item {
+                        tag: EMS::IDLE_PERIOD_HOUR.into(),
+                        data: Some(Box::new(time.hour() as u8)),
+                    },
+                    Item {
+                        tag: EMS::IDLE_PERIOD_MINUTE.into(),
+                        data: Some(Box::new(time.minute() as u8)),
+                    },
+                ],
+            )
+        };
+
+        let items: Vec<Item> = schedule
+            .iter()
+            .map(|period| {
+                Item::new(
+                    EMS::IDLE_PERIOD.into(),
+                    vec![
+                        Item {
+                            tag: EMS::IDLE_PERIOD_TYPE.into(),
+                            data: Some(Box::new(period.period_type.as_raw())),
+                        },
+                        Item {
+                            tag: EMS::IDLE_PERIOD_DAY.into(),
+                            data: Some(Box::new(period.day.num_days_from_monday() as u8)),
+                        },
+                        Item {
+                            tag: EMS::IDLE_PERIOD_ACTIVE.into(),
+                            data: Some(Box::new(period.active)),
+                        },
+                        time_item(EMS::IDLE_PERIOD_START, period.start),
+                        time_item(EMS::IDLE_PERIOD_END, period.end),
+                    ],
+                )
+            })
+            .collect();
+
+        let mut frame = Frame::new();
+        frame.push_item(Item::new(EMS::SET_IDLE_PERIODS.into(), items));
+
+        let response = self.send_request_as(QueryClass::Write, frame)?;
+        let all_items = any_to_items(&response.items)?;
+        if !get_tag::<bool>(&all_items, EMS::SET_IDLE_PERIODS.into())? {
+            return Err(E3dcError::WriteRejected {
+                tag: EMS::SET_IDLE_PERIODS.into(),
+                code: 0,
+            });
+        }
+
+        Ok(())
+    }
+
     pub fn batteries(&self) -> &Vec<BatteryInfo> {
         &self.batteries
     }
 
+    /// Scan the installed batteries and their DCB counts again
+    ///
+    /// Battery data queries use the result of the last scan, so hardware
+    /// changes are only picked up after calling this.
+    pub fn rediscover_batteries(&mut self) -> Result<Vec<DcbCountChange>, E3dcError> {
+        let batteries = Self::get_batteries(&mut self.client)?;
+        let changes = batteries
+            .iter()
+            .filter_map(|battery| {
+                let old = self.batteries.iter().find(|b| b.index == battery.index)?;
+                (old.dcb_count != battery.dcb_count).then_some(DcbCountChange {
+                    battery: battery.index,
+                    from: old.dcb_count,
+                    to: battery.dcb_count,
+                })
+            })
+            .collect();
+        self.batteries = batteries;
+        Ok(changes)
+    }
+
+    /// Query the DCB count of every known battery and update it
+    ///
+    /// Cheaper than [`Self::rediscover_batteries`], one small request per battery.
+    pub fn refresh_dcb_counts(&mut self) -> Result<Vec<DcbCountChange>, E3dcError> {
+        let mut changes = Vec::new();
+        for i in 0..self.batteries.len() {
+            let index = self.batteries[i].index;
+            let response =
+                self.send_request_as(QueryClass::Battery, Self::dcb_count_frame(index))?;
+            let dcb_count = Self::parse_dcb_count(&response)?;
+            let battery = &mut self.batteries[i];
+            if battery.dcb_count != dcb_count {
+                changes.push(DcbCountChange {
+                    battery: index,
+                    from: battery.dcb_count,
+                    to: dcb_count,
+                });
+                battery.dcb_count = dcb_count;
+            }
+        }
+        Ok(changes)
+    }
+
+    /// Request of `BAT::DCB_COUNT`, the battery data query itself always returns 0
+    fn dcb_count_frame(index: u64) -> Frame {
+        let mut frame = Frame::new();
+        frame.push_item(Item::new(
+            BAT::DATA.into(),
+            vec![
+                Item {
+                    tag: BAT::INDEX.into(),
+                    data: Some(Box::new(index as i32)),
+                },
+                Item {
+                    tag: BAT::DCB_COUNT.into(),
+                    data: None,
+                },
+            ],
+        ));
+        frame
+    }
+
+    fn parse_dcb_count(response: &Frame) -> Result<u64, E3dcError> {
+        let all_items = any_to_items(&response.items)?;
+        let data = get_items(&all_items, BAT::DATA.into())?;
+        get_tag::<u64>(&data, BAT::DCB_COUNT.into())
+    }
+
     /// Get current status (polled every interval)
     /// Queries all status values in one frame
     pub fn get_status(&mut self) -> Result<Status, E3dcError> {
+        self.traced("get_status", Self::query_status)
+    }
+
+    fn query_status(&mut self) -> Result<Status, E3dcError> {
         let mut frame = Frame::new();
 
-        // Request all status values in one frame
-        frame.push_item(empty_item(EMS::POWER_PV.into()));
-        frame.push_item(empty_item(EMS::POWER_BAT.into()));
-        frame.push_item(empty_item(EMS::POWER_GRID.into()));
-        frame.push_item(empty_item(EMS::POWER_HOME.into()));
-        frame.push_item(empty_item(EMS::BAT_SOC.into()));
-        frame.push_item(empty_item(EMS::AUTARKY.into()));
-        frame.push_item(empty_item(EMS::SELF_CONSUMPTION.into()));
-        frame.push_item(empty_item(EMS::POWER_WB_ALL.into()));
-        frame.push_item(empty_item(EMS::POWER_ADD.into()));
+        // Request all supported status values in one frame
+        for item in self.tags.request_items(&STATUS_TAGS) {
+            frame.push_item(item);
+        }
 
-        let response = self.send_request(frame)?;
+        let response = self.send_request_as(QueryClass::Status, frame)?;
 
         let time_stamp = response.time_stamp;
         let all_items = any_to_items(&response.items)?;
+        let last = self.last_status.as_ref();
+        let mut fields = FieldReader::new(&all_items, &self.tags);
+
+        // Missing values keep the previous value, so they are not published as changed
+        let power_add = fields.get(EMS::POWER_ADD.into(), last.and_then(|s| s.power_add));
+        let power_pv = fields.get(EMS::POWER_PV.into(), last.and_then(|s| s.power_pv));
+        let power_battery = fields.get(EMS::POWER_BAT.into(), last.and_then(|s| s.power_battery));
+        let power_grid = fields.get(EMS::POWER_GRID.into(), last.and_then(|s| s.power_grid));
+        let power_home = fields.get(EMS::POWER_HOME.into(), last.and_then(|s| s.power_home));
+        let power_wb = fields.get(EMS::POWER_WB_ALL.into(), last.and_then(|s| s.power_wb));
+        let power_wb_solar = fields.get(
+            EMS::POWER_WB_SOLAR.into(),
+            last.and_then(|s| s.power_wb_solar),
+        );
+        let battery_soc = fields.get(EMS::BAT_SOC.into(), last.and_then(|s| s.battery_soc));
+        let autarky = fields.get(EMS::AUTARKY.into(), last.and_then(|s| s.autarky));
+        let self_consumption = fields.get(
+            EMS::SELF_CONSUMPTION.into(),
+            last.and_then(|s| s.self_consumption),
+        );
+        let emergency_power_state = match fields.try_get(EMS::EMERGENCY_POWER_STATUS.into()) {
+            Some(raw) => EmergencyPowerState::from_raw(raw),
+            None => last.map_or(EmergencyPowerState::NotAvailable, |s| {
+                s.emergency_power_state
+            }),
+        };
+        let missing = fields.into_missing();
+        if !missing.is_empty() && last.is_none_or(|s| s.missing != missing) {
+            warn!(
+                "Status response misses {}",
+                tag_list(missing.iter().copied())
+            );
+        }
 
-        // Extract values - use get_item_data for i32 (works reliably)
-        let power_add = get_number(&all_items, EMS::POWER_ADD.into())?;
-        let power_pv = get_number(&all_items, EMS::POWER_PV.into())?;
-        let power_battery = get_number(&all_items, EMS::POWER_BAT.into())?;
-        let power_grid = get_number(&all_items, EMS::POWER_GRID.into())?;
-        let power_home = get_number(&all_items, EMS::POWER_HOME.into())?;
-        let power_wb = get_number(&all_items, EMS::POWER_WB_ALL.into())?;
-        let battery_soc = get_number(&all_items, EMS::BAT_SOC.into())?;
-        let autarky = get_number(&all_items, EMS::AUTARKY.into())?;
-        let self_consumption = get_number(&all_items, EMS::SELF_CONSUMPTION.into())?;
-
-        Ok(Status {
+        let status = Status {
             time_stamp,
             power_add,
             power_pv,
@@ -494,12 +840,335 @@ impl E3dcClient {
             power_grid,
             power_home,
             power_wb,
+            power_wb_solar,
             battery_soc,
             autarky,
             self_consumption,
+            emergency_power_state,
+            missing,
+        };
+        self.last_status = Some(status.clone());
+        Ok(status)
+    }
+
+    /// Get the charge/discharge limits the EMS applies right now
+    pub fn get_charge_limits(&mut self) -> Result<ChargeLimits, E3dcError> {
+        let mut frame = Frame::new();
+        for item in self.tags.request_items(&CHARGE_LIMIT_TAGS) {
+            frame.push_item(item);
+        }
+
+        let response = self.send_request_as(QueryClass::Status, frame)?;
+        let all_items = any_to_items(&response.items)?;
+        let value = |tag: EMS| self.tags.get::<f64>(&all_items, tag.into());
+
+        Ok(ChargeLimits {
+            time_stamp: response.time_stamp,
+            used_charge_limit: value(EMS::USED_CHARGE_LIMIT)?,
+            bat_charge_limit: value(EMS::BAT_CHARGE_LIMIT)?,
+            dcdc_charge_limit: value(EMS::DCDC_CHARGE_LIMIT)?,
+            user_charge_limit: value(EMS::USER_CHARGE_LIMIT)?,
+            used_discharge_limit: value(EMS::USED_DISCHARGE_LIMIT)?,
+            bat_discharge_limit: value(EMS::BAT_DISCHARGE_LIMIT)?,
+            dcdc_discharge_limit: value(EMS::DCDC_DISCHARGE_LIMIT)?,
+            user_discharge_limit: value(EMS::USER_DISCHARGE_LIMIT)?,
+            remaining_bat_charge_power: value(EMS::REMAINING_BAT_CHARGE_POWER)?,
+            remaining_bat_discharge_power: value(EMS::REMAINING_BAT_DISCHARGE_POWER)?,
+        })
+    }
+
+    /// Get the phases the EMS balances and the per-phase power of the grid meter
+    /// (power meter index 0)
+    pub fn get_phase_balance(&mut self) -> Result<PhaseBalance, E3dcError> {
+        let mut frame = Frame::new();
+        frame.push_item(empty_item(EMS::BALANCED_PHASES.into()));
+        frame.push_item(Item::new(
+            PM::DATA.into(),
+            vec![
+                Item {
+                    tag: PM::INDEX.into(),
+                    data: Some(Box::new(0i32)),
+                },
+                empty_item(PM::POWER_L1.into()),
+                empty_item(PM::POWER_L2.into()),
+                empty_item(PM::POWER_L3.into()),
+            ],
+        ));
+
+        let response = self.send_request_as(QueryClass::Status, frame)?;
+        let all_items = any_to_items(&response.items)?;
+        let meter = get_items(&all_items, PM::DATA.into())?;
+
+        Ok(PhaseBalance {
+            time_stamp: response.time_stamp,
+            balanced_phases: get_tag::<u64>(&all_items, EMS::BALANCED_PHASES.into())?,
+            power_l1: get_tag::<f64>(&meter, PM::POWER_L1.into())?,
+            power_l2: get_tag::<f64>(&meter, PM::POWER_L2.into())?,
+            power_l3: get_tag::<f64>(&meter, PM::POWER_L3.into())?,
         })
     }
 
+    /// Get the unit's UTC time, local time and time zone
+    pub fn get_device_clock(&mut self) -> Result<DeviceClock, E3dcError> {
+        let mut frame = Frame::new();
+        frame.push_item(empty_item(INFO::UTC_TIME.into()));
+        frame.push_item(empty_item(INFO::TIME.into()));
+        frame.push_item(empty_item(INFO::TIME_ZONE.into()));
+
+        let sent = Utc::now();
+        let response = self.send_request_as(QueryClass::Other, frame)?;
+        let time_stamp = sent + (Utc::now() - sent) / 2;
+        let all_items = any_to_items(&response.items)?;
+        let timestamp = |tag: u32| -> Result<DateTime<Utc>, E3dcError> {
+            let seconds = get_tag::<i64>(&all_items, tag)?;
+            DateTime::from_timestamp(seconds, 0)
+                .ok_or_else(|| E3dcError::ParseError(format!("Invalid timestamp: {}", seconds)))
+        };
+
+        Ok(DeviceClock {
+            time_stamp,
+            utc_time: timestamp(INFO::UTC_TIME.into())?,
+            // The local time is sent as if it were UTC
+            local_time: timestamp(INFO::TIME.into())?.naive_utc(),
+            time_zone: get_tag::<String>(&all_items, INFO::TIME_ZONE.into())?,
+        })
+    }
+
+    /// Get the emergency power capability flags and the battery reserve
+    ///
+    /// Tags the firmware doesn't answer are None. Without
+    /// `EP::PARAM_EP_RESERVE_ENERGY` the reserve in Wh is derived from the
+    /// percentage and `battery_capacity` (Wh).
+    pub fn get_emergency_power_capabilities(
+        &mut self,
+        battery_capacity: Option<f64>,
+    ) -> Result<EmergencyPowerCapabilities, E3dcError> {
+        let mut frame = Frame::new();
+        frame.push_item(empty_item(EP::IS_POSSIBLE.into()));
+        frame.push_item(empty_item(EP::IS_READY_FOR_SWITCH.into()));
+        frame.push_item(empty_item(EP::IS_POSSIBLE_PWR_OFF.into()));
+        frame.push_item(empty_item(EP::EP_RESERVE.into()));
+        frame.push_item(empty_item(EMS::BAT_SOC.into()));
+
+        let response = self.send_request_as(QueryClass::Other, frame)?;
+        let all_items = any_to_items(&response.items)?;
+        let reserve = get_items(&all_items, EP::EP_RESERVE.into()).unwrap_or_default();
+        let mut fields = FieldReader::new(&all_items, &self.tags);
+        let possible = fields.try_get(EP::IS_POSSIBLE.into());
+        let ready_for_switch = fields.try_get(EP::IS_READY_FOR_SWITCH.into());
+        let power_off_possible = fields.try_get(EP::IS_POSSIBLE_PWR_OFF.into());
+        let battery_soc = fields.try_get(EMS::BAT_SOC.into());
+        let mut reserve_fields = FieldReader::new(&reserve, &self.tags);
+        let reserve_percent = reserve_fields.try_get(EP::PARAM_EP_RESERVE.into());
+        let reserve_energy = reserve_energy(
+            reserve_percent,
+            reserve_fields.try_get(EP::PARAM_EP_RESERVE_ENERGY.into()),
+            battery_capacity,
+        );
+        let missing: Vec<u32> = fields
+            .into_missing()
+            .into_iter()
+            .chain(reserve_fields.into_missing())
+            .collect();
+        if !missing.is_empty() {
+            debug!("Emergency power response without {}", tag_list(missing));
+        }
+
+        Ok(EmergencyPowerCapabilities {
+            possible,
+            ready_for_switch,
+            power_off_possible,
+            reserve_configured: reserve_percent
+                .zip(reserve_energy)
+                .map(|(percent, energy)| percent > 0.0 || energy > 0.0),
+            reserve_percent,
+            reserve_energy,
+            reserve_held_energy: reserve_percent
+                .zip(reserve_energy)
+                .zip(battery_soc)
+                .map(|((percent, energy), soc)| reserve_held_energy(percent, energy, soc)),
+        })
+    }
+
+    /// Start the emergency power self-test (`EMS::START_EMERGENCYPOWER_TEST`)
+    pub fn start_emergency_power_test(&mut self) -> Result<(), E3dcError> {
+        let mut frame = Frame::new();
+        frame.push_item(Item::new(EMS::START_EMERGENCYPOWER_TEST.into(), 1u8));
+
+        let response = self.send_request_as(QueryClass::Write, frame)?;
+        let all_items = any_to_items(&response.items)?;
+        let code = get_tag::<i64>(&all_items, EMS::START_EMERGENCYPOWER_TEST.into())?;
+        if code < 0 {
+            return Err(E3dcError::WriteRejected {
+                tag: EMS::START_EMERGENCYPOWER_TEST.into(),
+                code,
+            });
+        }
+        Ok(())
+    }
+
+    /// Raw status and result of the emergency power self-test
+    pub fn get_emergency_power_test(&mut self) -> Result<(u64, u64), E3dcError> {
+        let mut frame = Frame::new();
+        frame.push_item(empty_item(EMS::EMERGENCYPOWER_TEST_STATUS.into()));
+        frame.push_item(empty_item(EMS::EMERGENCY_POWER_TEST_RESULT.into()));
+
+        let response = self.send_request_as(QueryClass::Status, frame)?;
+        let all_items = any_to_items(&response.items)?;
+        Ok((
+            get_tag::<u64>(&all_items, EMS::EMERGENCYPOWER_TEST_STATUS.into())?,
+            get_tag::<u64>(&all_items, EMS::EMERGENCY_POWER_TEST_RESULT.into())?,
+        ))
+    }
+
+    /// Manual charge state and the discharge power limit
+    pub fn get_ems_control_state(&mut self) -> Result<EmsControlState, E3dcError> {
+        let mut frame = Frame::new();
+        frame.push_item(empty_item(EMS::MANUAL_CHARGE_ACTIVE.into()));
+        frame.push_item(empty_item(EMS::GET_POWER_SETTINGS.into()));
+
+        let response = self.send_request_as(QueryClass::Status, frame)?;
+        let all_items = any_to_items(&response.items)?;
+        let power_settings = get_items(&all_items, EMS::GET_POWER_SETTINGS.into())?;
+        Ok(EmsControlState {
+            manual_charge_active: get_tag::<bool>(&all_items, EMS::MANUAL_CHARGE_ACTIVE.into())?,
+            max_discharge_power: get_tag::<u64>(&power_settings, EMS::MAX_DISCHARGE_POWER.into())?,
+            power_limits_used: get_tag::<bool>(&power_settings, EMS::POWER_LIMITS_USED.into())?,
+        })
+    }
+
+    /// Set the emergency power reserve in % of the usable battery energy
+    /// (`EP::SET_EP_RESERVE`)
+    pub fn set_emergency_power_reserve(&mut self, percent: f64) -> Result<(), E3dcError> {
+        let mut frame = Frame::new();
+        frame.push_item(Item::new(
+            EP::SET_EP_RESERVE.into(),
+            vec![
+                Item::new(EP::PARAM_INDEX.into(), 0u8),
+                Item::new(EP::PARAM_EP_RESERVE.into(), percent as f32),
+            ],
+        ));
+
+        let response = self.send_request_as(QueryClass::Write, frame)?;
+        let all_items = any_to_items(&response.items)?;
+        let reserve = get_items(&all_items, EP::SET_EP_RESERVE.into())?;
+        // The unit answers with the reserve it set, an error item if it refused
+        get_tag::<f64>(&reserve, EP::PARAM_EP_RESERVE.into())?;
+        Ok(())
+    }
+
+    /// Charge `energy` Wh from the grid (`EMS::START_MANUAL_CHARGE`), 0 stops a
+    /// running manual charge
+    pub fn start_manual_charge(&mut self, energy: u64) -> Result<(), E3dcError> {
+        let energy = u32::try_from(energy)
+            .map_err(|_| E3dcError::ParseError(format!("Energy {} Wh out of range", energy)))?;
+        let mut frame = Frame::new();
+        frame.push_item(Item::new(EMS::START_MANUAL_CHARGE.into(), energy));
+
+        let response = self.send_request_as(QueryClass::Write, frame)?;
+        let all_items = any_to_items(&response.items)?;
+        if !get_tag::<bool>(&all_items, EMS::START_MANUAL_CHARGE.into())? {
+            return Err(E3dcError::WriteRejected {
+                tag: EMS::START_MANUAL_CHARGE.into(),
+                code: 0,
+            });
+        }
+        Ok(())
+    }
+
+    /// Get the unit's network settings
+    pub fn get_network_config(&mut self) -> Result<NetworkConfig, E3dcError> {
+        let mut frame = Frame::new();
+        frame.push_item(empty_item(INFO::DHCP_STATUS.into()));
+        frame.push_item(empty_item(INFO::IP_ADDRESS.into()));
+        frame.push_item(empty_item(INFO::SUBNET_MASK.into()));
+        frame.push_item(empty_item(INFO::GATEWAY.into()));
+        frame.push_item(empty_item(INFO::DNS.into()));
+
+        let response = self.send_request_as(QueryClass::Other, frame)?;
+        let all_items = any_to_items(&response.items)?;
+        Ok(NetworkConfig {
+            dhcp: get_tag::<bool>(&all_items, INFO::DHCP_STATUS.into())?,
+            ip_address: get_tag::<String>(&all_items, INFO::IP_ADDRESS.into())?,
+            subnet_mask: get_tag::<String>(&all_items, INFO::SUBNET_MASK.into())?,
+            gateway: get_tag::<String>(&all_items, INFO::GATEWAY.into())?,
+            dns: get_tag::<String>(&all_items, INFO::DNS.into())?,
+        })
+    }
+
+    /// Whether the unit is connected to the E3DC portal (`SRV::IS_ONLINE`)
+    pub fn get_portal_online(&mut self) -> Result<bool, E3dcError> {
+        let mut frame = Frame::new();
+        frame.push_item(empty_item(SRV::IS_ONLINE.into()));
+
+        let response = self.send_request_as(QueryClass::Other, frame)?;
+        let all_items = any_to_items(&response.items)?;
+        get_tag::<bool>(&all_items, SRV::IS_ONLINE.into())
+    }
+
+    /// Number of phases wallbox `index` charges with, None if it can't switch them
+    pub fn get_wallbox_phases(&mut self, index: u8) -> Result<Option<u8>, E3dcError> {
+        let mut frame = Frame::new();
+        frame.push_item(Item::new(
+            WB::DATA.into(),
+            vec![
+                Item::new(WB::INDEX.into(), index),
+                empty_item(WB::NUMBER_PHASES.into()),
+            ],
+        ));
+
+        let response = self.send_request_as(QueryClass::Status, frame)?;
+        let all_items = any_to_items(&response.items)?;
+        let data = get_items(&all_items, WB::DATA.into())?;
+        // Wallboxes without phase switching answer with an error item
+        Ok(get_tag::<u64>(&data, WB::NUMBER_PHASES.into())
+            .ok()
+            .and_then(|phases| u8::try_from(phases).ok()))
+    }
+
+    /// Switch wallbox `index` to charging with 1 or 3 phases
+    pub fn set_wallbox_phases(&mut self, index: u8, phases: u8) -> Result<(), E3dcError> {
+        let mut frame = Frame::new();
+        frame.push_item(Item::new(
+            WB::DATA.into(),
+            vec![
+                Item::new(WB::INDEX.into(), index),
+                Item::new(WB::SET_NUMBER_PHASES.into(), phases),
+            ],
+        ));
+
+        let response = self.send_request_as(QueryClass::Write, frame)?;
+        let all_items = any_to_items(&response.items)?;
+        let data = get_items(&all_items, WB::DATA.into())?;
+        // The unit answers with the new value, an error item if it refused
+        get_tag::<u64>(&data, WB::SET_NUMBER_PHASES.into())?;
+        Ok(())
+    }
+
+    /// Set the unit's clock via `INFO::SET_TIME`, and its time zone if given
+    pub fn set_time(
+        &mut self,
+        time: DateTime<Utc>,
+        time_zone: Option<&str>,
+    ) -> Result<(), E3dcError> {
+        let seconds = u64::try_from(time.timestamp())
+            .map_err(|_| E3dcError::ParseError(format!("Invalid timestamp: {}", time)))?;
+        let mut frame = Frame::new();
+        frame.push_item(Item::new(INFO::SET_TIME.into(), seconds));
+        if let Some(time_zone) = time_zone {
+            frame.push_item(Item::new(INFO::SET_TIME_ZONE.into(), time_zone.to_string()));
+        }
+
+        let response = self.send_request_as(QueryClass::Write, frame)?;
+        let all_items = any_to_items(&response.items)?;
+        // The unit answers with the new value, an error item if it refused
+        get_tag::<i64>(&all_items, INFO::SET_TIME.into())?;
+        if time_zone.is_some() {
+            get_tag::<String>(&all_items, INFO::SET_TIME_ZONE.into())?;
+        }
+        Ok(())
+    }
+
     /// Scan for installed batteries (up to 8 batteries)
     /// Uses BATCH query - ONE network call instead of 8 (saves ~7 seconds!)
     /// Returns list of BatteryInfo with index and DCB count
@@ -518,33 +1187,16 @@ impl E3dcClient {
             .into_iter()
             .map(|battery| -> Result<BatteryInfo, E3dcError> {
                 let spec = any_to_items(&battery.data)?;
-                let index = get_integer(&spec, BAT::INDEX.into())?;
-                let param_bat_number = get_integer(&spec, BAT::PARAM_BAT_NUMBER.into())?;
-                let device_name = get_string(&spec, BAT::DEVICE_NAME.into())?;
-                let manufacturer_name = get_string(&spec, BAT::MANUFACTURER_NAME.into())?;
-                let serialno = get_integer(&spec, BAT::SERIALNO.into())?;
-                let instance_descriptor = get_string(&spec, BAT::INSTANCE_DESCRIPTOR.into())?;
-
-                let mut frame = Frame::new();
-                frame.push_item(Item::new(
-                    BAT::DATA.into(),
-                    vec![
-                        Item {
-                            tag: BAT::INDEX.into(),
-                            data: Some(Box::new(index as i32)),
-                        },
-                        Item {
-                            tag: BAT::DCB_COUNT.into(),
-                            data: None,
-                        },
-                    ],
-                ));
-                let response = send_request(client, frame)?;
-
-                let all_items = any_to_items(&response.items)?;
-                let data = get_items(&all_items, BAT::DATA.into())?;
+                let index = get_tag::<u64>(&spec, BAT::INDEX.into())?;
+                let param_bat_number = get_tag::<u64>(&spec, BAT::PARAM_BAT_NUMBER.into())?;
+                let device_name = get_tag::<String>(&spec, BAT::DEVICE_NAME.into())?;
+                let manufacturer_name = get_tag::<String>(&spec, BAT::MANUFACTURER_NAME.into())?;
+                let serialno = get_tag::<u64>(&spec, BAT::SERIALNO.into())?;
+                let instance_descriptor =
+                    get_tag::<String>(&spec, BAT::INSTANCE_DESCRIPTOR.into())?;
 
-                let dcb_count = get_integer(&data, BAT::DCB_COUNT.into())?;
+                let response = send_request(client, &Self::dcb_count_frame(index))?;
+                let dcb_count = Self::parse_dcb_count(&response)?;
 
                 Ok(BatteryInfo {
                     index,
@@ -561,6 +1213,202 @@ impl E3dcClient {
         Ok(batteries)
     }
 
+    /// Scan for connected PVIs (up to `MAX_PVIS`)
+    ///
+    /// Indexes without an inverter answer with an error instead of the
+    /// device state and are skipped.
+    fn get_pvis(client: &mut Client) -> Result<Vec<PviInfo>, E3dcError> {
+        let mut frame = Frame::new();
+        for index in 0..MAX_PVIS {
+            frame.push_item(Item::new(
+                PVI::DATA.into(),
+                vec![
+                    Item {
+                        tag: PVI::INDEX.into(),
+                        data: Some(Box::new(index as u8)),
+                    },
+                    empty_item(PVI::DEVICE_STATE.into()),
+                    empty_item(PVI::USED_STRING_COUNT.into()),
+                    empty_item(PVI::AC_MAX_PHASE_COUNT.into()),
+                ],
+            ));
+        }
+
+        let response = send_request(client, &frame)?;
+        let all_items = any_to_items(&response.items)?;
+        let pvis: Vec<PviInfo> = all_items
+            .iter()
+            .filter(|item| item.tag == u32::from(PVI::DATA))
+            .filter_map(|item| {
+                let data = any_to_items(&item.data).ok()?;
+                let state = get_items(&data, PVI::DEVICE_STATE.into()).ok()?;
+                if !get_tag::<bool>(&state, PVI::DEVICE_CONNECTED.into()).ok()? {
+                    return None;
+                }
+                Some(PviInfo {
+                    index: get_tag::<u64>(&data, PVI::INDEX.into()).ok()?,
+                    string_count: get_tag::<u64>(&data, PVI::USED_STRING_COUNT.into()).ok()?,
+                    phase_count: get_tag::<u64>(&data, PVI::AC_MAX_PHASE_COUNT.into()).ok()?,
+                })
+            })
+            .collect();
+        info!("Found {} PVI(s)", pvis.len());
+        Ok(pvis)
+    }
+
+    /// PVIs found at startup
+    pub fn pvis(&self) -> &[PviInfo] {
+        &self.pvis
+    }
+
+    /// Get the live data of all PVIs found at startup, in one request
+    pub fn get_pvi_data(&mut self) -> Result<Vec<PviData>, E3dcError> {
+        let mut frame = Frame::new();
+        for pvi in &self.pvis {
+            let mut items = vec![
+                Item {
+                    tag: PVI::INDEX.into(),
+                    data: Some(Box::new(pvi.index as u8)),
+                },
+                empty_item(PVI::ON_GRID.into()),
+            ];
+            for string in 0..pvi.string_count {
+                for tag in [PVI::DC_POWER, PVI::DC_VOLTAGE, PVI::DC_CURRENT] {
+                    items.push(Item {
+                        tag: tag.into(),
+                        data: Some(Box::new(string as u8)),
+                    });
+                }
+            }
+            for phase in 0..pvi.phase_count {
+                items.push(Item {
+                    tag: PVI::AC_POWER.into(),
+                    data: Some(Box::new(phase as u8)),
+                });
+            }
+            frame.push_item(Item::new(PVI::DATA.into(), items));
+        }
+
+        let response = self.send_request_as(QueryClass::Status, frame)?;
+        let all_items = any_to_items(&response.items)?;
+        all_items
+            .iter()
+            .filter(|item| item.tag == u32::from(PVI::DATA))
+            .map(|item| {
+                let data = any_to_items(&item.data)?;
+                let dc_power = Self::pvi_values(&data, PVI::DC_POWER)?;
+                let dc_voltage = Self::pvi_values(&data, PVI::DC_VOLTAGE)?;
+                let dc_current = Self::pvi_values(&data, PVI::DC_CURRENT)?;
+                let strings: Vec<PviString> = dc_power
+                    .iter()
+                    .map(|&(index, dc_power)| {
+                        let value = |values: &[(u64, f64)]| {
+                            values
+                                .iter()
+                                .find(|(i, _)| *i == index)
+                                .map_or(0.0, |(_, value)| *value)
+                        };
+                        PviString {
+                            index,
+                            dc_power,
+                            dc_voltage: value(&dc_voltage),
+                            dc_current: value(&dc_current),
+                        }
+                    })
+                    .collect();
+                Ok(PviData {
+                    time_stamp: response.time_stamp,
+                    index: get_tag::<u64>(&data, PVI::INDEX.into())?,
+                    on_grid: get_tag::<bool>(&data, PVI::ON_GRID.into())?,
+                    ac_power: Self::pvi_values(&data, PVI::AC_POWER)?
+                        .iter()
+                        .map(|(_, value)| value)
+                        .sum(),
+                    dc_power: strings.iter().map(|string| string.dc_power).sum(),
+                    strings,
+                })
+            })
+            .collect()
+    }
+
+    /// Get the live data of the connected DCDC converters, in one request
+    ///
+    /// All indexes up to `MAX_DCDCS` are queried every time, a converter
+    /// that fails or is replaced shows up without a restart.
+    pub fn get_dcdc_data(&mut self) -> Result<Vec<DcdcData>, E3dcError> {
+        let mut frame = Frame::new();
+        for index in 0..MAX_DCDCS {
+            let mut items = vec![Item {
+                tag: DCDC::INDEX.into(),
+                data: Some(Box::new(index as u8)),
+            }];
+            for tag in [
+                DCDC::DEVICE_STATE,
+                DCDC::I_BAT,
+                DCDC::U_BAT,
+                DCDC::P_BAT,
+                DCDC::I_DCL,
+                DCDC::U_DCL,
+                DCDC::P_DCL,
+                DCDC::STATUS_AS_STRING,
+                DCDC::STATE,
+                DCDC::SUBSTATE,
+                DCDC::ERROR_CODE,
+                DCDC::WARNING_CODE,
+            ] {
+                items.push(empty_item(tag.into()));
+            }
+            frame.push_item(Item::new(DCDC::DATA.into(), items));
+        }
+
+        let response = self.send_request_as(QueryClass::Status, frame)?;
+        let all_items = any_to_items(&response.items)?;
+        Ok(all_items
+            .iter()
+            .filter(|item| item.tag == u32::from(DCDC::DATA))
+            .filter_map(|item| {
+                // Missing indexes answer with error items instead of values
+                let data = any_to_items(&item.data).ok()?;
+                let state = get_items(&data, DCDC::DEVICE_STATE.into()).ok()?;
+                if !get_tag::<bool>(&state, DCDC::DEVICE_CONNECTED.into()).ok()? {
+                    return None;
+                }
+                Some(DcdcData {
+                    time_stamp: response.time_stamp,
+                    index: get_tag::<u64>(&data, DCDC::INDEX.into()).ok()?,
+                    battery_current: get_tag::<f64>(&data, DCDC::I_BAT.into()).ok()?,
+                    battery_voltage: get_tag::<f64>(&data, DCDC::U_BAT.into()).ok()?,
+                    battery_power: get_tag::<f64>(&data, DCDC::P_BAT.into()).ok()?,
+                    dcl_current: get_tag::<f64>(&data, DCDC::I_DCL.into()).ok()?,
+                    dcl_voltage: get_tag::<f64>(&data, DCDC::U_DCL.into()).ok()?,
+                    dcl_power: get_tag::<f64>(&data, DCDC::P_DCL.into()).ok()?,
+                    status: get_tag::<String>(&data, DCDC::STATUS_AS_STRING.into())
+                        .unwrap_or_default(),
+                    state: get_tag::<u64>(&data, DCDC::STATE.into()).unwrap_or_default(),
+                    substate: get_tag::<u64>(&data, DCDC::SUBSTATE.into()).unwrap_or_default(),
+                    error_code: get_tag::<u64>(&data, DCDC::ERROR_CODE.into()).unwrap_or_default(),
+                    warning_code: get_tag::<u64>(&data, DCDC::WARNING_CODE.into())
+                        .unwrap_or_default(),
+                })
+            })
+            .collect())
+    }
+
+    /// Values of the per-string/per-phase containers `tag` (`PVI::INDEX` and `PVI::VALUE`)
+    fn pvi_values(data: &[&Item], tag: PVI) -> Result<Vec<(u64, f64)>, E3dcError> {
+        let tag = u32::from(tag);
+        data.iter()
+            .filter(|item| item.tag == tag)
+            .map(|item| {
+                let value = any_to_items(&item.data)?;
+                Ok((
+                    get_tag::<u64>(&value, PVI::INDEX.into())?,
+                    get_tag::<f64>(&value, PVI::VALUE.into())?,
+                ))
+            })
+            .collect()
+    }
+
     pub fn get_battery_data(&mut self) -> Result<Vec<BatteryData>, E3dcError> {
         let batteries = self.batteries.clone();
         batteries
@@ -569,114 +1417,180 @@ impl E3dcClient {
             .collect()
     }
 
+    /// `BAT::DATA` request of battery `index` with the supported battery tags
+    fn battery_data_item(index: u64, tags: &TagSupport) -> Item {
+        let mut items = vec![Item {
+            tag: BAT::INDEX.into(),
+            data: Some(Box::new(index)),
+        }];
+        items.extend(tags.request_items(&BATTERY_TAGS));
+        Item::new(BAT::DATA.into(), items)
+    }
+
+    /// Probe the tags of the recurring status, charge limit and battery requests
+    ///
+    /// The battery tags are probed on the first battery only.
+    fn probe_tags(client: &mut Client, batteries: &[BatteryInfo]) -> Result<TagSupport, E3dcError> {
+        let mut support = TagSupport::default();
+
+        let mut frame = Frame::new();
+        for &tag in STATUS_TAGS.iter().chain(&CHARGE_LIMIT_TAGS) {
+            frame.push_item(empty_item(tag));
+        }
+        let response = send_request(client, &frame)?;
+        let all_items = any_to_items(&response.items)?;
+        support.record(&STATUS_TAGS, &all_items);
+        support.record(&CHARGE_LIMIT_TAGS, &all_items);
+
+        if let Some(battery) = batteries.first() {
+            let mut frame = Frame::new();
+            frame.push_item(Self::battery_data_item(battery.index, &support));
+            let response = send_request(client, &frame)?;
+            let all_items = any_to_items(&response.items)?;
+            let bat_data_items = get_items(&all_items, BAT::DATA.into())?;
+            support.record(&BATTERY_TAGS, &bat_data_items);
+        }
+        Ok(support)
+    }
+
+    /// Tags found unsupported at startup
+    pub fn tag_support(&self) -> &TagSupport {
+        &self.tags
+    }
+
     /// Get comprehensive battery data for specific battery index
     /// Queries all available battery parameters in one request
     /// Matches Python implementation with all fields
     fn get_battery_data_idx(&mut self, battery: &BatteryInfo) -> Result<BatteryData, E3dcError> {
+        self.traced("get_battery_data_idx", |client| {
+            client.query_battery_data_idx(battery)
+        })
+    }
+
+    fn query_battery_data_idx(&mut self, battery: &BatteryInfo) -> Result<BatteryData, E3dcError> {
         let mut frame = Frame::new();
 
-        // Request comprehensive battery data with ALL fields from Python implementation
-        frame.push_item(Item::new(
-            BAT::DATA.into(),
-            vec![
-                Item {
-                    tag: BAT::INDEX.into(),
-                    data: Some(Box::new(battery.index)),
-                },
-                // State of Charge
-                empty_item(BAT::RSOC.into()),
-                empty_item(BAT::RSOC_REAL.into()),
-                empty_item(BAT::ASOC.into()),
-                // Electrical measurements
-                empty_item(BAT::CURRENT.into()),
-                empty_item(BAT::MODULE_VOLTAGE.into()),
-                empty_item(BAT::TERMINAL_VOLTAGE.into()),
-                empty_item(BAT::MAX_BAT_VOLTAGE.into()),
-                empty_item(BAT::EOD_VOLTAGE.into()),
-                // Capacity
-                empty_item(BAT::FCC.into()),
-                empty_item(BAT::RC.into()),
-                empty_item(BAT::DESIGN_CAPACITY.into()),
-                empty_item(BAT::USABLE_CAPACITY.into()),
-                empty_item(BAT::USABLE_REMAINING_CAPACITY.into()),
-                // Current limits
-                empty_item(BAT::MAX_CHARGE_CURRENT.into()),
-                empty_item(BAT::MAX_DISCHARGE_CURRENT.into()),
-                // Temperature
-                empty_item(BAT::MAX_DCB_CELL_TEMPERATURE.into()),
-                empty_item(BAT::MIN_DCB_CELL_TEMPERATURE.into()),
-                // Status and errors
-                empty_item(BAT::STATUS_CODE.into()),
-                empty_item(BAT::ERROR_CODE.into()),
-                // Cycles and usage
-                empty_item(BAT::CHARGE_CYCLES.into()),
-                empty_item(BAT::TOTAL_USE_TIME.into()),
-                empty_item(BAT::TOTAL_DISCHARGE_TIME.into()),
-                // DCB info
-                empty_item(BAT::DCB_COUNT.into()),
-                // Operational state
-                empty_item(BAT::READY_FOR_SHUTDOWN.into()),
-                empty_item(BAT::TRAINING_MODE.into()),
-            ],
-        ));
+        // Request comprehensive battery data with all supported fields
+        frame.push_item(Self::battery_data_item(battery.index, &self.tags));
 
-        let response = self.send_request(frame)?;
+        let response = self.send_request_as(QueryClass::Battery, frame)?;
         let all_items = any_to_items(&response.items)?;
 
         // Find BAT::DATA container
         let bat_data_items = get_items(&all_items, BAT::DATA.into())?;
 
-        // Build comprehensive battery data response
-        Ok(BatteryData {
+        // DCB info - use the count from startup, not from the query (which returns 0)
+        let dcbs = if self.poll_dcbs {
+            (0..battery.dcb_count)
+                .map(|idx| self.get_dcb_data(battery.index, idx))
+                .collect::<Result<Vec<_>, _>>()?
+        } else {
+            Vec::new()
+        };
+        let last = self.last_batteries.get(&battery.index);
+        let mut fields = FieldReader::new(&bat_data_items, &self.tags);
+
+        // Build comprehensive battery data response, missing values keep the previous value
+        let mut data = BatteryData {
             index: battery.index,
             time_stamp: response.time_stamp,
             // State of Charge
-            rsoc: get_number(&bat_data_items, BAT::RSOC.into())?,
-            rsoc_real: get_number(&bat_data_items, BAT::RSOC_REAL.into())?,
-            asoc: get_number(&bat_data_items, BAT::ASOC.into())?,
+            rsoc: fields.get(BAT::RSOC.into(), last.and_then(|b| b.rsoc)),
+            rsoc_real: fields.get(BAT::RSOC_REAL.into(), last.and_then(|b| b.rsoc_real)),
+            asoc: fields.get(BAT::ASOC.into(), last.and_then(|b| b.asoc)),
             // Electrical measurements
-            current: get_number(&bat_data_items, BAT::CURRENT.into())?,
-            module_voltage: get_number(&bat_data_items, BAT::MODULE_VOLTAGE.into())?,
-            terminal_voltage: get_number(&bat_data_items, BAT::TERMINAL_VOLTAGE.into())?,
-            max_bat_voltage: get_number(&bat_data_items, BAT::MAX_BAT_VOLTAGE.into())?,
-            eod_voltage: get_number(&bat_data_items, BAT::EOD_VOLTAGE.into())?,
+            current: fields.get(BAT::CURRENT.into(), last.and_then(|b| b.current)),
+            module_voltage: fields.get(
+                BAT::MODULE_VOLTAGE.into(),
+                last.and_then(|b| b.module_voltage),
+            ),
+            terminal_voltage: fields.get(
+                BAT::TERMINAL_VOLTAGE.into(),
+                last.and_then(|b| b.terminal_voltage),
+            ),
+            max_bat_voltage: fields.get(
+                BAT::MAX_BAT_VOLTAGE.into(),
+                last.and_then(|b| b.max_bat_voltage),
+            ),
+            eod_voltage: fields.get(BAT::EOD_VOLTAGE.into(), last.and_then(|b| b.eod_voltage)),
             // Capacity
-            fcc: get_number(&bat_data_items, BAT::FCC.into())?,
-            rc: get_number(&bat_data_items, BAT::RC.into())?,
-            design_capacity: get_number(&bat_data_items, BAT::DESIGN_CAPACITY.into())?,
-            usable_capacity: get_number(&bat_data_items, BAT::USABLE_CAPACITY.into())?,
-            usable_remaining_capacity: get_number(
-                &bat_data_items,
+            fcc: fields.get(BAT::FCC.into(), last.and_then(|b| b.fcc)),
+            rc: fields.get(BAT::RC.into(), last.and_then(|b| b.rc)),
+            design_capacity: fields.get(
+                BAT::DESIGN_CAPACITY.into(),
+                last.and_then(|b| b.design_capacity),
+            ),
+            usable_capacity: fields.get(
+                BAT::USABLE_CAPACITY.into(),
+                last.and_then(|b| b.usable_capacity),
+            ),
+            usable_remaining_capacity: fields.get(
                 BAT::USABLE_REMAINING_CAPACITY.into(),
-            )?,
+                last.and_then(|b| b.usable_remaining_capacity),
+            ),
             // Current limits
-            max_charge_current: get_number(&bat_data_items, BAT::MAX_CHARGE_CURRENT.into())?,
-            max_discharge_current: get_number(&bat_data_items, BAT::MAX_DISCHARGE_CURRENT.into())?,
+            max_charge_current: fields.get(
+                BAT::MAX_CHARGE_CURRENT.into(),
+                last.and_then(|b| b.max_charge_current),
+            ),
+            max_discharge_current: fields.get(
+                BAT::MAX_DISCHARGE_CURRENT.into(),
+                last.and_then(|b| b.max_discharge_current),
+            ),
             // Temperature
-            max_dcb_cell_temp: get_number(&bat_data_items, BAT::MAX_DCB_CELL_TEMPERATURE.into())?,
-            min_dcb_cell_temp: get_number(&bat_data_items, BAT::MIN_DCB_CELL_TEMPERATURE.into())?,
+            max_dcb_cell_temp: fields.get(
+                BAT::MAX_DCB_CELL_TEMPERATURE.into(),
+                last.and_then(|b| b.max_dcb_cell_temp),
+            ),
+            min_dcb_cell_temp: fields.get(
+                BAT::MIN_DCB_CELL_TEMPERATURE.into(),
+                last.and_then(|b| b.min_dcb_cell_temp),
+            ),
             // Status and errors
-            status_code: get_number(&bat_data_items, BAT::STATUS_CODE.into())?,
-            error_code: get_number(&bat_data_items, BAT::ERROR_CODE.into())?,
+            status_code: fields.get(BAT::STATUS_CODE.into(), last.and_then(|b| b.status_code)),
+            error_code: fields.get(BAT::ERROR_CODE.into(), last.and_then(|b| b.error_code)),
             // Cycles and usage
-            charge_cycles: get_number(&bat_data_items, BAT::CHARGE_CYCLES.into())?,
-            total_use_time: get_integer(&bat_data_items, BAT::TOTAL_USE_TIME.into())?,
-            total_discharge_time: get_integer(&bat_data_items, BAT::TOTAL_DISCHARGE_TIME.into())?,
+            charge_cycles: fields.get(
+                BAT::CHARGE_CYCLES.into(),
+                last.and_then(|b| b.charge_cycles),
+            ),
+            total_use_time: fields.get(
+                BAT::TOTAL_USE_TIME.into(),
+                last.and_then(|b| b.total_use_time),
+            ),
+            total_discharge_time: fields.get(
+                BAT::TOTAL_DISCHARGE_TIME.into(),
+                last.and_then(|b| b.total_discharge_time),
+            ),
             // Device info
             device_name: battery.device_name.clone(),
-            // DCB info - use the count from startup, not from the query (which returns 0)
             dcb_count: battery.dcb_count,
-            dcbs: (0..battery.dcb_count)
-                .map(|idx| self.get_dcb_data(battery.index, idx))
-                .collect::<Result<Vec<_>, _>>()?,
+            dcbs,
             // Operational state
-            ready_for_shutdown: get_bool(&bat_data_items, BAT::READY_FOR_SHUTDOWN.into())?,
-            training_mode: get_bool(&bat_data_items, BAT::TRAINING_MODE.into())?,
+            ready_for_shutdown: fields.get(
+                BAT::READY_FOR_SHUTDOWN.into(),
+                last.and_then(|b| b.ready_for_shutdown),
+            ),
+            training_mode: fields.get(
+                BAT::TRAINING_MODE.into(),
+                last.and_then(|b| b.training_mode),
+            ),
             param_bat_number: battery.param_bat_number,
             instance_descriptor: battery.instance_descriptor.clone(),
             manufacturer_name: battery.manufacturer_name.clone(),
             serialno: battery.serialno,
-        })
+            missing: Vec::new(),
+        };
+        data.missing = fields.into_missing();
+        if !data.missing.is_empty() && last.is_none_or(|b| b.missing != data.missing) {
+            warn!(
+                "Battery {} response misses {}",
+                battery.index,
+                tag_list(data.missing.iter().copied())
+            );
+        }
+        self.last_batteries.insert(battery.index, data.clone());
+        Ok(data)
     }
 
     /// Extract cell data (temperatures or voltages) from a nested DCB container
@@ -695,7 +1609,7 @@ impl E3dcClient {
                     .data
                     .as_ref()
                     .ok_or_else(|| E3dcError::MissingData(item.tag))?;
-                any_to_f64(data)
+                any_to::<f64>(data)
             })
             .collect()
     }
@@ -710,6 +1624,12 @@ impl E3dcClient {
         battery_index: u64,
         dcb_index: u64,
     ) -> Result<DcbData, E3dcError> {
+        self.traced("get_dcb_data", |client| {
+            client.query_dcb_data(battery_index, dcb_index)
+        })
+    }
+
+    fn query_dcb_data(&mut self, battery_index: u64, dcb_index: u64) -> Result<DcbData, E3dcError> {
         let mut frame = Frame::new();
         frame.push_item(Item::new(
             BAT::DATA.into(),
@@ -734,7 +1654,7 @@ impl E3dcClient {
             ],
         ));
 
-        let response = self.send_request(frame)?;
+        let response = self.send_request_as(QueryClass::Battery, frame)?;
         let all_items = any_to_items(&response.items)?;
 
         // Find BAT::DATA container
@@ -744,9 +1664,10 @@ impl E3dcClient {
         let dcb_info_items = get_items(&container_items, BAT::DCB_INFO.into())?;
 
         // Get counts
-        let sensor_count = get_integer(&dcb_info_items, BAT::DCB_NR_SENSOR.into())?;
-        let series_cell_count = get_integer(&dcb_info_items, BAT::DCB_NR_SERIES_CELL.into())?;
-        let parallel_cell_count = get_integer(&dcb_info_items, BAT::DCB_NR_PARALLEL_CELL.into())?;
+        let sensor_count = get_tag::<u64>(&dcb_info_items, BAT::DCB_NR_SENSOR.into())?;
+        let series_cell_count = get_tag::<u64>(&dcb_info_items, BAT::DCB_NR_SERIES_CELL.into())?;
+        let parallel_cell_count =
+            get_tag::<u64>(&dcb_info_items, BAT::DCB_NR_PARALLEL_CELL.into())?;
 
         // Extract temperatures
         let all_temps_vec = get_items(&container_items, BAT::DCB_ALL_CELL_TEMPERATURES.into())?;
@@ -786,51 +1707,60 @@ impl E3dcClient {
         Ok(DcbData {
             index: dcb_index,
             // Current measurements
-            current: get_number(&dcb_info_items, BAT::DCB_CURRENT.into())?,
-            current_avg_30s: get_number(&dcb_info_items, BAT::DCB_CURRENT_AVG_30S.into())?,
-            voltage: get_number(&dcb_info_items, BAT::DCB_VOLTAGE.into())?,
-            voltage_avg_30s: get_number(&dcb_info_items, BAT::DCB_VOLTAGE_AVG_30S.into())?,
+            current: get_tag::<f64>(&dcb_info_items, BAT::DCB_CURRENT.into())?,
+            current_avg_30s: get_tag::<f64>(&dcb_info_items, BAT::DCB_CURRENT_AVG_30S.into())?,
+            voltage: get_tag::<f64>(&dcb_info_items, BAT::DCB_VOLTAGE.into())?,
+            voltage_avg_30s: get_tag::<f64>(&dcb_info_items, BAT::DCB_VOLTAGE_AVG_30S.into())?,
             // State
-            soc: get_number(&dcb_info_items, BAT::DCB_SOC.into())?,
-            soh: get_number(&dcb_info_items, BAT::DCB_SOH.into())?,
-            cycle_count: get_number(&dcb_info_items, BAT::DCB_CYCLE_COUNT.into())?,
+            soc: get_tag::<f64>(&dcb_info_items, BAT::DCB_SOC.into())?,
+            soh: get_tag::<f64>(&dcb_info_items, BAT::DCB_SOH.into())?,
+            cycle_count: get_tag::<f64>(&dcb_info_items, BAT::DCB_CYCLE_COUNT.into())?,
             // Capacity
-            design_capacity: get_number(&dcb_info_items, BAT::DCB_DESIGN_CAPACITY.into())?,
-            design_voltage: get_number(&dcb_info_items, BAT::DCB_DESIGN_VOLTAGE.into())?,
-            full_charge_capacity: get_number(
+            design_capacity: get_tag::<f64>(&dcb_info_items, BAT::DCB_DESIGN_CAPACITY.into())?,
+            design_voltage: get_tag::<f64>(&dcb_info_items, BAT::DCB_DESIGN_VOLTAGE.into())?,
+            full_charge_capacity: get_tag::<f64>(
                 &dcb_info_items,
                 BAT::DCB_FULL_CHARGE_CAPACITY.into(),
             )?,
-            remaining_capacity: get_number(&dcb_info_items, BAT::DCB_REMAINING_CAPACITY.into())?,
+            remaining_capacity: get_tag::<f64>(
+                &dcb_info_items,
+                BAT::DCB_REMAINING_CAPACITY.into(),
+            )?,
             // Limits
-            max_charge_voltage: get_number(&dcb_info_items, BAT::DCB_MAX_CHARGE_VOLTAGE.into())?,
-            max_charge_current: get_number(&dcb_info_items, BAT::DCB_MAX_CHARGE_CURRENT.into())?,
-            max_discharge_current: get_number(
+            max_charge_voltage: get_tag::<f64>(
+                &dcb_info_items,
+                BAT::DCB_MAX_CHARGE_VOLTAGE.into(),
+            )?,
+            max_charge_current: get_tag::<f64>(
+                &dcb_info_items,
+                BAT::DCB_MAX_CHARGE_CURRENT.into(),
+            )?,
+            max_discharge_current: get_tag::<f64>(
                 &dcb_info_items,
                 BAT::DCB_MAX_DISCHARGE_CURRENT.into(),
             )?,
-            end_of_discharge: get_number(&dcb_info_items, BAT::DCB_END_OF_DISCHARGE.into())?,
-            max_charge_temperature: get_number(
+            end_of_discharge: get_tag::<f64>(&dcb_info_items, BAT::DCB_END_OF_DISCHARGE.into())?,
+            max_charge_temperature: get_tag::<f64>(
                 &dcb_info_items,
                 BAT::DCB_CHARGE_HIGH_TEMPERATURE.into(),
             )?,
-            min_charge_temperature: get_number(
+            min_charge_temperature: get_tag::<f64>(
                 &dcb_info_items,
                 BAT::DCB_CHARGE_LOW_TEMPERATURE.into(),
             )?,
             // Device info
-            device_name: get_string(&dcb_info_items, BAT::DCB_DEVICE_NAME.into())?,
-            manufacture_name: get_string(&dcb_info_items, BAT::DCB_MANUFACTURE_NAME.into())?,
-            manufacture_date: get_number(&dcb_info_items, BAT::DCB_MANUFACTURE_DATE.into())?,
-            serial_code: get_string(&dcb_info_items, BAT::DCB_SERIALCODE.into())?,
-            serial_no: get_number(&dcb_info_items, BAT::DCB_SERIALNO.into())?,
-            fw_version: get_number(&dcb_info_items, BAT::DCB_FW_VERSION.into())?,
-            pcb_version: get_number(&dcb_info_items, BAT::DCB_PCB_VERSION.into())?,
-            protocol_version: get_number(&dcb_info_items, BAT::DCB_PROTOCOL_VERSION.into())?,
+            device_name: get_tag::<String>(&dcb_info_items, BAT::DCB_DEVICE_NAME.into())?,
+            manufacture_name: get_tag::<String>(&dcb_info_items, BAT::DCB_MANUFACTURE_NAME.into())?,
+            manufacture_date: get_tag::<f64>(&dcb_info_items, BAT::DCB_MANUFACTURE_DATE.into())?,
+            serial_code: get_tag::<String>(&dcb_info_items, BAT::DCB_SERIALCODE.into())?,
+            serial_no: get_tag::<f64>(&dcb_info_items, BAT::DCB_SERIALNO.into())?,
+            fw_version: get_tag::<f64>(&dcb_info_items, BAT::DCB_FW_VERSION.into())?,
+            pcb_version: get_tag::<f64>(&dcb_info_items, BAT::DCB_PCB_VERSION.into())?,
+            protocol_version: get_tag::<f64>(&dcb_info_items, BAT::DCB_PROTOCOL_VERSION.into())?,
             // Status
-            error: get_number(&dcb_info_items, BAT::DCB_ERROR.into())?,
-            warning: get_number(&dcb_info_items, BAT::DCB_WARNING.into())?,
-            status: get_number(&dcb_info_items, BAT::DCB_STATUS.into())?,
+            error: get_tag::<f64>(&dcb_info_items, BAT::DCB_ERROR.into())?,
+            warning: get_tag::<f64>(&dcb_info_items, BAT::DCB_WARNING.into())?,
+            status: get_tag::<f64>(&dcb_info_items, BAT::DCB_STATUS.into())?,
             // Cell configuration
             series_cell_count: actual_series_cell_count,
             parallel_cell_count,
@@ -841,23 +1771,95 @@ impl E3dcClient {
         })
     }
 
-    /// Get daily statistics for today
+    /// Blocking iterator yielding a [`Status`] every `interval`
+    ///
+    /// Polls are aligned to multiples of the interval, like the bridge's main loop.
+    pub fn status_stream(&mut self, interval: Duration) -> StatusStream<'_> {
+        StatusStream::new(self, interval)
+    }
+
+    /// Poll status, daily statistics and battery data in one go
+    ///
+    /// Runs the same requests in the same order as the bridge's main loop, so
+    /// embedders get a consistent view without replicating the scheduling.
+    /// `stat_interval` is passed on to [`Self::get_daily_statistics`].
+    pub fn poll_snapshot(&mut self, stat_interval: Duration) -> Result<Snapshot, E3dcError> {
+        let status = self.get_status()?;
+        let statistics = self.get_daily_statistics(stat_interval)?;
+        let batteries = self.get_battery_data()?;
+
+        Ok(Snapshot {
+            time_stamp: status.time_stamp,
+            status,
+            statistics,
+            batteries,
+        })
+    }
+
+    /// Get daily statistics for today (UTC)
     pub fn get_daily_statistics(
         &mut self,
         stat_interval: Duration,
     ) -> Result<DailyStatistics, E3dcError> {
-        // Get start of today (midnight) in UTC
-        let now = Utc::now();
+        self.get_window_statistics(StatisticsWindow::UtcDay, stat_interval)
+    }
 
-        let timespan = Duration::seconds(now.num_seconds_from_midnight().into());
-        let start = now - timespan;
+    /// Get the statistics of a window ending now
+    ///
+    /// `stat_interval` is passed on to [`StatisticsWindow::bounds`].
+    pub fn get_window_statistics(
+        &mut self,
+        window: StatisticsWindow,
+        stat_interval: Duration,
+    ) -> Result<DailyStatistics, E3dcError> {
+        let (start, timespan) = window.bounds(Utc::now(), stat_interval);
+        self.get_db_data_timestamp(start, timespan)
+    }
 
-        if timespan <= stat_interval {
-            let yesterday = start - Duration::days(1);
-            self.get_db_data_timestamp(yesterday, Duration::hours(1))
-        } else {
-            self.get_db_data_timestamp(start, timespan)
-        }
+    /// Get the statistics of the last `days` complete days (UTC), oldest first
+    ///
+    /// Each day is a separate DB request.
+    pub fn get_past_days(&mut self, days: u32) -> Result<Vec<DailyStatistics>, E3dcError> {
+        let now = Utc::now();
+        let today = now - Duration::seconds(now.num_seconds_from_midnight().into());
+        (1..=i64::from(days))
+            .rev()
+            .map(|day| self.get_db_data_timestamp(today - Duration::days(day), Duration::days(1)))
+            .collect()
+    }
+
+    /// Get the sums of every `slot` of today (UTC) so far, e.g. 15 minutes
+    ///
+    /// The DB history answers with one value container per slot besides the
+    /// sum of the whole span; slots without data are left out.
+    pub fn get_day_curve(&mut self, slot: Duration) -> Result<DayCurve, E3dcError> {
+        let now = Utc::now();
+        let start = now - Duration::seconds(now.num_seconds_from_midnight().into());
+        let timespan = (now - start).max(slot);
+        self.traced("get_day_curve", |client| {
+            let frame = Self::db_history_frame(DB::HISTORY_DATA_DAY.into(), start, slot, timespan)?;
+            let response = client.send_request_as(QueryClass::Statistics, frame)?;
+            let all_items = any_to_items(&response.items)?;
+            let history_container = get_items(&all_items, DB::HISTORY_DATA_DAY.into())?;
+            let slots = history_container
+                .iter()
+                .filter(|item| item.tag == u32::from(DB::VALUE_CONTAINER))
+                .map(|item| {
+                    let values = any_to_items(&item.data)?;
+                    Ok(CurveSlot {
+                        index: get_tag::<f64>(&values, DB::GRAPH_INDEX.into())? as u64,
+                        solar_production: get_tag::<f64>(&values, DB::DC_POWER.into())?,
+                        consumption: get_tag::<f64>(&values, DB::CONSUMPTION.into())?,
+                    })
+                })
+                .collect::<Result<Vec<_>, E3dcError>>()?;
+            Ok(DayCurve {
+                time_stamp: response.time_stamp,
+                start,
+                slot,
+                slots,
+            })
+        })
     }
 
     /// Get database statistics for a specific timespan
@@ -866,9 +1868,61 @@ impl E3dcClient {
         start: DateTime<Utc>,
         timespan: Duration,
     ) -> Result<DailyStatistics, E3dcError> {
+        self.get_db_history(DB::HISTORY_DATA_DAY.into(), start, timespan)
+    }
+
+    /// Get the statistics of one calendar year (UTC) from the yearly DB history
+    pub fn get_db_data_year(&mut self, year: i32) -> Result<DailyStatistics, E3dcError> {
+        let start = Utc
+            .with_ymd_and_hms(year, 1, 1, 0, 0, 0)
+            .single()
+            .ok_or_else(|| E3dcError::ParseError(format!("Invalid year: {}", year)))?;
+        let end = Utc
+            .with_ymd_and_hms(year + 1, 1, 1, 0, 0, 0)
+            .single()
+            .ok_or_else(|| E3dcError::ParseError(format!("Invalid year: {}", year + 1)))?;
+        self.get_db_history(DB::HISTORY_DATA_YEAR.into(), start, end - start)
+    }
+
+    /// Sum of all years with data in the yearly DB history
+    ///
+    /// Walks back from the current year until a year without production and
+    /// consumption is found, one request per year.
+    pub fn get_lifetime_totals(&mut self) -> Result<DailyStatistics, E3dcError> {
+        let current_year = Utc::now().year();
+        let mut total = self.get_db_data_year(current_year)?;
+        for year in (FIRST_HISTORY_YEAR..current_year).rev() {
+            let stats = self.get_db_data_year(year)?;
+            if stats.solar_production == 0.0 && stats.consumption == 0.0 {
+                break;
+            }
+            total = stats.combine(&total);
+        }
+        Ok(total)
+    }
+
+    /// Query one DB history container (`DB::HISTORY_DATA_*`) as a single sum
+    fn get_db_history(
+        &mut self,
+        container: u32,
+        start: DateTime<Utc>,
+        timespan: Duration,
+    ) -> Result<DailyStatistics, E3dcError> {
+        self.traced("get_db_history", |client| {
+            client.query_db_history(container, start, timespan)
+        })
+    }
+
+    /// DB history request for `timespan` from `start`, one value container per `interval`
+    fn db_history_frame(
+        container: u32,
+        start: DateTime<Utc>,
+        interval: Duration,
+        timespan: Duration,
+    ) -> Result<Frame, E3dcError> {
         let mut frame = Frame::new();
 
-        // Create DB_REQ_HISTORY_DATA_DAY container with time parameters
+        // Create DB_REQ_HISTORY_DATA_* container with time parameters
         let time_params = vec![
             Item {
                 tag: DB::HISTORY_TIME_START.into(),
@@ -878,7 +1932,7 @@ impl E3dcClient {
             },
             Item {
                 tag: DB::HISTORY_TIME_INTERVAL.into(),
-                data: Some(Box::new(timespan.num_seconds())),
+                data: Some(Box::new(interval.num_seconds())),
             },
             Item {
                 tag: DB::HISTORY_TIME_SPAN.into(),
@@ -887,18 +1941,27 @@ impl E3dcClient {
         ];
 
         frame.push_item(Item {
-            tag: DB::HISTORY_DATA_DAY.into(),
+            tag: container,
             data: Some(Box::new(time_params)),
         });
+        Ok(frame)
+    }
 
-        let response = self.client.send_receive_frame(&frame)?;
+    fn query_db_history(
+        &mut self,
+        container: u32,
+        start: DateTime<Utc>,
+        timespan: Duration,
+    ) -> Result<DailyStatistics, E3dcError> {
+        let frame = Self::db_history_frame(container, start, timespan, timespan)?;
+        let response = self.send_request_as(QueryClass::Statistics, frame)?;
 
         let time_stamp = response.time_stamp;
         let all_items = any_to_items(&response.items)?;
         // Extract SUM_CONTAINER from response
 
-        // Find HISTORY_DATA_DAY response container
-        let history_container = get_items(&all_items, DB::HISTORY_DATA_DAY.into())?;
+        // Find HISTORY_DATA_* response container
+        let history_container = get_items(&all_items, container)?;
 
         // Find SUM_CONTAINER within history data
         let sum_container = get_items(&history_container, DB::SUM_CONTAINER.into())?;
@@ -907,15 +1970,15 @@ impl E3dcClient {
 
         Ok(DailyStatistics {
             time_stamp,
-            autarky: get_number(&sum_container, DB::AUTARKY.into())?,
-            consumed_production: get_number(&sum_container, DB::CONSUMED_PRODUCTION.into())?,
-            solar_production: get_number(&sum_container, DB::DC_POWER.into())?,
-            consumption: get_number(&sum_container, DB::CONSUMPTION.into())?,
-            bat_power_in: get_number(&sum_container, DB::BAT_POWER_IN.into())?,
-            bat_power_out: get_number(&sum_container, DB::BAT_POWER_OUT.into())?,
-            grid_power_in: get_number(&sum_container, DB::GRID_POWER_IN.into())?,
-            grid_power_out: get_number(&sum_container, DB::GRID_POWER_OUT.into())?,
-            state_of_charge: get_number(&sum_container, DB::BAT_CHARGE_LEVEL.into())?,
+            autarky: get_tag::<f64>(&sum_container, DB::AUTARKY.into())?,
+            consumed_production: get_tag::<f64>(&sum_container, DB::CONSUMED_PRODUCTION.into())?,
+            solar_production: get_tag::<f64>(&sum_container, DB::DC_POWER.into())?,
+            consumption: get_tag::<f64>(&sum_container, DB::CONSUMPTION.into())?,
+            bat_power_in: get_tag::<f64>(&sum_container, DB::BAT_POWER_IN.into())?,
+            bat_power_out: get_tag::<f64>(&sum_container, DB::BAT_POWER_OUT.into())?,
+            grid_power_in: get_tag::<f64>(&sum_container, DB::GRID_POWER_IN.into())?,
+            grid_power_out: get_tag::<f64>(&sum_container, DB::GRID_POWER_OUT.into())?,
+            state_of_charge: get_tag::<f64>(&sum_container, DB::BAT_CHARGE_LEVEL.into())?,
             start,
             timespan,
         })
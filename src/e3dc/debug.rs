@@ -0,0 +1,182 @@
+//! RSCP request/response debug log
+//!
+//! [`DebugLog`] is a [`FrameObserver`] that renders every request and response
+//! as an item tree, e.g. to diagnose firmware incompatibilities remotely:
+//!
+//! ```text
+//! -> Status request
+//!   EMS::POWER_PV (0x01000001)
+//! <- Status response (23 ms)
+//!   EMS::POWER_PV (0x01000001) = Int(2315)
+//! ```
+//!
+//! Lines go to the `e3dc_mqtt_rs::rscp` log target and, optionally, to a sink
+//! such as the `bridge/debug` MQTT topic.
+
+use std::time::Duration;
+
+use rscp::{Frame, Item};
+use tracing::debug;
+
+use super::items::Value;
+use super::observer::FrameObserver;
+use super::retry::QueryClass;
+use super::tags::{tag_name, tag_namespace, TagName};
+use crate::errors::E3dcError;
+
+/// Receives each rendered request/response
+pub type DebugSink = Box<dyn Fn(String) + Send>;
+
+/// Tag filter: namespaces (`BAT`), tag names (`EMS::POWER_PV`) or hex ids
+/// (`0x01800001`); empty matches every tag
+#[derive(Debug, Clone, Default)]
+pub struct TagFilter(Vec<String>);
+
+impl TagFilter {
+    pub fn new(patterns: Vec<String>) -> Self {
+        Self(patterns)
+    }
+
+    pub fn matches(&self, tag: u32) -> bool {
+        if self.0.is_empty() {
+            return true;
+        }
+        let hex = format!("0x{:08X}", tag);
+        self.0.iter().any(|pattern| {
+            pattern.eq_ignore_ascii_case(&hex)
+                || tag_namespace(tag).is_some_and(|ns| pattern == ns)
+                || tag_name(tag).is_some_and(|name| pattern == name)
+        })
+    }
+
+    /// Whether `item` or one of its children matches
+    fn matches_tree(&self, item: &Item) -> bool {
+        self.matches(item.tag)
+            || children(item).is_some_and(|c| c.iter().any(|i| self.matches_tree(i)))
+    }
+}
+
+fn children(item: &Item) -> Option<&Vec<Item>> {
+    item.data.as_ref()?.downcast_ref::<Vec<Item>>()
+}
+
+/// Render the top-level items of `frame` that match `filter`, one line per item
+pub fn render_frame(frame: &Frame, filter: &TagFilter) -> Vec<String> {
+    let mut lines = Vec::new();
+    if let Some(items) = frame
+        .items
+        .as_ref()
+        .and_then(|i| i.downcast_ref::<Vec<Item>>())
+    {
+        for item in items.iter().filter(|item| filter.matches_tree(item)) {
+            render_item(item, 1, &mut lines);
+        }
+    }
+    lines
+}
+
+fn render_item(item: &Item, depth: usize, lines: &mut Vec<String>) {
+    let indent = "  ".repeat(depth);
+    match (children(item), &item.data) {
+        (Some(children), _) => {
+            lines.push(format!("{}{}", indent, TagName(item.tag)));
+            for child in children {
+                render_item(child, depth + 1, lines);
+            }
+        }
+        (None, Some(data)) => match Value::from_any(data) {
+            Ok(value) => lines.push(format!("{}{} = {:?}", indent, TagName(item.tag), value)),
+            Err(_) => lines.push(format!(
+                "{}{} = <error or unknown type>",
+                indent,
+                TagName(item.tag)
+            )),
+        },
+        (None, None) => lines.push(format!("{}{}", indent, TagName(item.tag))),
+    }
+}
+
+/// Logs every request and response (debug level, target `e3dc_mqtt_rs::rscp`)
+pub struct DebugLog {
+    filter: TagFilter,
+    sink: Option<DebugSink>,
+}
+
+impl DebugLog {
+    pub fn new(filter: TagFilter) -> Self {
+        Self { filter, sink: None }
+    }
+
+    /// Also pass every rendered request/response to `sink`
+    pub fn with_sink(mut self, sink: DebugSink) -> Self {
+        self.sink = Some(sink);
+        self
+    }
+
+    fn emit(&self, header: String, lines: Vec<String>) {
+        // Frames without a matching item are filtered out entirely
+        if lines.is_empty() {
+            return;
+        }
+        let text = format!("{}\n{}", header, lines.join("\n"));
+        debug!(target: "e3dc_mqtt_rs::rscp", "{}", text);
+        if let Some(sink) = &self.sink {
+            sink(text);
+        }
+    }
+}
+
+impl FrameObserver for DebugLog {
+    fn on_request(&self, class: QueryClass, frame: &Frame) {
+        self.emit(
+            format!("-> {:?} request", class),
+            render_frame(frame, &self.filter),
+        );
+    }
+
+    fn on_response(
+        &self,
+        class: QueryClass,
+        result: Result<&Frame, &E3dcError>,
+        elapsed: Duration,
+    ) {
+        let header = format!("<- {:?} response ({} ms)", class, elapsed.as_millis());
+        match result {
+            Ok(frame) => self.emit(header, render_frame(frame, &self.filter)),
+            Err(e) => self.emit(header, vec![format!("  {}", e)]),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rscp::tags::{BAT, EMS};
+
+    #[test]
+    fn test_render_filtered() {
+        let mut frame = Frame::new();
+        frame.push_item(Item {
+            tag: EMS::POWER_PV.into(),
+            data: Some(Box::new(2315_i32)),
+        });
+        frame.push_item(Item::new(
+            BAT::DATA.into(),
+            vec![Item {
+                tag: BAT::RSOC.into(),
+                data: Some(Box::new(55.5_f32)),
+            }],
+        ));
+
+        let all = render_frame(&frame, &TagFilter::default());
+        assert_eq!(all.len(), 3);
+        assert!(all[0].starts_with("  EMS::POWER_PV"));
+        assert!(all[0].ends_with("= Int(2315)"));
+
+        // A matching child keeps its container
+        let bat = render_frame(&frame, &TagFilter::new(vec!["BAT::RSOC".to_string()]));
+        assert_eq!(bat.len(), 2);
+        assert!(bat[0].starts_with("  BAT::DATA"));
+        assert!(bat[1].starts_with("    BAT::RSOC"));
+    }
+}
@@ -0,0 +1,322 @@
+//! Typed access to RSCP items
+//!
+//! RSCP values arrive as `Box<dyn Any>` holding whatever integer/float width the
+//! tag uses. [`Value`] normalizes them, and [`get_tag`] reads one tag as the type
+//! the caller needs:
+//!
+//! ```ignore
+//! let soc = get_tag::<f64>(&items, EMS::BAT_SOC.into())?;
+//! let serial = get_tag::<String>(&items, INFO::SERIAL_NUMBER.into())?;
+//! ```
+
+use std::any::Any;
+
+use rscp::{Frame, Item};
+use serde::{Deserialize, Serialize};
+
+use crate::errors::E3dcError;
+
+/// Typed RSCP item value
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Value {
+    Bool(bool),
+    Int(i64),
+    UInt(u64),
+    Float32(f32),
+    Float64(f64),
+    String(String),
+}
+
+impl Value {
+    /// Normalize the raw data of an item
+    pub fn from_any(value: &Box<dyn Any>) -> Result<Self, E3dcError> {
+        if let Some(&v) = value.downcast_ref::<bool>() {
+            return Ok(Value::Bool(v));
+        }
+        if let Some(&v) = value.downcast_ref::<i8>() {
+            return Ok(Value::Int(v.into()));
+        }
+        if let Some(&v) = value.downcast_ref::<u8>() {
+            return Ok(Value::UInt(v.into()));
+        }
+        if let Some(&v) = value.downcast_ref::<i16>() {
+            return Ok(Value::Int(v.into()));
+        }
+        if let Some(&v) = value.downcast_ref::<u16>() {
+            return Ok(Value::UInt(v.into()));
+        }
+        if let Some(&v) = value.downcast_ref::<i32>() {
+            return Ok(Value::Int(v.into()));
+        }
+        if let Some(&v) = value.downcast_ref::<u32>() {
+            return Ok(Value::UInt(v.into()));
+        }
+        if let Some(&v) = value.downcast_ref::<i64>() {
+            return Ok(Value::Int(v));
+        }
+        if let Some(&v) = value.downcast_ref::<u64>() {
+            return Ok(Value::UInt(v));
+        }
+        if let Some(&v) = value.downcast_ref::<f32>() {
+            return Ok(Value::Float32(v));
+        }
+        if let Some(&v) = value.downcast_ref::<f64>() {
+            return Ok(Value::Float64(v));
+        }
+        if let Some(v) = value.downcast_ref::<String>() {
+            return Ok(Value::String(v.clone()));
+        }
+        Err(E3dcError::Type(format!(
+            "Unsupported item value {:?}",
+            (**value).type_id()
+        )))
+    }
+}
+
+/// Conversion from a [`Value`] into the type a query needs
+pub trait FromValue: Sized {
+    fn from_value(value: Value) -> Result<Self, E3dcError>;
+}
+
+fn cannot_convert<T>(value: &Value, target: &str) -> Result<T, E3dcError> {
+    Err(E3dcError::Type(format!(
+        "Cannot convert {:?} to {}",
+        value, target
+    )))
+}
+
+impl FromValue for f64 {
+    fn from_value(value: Value) -> Result<Self, E3dcError> {
+        match value {
+            Value::Bool(v) => Ok(if v { 1.0 } else { 0.0 }),
+            Value::Int(v) => Ok(v as f64),
+            Value::UInt(v) => Ok(v as f64),
+            Value::Float32(v) => Ok(v.into()),
+            Value::Float64(v) => Ok(v),
+            Value::String(_) => cannot_convert(&value, "f64"),
+        }
+    }
+}
+
+impl FromValue for u64 {
+    fn from_value(value: Value) -> Result<Self, E3dcError> {
+        match value {
+            Value::Bool(v) => Ok(v.into()),
+            Value::Int(v) => v.try_into().map_err(|_| {
+                E3dcError::Type(format!("Negative value {} cannot convert to u64", v))
+            }),
+            Value::UInt(v) => Ok(v),
+            Value::Float32(v) => Self::from_value(Value::Float64(v.into())),
+            Value::Float64(v) => {
+                if !v.is_finite() || v < 0.0 || v > u64::MAX as f64 {
+                    return Err(E3dcError::Type(format!("Invalid float {} for u64", v)));
+                }
+                Ok(v as u64)
+            }
+            Value::String(_) => cannot_convert(&value, "u64"),
+        }
+    }
+}
+
+impl FromValue for i64 {
+    fn from_value(value: Value) -> Result<Self, E3dcError> {
+        match value {
+            Value::Bool(v) => Ok(v.into()),
+            Value::Int(v) => Ok(v),
+            Value::UInt(v) => v
+                .try_into()
+                .map_err(|_| E3dcError::Type(format!("Value {} out of range for i64", v))),
+            Value::Float32(v) => Self::from_value(Value::Float64(v.into())),
+            Value::Float64(v) => {
+                if !v.is_finite() || v < i64::MIN as f64 || v > i64::MAX as f64 {
+                    return Err(E3dcError::Type(format!("Invalid float {} for i64", v)));
+                }
+                Ok(v as i64)
+            }
+            Value::String(_) => cannot_convert(&value, "i64"),
+        }
+    }
+}
+
+impl FromValue for bool {
+    fn from_value(value: Value) -> Result<Self, E3dcError> {
+        const EPSILON32: f32 = 1e-10;
+        const EPSILON64: f64 = 1e-10;
+
+        match value {
+            Value::Bool(v) => Ok(v),
+            Value::Int(v) => Ok(v != 0),
+            Value::UInt(v) => Ok(v != 0),
+            Value::Float32(v) => Ok(v.abs() >= EPSILON32),
+            Value::Float64(v) => Ok(v.abs() >= EPSILON64),
+            Value::String(_) => cannot_convert(&value, "bool"),
+        }
+    }
+}
+
+impl FromValue for String {
+    fn from_value(value: Value) -> Result<Self, E3dcError> {
+        Ok(match value {
+            Value::Bool(v) => v.to_string(),
+            Value::Int(v) => v.to_string(),
+            Value::UInt(v) => v.to_string(),
+            Value::Float32(v) => v.to_string(),
+            Value::Float64(v) => v.to_string(),
+            Value::String(v) => v,
+        })
+    }
+}
+
+/// Convert raw item data into `T`
+pub fn any_to<T: FromValue>(value: &Box<dyn Any>) -> Result<T, E3dcError> {
+    T::from_value(Value::from_any(value)?)
+}
+
+/// Child items of a container item (empty if the data is no container)
+pub fn any_to_items(data: &Option<Box<dyn Any>>) -> Result<Vec<&Item>, E3dcError> {
+    if let Some(value) = data {
+        return match value.downcast_ref::<Vec<Item>>() {
+            Some(v) => Ok(v.iter().collect()),
+            None => Ok(Vec::new()),
+        };
+    }
+    Ok(Vec::new())
+}
+
+/// Request item without data
+pub fn empty_item(tag: u32) -> Item {
+    Item { tag, data: None }
+}
+
+pub fn find_item<'a>(items: &'a [&'a Item], tag: u32) -> Result<&'a Item, E3dcError> {
+    items
+        .iter()
+        .find(|item| item.tag == tag)
+        .copied()
+        .ok_or(E3dcError::MissingTag(tag))
+}
+
+pub fn find_item_data<'a>(items: &'a [&'a Item], tag: u32) -> Result<&'a Box<dyn Any>, E3dcError> {
+    let item = items
+        .iter()
+        .find(|item| item.tag == tag)
+        .ok_or(E3dcError::MissingTag(tag))?;
+
+    item.data.as_ref().ok_or(E3dcError::MissingData(tag))
+}
+
+/// Child items of the container `tag`
+pub fn get_items<'a>(items: &'a [&'a Item], tag: u32) -> Result<Vec<&'a Item>, E3dcError> {
+    let item = find_item(items, tag)?;
+    any_to_items(&item.data)
+}
+
+/// Read the value of `tag` as `T`
+pub fn get_tag<T: FromValue>(items: &[&Item], tag: u32) -> Result<T, E3dcError> {
+    any_to(find_item_data(items, tag)?)
+}
+
+/// RSCP header of an item: tag, data type and length
+const ITEM_HEADER_BYTES: usize = 7;
+
+/// Frame header (magic, control, timestamp, length) and CRC32
+const FRAME_OVERHEAD_BYTES: usize = 22;
+
+/// Encoded size of item data, 0 for types without a known encoding
+fn data_size(data: &Box<dyn Any>) -> usize {
+    if let Some(children) = data.downcast_ref::<Vec<Item>>() {
+        return children.iter().map(item_size).sum();
+    }
+    if let Some(v) = data.downcast_ref::<String>() {
+        return v.len();
+    }
+    if let Some(v) = data.downcast_ref::<Vec<u8>>() {
+        return v.len();
+    }
+    if data.is::<bool>() || data.is::<i8>() || data.is::<u8>() {
+        1
+    } else if data.is::<i16>() || data.is::<u16>() {
+        2
+    } else if data.is::<i32>() || data.is::<u32>() || data.is::<f32>() {
+        4
+    } else if data.is::<i64>() || data.is::<u64>() || data.is::<f64>() {
+        8
+    } else {
+        0
+    }
+}
+
+fn item_size(item: &Item) -> usize {
+    ITEM_HEADER_BYTES + item.data.as_ref().map_or(0, data_size)
+}
+
+fn count_tags(item: &Item) -> usize {
+    1 + item
+        .data
+        .as_ref()
+        .and_then(|data| data.downcast_ref::<Vec<Item>>())
+        .map_or(0, |children| children.iter().map(count_tags).sum())
+}
+
+/// Number of tags (containers and their children) and the encoded size in
+/// bytes of a frame, computed from its items
+pub fn frame_size(frame: &Frame) -> (usize, usize) {
+    let items = frame
+        .items
+        .as_ref()
+        .and_then(|items| items.downcast_ref::<Vec<Item>>())
+        .map(Vec::as_slice)
+        .unwrap_or_default();
+    (
+        items.iter().map(count_tags).sum(),
+        FRAME_OVERHEAD_BYTES + items.iter().map(item_size).sum::<usize>(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(tag: u32, data: impl Any) -> Item {
+        Item {
+            tag,
+            data: Some(Box::new(data)),
+        }
+    }
+
+    #[test]
+    fn test_get_tag_conversions() {
+        let owned = [
+            item(1, 42_u16),
+            item(2, -5_i32),
+            item(3, 2.5_f32),
+            item(4, "S10".to_string()),
+            item(5, true),
+        ];
+        let items: Vec<&Item> = owned.iter().collect();
+
+        assert_eq!(get_tag::<u64>(&items, 1).unwrap(), 42);
+        assert_eq!(get_tag::<f64>(&items, 1).unwrap(), 42.0);
+        assert_eq!(get_tag::<i64>(&items, 2).unwrap(), -5);
+        assert!(get_tag::<u64>(&items, 2).is_err());
+        assert_eq!(get_tag::<f64>(&items, 3).unwrap(), 2.5);
+        assert_eq!(get_tag::<String>(&items, 3).unwrap(), "2.5");
+        assert_eq!(get_tag::<String>(&items, 4).unwrap(), "S10");
+        assert!(get_tag::<f64>(&items, 4).is_err());
+        assert!(get_tag::<bool>(&items, 5).unwrap());
+        assert!(matches!(
+            get_tag::<u64>(&items, 99),
+            Err(E3dcError::MissingTag(99))
+        ));
+    }
+
+    #[test]
+    fn test_frame_size() {
+        let mut frame = Frame::new();
+        frame.push_item(item(1, vec![item(2, 7_u16), item(3, "S10".to_string())]));
+        frame.push_item(item(4, 1.5_f64));
+        // 4 tags; container 7 + (7 + 2) + (7 + 3), float 7 + 8, frame 22
+        assert_eq!(frame_size(&frame), (4, 63));
+        assert_eq!(frame_size(&Frame::new()), (0, FRAME_OVERHEAD_BYTES));
+    }
+}
@@ -2,8 +2,30 @@
 //!
 //! Provides a high-level interface to query E3DC data via RSCP protocol.
 
+pub mod capabilities;
+pub mod capture;
 pub mod client;
+pub mod debug;
+pub mod items;
+pub mod model;
+pub mod observer;
+pub mod retry;
+pub mod shared;
+pub mod stream;
+pub mod tags;
+pub mod totals;
 pub mod types;
+pub mod windows;
+pub mod worker;
 
+pub use capabilities::TagSupport;
 pub use client::E3dcClient;
+pub use items::{get_tag, FromValue, Value};
+pub use observer::FrameObserver;
+pub use retry::{Degraded, QueryClass, Retry, RetryPolicy};
+pub use shared::SharedE3dcClient;
+pub use stream::{next_aligned, next_interval, StatusStream};
+pub use tags::TagName;
+pub use totals::{PeriodTotals, StatisticsPeriod};
 pub use types::*;
+pub use windows::StatisticsWindow;
@@ -0,0 +1,80 @@
+//! Model name from the serial number (`[e3dc] model` and `[e3dc.models]`)
+//!
+//! RSCP doesn't report the model, it follows from the leading digits of the
+//! serial number. E3DC adds serial ranges with new models, so the built-in
+//! table is extended by `[e3dc.models]` and overridden by `[e3dc] model`.
+//! An unknown range falls back to the raw serial prefix (e.g. `S10`), which
+//! keeps the device id stable once the range is added to the table.
+
+use std::collections::BTreeMap;
+
+/// Serial number prefixes of the known models
+const MODELS: &[(&str, &str)] = &[
+    ("4", "S10E"),
+    ("5", "S10_Mini"),
+    ("6", "Quattroporte"),
+    ("70", "S10E_Pro"),
+    ("72", "S10E"),
+    ("74", "S10E_Compact"),
+    ("75", "S10E_Pro_Compact"),
+    ("8", "S10X"),
+];
+
+/// Model of `serial_number`, the longest matching prefix of `models` and the
+/// built-in table wins (`models` on a tie)
+///
+/// `serial_prefix` is the part of the raw serial before the number, the
+/// fallback for unknown ranges.
+pub fn model_for_serial(
+    serial_number: &str,
+    serial_prefix: &str,
+    models: &BTreeMap<String, String>,
+) -> String {
+    let configured = models
+        .iter()
+        .map(|(prefix, model)| (prefix.as_str(), model.as_str()));
+    let mut best: Option<(&str, &str)> = None;
+    for (prefix, model) in configured.chain(MODELS.iter().copied()) {
+        if serial_number.starts_with(prefix) && best.is_none_or(|(p, _)| prefix.len() > p.len()) {
+            best = Some((prefix, model));
+        }
+    }
+    match best {
+        Some((_, model)) => model.to_string(),
+        None => {
+            let prefix = serial_prefix.trim_matches(|c: char| c == '-' || c.is_whitespace());
+            if prefix.is_empty() {
+                serial_number.chars().take(2).collect()
+            } else {
+                prefix.to_string()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_model_for_serial() {
+        let none = BTreeMap::new();
+        assert_eq!(model_for_serial("721234", "S10-", &none), "S10E");
+        assert_eq!(
+            model_for_serial("751234", "S10-", &none),
+            "S10E_Pro_Compact"
+        );
+        assert_eq!(model_for_serial("811234", "S10-", &none), "S10X");
+        // Unknown range: the raw prefix instead of "N/A"
+        assert_eq!(model_for_serial("911234", "S10-", &none), "S10");
+        assert_eq!(model_for_serial("911234", "", &none), "91");
+
+        let models = BTreeMap::from([
+            ("91".to_string(), "S10X_Pro".to_string()),
+            ("8".to_string(), "S10X_Compact".to_string()),
+        ]);
+        assert_eq!(model_for_serial("911234", "S10-", &models), "S10X_Pro");
+        assert_eq!(model_for_serial("811234", "S10-", &models), "S10X_Compact");
+        assert_eq!(model_for_serial("721234", "S10-", &models), "S10E");
+    }
+}
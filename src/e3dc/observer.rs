@@ -0,0 +1,30 @@
+//! Frame-level observability hooks
+//!
+//! Observers registered with [`E3dcClient::add_observer`] see every request
+//! frame and its result, e.g. for custom logging, metrics or caching.
+//! Requests sent while connecting (battery discovery, static system info) run
+//! before any observer can be registered.
+//!
+//! [`E3dcClient::add_observer`]: super::E3dcClient::add_observer
+
+use std::time::Duration;
+
+use rscp::Frame;
+
+use super::retry::QueryClass;
+use crate::errors::E3dcError;
+
+/// Callbacks for outgoing and incoming frames, all methods default to no-ops
+pub trait FrameObserver: Send {
+    /// Called before a frame is sent (once per attempt)
+    fn on_request(&self, _class: QueryClass, _frame: &Frame) {}
+
+    /// Called with the decoded response or the error of one attempt
+    fn on_response(
+        &self,
+        _class: QueryClass,
+        _result: Result<&Frame, &E3dcError>,
+        _elapsed: Duration,
+    ) {
+    }
+}
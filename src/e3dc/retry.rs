@@ -0,0 +1,291 @@
+//! Retry policy for RSCP requests
+//!
+//! Each query class gets its own number of attempts and backoff, so a single
+//! transient RSCP hiccup doesn't abort a whole statistics cycle.
+
+use serde::Deserialize;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+use crate::errors::E3dcError;
+
+/// Kind of request, selects the retry settings
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueryClass {
+    /// Real-time status (polled every interval)
+    Status,
+    /// Database statistics
+    Statistics,
+    /// Battery and DCB data
+    Battery,
+    /// Everything else (system info, settings)
+    Other,
+    /// Writes, never retried: a lost response doesn't mean the E3DC didn't act
+    Write,
+}
+
+/// Attempts and backoff for one query class
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub struct Retry {
+    /// Total attempts including the first one (1 = no retry)
+    #[serde(default = "default_attempts")]
+    pub attempts: u32,
+
+    /// Delay before the first retry, doubled for every further retry
+    #[serde(default = "default_backoff", with = "humantime_serde")]
+    pub backoff: Duration,
+}
+
+fn default_attempts() -> u32 {
+    3
+}
+
+fn default_backoff() -> Duration {
+    Duration::from_secs(1)
+}
+
+impl Retry {
+    pub const NONE: Retry = Retry {
+        attempts: 1,
+        backoff: Duration::ZERO,
+    };
+
+    /// Delay before attempt `attempt` (1-based, so attempt 2 is the first retry)
+    pub fn delay(&self, attempt: u32) -> Duration {
+        self.backoff
+            .saturating_mul(2_u32.saturating_pow(attempt.saturating_sub(2)))
+    }
+
+    /// Run `request` until it succeeds or the attempts are used up
+    pub fn run<T>(
+        &self,
+        class: QueryClass,
+        mut request: impl FnMut() -> Result<T, E3dcError>,
+    ) -> Result<T, E3dcError> {
+        let attempts = self.attempts.max(1);
+        let mut attempt = 1;
+        loop {
+            match request() {
+                Ok(value) => return Ok(value),
+                Err(e) if attempt < attempts => {
+                    attempt += 1;
+                    let delay = self.delay(attempt);
+                    warn!(
+                        "{:?} request failed ({}), retry {}/{} in {:?}",
+                        class,
+                        e,
+                        attempt - 1,
+                        attempts - 1,
+                        delay
+                    );
+                    std::thread::sleep(delay);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+impl Default for Retry {
+    fn default() -> Self {
+        Self {
+            attempts: default_attempts(),
+            backoff: default_backoff(),
+        }
+    }
+}
+
+/// Retry settings per query class
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub struct RetryPolicy {
+    #[serde(default = "default_status_retry")]
+    pub status: Retry,
+    #[serde(default)]
+    pub statistics: Retry,
+    #[serde(default)]
+    pub battery: Retry,
+    #[serde(default = "default_other_retry")]
+    pub other: Retry,
+}
+
+/// Status is polled every few seconds, one quick retry is enough
+fn default_status_retry() -> Retry {
+    Retry {
+        attempts: 2,
+        backoff: Duration::from_millis(500),
+    }
+}
+
+fn default_other_retry() -> Retry {
+    Retry::NONE
+}
+
+impl RetryPolicy {
+    pub fn for_class(&self, class: QueryClass) -> Retry {
+        match class {
+            QueryClass::Status => self.status,
+            QueryClass::Statistics => self.statistics,
+            QueryClass::Battery => self.battery,
+            QueryClass::Other => self.other,
+            QueryClass::Write => Retry::NONE,
+        }
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            status: default_status_retry(),
+            statistics: Retry::default(),
+            battery: Retry::default(),
+            other: default_other_retry(),
+        }
+    }
+}
+
+/// First reconnect attempt after the E3DC went offline
+const RECONNECT_MIN: Duration = Duration::from_secs(5);
+
+/// Longest time between two reconnect attempts
+const RECONNECT_MAX: Duration = Duration::from_secs(300);
+
+/// Offline state of `[on_error] e3dc = "degrade"` with the reconnect backoff
+#[derive(Debug, Default)]
+pub struct Degraded {
+    /// Next reconnect attempt, None while online
+    next_reconnect: Option<Instant>,
+    backoff: Duration,
+}
+
+impl Degraded {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_offline(&self) -> bool {
+        self.next_reconnect.is_some()
+    }
+
+    /// A cycle failed, returns true if this switches to offline
+    pub fn fail(&mut self, now: Instant) -> bool {
+        if self.is_offline() {
+            return false;
+        }
+        self.backoff = RECONNECT_MIN;
+        self.next_reconnect = Some(now + self.backoff);
+        true
+    }
+
+    /// A cycle succeeded, returns true if this switches back online
+    pub fn recover(&mut self) -> bool {
+        self.next_reconnect.take().is_some()
+    }
+
+    /// Whether to reconnect at `now`, the attempt after it waits twice as long
+    pub fn reconnect_due(&mut self, now: Instant) -> bool {
+        match self.next_reconnect {
+            Some(next) if now >= next => {
+                self.backoff = (self.backoff * 2).min(RECONNECT_MAX);
+                self.next_reconnect = Some(now + self.backoff);
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retry_backoff() {
+        let retry = Retry {
+            attempts: 4,
+            backoff: Duration::from_millis(100),
+        };
+        assert_eq!(retry.delay(2), Duration::from_millis(100));
+        assert_eq!(retry.delay(3), Duration::from_millis(200));
+        assert_eq!(retry.delay(4), Duration::from_millis(400));
+    }
+
+    #[test]
+    fn test_retry_run() {
+        let retry = Retry {
+            attempts: 3,
+            backoff: Duration::ZERO,
+        };
+
+        let mut calls = 0;
+        let result = retry.run(QueryClass::Statistics, || {
+            calls += 1;
+            if calls < 3 {
+                Err(E3dcError::QueryFailed("timeout".to_string()))
+            } else {
+                Ok(calls)
+            }
+        });
+        assert_eq!(result.unwrap(), 3);
+
+        let mut calls = 0;
+        let result: Result<(), _> = Retry::NONE.run(QueryClass::Other, || {
+            calls += 1;
+            Err(E3dcError::QueryFailed("timeout".to_string()))
+        });
+        assert!(result.is_err());
+        assert_eq!(calls, 1);
+    }
+
+    #[test]
+    fn test_write_is_sent_once() {
+        let retry = Retry {
+            attempts: 3,
+            backoff: Duration::ZERO,
+        };
+        let policy = RetryPolicy {
+            status: retry,
+            statistics: retry,
+            battery: retry,
+            other: retry,
+        };
+
+        let mut calls = 0;
+        let result: Result<(), _> =
+            policy
+                .for_class(QueryClass::Write)
+                .run(QueryClass::Write, || {
+                    calls += 1;
+                    Err(E3dcError::QueryFailed("timeout".to_string()))
+                });
+        assert!(result.is_err());
+        assert_eq!(calls, 1);
+    }
+
+    #[test]
+    fn test_degraded_reconnect() {
+        let start = Instant::now();
+        let mut degraded = Degraded::new();
+        assert!(!degraded.reconnect_due(start));
+        assert!(degraded.fail(start));
+        assert!(!degraded.fail(start));
+        assert!(degraded.is_offline());
+
+        assert!(!degraded.reconnect_due(start + Duration::from_secs(4)));
+        assert!(degraded.reconnect_due(start + Duration::from_secs(5)));
+        // Backoff doubled: next attempt 10 s later
+        assert!(!degraded.reconnect_due(start + Duration::from_secs(14)));
+        assert!(degraded.reconnect_due(start + Duration::from_secs(15)));
+        // Capped at 5 minutes
+        let mut now = start + Duration::from_secs(15);
+        for _ in 0..10 {
+            now += Duration::from_secs(3600);
+            assert!(degraded.reconnect_due(now));
+        }
+        assert!(!degraded.reconnect_due(now + Duration::from_secs(299)));
+        assert!(degraded.reconnect_due(now + Duration::from_secs(300)));
+
+        assert!(degraded.recover());
+        assert!(!degraded.recover());
+        assert!(!degraded.is_offline());
+    }
+}
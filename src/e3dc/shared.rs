@@ -0,0 +1,68 @@
+//! E3DC client shared between threads
+//!
+//! The E3DC accepts one RSCP request at a time per connection. A
+//! [`SharedE3dcClient`] is a cloneable, `Send + Sync` handle to a single
+//! [`E3dcClient`]: every query holds the lock for its request/response
+//! round trip, so pollers, the command subscriber or an embedding web
+//! server can query from their own threads without a connection each.
+//!
+//! ```ignore
+//! let shared = SharedE3dcClient::new(client);
+//! let poller = shared.clone();
+//! std::thread::spawn(move || poller.with(|client| client.get_status()));
+//! let limits = shared.with(|client| client.get_charge_limits())?;
+//! ```
+
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use super::E3dcClient;
+
+/// Cloneable handle to one E3DC connection, queries are serialized
+#[derive(Clone)]
+pub struct SharedE3dcClient {
+    client: Arc<Mutex<E3dcClient>>,
+}
+
+impl SharedE3dcClient {
+    pub fn new(client: E3dcClient) -> Self {
+        Self {
+            client: Arc::new(Mutex::new(client)),
+        }
+    }
+
+    /// Exclusive access until the guard is dropped, for several queries in a row
+    ///
+    /// A thread panicking during a query doesn't lock out the others, the
+    /// next failing query reconnects as usual.
+    pub fn lock(&self) -> MutexGuard<'_, E3dcClient> {
+        self.client.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Run `f` with exclusive access to the client
+    pub fn with<R>(&self, f: impl FnOnce(&mut E3dcClient) -> R) -> R {
+        f(&mut self.lock())
+    }
+
+    /// The client, None while other handles exist
+    pub fn into_inner(self) -> Option<E3dcClient> {
+        Arc::into_inner(self.client)
+            .map(|client| client.into_inner().unwrap_or_else(PoisonError::into_inner))
+    }
+}
+
+impl From<E3dcClient> for SharedE3dcClient {
+    fn from(client: E3dcClient) -> Self {
+        Self::new(client)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shared_client_is_send_sync() {
+        fn assert_send_sync<T: Send + Sync + Clone + 'static>() {}
+        assert_send_sync::<SharedE3dcClient>();
+    }
+}
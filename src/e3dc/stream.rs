@@ -0,0 +1,99 @@
+//! Blocking status stream
+//!
+//! Polls the status at a fixed cadence aligned to wall-clock multiples of the
+//! interval (e.g. :00, :05, :10 for 5s), the same timing the bridge uses.
+
+use chrono::{DateTime, Duration, Utc};
+
+use super::{E3dcClient, Status};
+use crate::errors::E3dcError;
+
+/// Round timestamp to next modulo seconds (Python-style precise timing)
+/// Example: next_interval(12.3, 5.0) -> 15.0
+pub fn next_interval(time: DateTime<Utc>, interval: Duration) -> DateTime<Utc> {
+    let duration_since_last_interval = Duration::seconds(time.timestamp() % interval.num_seconds());
+    time - duration_since_last_interval + interval
+}
+
+/// Next multiple of `interval` counted from `anchor` after `time`
+///
+/// Unlike [`next_interval`] the fraction of a second is aligned as well.
+pub fn next_aligned(
+    time: DateTime<Utc>,
+    interval: Duration,
+    anchor: DateTime<Utc>,
+) -> DateTime<Utc> {
+    let period = interval.num_milliseconds().max(1);
+    let elapsed = (time - anchor).num_milliseconds();
+    anchor + Duration::milliseconds((elapsed.div_euclid(period) + 1) * period)
+}
+
+/// Iterator returned by [`E3dcClient::status_stream`]
+///
+/// The first status is polled immediately, later ones at aligned interval
+/// boundaries. Errors are yielded, the stream itself never ends.
+pub struct StatusStream<'a> {
+    client: &'a mut E3dcClient,
+    interval: Duration,
+    next_poll: DateTime<Utc>,
+}
+
+impl<'a> StatusStream<'a> {
+    pub(crate) fn new(client: &'a mut E3dcClient, interval: Duration) -> Self {
+        Self {
+            client,
+            interval,
+            next_poll: Utc::now(),
+        }
+    }
+}
+
+impl Iterator for StatusStream<'_> {
+    type Item = Result<Status, E3dcError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // Compensate for execution time, sleep only what's left
+        if let Ok(sleep) = (self.next_poll - Utc::now()).to_std() {
+            std::thread::sleep(sleep);
+        }
+        let now = Utc::now();
+        self.next_poll = next_interval(now, self.interval);
+        Some(self.client.get_status())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_next_interval() {
+        let time = Utc.with_ymd_and_hms(2024, 1, 15, 12, 0, 12).unwrap();
+        assert_eq!(
+            next_interval(time, Duration::seconds(5)),
+            Utc.with_ymd_and_hms(2024, 1, 15, 12, 0, 15).unwrap()
+        );
+        // Exactly on a boundary moves to the next one
+        let time = Utc.with_ymd_and_hms(2024, 1, 15, 12, 5, 0).unwrap();
+        assert_eq!(
+            next_interval(time, Duration::minutes(5)),
+            Utc.with_ymd_and_hms(2024, 1, 15, 12, 10, 0).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_next_aligned() {
+        let anchor = Utc.with_ymd_and_hms(2024, 1, 15, 12, 0, 3).unwrap();
+        let time = Utc.with_ymd_and_hms(2024, 1, 15, 12, 0, 12).unwrap();
+        assert_eq!(
+            next_aligned(time, Duration::seconds(5), anchor),
+            Utc.with_ymd_and_hms(2024, 1, 15, 12, 0, 13).unwrap()
+        );
+        // Before the anchor
+        assert_eq!(
+            next_aligned(anchor - Duration::seconds(7), Duration::seconds(5), anchor),
+            anchor - Duration::seconds(5)
+        );
+    }
+}
@@ -0,0 +1,301 @@
+//! Human readable RSCP tag names for errors and logs
+//!
+//! The rscp tag enums only convert into `u32`, so the names of the tags this
+//! crate queries are listed here. Unlisted tags still get their namespace
+//! (the high byte of the tag) resolved.
+
+use std::fmt;
+
+use rscp::tags::{BAT, DB, DCDC, EMS, EP, INFO, PM, PVI, SRV};
+
+macro_rules! tag_names {
+    ($($ns:ident::$name:ident),* $(,)?) => {
+        const TAG_NAMES: &[(u32, &str)] = &[
+            $(($ns::$name as u32, concat!(stringify!($ns), "::", stringify!($name))),)*
+        ];
+    };
+}
+
+tag_names! {
+    // EMS
+    EMS::AUTARKY,
+    EMS::BALANCED_PHASES,
+    EMS::BAT_CHARGE_LIMIT,
+    EMS::BAT_DISCHARGE_LIMIT,
+    EMS::BAT_SOC,
+    EMS::DCDC_CHARGE_LIMIT,
+    EMS::DCDC_DISCHARGE_LIMIT,
+    EMS::DERATE_AT_PERCENT_VALUE,
+    EMS::DERATE_AT_POWER_VALUE,
+    EMS::DISCHARGE_START_POWER,
+    EMS::EMERGENCYPOWER_TEST_STATUS,
+    EMS::EMERGENCY_POWER_STATUS,
+    EMS::EMERGENCY_POWER_TEST_RESULT,
+    EMS::EXT_SRC_AVAILABLE,
+    EMS::GET_IDLE_PERIODS,
+    EMS::GET_POWER_SETTINGS,
+    EMS::GET_SYS_SPECS,
+    EMS::IDLE_PERIOD,
+    EMS::IDLE_PERIOD_ACTIVE,
+    EMS::IDLE_PERIOD_DAY,
+    EMS::IDLE_PERIOD_END,
+    EMS::IDLE_PERIOD_HOUR,
+    EMS::IDLE_PERIOD_MINUTE,
+    EMS::IDLE_PERIOD_START,
+    EMS::IDLE_PERIOD_TYPE,
+    EMS::INSTALLED_PEAK_POWER,
+    EMS::MANUAL_CHARGE_ACTIVE,
+    EMS::MAX_CHARGE_POWER,
+    EMS::MAX_DISCHARGE_POWER,
+    EMS::POWERSAVE_ENABLED,
+    EMS::POWER_ADD,
+    EMS::POWER_BAT,
+    EMS::POWER_GRID,
+    EMS::POWER_HOME,
+    EMS::POWER_LIMITS_USED,
+    EMS::POWER_PV,
+    EMS::POWER_WB_ALL,
+    EMS::POWER_WB_SOLAR,
+    EMS::REMAINING_BAT_CHARGE_POWER,
+    EMS::REMAINING_BAT_DISCHARGE_POWER,
+    EMS::REQ_GET_SYS_SPECS,
+    EMS::RES_DISCHARGE_START_POWER,
+    EMS::RES_MAX_CHARGE_POWER,
+    EMS::RES_MAX_DISCHARGE_POWER,
+    EMS::RES_POWERSAVE_ENABLED,
+    EMS::RES_POWER_LIMITS,
+    EMS::RES_WEATHER_FORECAST_MODE,
+    EMS::RES_WEATHER_REGULATED_CHARGE_ENABLED,
+    EMS::SELF_CONSUMPTION,
+    EMS::START_MANUAL_CHARGE,
+    EMS::START_EMERGENCYPOWER_TEST,
+    EMS::SET_IDLE_PERIODS,
+    EMS::SET_POWER_SETTINGS,
+    EMS::SYS_SPEC,
+    EMS::SYS_SPEC_NAME,
+    EMS::SYS_SPEC_VALUE_INT,
+    EMS::USED_CHARGE_LIMIT,
+    EMS::USED_DISCHARGE_LIMIT,
+    EMS::USER_CHARGE_LIMIT,
+    EMS::USER_DISCHARGE_LIMIT,
+    EMS::WEATHER_FORECAST_MODE,
+    EMS::WEATHER_REGULATED_CHARGE_ENABLED,
+    // BAT
+    BAT::ASOC,
+    BAT::AVAILABLE_BATTERIES,
+    BAT::CHARGE_CYCLES,
+    BAT::CURRENT,
+    BAT::DATA,
+    BAT::DCB_ALL_CELL_TEMPERATURES,
+    BAT::DCB_ALL_CELL_VOLTAGES,
+    BAT::DCB_CELL_TEMPERATURE,
+    BAT::DCB_CELL_VOLTAGE,
+    BAT::DCB_CHARGE_HIGH_TEMPERATURE,
+    BAT::DCB_CHARGE_LOW_TEMPERATURE,
+    BAT::DCB_COUNT,
+    BAT::DCB_CURRENT,
+    BAT::DCB_CURRENT_AVG_30S,
+    BAT::DCB_CYCLE_COUNT,
+    BAT::DCB_DESIGN_CAPACITY,
+    BAT::DCB_DESIGN_VOLTAGE,
+    BAT::DCB_DEVICE_NAME,
+    BAT::DCB_END_OF_DISCHARGE,
+    BAT::DCB_ERROR,
+    BAT::DCB_FULL_CHARGE_CAPACITY,
+    BAT::DCB_FW_VERSION,
+    BAT::DCB_INFO,
+    BAT::DCB_MANUFACTURE_DATE,
+    BAT::DCB_MANUFACTURE_NAME,
+    BAT::DCB_MAX_CHARGE_CURRENT,
+    BAT::DCB_MAX_CHARGE_VOLTAGE,
+    BAT::DCB_MAX_DISCHARGE_CURRENT,
+    BAT::DCB_NR_PARALLEL_CELL,
+    BAT::DCB_NR_SENSOR,
+    BAT::DCB_NR_SERIES_CELL,
+    BAT::DCB_PCB_VERSION,
+    BAT::DCB_PROTOCOL_VERSION,
+    BAT::DCB_REMAINING_CAPACITY,
+    BAT::DCB_SERIALCODE,
+    BAT::DCB_SERIALNO,
+    BAT::DCB_SOC,
+    BAT::DCB_SOH,
+    BAT::DCB_STATUS,
+    BAT::DCB_VOLTAGE,
+    BAT::DCB_VOLTAGE_AVG_30S,
+    BAT::DCB_WARNING,
+    BAT::DESIGN_CAPACITY,
+    BAT::DEVICE_NAME,
+    BAT::EOD_VOLTAGE,
+    BAT::ERROR_CODE,
+    BAT::FCC,
+    BAT::INDEX,
+    BAT::INSTANCE_DESCRIPTOR,
+    BAT::MANUFACTURER_NAME,
+    BAT::MAX_BAT_VOLTAGE,
+    BAT::MAX_CHARGE_CURRENT,
+    BAT::MAX_DCB_CELL_TEMPERATURE,
+    BAT::MAX_DISCHARGE_CURRENT,
+    BAT::MIN_DCB_CELL_TEMPERATURE,
+    BAT::MODULE_VOLTAGE,
+    BAT::PARAM_BAT_NUMBER,
+    BAT::RC,
+    BAT::READY_FOR_SHUTDOWN,
+    BAT::REQ_AVAILABLE_BATTERIES,
+    BAT::RSOC,
+    BAT::RSOC_REAL,
+    BAT::SERIALNO,
+    BAT::STATUS_CODE,
+    BAT::TERMINAL_VOLTAGE,
+    BAT::TOTAL_DISCHARGE_TIME,
+    BAT::TOTAL_USE_TIME,
+    BAT::TRAINING_MODE,
+    BAT::USABLE_CAPACITY,
+    BAT::USABLE_REMAINING_CAPACITY,
+    // DCDC
+    DCDC::DATA,
+    DCDC::DEVICE_CONNECTED,
+    DCDC::DEVICE_STATE,
+    DCDC::ERROR_CODE,
+    DCDC::INDEX,
+    DCDC::I_BAT,
+    DCDC::I_DCL,
+    DCDC::P_BAT,
+    DCDC::P_DCL,
+    DCDC::STATE,
+    DCDC::STATUS_AS_STRING,
+    DCDC::SUBSTATE,
+    DCDC::U_BAT,
+    DCDC::U_DCL,
+    DCDC::WARNING_CODE,
+    // DB
+    DB::AUTARKY,
+    DB::BAT_CHARGE_LEVEL,
+    DB::BAT_POWER_IN,
+    DB::BAT_POWER_OUT,
+    DB::CONSUMED_PRODUCTION,
+    DB::CONSUMPTION,
+    DB::DC_POWER,
+    DB::GRID_POWER_IN,
+    DB::GRID_POWER_OUT,
+    DB::HISTORY_DATA_DAY,
+    DB::HISTORY_DATA_YEAR,
+    DB::HISTORY_TIME_INTERVAL,
+    DB::HISTORY_TIME_SPAN,
+    DB::HISTORY_TIME_START,
+    DB::SUM_CONTAINER,
+    // PM
+    PM::DATA,
+    PM::INDEX,
+    PM::POWER_L1,
+    PM::POWER_L2,
+    PM::POWER_L3,
+    // PVI
+    PVI::AC_MAX_PHASE_COUNT,
+    PVI::AC_POWER,
+    PVI::DATA,
+    PVI::DC_CURRENT,
+    PVI::DC_POWER,
+    PVI::DC_VOLTAGE,
+    PVI::DEVICE_CONNECTED,
+    PVI::DEVICE_STATE,
+    PVI::INDEX,
+    PVI::ON_GRID,
+    PVI::USED_STRING_COUNT,
+    PVI::VALUE,
+    // EP
+    EP::EP_RESERVE,
+    EP::IS_POSSIBLE,
+    EP::IS_POSSIBLE_PWR_OFF,
+    EP::IS_READY_FOR_SWITCH,
+    EP::PARAM_EP_RESERVE,
+    EP::PARAM_EP_RESERVE_ENERGY,
+    EP::PARAM_INDEX,
+    EP::SET_EP_RESERVE,
+    // INFO
+    INFO::ASSEMBLY_SERIAL_NUMBER,
+    INFO::DHCP_STATUS,
+    INFO::DNS,
+    INFO::GATEWAY,
+    INFO::IP_ADDRESS,
+    INFO::MAC_ADDRESS,
+    INFO::PLATFORM_TYPE,
+    INFO::PRODUCTION_DATE,
+    INFO::SERIAL_NUMBER,
+    INFO::SET_TIME,
+    INFO::SET_TIME_ZONE,
+    INFO::SUBNET_MASK,
+    INFO::SW_RELEASE,
+    INFO::TIME,
+    INFO::TIME_ZONE,
+    INFO::UTC_TIME,
+    // SRV
+    SRV::IS_ONLINE,
+}
+
+/// RSCP namespaces by the high byte of the tag
+const NAMESPACES: &[(u8, &str)] = &[
+    (0x01, "EMS"),
+    (0x02, "PVI"),
+    (0x03, "BAT"),
+    (0x04, "DCDC"),
+    (0x05, "PM"),
+    (0x06, "DB"),
+    (0x07, "FMS"),
+    (0x08, "SRV"),
+    (0x09, "HA"),
+    (0x0A, "INFO"),
+    (0x0B, "EP"),
+    (0x0C, "SYS"),
+    (0x0D, "UM"),
+    (0x0E, "WB"),
+];
+
+/// Name of a known tag, e.g. `BAT::DCB_NR_SENSOR`
+pub fn tag_name(tag: u32) -> Option<&'static str> {
+    TAG_NAMES
+        .iter()
+        .find(|(value, _)| *value == tag)
+        .map(|(_, name)| *name)
+}
+
+/// Namespace of a tag, e.g. `BAT`
+pub fn tag_namespace(tag: u32) -> Option<&'static str> {
+    let namespace = (tag >> 24) as u8;
+    NAMESPACES
+        .iter()
+        .find(|(value, _)| *value == namespace)
+        .map(|(_, name)| *name)
+}
+
+/// Displays a tag as `BAT::DCB_NR_SENSOR (0x03800103)`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TagName(pub u32);
+
+impl fmt::Display for TagName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (tag_name(self.0), tag_namespace(self.0)) {
+            (Some(name), _) => write!(f, "{} (0x{:08X})", name, self.0),
+            (None, Some(namespace)) => write!(f, "{}::<unknown> (0x{:08X})", namespace, self.0),
+            (None, None) => write!(f, "0x{:08X} ({})", self.0, self.0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tag_name_display() {
+        let tag = BAT::DCB_NR_SENSOR as u32;
+        assert_eq!(
+            TagName(tag).to_string(),
+            format!("BAT::DCB_NR_SENSOR (0x{:08X})", tag)
+        );
+        assert_eq!(
+            TagName(0x0B00_FFFF).to_string(),
+            "EP::<unknown> (0x0B00FFFF)"
+        );
+        assert_eq!(TagName(42).to_string(), "0x0000002A (42)");
+    }
+}
@@ -0,0 +1,150 @@
+//! Month- and year-to-date energy totals
+//!
+//! The completed days of the period come from one DB history request, cached
+//! until the day changes, and are combined with today's live sums in every
+//! statistics cycle.
+
+use chrono::{DateTime, Datelike, NaiveDate, Utc};
+
+use super::{DailyStatistics, E3dcClient};
+use crate::errors::E3dcError;
+
+/// Period of a running total
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatisticsPeriod {
+    Month,
+    Year,
+}
+
+impl StatisticsPeriod {
+    /// Topic suffix, e.g. `status_sums_month`
+    pub fn name(self) -> &'static str {
+        match self {
+            StatisticsPeriod::Month => "month",
+            StatisticsPeriod::Year => "year",
+        }
+    }
+
+    /// First day of the period containing `day`
+    pub fn first_day(self, day: NaiveDate) -> NaiveDate {
+        match self {
+            StatisticsPeriod::Month => day.with_day(1),
+            StatisticsPeriod::Year => day.with_ordinal(1),
+        }
+        .expect("first day of month/year always exists")
+    }
+}
+
+/// Running total of one period
+pub struct PeriodTotals {
+    period: StatisticsPeriod,
+    /// Day the cache was built for and the sums of the completed days before it
+    completed: Option<(NaiveDate, Option<DailyStatistics>)>,
+}
+
+impl PeriodTotals {
+    pub fn new(period: StatisticsPeriod) -> Self {
+        Self {
+            period,
+            completed: None,
+        }
+    }
+
+    pub fn period(&self) -> StatisticsPeriod {
+        self.period
+    }
+
+    /// Combine the completed days of the period with `today`'s sums
+    ///
+    /// `today` is the result of [`E3dcClient::get_daily_statistics`], its
+    /// `start` decides which day counts as current (shortly after midnight it
+    /// is still yesterday).
+    pub fn update(
+        &mut self,
+        client: &mut E3dcClient,
+        today: &DailyStatistics,
+    ) -> Result<DailyStatistics, E3dcError> {
+        let day = today.start.date_naive();
+        let cached = self.completed.as_ref().filter(|(date, _)| *date == day);
+        let completed = match cached {
+            Some((_, completed)) => completed.clone(),
+            None => {
+                let first_day = midnight(self.period.first_day(day));
+                let completed = if first_day < today.start {
+                    Some(client.get_db_data_timestamp(first_day, today.start - first_day)?)
+                } else {
+                    // First day of the period, nothing completed yet
+                    None
+                };
+                self.completed = Some((day, completed.clone()));
+                completed
+            }
+        };
+        Ok(match completed {
+            Some(completed) => completed.combine(today),
+            None => today.clone(),
+        })
+    }
+}
+
+fn midnight(day: NaiveDate) -> DateTime<Utc> {
+    day.and_hms_opt(0, 0, 0)
+        .expect("midnight always exists")
+        .and_utc()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn stats(
+        start: DateTime<Utc>,
+        days: i64,
+        consumption: f64,
+        grid_import: f64,
+    ) -> DailyStatistics {
+        DailyStatistics {
+            time_stamp: start,
+            autarky: 0.0,
+            consumption,
+            solar_production: 4000.0,
+            consumed_production: 0.0,
+            bat_power_in: 1000.0,
+            bat_power_out: 1000.0,
+            grid_power_in: 1000.0,
+            grid_power_out: grid_import,
+            state_of_charge: 50.0,
+            start,
+            timespan: chrono::Duration::days(days),
+        }
+    }
+
+    #[test]
+    fn test_combine() {
+        let first = Utc.with_ymd_and_hms(2024, 3, 1, 0, 0, 0).unwrap();
+        let today = Utc.with_ymd_and_hms(2024, 3, 15, 0, 0, 0).unwrap();
+        let completed = stats(first, 14, 6000.0, 3000.0);
+        let total = completed.combine(&stats(today, 1, 2000.0, 1000.0));
+        assert_eq!(total.consumption, 8000.0);
+        assert_eq!(total.grid_power_out, 4000.0);
+        assert_eq!(total.autarky, 50.0);
+        // 8000 Wh produced, 2000 Wh exported
+        assert_eq!(total.consumed_production, 75.0);
+        assert_eq!(total.start, first);
+        assert_eq!(total.timespan, chrono::Duration::days(15));
+    }
+
+    #[test]
+    fn test_first_day() {
+        let day = NaiveDate::from_ymd_opt(2024, 3, 15).unwrap();
+        assert_eq!(
+            StatisticsPeriod::Month.first_day(day),
+            NaiveDate::from_ymd_opt(2024, 3, 1).unwrap()
+        );
+        assert_eq!(
+            StatisticsPeriod::Year.first_day(day),
+            NaiveDate::from_ymd_opt(2024, 1, 1).unwrap()
+        );
+    }
+}
@@ -2,26 +2,54 @@
 //!
 //! These types mirror the data structures from the Python implementation
 
-use chrono::{DateTime, Duration, Utc};
+use chrono::{DateTime, Duration, NaiveDateTime, NaiveTime, Utc, Weekday};
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone)]
+/// Serializes `chrono::Duration` as whole seconds (chrono has no serde impl for it)
+mod duration_seconds {
+    use chrono::Duration;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_i64(duration.num_seconds())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        i64::deserialize(deserializer).map(Duration::seconds)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SystemInfoStatic {
     pub serial_number: String,
-    pub model: &'static str,
+    /// Raw serial before `serial_number`, e.g. `S10-`
+    #[serde(default)]
+    pub serial_prefix: String,
+    pub model: String,
     pub mac_address: String,
     pub installed_peak_power: u64,
     pub derate_at_percent_value: f64,
     pub derate_at_power_value: u64,
     pub ext_source_available: bool,
+    pub hardware: HardwareInfo,
+}
+
+/// Device metadata from the remaining INFO tags, None if the firmware doesn't answer
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct HardwareInfo {
+    pub production_date: Option<String>,
+    /// Hardware platform (revision) of the unit
+    pub platform_type: Option<u64>,
+    pub assembly_serial_number: Option<String>,
 }
 /// System information (retrieved once at startup)
-#[derive(Debug, Clone)]
-pub struct SystemInfo<'a> {
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SystemInfo {
     pub time_stamp: DateTime<Utc>,
-    pub serial_number: &'a String,
-    pub mac_address: &'a String,
+    pub serial_number: String,
+    pub mac_address: String,
     pub ip_address: String,
-    pub model: &'static str,
+    pub model: String,
     pub software_release: String,
     pub installed_peak_power: u64,                // W
     pub installed_battery_capacity: Option<u64>,  // Wh
@@ -41,64 +69,394 @@ pub struct SystemInfo<'a> {
     pub weather_regulated_charge_enabled: bool,
     // External source (not available in rscp tags, set to 0)
     pub external_source_available: bool,
+    pub hardware: HardwareInfo,
+}
+
+/// Changes to the EMS power settings, only fields that are set are written
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PowerSettingsUpdate {
+    pub power_limits_used: Option<bool>,
+    pub max_charge_power: Option<u64>,      // W
+    pub max_discharge_power: Option<u64>,   // W
+    pub discharge_start_power: Option<u64>, // W
+    pub power_save_enabled: Option<bool>,
+    pub weather_regulated_charge_enabled: Option<bool>,
+    pub weather_forecast_mode: Option<WeatherForecastMode>,
+}
+
+/// Weather forecast mode of the EMS (`EMS::WEATHER_FORECAST_MODE`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WeatherForecastMode {
+    /// No forecast-based charging
+    Off,
+    /// Forecast-based charging
+    Forecast,
+    /// Forecast-based charging optimized for grid feed-in limits
+    ForecastOptimized,
+}
+
+impl WeatherForecastMode {
+    pub const ALL: [WeatherForecastMode; 3] = [
+        WeatherForecastMode::Off,
+        WeatherForecastMode::Forecast,
+        WeatherForecastMode::ForecastOptimized,
+    ];
+
+    pub fn from_raw(value: u64) -> Option<Self> {
+        Self::ALL.into_iter().find(|mode| mode.as_raw() == value)
+    }
+
+    pub fn as_raw(&self) -> u64 {
+        match self {
+            WeatherForecastMode::Off => 0,
+            WeatherForecastMode::Forecast => 1,
+            WeatherForecastMode::ForecastOptimized => 2,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WeatherForecastMode::Off => "off",
+            WeatherForecastMode::Forecast => "forecast",
+            WeatherForecastMode::ForecastOptimized => "forecast_optimized",
+        }
+    }
+
+    /// Parse a mode name (as returned by `as_str`) or its raw numeric value
+    pub fn parse(value: &str) -> Option<Self> {
+        let value = value.trim();
+        Self::ALL
+            .into_iter()
+            .find(|mode| mode.as_str().eq_ignore_ascii_case(value))
+            .or_else(|| value.parse().ok().and_then(Self::from_raw))
+    }
+}
+
+/// Idle period kind: battery charging or discharging is locked during the window
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IdlePeriodType {
+    Charge,
+    Discharge,
+}
+
+impl IdlePeriodType {
+    pub fn from_raw(value: u64) -> Option<Self> {
+        match value {
+            0 => Some(IdlePeriodType::Charge),
+            1 => Some(IdlePeriodType::Discharge),
+            _ => None,
+        }
+    }
+
+    pub fn as_raw(&self) -> u8 {
+        match self {
+            IdlePeriodType::Charge => 0,
+            IdlePeriodType::Discharge => 1,
+        }
+    }
+}
+
+/// One idle period window of the weekly EMS schedule
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IdlePeriod {
+    pub period_type: IdlePeriodType,
+    pub day: Weekday,
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+    pub active: bool,
+}
+
+impl IdlePeriod {
+    /// Validate a weekly schedule before writing it
+    ///
+    /// The EMS stores exactly one window per type and day, so overlapping or
+    /// duplicate windows cannot be represented.
+    pub fn validate_schedule(periods: &[IdlePeriod]) -> Result<(), String> {
+        for (i, period) in periods.iter().enumerate() {
+            if period.active && period.start >= period.end {
+                return Err(format!(
+                    "{:?} {:?} window starts at {} but ends at {}",
+                    period.period_type, period.day, period.start, period.end
+                ));
+            }
+            for other in &periods[i + 1..] {
+                if other.period_type != period.period_type || other.day != period.day {
+                    continue;
+                }
+                let both_active = period.active && other.active;
+                if both_active && other.start < period.end && period.start < other.end {
+                    return Err(format!(
+                        "{:?} {:?} windows {}-{} and {}-{} overlap",
+                        period.period_type,
+                        period.day,
+                        period.start,
+                        period.end,
+                        other.start,
+                        other.end
+                    ));
+                }
+                return Err(format!(
+                    "only one {:?} window per day is supported ({:?})",
+                    period.period_type, period.day
+                ));
+            }
+        }
+        Ok(())
+    }
 }
 
 /// Current status (polled every interval, e.g., 5s)
-#[derive(Debug, Clone)]
+///
+/// A value is None if the firmware doesn't answer its tag or hasn't answered
+/// it since the start.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Status {
     pub time_stamp: DateTime<Utc>,
-    pub power_battery: f64, // W (positive = charging, negative = discharging)
-    pub power_wb: f64,      // W (positive = charging, negative = discharging)
-    pub power_home: f64,    // W
-    pub power_pv: f64,      // W
-    pub power_grid: f64,    // W (positive = to grid, negative = from grid)
-    pub power_add: f64,     // W
-    pub battery_soc: f64,   // %
-    pub autarky: f64,       // %
-    pub self_consumption: f64, // %
+    pub power_battery: Option<f64>, // W (positive = charging, negative = discharging)
+    pub power_wb: Option<f64>,      // W (positive = charging, negative = discharging)
+    pub power_wb_solar: Option<f64>, // W (solar share of the wallbox power)
+    pub power_home: Option<f64>,    // W
+    pub power_pv: Option<f64>,      // W
+    pub power_grid: Option<f64>,    // W (positive = to grid, negative = from grid)
+    pub power_add: Option<f64>,     // W
+    pub battery_soc: Option<f64>,   // %
+    pub autarky: Option<f64>,       // %
+    pub self_consumption: Option<f64>, // %
+    pub emergency_power_state: EmergencyPowerState,
+    /// Tags missing from the response, their fields hold the previous value
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub missing: Vec<u32>,
+}
+
+impl Status {
+    /// Power not accounted for by the meters (W), close to 0 on a healthy system
+    ///
+    /// `pv + grid − battery − home − wallbox − additional` with the E3DC signs
+    /// (grid positive when importing, additional negative when producing).
+    /// None if one of the values is unknown.
+    pub fn energy_balance_residual(&self) -> Option<f64> {
+        Some(
+            self.power_pv? + self.power_grid?
+                - self.power_battery?
+                - self.power_home?
+                - self.power_wb?
+                - self.power_add?,
+        )
+    }
+}
+
+/// Charge/discharge limits currently applied by the EMS (W)
+///
+/// `used_*` is the effective limit, the minimum of the battery, DC-DC
+/// converter and user limits. None if the firmware doesn't answer the tag.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChargeLimits {
+    pub time_stamp: DateTime<Utc>,
+    pub used_charge_limit: Option<f64>,
+    pub bat_charge_limit: Option<f64>,
+    pub dcdc_charge_limit: Option<f64>,
+    pub user_charge_limit: Option<f64>,
+    pub used_discharge_limit: Option<f64>,
+    pub bat_discharge_limit: Option<f64>,
+    pub dcdc_discharge_limit: Option<f64>,
+    pub user_discharge_limit: Option<f64>,
+    pub remaining_bat_charge_power: Option<f64>,
+    pub remaining_bat_discharge_power: Option<f64>,
 }
+
+/// What the emergency power (EP) hardware of the unit supports
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EmergencyPowerCapabilities {
+    /// Emergency power hardware installed and configured (`EP::IS_POSSIBLE`)
+    pub possible: Option<bool>,
+    /// Ready to switch to island operation (`EP::IS_READY_FOR_SWITCH`)
+    pub ready_for_switch: Option<bool>,
+    /// The unit may switch the house off (`EP::IS_POSSIBLE_PWR_OFF`)
+    pub power_off_possible: Option<bool>,
+    /// A battery reserve is kept for emergency power
+    pub reserve_configured: Option<bool>,
+    /// Configured reserve in % of the usable battery energy (`EP::PARAM_EP_RESERVE`)
+    pub reserve_percent: Option<f64>,
+    /// Configured reserve in Wh (`EP::PARAM_EP_RESERVE_ENERGY`), see [`reserve_energy`]
+    pub reserve_energy: Option<f64>,
+    /// Battery energy currently held back for the reserve in Wh, less than
+    /// the reserve while the SOC is below it
+    pub reserve_held_energy: Option<f64>,
+}
+
+/// Configured reserve in Wh: `energy` if the firmware answers it, else
+/// `percent` of the installed battery `capacity` (Wh)
+///
+/// None if neither is known, a missing capacity isn't taken as 0 Wh.
+pub fn reserve_energy(
+    percent: Option<f64>,
+    energy: Option<f64>,
+    capacity: Option<f64>,
+) -> Option<f64> {
+    energy.or_else(|| Some(percent? / 100.0 * capacity?))
+}
+
+/// Battery energy held back at `battery_soc` for a reserve of `percent` (`energy` Wh)
+///
+/// The reserve is the lowest `percent` of the usable battery energy, below
+/// it the whole charge is held back.
+pub fn reserve_held_energy(percent: f64, energy: f64, battery_soc: f64) -> f64 {
+    if percent > 0.0 {
+        energy * (battery_soc / percent).clamp(0.0, 1.0)
+    } else {
+        0.0
+    }
+}
+
+/// EMS settings the charge schedule is enforced against
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EmsControlState {
+    /// A manual (grid) charge is running (`EMS::MANUAL_CHARGE_ACTIVE`)
+    pub manual_charge_active: bool,
+    pub max_discharge_power: u64,
+    pub power_limits_used: bool,
+}
+
+/// Network settings of the unit
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NetworkConfig {
+    pub dhcp: bool,
+    pub ip_address: String,
+    pub subnet_mask: String,
+    pub gateway: String,
+    pub dns: String,
+}
+
+/// Clock of the unit, it defines the day boundaries of its statistics
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeviceClock {
+    /// Host time the answer arrived (midpoint of the request)
+    pub time_stamp: DateTime<Utc>,
+    pub utc_time: DateTime<Utc>,
+    /// Wall clock time in the unit's time zone
+    pub local_time: NaiveDateTime,
+    pub time_zone: String,
+}
+
+impl DeviceClock {
+    /// How far the unit's clock is ahead of the host clock (negative if behind)
+    pub fn offset(&self) -> Duration {
+        self.utc_time - self.time_stamp
+    }
+}
+
+/// Phase balancing state and per-phase power at the grid meter
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PhaseBalance {
+    pub time_stamp: DateTime<Utc>,
+    /// Phases the EMS balances, bit 0 = L1 to bit 2 = L3 (`EMS::BALANCED_PHASES`)
+    pub balanced_phases: u64,
+    pub power_l1: f64, // W
+    pub power_l2: f64, // W
+    pub power_l3: f64, // W
+}
+
+impl PhaseBalance {
+    /// Whether phase `phase` (1-3) is balanced
+    pub fn is_balanced(&self, phase: u32) -> bool {
+        self.balanced_phases & (1 << (phase - 1)) != 0
+    }
+}
+
+/// Emergency power (EP) state as reported by `EMS::EMERGENCY_POWER_STATUS`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EmergencyPowerState {
+    /// No emergency power hardware installed
+    NotPossible,
+    /// Running in island mode (grid lost)
+    Active,
+    /// Connected to the grid, emergency power ready
+    NotActive,
+    /// Emergency power temporarily unavailable
+    NotAvailable,
+    /// Switch is in island position but emergency power is not active
+    SwitchInIsland,
+    /// Value not known to this bridge
+    Unknown(u64),
+}
+
+impl EmergencyPowerState {
+    pub fn from_raw(value: u64) -> Self {
+        match value {
+            0 => EmergencyPowerState::NotPossible,
+            1 => EmergencyPowerState::Active,
+            2 => EmergencyPowerState::NotActive,
+            3 => EmergencyPowerState::NotAvailable,
+            4 => EmergencyPowerState::SwitchInIsland,
+            other => EmergencyPowerState::Unknown(other),
+        }
+    }
+
+    /// Whether the system is currently supplying the house in island mode
+    pub fn is_island(&self) -> bool {
+        matches!(self, EmergencyPowerState::Active)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EmergencyPowerState::NotPossible => "not_possible",
+            EmergencyPowerState::Active => "island",
+            EmergencyPowerState::NotActive => "grid",
+            EmergencyPowerState::NotAvailable => "not_available",
+            EmergencyPowerState::SwitchInIsland => "switch_in_island",
+            EmergencyPowerState::Unknown(_) => "unknown",
+        }
+    }
+}
+
 /// Battery data (polled at longer interval, e.g., 300s)
 /// Comprehensive battery information matching Python implementation
-#[derive(Debug, Clone, PartialEq)]
+///
+/// A polled value is None if the firmware doesn't answer its tag or hasn't
+/// answered it since the start.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
 pub struct BatteryData {
     pub index: u64,
     pub time_stamp: DateTime<Utc>,
 
     // State of Charge
-    pub rsoc: f64,      // Relative State of Charge %
-    pub rsoc_real: f64, // Real Relative State of Charge %
-    pub asoc: f64,      // Absolute State of Charge %
+    pub rsoc: Option<f64>,      // Relative State of Charge %
+    pub rsoc_real: Option<f64>, // Real Relative State of Charge %
+    pub asoc: Option<f64>,      // Absolute State of Charge %
 
     // Electrical measurements
-    pub current: f64,          // A
-    pub module_voltage: f64,   // V
-    pub terminal_voltage: f64, // V
-    pub max_bat_voltage: f64,  // V
-    pub eod_voltage: f64,      // End of Discharge voltage (V)
+    pub current: Option<f64>,          // A
+    pub module_voltage: Option<f64>,   // V
+    pub terminal_voltage: Option<f64>, // V
+    pub max_bat_voltage: Option<f64>,  // V
+    pub eod_voltage: Option<f64>,      // End of Discharge voltage (V)
 
     // Capacity
-    pub fcc: f64,                       // Full Charge Capacity (Ah)
-    pub rc: f64,                        // Remaining Capacity (Ah)
-    pub design_capacity: f64,           // Design Capacity (Ah)
-    pub usable_capacity: f64,           // Usable Capacity (Ah)
-    pub usable_remaining_capacity: f64, // Usable Remaining Capacity (Ah)
+    pub fcc: Option<f64>,                       // Full Charge Capacity (Ah)
+    pub rc: Option<f64>,                        // Remaining Capacity (Ah)
+    pub design_capacity: Option<f64>,           // Design Capacity (Ah)
+    pub usable_capacity: Option<f64>,           // Usable Capacity (Ah)
+    pub usable_remaining_capacity: Option<f64>, // Usable Remaining Capacity (Ah)
 
     // Current limits
-    pub max_charge_current: f64,    // A
-    pub max_discharge_current: f64, // A
+    pub max_charge_current: Option<f64>,    // A
+    pub max_discharge_current: Option<f64>, // A
 
     // Temperature
-    pub max_dcb_cell_temp: f64, // °C
-    pub min_dcb_cell_temp: f64, // °C
+    pub max_dcb_cell_temp: Option<f64>, // °C
+    pub min_dcb_cell_temp: Option<f64>, // °C
 
     // Status and errors
-    pub status_code: f64,
-    pub error_code: f64,
+    pub status_code: Option<f64>,
+    pub error_code: Option<f64>,
 
     // Cycles and usage
-    pub charge_cycles: f64,
-    pub total_use_time: u64,       // seconds
-    pub total_discharge_time: u64, // seconds
+    pub charge_cycles: Option<f64>,
+    pub total_use_time: Option<u64>,       // seconds
+    pub total_discharge_time: Option<u64>, // seconds
 
     // Device info
     pub device_name: String,
@@ -111,13 +469,17 @@ pub struct BatteryData {
     pub dcb_count: u64,
     pub dcbs: Vec<DcbData>, // DCB modules (DC Battery Controllers) with detailed cell data
 
+    /// Tags missing from the response, their fields hold the previous value
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub missing: Vec<u32>,
+
     // Operational state
-    pub ready_for_shutdown: bool,
-    pub training_mode: bool,
+    pub ready_for_shutdown: Option<bool>,
+    pub training_mode: Option<bool>,
 }
 
 /// Daily statistics (polled at longer interval)
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DailyStatistics {
     pub time_stamp: DateTime<Utc>,
     pub autarky: f64,             // %
@@ -130,11 +492,126 @@ pub struct DailyStatistics {
     pub grid_power_out: f64,      // Wh
     pub state_of_charge: f64,     // %
     pub start: DateTime<Utc>,     // Unix timestamp
-    pub timespan: Duration,       // Duration in seconds
+    #[serde(with = "duration_seconds")]
+    pub timespan: Duration, // Duration in seconds
+}
+
+impl DailyStatistics {
+    /// Sums of two consecutive timespans, `self` followed by `next`
+    ///
+    /// Energies are added, autarky and self-consumption recomputed from them,
+    /// the state of charge is taken from `next`.
+    pub fn combine(&self, next: &DailyStatistics) -> DailyStatistics {
+        let consumption = self.consumption + next.consumption;
+        let solar_production = self.solar_production + next.solar_production;
+        // grid_power_out is the import, grid_power_in the export
+        let grid_power_out = self.grid_power_out + next.grid_power_out;
+        let grid_power_in = self.grid_power_in + next.grid_power_in;
+        DailyStatistics {
+            time_stamp: next.time_stamp,
+            autarky: percentage(consumption - grid_power_out, consumption),
+            consumption,
+            solar_production,
+            consumed_production: percentage(solar_production - grid_power_in, solar_production),
+            bat_power_in: self.bat_power_in + next.bat_power_in,
+            bat_power_out: self.bat_power_out + next.bat_power_out,
+            grid_power_in,
+            grid_power_out,
+            state_of_charge: next.state_of_charge,
+            start: self.start,
+            timespan: self.timespan + next.timespan,
+        }
+    }
+}
+
+/// Share of `part` in `total` in percent, clamped to 0..=100
+fn percentage(part: f64, total: f64) -> f64 {
+    if total > 0.0 {
+        (part / total * 100.0).clamp(0.0, 100.0)
+    } else {
+        0.0
+    }
+}
+
+/// Sums of one slot of a [`DayCurve`]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CurveSlot {
+    /// Position of the slot from the start of the day (`DB::GRAPH_INDEX`)
+    pub index: u64,
+    pub solar_production: f64, // Wh
+    pub consumption: f64,      // Wh
+}
+
+/// Today's DB history in fixed slots, the data of the E3DC day chart
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DayCurve {
+    pub time_stamp: DateTime<Utc>,
+    pub start: DateTime<Utc>,
+    #[serde(with = "duration_seconds")]
+    pub slot: Duration,
+    /// Slots with data, ordered as answered
+    pub slots: Vec<CurveSlot>,
+}
+
+/// Status, statistics and battery data polled together
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Snapshot {
+    /// Common timestamp of the snapshot (taken from the status response)
+    pub time_stamp: DateTime<Utc>,
+    pub status: Status,
+    pub statistics: DailyStatistics,
+    pub batteries: Vec<BatteryData>,
+}
+
+/// PVI (solar inverter) found at startup
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PviInfo {
+    pub index: u64,
+    pub string_count: u64,
+    pub phase_count: u64,
+}
+
+/// Live data of one PVI
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PviData {
+    pub time_stamp: DateTime<Utc>,
+    pub index: u64,
+    pub on_grid: bool,
+    pub ac_power: f64, // W (sum of all phases)
+    pub dc_power: f64, // W (sum of all strings)
+    pub strings: Vec<PviString>,
+}
+
+/// DC values of one PV string
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PviString {
+    pub index: u64,
+    pub dc_power: f64,   // W
+    pub dc_voltage: f64, // V
+    pub dc_current: f64, // A
+}
+
+/// Live data of one DCDC converter (battery string to DC link)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DcdcData {
+    pub time_stamp: DateTime<Utc>,
+    pub index: u64,
+    pub battery_current: f64, // A
+    pub battery_voltage: f64, // V
+    pub battery_power: f64,   // W
+    pub dcl_current: f64,     // A (DC link)
+    pub dcl_voltage: f64,     // V
+    pub dcl_power: f64,       // W
+    /// Status text of the converter (`DCDC::STATUS_AS_STRING`)
+    pub status: String,
+    pub state: u64,
+    pub substate: u64,
+    pub error_code: u64,
+    pub warning_code: u64,
 }
 
 /// Battery info (index and DCB count)
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BatteryInfo {
     pub index: u64,
     pub device_name: String,
@@ -145,9 +622,17 @@ pub struct BatteryInfo {
     pub dcb_count: u64,
 }
 
+/// DCB count of a battery that differs from the previous scan
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DcbCountChange {
+    pub battery: u64,
+    pub from: u64,
+    pub to: u64,
+}
+
 /// DCB (DC Battery Controller) detailed information
 /// Matches Python pye3dc implementation
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
 pub struct DcbData {
     pub index: u64,
     // Current measurements
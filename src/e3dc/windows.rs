@@ -0,0 +1,102 @@
+//! Statistics windows (`[[statistics.windows]]`)
+//!
+//! `status_sums` covers the UTC day so far. Further windows query the DB
+//! history for the time since local midnight or for a rolling span ending
+//! now, each published to a topic group of its own.
+
+use chrono::{DateTime, Duration, Local, NaiveTime, TimeZone, Utc};
+use serde::Deserialize;
+
+/// Time span of a statistics query, ending now
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(tag = "window", rename_all = "snake_case")]
+pub enum StatisticsWindow {
+    /// Since UTC midnight, the window of `status_sums`
+    UtcDay,
+    /// Since midnight in the host's local time
+    LocalDay,
+    /// The last `span`, e.g. "1h" or "24h"
+    Rolling {
+        #[serde(with = "humantime_serde")]
+        span: std::time::Duration,
+    },
+}
+
+impl StatisticsWindow {
+    /// Start and length of the window at `now`
+    ///
+    /// Within `stat_interval` after midnight the DB has no sums of the new
+    /// day yet, a day window then queries the first hour of the previous day.
+    pub fn bounds(&self, now: DateTime<Utc>, stat_interval: Duration) -> (DateTime<Utc>, Duration) {
+        match self {
+            StatisticsWindow::UtcDay => day_bounds(now, &Utc, stat_interval),
+            StatisticsWindow::LocalDay => day_bounds(now, &Local, stat_interval),
+            StatisticsWindow::Rolling { span } => {
+                let span = Duration::from_std(*span).unwrap_or(Duration::MAX);
+                let span = span.min(now - DateTime::UNIX_EPOCH);
+                (now - span, span)
+            }
+        }
+    }
+}
+
+/// Bounds of the day of `now` in `tz` up to now
+fn day_bounds<Tz: TimeZone>(
+    now: DateTime<Utc>,
+    tz: &Tz,
+    stat_interval: Duration,
+) -> (DateTime<Utc>, Duration) {
+    let day = now.with_timezone(tz).date_naive();
+    // A DST switch at midnight skips it, the day then starts with its first valid time
+    let start = day
+        .and_time(NaiveTime::MIN)
+        .and_local_timezone(tz.clone())
+        .earliest()
+        .map_or(now - Duration::hours(1), |start| start.with_timezone(&Utc));
+    let timespan = now - start;
+    if timespan <= stat_interval {
+        (start - Duration::days(1), Duration::hours(1))
+    } else {
+        (start, timespan)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::FixedOffset;
+
+    #[test]
+    fn test_window_bounds() {
+        let now = Utc.with_ymd_and_hms(2024, 3, 1, 10, 30, 0).unwrap();
+        let interval = Duration::minutes(1);
+
+        let (start, span) = StatisticsWindow::UtcDay.bounds(now, interval);
+        assert_eq!(start, Utc.with_ymd_and_hms(2024, 3, 1, 0, 0, 0).unwrap());
+        assert_eq!(span, Duration::minutes(630));
+
+        let rolling = StatisticsWindow::Rolling {
+            span: std::time::Duration::from_secs(3600),
+        };
+        assert_eq!(
+            rolling.bounds(now, interval),
+            (now - Duration::hours(1), Duration::hours(1))
+        );
+
+        // UTC+2: the local day started at 22:00 UTC the day before
+        let tz = FixedOffset::east_opt(2 * 3600).unwrap();
+        let (start, span) = day_bounds(now, &tz, interval);
+        assert_eq!(start, Utc.with_ymd_and_hms(2024, 2, 29, 22, 0, 0).unwrap());
+        assert_eq!(span, Duration::minutes(750));
+
+        // Right after midnight: the first hour of the previous day
+        let midnight = Utc.with_ymd_and_hms(2024, 3, 1, 0, 0, 30).unwrap();
+        assert_eq!(
+            StatisticsWindow::UtcDay.bounds(midnight, interval),
+            (
+                Utc.with_ymd_and_hms(2024, 2, 29, 0, 0, 0).unwrap(),
+                Duration::hours(1)
+            )
+        );
+    }
+}
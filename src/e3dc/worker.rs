@@ -0,0 +1,120 @@
+//! Battery polling on a second RSCP connection (`[e3dc] battery_worker`)
+//!
+//! A battery cycle takes a request per battery plus one per DCB module, on
+//! larger systems that is a dozen round trips. Polled in the main loop it
+//! delays the next status poll; the worker runs it on its own connection
+//! and thread instead and hands the readings back over a channel.
+//!
+//! The worker connects on the first request and reconnects with the next
+//! request after a failed poll. Requests arriving while a poll is still
+//! running are dropped, a slow E3DC never builds up a backlog.
+
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::thread;
+
+use tracing::{debug, warn};
+
+use super::{BatteryData, DcbCountChange, E3dcClient};
+use crate::config::E3dcConfig;
+use crate::e3dc::RetryPolicy;
+use crate::errors::E3dcError;
+
+/// Battery readings of one poll
+#[derive(Debug, Clone, Default)]
+pub struct BatteryPoll {
+    /// DCB counts changed since the previous scan
+    pub dcb_changes: Vec<DcbCountChange>,
+    pub batteries: Vec<BatteryData>,
+}
+
+impl BatteryPoll {
+    /// Poll as configured in `[e3dc]`: rescan or DCB count refresh, then the battery data
+    ///
+    /// Nothing is queried with `poll_batteries = false`.
+    pub fn query(client: &mut E3dcClient, config: &E3dcConfig) -> Result<Self, E3dcError> {
+        if !config.poll_batteries {
+            return Ok(Self::default());
+        }
+        let dcb_changes = if config.rediscover_batteries {
+            client.rediscover_batteries()?
+        } else if config.poll_dcbs {
+            client.refresh_dcb_counts()?
+        } else {
+            Vec::new()
+        };
+        Ok(Self {
+            dcb_changes,
+            batteries: client.get_battery_data()?,
+        })
+    }
+}
+
+/// Handle of the battery worker thread
+pub struct BatteryWorker {
+    requests: SyncSender<()>,
+    results: Receiver<Result<BatteryPoll, E3dcError>>,
+}
+
+impl BatteryWorker {
+    /// Start the worker thread, it connects with the first request
+    pub fn spawn(config: &E3dcConfig, retry: RetryPolicy) -> std::io::Result<Self> {
+        let (requests, pending) = mpsc::sync_channel(0);
+        let (completed, results) = mpsc::channel();
+        let config = config.clone();
+        thread::Builder::new()
+            .name("battery-worker".to_string())
+            .spawn(move || {
+                let mut client: Option<E3dcClient> = None;
+                for () in pending {
+                    let result = match &mut client {
+                        Some(client) => BatteryPoll::query(client, &config),
+                        None => connect(&config, retry).and_then(|mut connected| {
+                            let poll = BatteryPoll::query(&mut connected, &config);
+                            client = Some(connected);
+                            poll
+                        }),
+                    };
+                    if result.is_err() {
+                        client = None;
+                    }
+                    // Receiver is gone only during shutdown
+                    if completed.send(result).is_err() {
+                        break;
+                    }
+                }
+            })?;
+        Ok(Self { requests, results })
+    }
+
+    /// Ask for a poll, dropped while the previous one is still running
+    pub fn request(&self) {
+        match self.requests.try_send(()) {
+            Ok(()) => {}
+            Err(TrySendError::Full(())) => {
+                debug!("Battery poll still running, request dropped");
+            }
+            Err(TrySendError::Disconnected(())) => {
+                warn!("Battery worker stopped, batteries are no longer polled");
+            }
+        }
+    }
+
+    /// Result of a finished poll, None while none is waiting
+    pub fn try_result(&self) -> Option<Result<BatteryPoll, E3dcError>> {
+        self.results.try_recv().ok()
+    }
+}
+
+/// Client on a connection of its own, for work next to the main loop
+pub(crate) fn connect(config: &E3dcConfig, retry: RetryPolicy) -> Result<E3dcClient, E3dcError> {
+    let mut client = E3dcClient::new(
+        config.host.clone(),
+        config.key.clone(),
+        config.username.clone(),
+        config.password.clone(),
+        config.connect_timeout,
+    )?;
+    client.set_retry_policy(retry);
+    client.set_poll_dcbs(config.poll_dcbs);
+    Ok(client)
+}
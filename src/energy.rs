@@ -0,0 +1,165 @@
+//! Energy integrated from the status power readings (`[e3dc] integrate_energy`)
+//!
+//! The E3DC's DB sums behind the `*_today` topics occasionally jump after a
+//! firmware update. The bridge integrates the power of every status reading
+//! into Wh counters of its own (trapezoid rule), published next to the DB
+//! sums as `*_energy_integrated` for a cross-check.
+//!
+//! The counters restart at UTC midnight like the DB sums. Readings further
+//! apart than the maximum gap (a reconnect, a degraded E3DC) aren't
+//! integrated, the energy of such a gap is missing from the counters. The
+//! counters are kept in the state directory (`energy.json`), a restart during
+//! the day continues them.
+
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::mqtt::Status;
+use crate::state::StateFile;
+
+/// Integrated energy of one day in Wh
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct EnergyTotals {
+    pub solar_production: f64,
+    pub house_consumption: f64,
+    pub battery_charge: f64,
+    pub battery_discharge: f64,
+    pub export_to_grid: f64,
+    pub consumption_from_grid: f64,
+}
+
+impl EnergyTotals {
+    /// Power values of a reading in the order of [`EnergyTotals::counters`],
+    /// None if one of them is unknown
+    fn powers(status: &Status) -> Option<[f64; 6]> {
+        let powers = [
+            ("solar_production", status.solar_production),
+            ("house_consumption", status.house_consumption),
+            ("battery_charge", status.battery_charge),
+            ("battery_discharge", status.battery_discharge),
+            ("export_to_grid", status.export_to_grid),
+            ("consumption_from_grid", status.consumption_from_grid),
+        ];
+        powers
+            .iter()
+            .all(|(field, _)| status.is_known(field))
+            .then(|| powers.map(|(_, power)| power))
+    }
+
+    fn counters(&mut self) -> [&mut f64; 6] {
+        [
+            &mut self.solar_production,
+            &mut self.house_consumption,
+            &mut self.battery_charge,
+            &mut self.battery_discharge,
+            &mut self.export_to_grid,
+            &mut self.consumption_from_grid,
+        ]
+    }
+}
+
+/// Integrates the status readings into the counters of the current day
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct EnergyIntegrator {
+    day: Option<NaiveDate>,
+    totals: EnergyTotals,
+    /// Previous reading, a restart starts without one
+    #[serde(skip)]
+    last: Option<(DateTime<Utc>, [f64; 6])>,
+}
+
+impl StateFile for EnergyIntegrator {
+    const NAME: &'static str = "energy";
+    const VERSION: u32 = 1;
+}
+
+impl EnergyIntegrator {
+    /// Add the energy since the previous reading, unless it is more than `max_gap` ago
+    ///
+    /// The first reading of a new (UTC) day restarts the counters, the few
+    /// seconds before midnight since the previous reading are dropped. A
+    /// reading with unknown values counts as a gap.
+    pub fn update(&mut self, status: &Status, max_gap: Duration) {
+        let day = status.time.date_naive();
+        if self.day != Some(day) {
+            self.day = Some(day);
+            self.totals = EnergyTotals::default();
+            self.last = None;
+        }
+        let Some(powers) = EnergyTotals::powers(status) else {
+            self.last = None;
+            return;
+        };
+        if let Some((time, last)) = self.last {
+            let span = status.time - time;
+            if span > Duration::zero() && span <= max_gap {
+                let hours = span.num_milliseconds() as f64 / 3_600_000.0;
+                for ((counter, power), last) in
+                    self.totals.counters().into_iter().zip(powers).zip(last)
+                {
+                    *counter += (last + power) / 2.0 * hours;
+                }
+            }
+        }
+        self.last = Some((status.time, powers));
+    }
+
+    /// Counters of the current day, None before the first reading
+    pub fn totals(&self) -> Option<&EnergyTotals> {
+        self.day.map(|_| &self.totals)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::e3dc::EmergencyPowerState;
+    use chrono::TimeZone;
+
+    fn status(time: DateTime<Utc>, solar: f64, export: f64) -> Status {
+        Status {
+            time,
+            additional: 0.0,
+            autarky: 100.0,
+            battery_charge: 0.0,
+            battery_discharge: 0.0,
+            battery_consumption: 0.0,
+            consumption_from_grid: 0.0,
+            export_to_grid: export,
+            grid_production: 0.0,
+            house_consumption: solar - export,
+            self_consumption: 0.0,
+            solar_production: solar,
+            solar_production_excess: 0.0,
+            state_of_charge: 50.0,
+            wb_consumption: 0.0,
+            emergency_power: EmergencyPowerState::NotPossible,
+            unknown: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_energy_integrator() {
+        let start = Utc.with_ymd_and_hms(2024, 3, 1, 23, 0, 0).unwrap();
+        let at = |minutes| start + Duration::minutes(minutes);
+        let gap = Duration::minutes(5);
+        let mut energy = EnergyIntegrator::default();
+        assert_eq!(energy.totals(), None);
+
+        energy.update(&status(at(0), 1000.0, 0.0), gap);
+        energy.update(&status(at(3), 2000.0, 600.0), gap);
+        let totals = energy.totals().unwrap();
+        // 3 min at a mean of 1500 W, 300 W
+        assert!((totals.solar_production - 75.0).abs() < 1e-9);
+        assert!((totals.export_to_grid - 15.0).abs() < 1e-9);
+        assert!((totals.house_consumption - 60.0).abs() < 1e-9);
+
+        // A gap longer than the maximum isn't integrated
+        energy.update(&status(at(20), 2000.0, 600.0), gap);
+        assert!((energy.totals().unwrap().solar_production - 75.0).abs() < 1e-9);
+
+        // A new day restarts the counters
+        energy.update(&status(at(61), 0.0, 0.0), gap);
+        assert_eq!(energy.totals(), Some(&EnergyTotals::default()));
+    }
+}
@@ -3,6 +3,8 @@
 //! Uses thiserror for ergonomic error definitions.
 //! These errors can be converted to anyhow::Error in the main application.
 
+use crate::e3dc::TagName;
+
 /// E3DC connection and communication errors
 #[derive(Debug, thiserror::Error)]
 pub enum E3dcError {
@@ -15,15 +17,18 @@ pub enum E3dcError {
     #[error("Failed to parse E3DC response: {0}")]
     ParseError(String),
 
-    #[error("Missing tag: {0}")]
+    #[error("Missing tag {}", TagName(*.0))]
     MissingTag(u32),
 
-    #[error("Missing data in tag: {0}")]
+    #[error("Missing data in tag {}", TagName(*.0))]
     MissingData(u32),
 
     #[error("Invalid Datatype expected: {0}")]
     Type(String),
 
+    #[error("E3DC rejected write of tag {}: result code {code}", TagName(*.tag))]
+    WriteRejected { tag: u32, code: i64 },
+
     #[error(transparent)]
     Other(#[from] anyhow::Error),
 }
@@ -36,4 +41,55 @@ pub enum MqttError {
 
     #[error("Failed to serialize data: {error:?}")]
     SerializationError { error: serde_json::Error },
+
+    #[error("Failed to encode CBOR payload: {0}")]
+    CborError(String),
+
+    #[error("Failed to subscribe to topic '{topic}': {reason}")]
+    SubscribeFailed { topic: String, reason: String },
+
+    #[error("Failed to connect to MQTT broker {broker}: {reason}")]
+    ConnectFailed { broker: String, reason: String },
+
+    #[error("Lost the connection to the MQTT broker: {0}")]
+    ConnectionLost(String),
+}
+
+/// PV forecast download errors
+#[derive(Debug, thiserror::Error)]
+pub enum ForecastError {
+    #[error("Failed to fetch forecast from {0}")]
+    Request(String),
+
+    #[error("Forecast request failed with HTTP {status}: {message}")]
+    Http { status: u16, message: String },
+
+    #[error("Failed to parse forecast: {0}")]
+    ParseError(String),
+}
+
+/// Failure of a poll cycle, classified for the `[on_error]` policy
+#[derive(Debug, thiserror::Error)]
+pub enum BridgeError {
+    #[error(transparent)]
+    E3dc(#[from] E3dcError),
+
+    #[error(transparent)]
+    Mqtt(#[from] MqttError),
+}
+
+/// MQTT command parsing and validation errors
+#[derive(Debug, thiserror::Error)]
+pub enum CommandError {
+    #[error("Unknown command: {0}")]
+    UnknownCommand(String),
+
+    #[error("Command '{0}' is not allowed")]
+    NotAllowed(String),
+
+    #[error("Invalid payload for command '{command}': {reason}")]
+    InvalidPayload { command: String, reason: String },
+
+    #[error("Command '{command}' failed: {reason}")]
+    Failed { command: String, reason: String },
 }
@@ -0,0 +1,1077 @@
+//! Discrete events derived from consecutive polls
+//!
+//! Trackers keep the previously seen state and emit an event whenever it changes,
+//! so MQTT consumers don't have to diff the state topics themselves.
+
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::e3dc::{BatteryData, DcbCountChange, DcbData, EmergencyPowerState, Status};
+use crate::state::StateFile;
+
+/// Emergency power state transition (e.g. grid → island → grid)
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct EmergencyPowerEvent {
+    pub time: DateTime<Utc>,
+    pub from: &'static str,
+    pub to: &'static str,
+    pub island: bool,
+    /// Time spent in the previous state (None if it started before the bridge)
+    pub duration_seconds: Option<i64>,
+}
+
+/// Command that was not executed (denied by the ACL or invalid)
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RejectedCommandEvent {
+    pub time: DateTime<Utc>,
+    pub command: String,
+    pub payload: String,
+    pub reason: String,
+}
+
+/// DCB modules added to or removed from a battery at runtime
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DcbCountChangedEvent {
+    pub time: DateTime<Utc>,
+    pub battery: u64,
+    pub from: u64,
+    pub to: u64,
+}
+
+impl DcbCountChangedEvent {
+    pub fn new(time: DateTime<Utc>, change: DcbCountChange) -> Self {
+        Self {
+            time,
+            battery: change.battery,
+            from: change.from,
+            to: change.to,
+        }
+    }
+}
+
+/// DCB module added, removed or swapped (another serial number) since the last battery poll
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DcbModuleChangedEvent {
+    pub time: DateTime<Utc>,
+    pub battery: u64,
+    pub dcb: u64,
+    /// "added", "removed" or "swapped"
+    pub change: &'static str,
+    pub old_serial: Option<String>,
+    pub new_serial: Option<String>,
+}
+
+/// Battery cell temperature crossed a configured limit (or returned)
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TemperatureAlertEvent {
+    pub time: DateTime<Utc>,
+    pub battery: u64,
+    /// "high" or "low"
+    pub limit: &'static str,
+    pub active: bool,
+    pub temperature: f64,
+    pub threshold: f64,
+}
+
+/// Cell imbalance of a battery (`dcb` None) or DCB exceeded the threshold (or returned)
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CellImbalanceEvent {
+    pub time: DateTime<Utc>,
+    pub battery: u64,
+    pub dcb: Option<u64>,
+    pub active: bool,
+    /// Imbalance in mV
+    pub imbalance: f64,
+    pub threshold: f64,
+}
+
+/// Error code of a battery changed, `active` while it is not 0
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BatteryErrorEvent {
+    pub time: DateTime<Utc>,
+    pub battery: u64,
+    pub active: bool,
+    pub error_code: f64,
+    pub previous_code: f64,
+}
+
+/// State of charge crossed a configured low/high threshold (or returned)
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SocAlertEvent {
+    pub time: DateTime<Utc>,
+    /// "low" or "high"
+    pub limit: &'static str,
+    pub active: bool,
+    pub state_of_charge: f64,
+    pub threshold: f64,
+    /// Running on emergency power, e.g. for "battery nearly empty in island mode"
+    pub island: bool,
+}
+
+/// Grid export reached the configured limit, or dropped below it again
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ExportLimitEvent {
+    pub time: DateTime<Utc>,
+    pub active: bool,
+    pub export: f64,
+    pub limit: f64,
+    /// Highest export while at the limit, above `limit` means the limiter overshot
+    pub peak: Option<f64>,
+    pub duration_seconds: Option<i64>,
+}
+
+/// Energy balance residual persistently above the threshold (or back below)
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct EnergyBalanceEvent {
+    pub time: DateTime<Utc>,
+    pub active: bool,
+    pub residual: f64, // W
+    pub threshold: f64,
+}
+
+/// Completed wallbox charging session
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct WallboxSessionEvent {
+    pub time: DateTime<Utc>,
+    pub start: DateTime<Utc>,
+    /// First poll the wallbox no longer charged
+    pub end: DateTime<Utc>,
+    pub duration_seconds: i64,
+    pub energy: f64,        // Wh
+    pub average_power: f64, // W
+    pub solar_share: f64,   // %
+}
+
+/// Grid outage began (`active`) or ended, detected from the status polls
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct GridOutageEvent {
+    pub time: DateTime<Utc>,
+    pub active: bool,
+    /// "island" (emergency power state) or "grid_power" (no grid power while loads run)
+    pub cause: &'static str,
+    /// First poll of the outage
+    pub start: DateTime<Utc>,
+    /// Set when the outage ended
+    pub duration_seconds: Option<i64>,
+    /// Energy the battery supplied during the outage (Wh), set when it ended
+    pub battery_energy: Option<f64>,
+}
+
+/// Progress of an emergency power self-test started by command
+///
+/// `status` and `result` are the raw `EMS::EMERGENCYPOWER_TEST_STATUS` and
+/// `EMS::EMERGENCY_POWER_TEST_RESULT` values of the unit.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct EmergencyPowerTestEvent {
+    pub time: DateTime<Utc>,
+    /// "started", "progress", "finished" or "timeout"
+    pub phase: &'static str,
+    pub status: Option<u64>,
+    pub result: Option<u64>,
+    pub duration_seconds: i64,
+}
+
+/// Give up following a test after this long
+const EMERGENCY_POWER_TEST_TIMEOUT_MINUTES: i64 = 15;
+
+/// Follows a running emergency power self-test
+#[derive(Debug, Default)]
+pub struct EmergencyPowerTestTracker {
+    started: Option<DateTime<Utc>>,
+    last_status: Option<u64>,
+}
+
+impl EmergencyPowerTestTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.started.is_some()
+    }
+
+    pub fn start(&mut self, time: DateTime<Utc>) -> EmergencyPowerTestEvent {
+        self.started = Some(time);
+        self.last_status = None;
+        EmergencyPowerTestEvent {
+            time,
+            phase: "started",
+            status: None,
+            result: None,
+            duration_seconds: 0,
+        }
+    }
+
+    /// Feed the polled test status, returns an event when it changed
+    ///
+    /// The test is finished when the status returns to 0 after it left it.
+    pub fn update(
+        &mut self,
+        status: u64,
+        result: u64,
+        time: DateTime<Utc>,
+    ) -> Option<EmergencyPowerTestEvent> {
+        let started = self.started?;
+        let duration_seconds = (time - started).num_seconds();
+        let phase = if status == 0 && self.last_status.is_some_and(|last| last != 0) {
+            "finished"
+        } else if duration_seconds >= EMERGENCY_POWER_TEST_TIMEOUT_MINUTES * 60 {
+            "timeout"
+        } else if self.last_status != Some(status) {
+            "progress"
+        } else {
+            return None;
+        };
+        self.last_status = Some(status);
+        if phase != "progress" {
+            self.started = None;
+        }
+        Some(EmergencyPowerTestEvent {
+            time,
+            phase,
+            status: Some(status),
+            result: (phase == "finished").then_some(result),
+            duration_seconds,
+        })
+    }
+}
+
+/// Tracks emergency power state changes between status polls
+#[derive(Debug, Default)]
+pub struct EmergencyPowerTracker {
+    last: Option<(EmergencyPowerState, DateTime<Utc>)>,
+    /// Set once a transition has been observed, so durations are known
+    since_observed: bool,
+}
+
+impl EmergencyPowerTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feed the current state, returns an event if it differs from the previous poll
+    pub fn update(
+        &mut self,
+        state: EmergencyPowerState,
+        time: DateTime<Utc>,
+    ) -> Option<EmergencyPowerEvent> {
+        match self.last {
+            None => {
+                self.last = Some((state, time));
+                None
+            }
+            Some((previous, _)) if previous == state => None,
+            Some((previous, since)) => {
+                let duration_seconds = if self.since_observed {
+                    Some((time - since).num_seconds())
+                } else {
+                    None
+                };
+                self.last = Some((state, time));
+                self.since_observed = true;
+                Some(EmergencyPowerEvent {
+                    time,
+                    from: previous.as_str(),
+                    to: state.as_str(),
+                    island: state.is_island(),
+                    duration_seconds,
+                })
+            }
+        }
+    }
+}
+
+/// Emergency power usage since the first start with `[default] state_dir`
+///
+/// Island time and the energy delivered to the house and wallbox are
+/// integrated between consecutive island polls at most `max_gap` apart, the
+/// time the bridge was down during an outage is missing from them.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct EmergencyPowerUsage {
+    /// Switchovers from grid to island operation
+    pub switchovers: u64,
+    pub island_seconds: f64,
+    /// Energy delivered in island operation (Wh)
+    pub island_energy: f64,
+    /// Island operation at the last poll, a restart during an outage doesn't count it twice
+    #[serde(default)]
+    island: bool,
+    /// Time and delivered power of the previous island poll
+    #[serde(skip)]
+    last: Option<(DateTime<Utc>, f64)>,
+}
+
+impl StateFile for EmergencyPowerUsage {
+    const NAME: &'static str = "emergency_power";
+    const VERSION: u32 = 1;
+}
+
+impl EmergencyPowerUsage {
+    /// Feed a status poll, returns whether island operation started or ended
+    ///
+    /// The seconds and energy integrated in between change on every island
+    /// poll, they are left to the caller's coarser save interval.
+    pub fn update(&mut self, status: &Status, max_gap: TimeDelta) -> bool {
+        let island = status.emergency_power_state.is_island();
+        let was_island = std::mem::replace(&mut self.island, island);
+        if !island {
+            self.last = None;
+            return was_island;
+        }
+        if !was_island {
+            self.switchovers += 1;
+        }
+        // Without a wallbox its power isn't answered
+        let power =
+            (status.power_home.unwrap_or_default() + status.power_wb.unwrap_or_default()).max(0.0);
+        if let Some((time, last_power)) = self.last {
+            let span = status.time_stamp - time;
+            if span > TimeDelta::zero() && span <= max_gap {
+                let seconds = span.num_milliseconds() as f64 / 1000.0;
+                self.island_seconds += seconds;
+                self.island_energy += (last_power + power) / 2.0 * seconds / 3600.0;
+            }
+        }
+        self.last = Some((status.time_stamp, power));
+        !was_island
+    }
+
+    /// Island operation at the last poll
+    pub fn is_island(&self) -> bool {
+        self.island
+    }
+}
+
+/// Serial numbers of the DCB modules per battery
+///
+/// Kept in the state directory (`dcb_modules.json`), so modules swapped while
+/// the bridge was stopped (a service visit) are reported after the restart.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct DcbModules {
+    batteries: BTreeMap<u64, BTreeMap<u64, String>>,
+}
+
+impl StateFile for DcbModules {
+    const NAME: &'static str = "dcb_modules";
+    const VERSION: u32 = 1;
+}
+
+impl DcbModules {
+    /// Serial of a module, the serial code if the DCB reports one
+    fn serial(dcb: &DcbData) -> String {
+        if dcb.serial_code.trim().is_empty() {
+            format!("{:.0}", dcb.serial_no)
+        } else {
+            dcb.serial_code.trim().to_string()
+        }
+    }
+
+    /// Compare the modules of `battery` with the recorded ones and record them,
+    /// None if nothing changed
+    ///
+    /// A battery seen for the first time is only recorded (no events).
+    pub fn update(
+        &mut self,
+        time: DateTime<Utc>,
+        battery: &BatteryData,
+    ) -> Option<Vec<DcbModuleChangedEvent>> {
+        let serials: BTreeMap<u64, String> = battery
+            .dcbs
+            .iter()
+            .map(|dcb| (dcb.index, Self::serial(dcb)))
+            .collect();
+        let Some(old) = self.batteries.get(&battery.index) else {
+            self.batteries.insert(battery.index, serials);
+            return Some(Vec::new());
+        };
+        if *old == serials {
+            return None;
+        }
+        let indexes: std::collections::BTreeSet<u64> =
+            old.keys().chain(serials.keys()).copied().collect();
+        let events = indexes
+            .into_iter()
+            .filter_map(|dcb| {
+                let old_serial = old.get(&dcb).cloned();
+                let new_serial = serials.get(&dcb).cloned();
+                let change = match (&old_serial, &new_serial) {
+                    (None, Some(_)) => "added",
+                    (Some(_), None) => "removed",
+                    (Some(old), Some(new)) if old != new => "swapped",
+                    _ => return None,
+                };
+                Some(DcbModuleChangedEvent {
+                    time,
+                    battery: battery.index,
+                    dcb,
+                    change,
+                    old_serial,
+                    new_serial,
+                })
+            })
+            .collect();
+        self.batteries.insert(battery.index, serials);
+        Some(events)
+    }
+}
+
+/// Tracks the error code of every battery between battery polls
+#[derive(Debug, Default)]
+pub struct BatteryErrorTracker {
+    codes: BTreeMap<u64, f64>,
+}
+
+impl BatteryErrorTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feed the current error code of `battery`, returns an event if it
+    /// differs from the previous poll (the first poll only records it)
+    pub fn update(
+        &mut self,
+        battery: u64,
+        error_code: f64,
+        time: DateTime<Utc>,
+    ) -> Option<BatteryErrorEvent> {
+        let previous_code = self.codes.insert(battery, error_code)?;
+        (previous_code != error_code).then_some(BatteryErrorEvent {
+            time,
+            battery,
+            active: error_code != 0.0,
+            error_code,
+            previous_code,
+        })
+    }
+}
+
+/// Edge-triggered SOC low/high alerts with hysteresis
+///
+/// The low alert is raised at or below `low` and cleared at `low + hysteresis`,
+/// the high alert raised at or above `high` and cleared at `high - hysteresis`.
+#[derive(Debug)]
+pub struct SocTracker {
+    low: Option<f64>,
+    high: Option<f64>,
+    hysteresis: f64,
+    /// Current (low, high) alert state, None until the first poll
+    state: Option<(bool, bool)>,
+}
+
+impl SocTracker {
+    pub fn new(low: Option<f64>, high: Option<f64>, hysteresis: f64) -> Self {
+        Self {
+            low,
+            high,
+            hysteresis,
+            state: None,
+        }
+    }
+
+    /// Feed the current SOC, returns the alerts that changed
+    ///
+    /// The first poll only sets the initial state, like the other trackers.
+    pub fn update(
+        &mut self,
+        soc: f64,
+        emergency_power: EmergencyPowerState,
+        time: DateTime<Utc>,
+    ) -> Vec<SocAlertEvent> {
+        let (was_low, was_high) = self.state.unwrap_or_default();
+        let low = self
+            .low
+            .is_some_and(|low| soc <= low || (was_low && soc < low + self.hysteresis));
+        let high = self
+            .high
+            .is_some_and(|high| soc >= high || (was_high && soc > high - self.hysteresis));
+        let first = self.state.is_none();
+        self.state = Some((low, high));
+        if first {
+            return Vec::new();
+        }
+
+        let changes = [
+            ("low", self.low, low, was_low),
+            ("high", self.high, high, was_high),
+        ];
+        changes
+            .into_iter()
+            .filter(|(_, _, active, was)| active != was)
+            .filter_map(|(limit, threshold, active, _)| {
+                Some(SocAlertEvent {
+                    time,
+                    limit,
+                    active,
+                    state_of_charge: soc,
+                    threshold: threshold?,
+                    island: emergency_power.is_island(),
+                })
+            })
+            .collect()
+    }
+}
+
+/// Wallbox power at or above this counts as charging (W)
+const WALLBOX_CHARGING_POWER: f64 = 100.0;
+
+/// A session ends after the wallbox did not charge for this long, so short
+/// pauses of the car don't split it
+const WALLBOX_SESSION_END: TimeDelta = TimeDelta::minutes(10);
+
+#[derive(Debug)]
+struct WallboxSession {
+    start: DateTime<Utc>,
+    /// Charging stopped (possibly only paused) at this poll
+    stopped: Option<DateTime<Utc>>,
+    energy: f64,
+    solar_energy: f64,
+}
+
+/// Tracks wallbox charging sessions from the status polls
+///
+/// Energy is integrated from the wallbox power between polls.
+#[derive(Debug, Default)]
+pub struct WallboxSessionTracker {
+    session: Option<WallboxSession>,
+    /// Time, power and solar power of the previous poll
+    last: Option<(DateTime<Utc>, f64, f64)>,
+    /// Energy of the last completed session
+    last_energy: Option<f64>,
+}
+
+impl WallboxSessionTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Energy of the running session, or of the last one if none is running (Wh)
+    pub fn session_energy(&self) -> Option<f64> {
+        self.session
+            .as_ref()
+            .map(|session| session.energy)
+            .or(self.last_energy)
+    }
+
+    /// Feed the wallbox power and its solar share, returns the session summary once it ended
+    pub fn update(
+        &mut self,
+        power: f64,
+        solar_power: f64,
+        time: DateTime<Utc>,
+    ) -> Option<WallboxSessionEvent> {
+        if let (Some(session), Some((last_time, last_power, last_solar))) =
+            (&mut self.session, self.last)
+        {
+            let hours = (time - last_time).num_milliseconds() as f64 / 3_600_000.0;
+            let last_power = last_power.max(0.0);
+            session.energy += last_power * hours;
+            session.solar_energy += last_solar.clamp(0.0, last_power) * hours;
+        }
+        self.last = Some((time, power, solar_power));
+
+        let charging = power >= WALLBOX_CHARGING_POWER;
+        match &mut self.session {
+            None if charging => {
+                self.session = Some(WallboxSession {
+                    start: time,
+                    stopped: None,
+                    energy: 0.0,
+                    solar_energy: 0.0,
+                });
+                None
+            }
+            Some(session) if charging => {
+                session.stopped = None;
+                None
+            }
+            Some(WallboxSession {
+                stopped: stopped @ None,
+                ..
+            }) => {
+                *stopped = Some(time);
+                None
+            }
+            Some(session)
+                if session
+                    .stopped
+                    .is_some_and(|stopped| time - stopped >= WALLBOX_SESSION_END) =>
+            {
+                let end = session.stopped.unwrap_or(time);
+                let duration = end - session.start;
+                let hours = duration.num_milliseconds() as f64 / 3_600_000.0;
+                let event = WallboxSessionEvent {
+                    time,
+                    start: session.start,
+                    end,
+                    duration_seconds: duration.num_seconds(),
+                    energy: session.energy.round(),
+                    average_power: if hours > 0.0 {
+                        (session.energy / hours).round()
+                    } else {
+                        0.0
+                    },
+                    solar_share: if session.energy > 0.0 {
+                        (session.solar_energy / session.energy * 1000.0).round() / 10.0
+                    } else {
+                        0.0
+                    },
+                };
+                self.last_energy = Some(session.energy);
+                self.session = None;
+                Some(event)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Outage candidate or ongoing outage
+#[derive(Debug)]
+struct GridOutage {
+    start: DateTime<Utc>,
+    cause: &'static str,
+    /// Polls the condition held so far
+    polls: u32,
+    announced: bool,
+    battery_energy: f64,
+    /// Time and battery discharge power of the previous poll
+    last: (DateTime<Utc>, f64),
+}
+
+/// Detects grid outages from the status polls
+///
+/// An island transition of the emergency power state starts an outage right
+/// away. Without emergency power hardware the state never changes, there an
+/// outage is assumed when the grid power reads exactly 0 W for `polls`
+/// consecutive polls while the house still consumes. The battery discharge
+/// is integrated from the first poll of the outage.
+#[derive(Debug)]
+pub struct GridOutageTracker {
+    polls: u32,
+    outage: Option<GridOutage>,
+}
+
+impl GridOutageTracker {
+    pub fn new(polls: u32) -> Self {
+        Self {
+            polls: polls.max(1),
+            outage: None,
+        }
+    }
+
+    /// Feed a status poll, returns an event when an outage began or ended
+    pub fn update(&mut self, status: &Status) -> Option<GridOutageEvent> {
+        let time = status.time_stamp;
+        let discharge = status.power_battery.map_or(0.0, |power| (-power).max(0.0));
+        let cause = if status.emergency_power_state.is_island() {
+            Some("island")
+        } else if status.power_grid == Some(0.0) && status.power_home.is_some_and(|home| home > 0.0)
+        {
+            Some("grid_power")
+        } else {
+            None
+        };
+        // The poll ending the outage closes its last interval
+        if let Some(outage) = &mut self.outage {
+            let (last_time, last_discharge) = outage.last;
+            let hours = (time - last_time).num_milliseconds() as f64 / 3_600_000.0;
+            outage.battery_energy += (last_discharge + discharge) / 2.0 * hours;
+            outage.last = (time, discharge);
+        }
+        match (&mut self.outage, cause) {
+            (None, None) => None,
+            (None, Some(cause)) => {
+                self.outage = Some(GridOutage {
+                    start: time,
+                    cause,
+                    polls: 0,
+                    announced: false,
+                    battery_energy: 0.0,
+                    last: (time, discharge),
+                });
+                self.announce(time)
+            }
+            (Some(outage), Some(cause)) => {
+                // The island state is the more reliable of the two
+                if cause == "island" {
+                    outage.cause = cause;
+                }
+                self.announce(time)
+            }
+            (Some(_), None) => {
+                let outage = self.outage.take()?;
+                outage.announced.then(|| GridOutageEvent {
+                    time,
+                    active: false,
+                    cause: outage.cause,
+                    start: outage.start,
+                    duration_seconds: Some((time - outage.start).num_seconds()),
+                    battery_energy: Some(outage.battery_energy.round()),
+                })
+            }
+        }
+    }
+
+    /// Count a poll of the outage, the begin event once it is confirmed
+    fn announce(&mut self, time: DateTime<Utc>) -> Option<GridOutageEvent> {
+        let outage = self.outage.as_mut()?;
+        outage.polls += 1;
+        let confirmed = outage.cause == "island" || outage.polls >= self.polls;
+        if outage.announced || !confirmed {
+            return None;
+        }
+        outage.announced = true;
+        Some(GridOutageEvent {
+            time,
+            active: true,
+            cause: outage.cause,
+            start: outage.start,
+            duration_seconds: None,
+            battery_energy: None,
+        })
+    }
+}
+
+/// Export within this share of the limit counts as at the limit
+const EXPORT_LIMIT_HIT: f64 = 0.99;
+
+/// Export must drop below this share of the limit to leave it
+const EXPORT_LIMIT_RELEASE: f64 = 0.95;
+
+/// Tracks when the grid export runs into the configured limit
+#[derive(Debug)]
+pub struct ExportLimitTracker {
+    limit: f64,
+    /// Start and peak export of the current period at the limit
+    hit: Option<(DateTime<Utc>, f64)>,
+}
+
+impl ExportLimitTracker {
+    pub fn new(limit: f64) -> Self {
+        Self { limit, hit: None }
+    }
+
+    pub fn limit(&self) -> f64 {
+        self.limit
+    }
+
+    /// Feed the current export in W, returns an event when the limit is hit or left
+    pub fn update(&mut self, export: f64, time: DateTime<Utc>) -> Option<ExportLimitEvent> {
+        match &mut self.hit {
+            None if export >= self.limit * EXPORT_LIMIT_HIT => {
+                self.hit = Some((time, export));
+                Some(ExportLimitEvent {
+                    time,
+                    active: true,
+                    export,
+                    limit: self.limit,
+                    peak: None,
+                    duration_seconds: None,
+                })
+            }
+            Some((since, peak)) if export < self.limit * EXPORT_LIMIT_RELEASE => {
+                let event = ExportLimitEvent {
+                    time,
+                    active: false,
+                    export,
+                    limit: self.limit,
+                    peak: Some(*peak),
+                    duration_seconds: Some((time - *since).num_seconds()),
+                };
+                self.hit = None;
+                Some(event)
+            }
+            Some((_, peak)) => {
+                *peak = peak.max(export);
+                None
+            }
+            None => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, TimeZone};
+
+    #[test]
+    fn test_battery_error_tracker() {
+        let time = Utc.with_ymd_and_hms(2024, 1, 15, 12, 0, 0).unwrap();
+        let mut tracker = BatteryErrorTracker::new();
+        assert_eq!(tracker.update(0, 0.0, time), None);
+        assert_eq!(tracker.update(1, 4.0, time), None);
+        assert_eq!(tracker.update(0, 0.0, time), None);
+
+        let raised = tracker.update(0, 17.0, time).unwrap();
+        assert!(raised.active);
+        assert_eq!(raised.previous_code, 0.0);
+        let cleared = tracker.update(0, 0.0, time).unwrap();
+        assert!(!cleared.active);
+        assert_eq!(cleared.previous_code, 17.0);
+    }
+
+    #[test]
+    fn test_emergency_power_test_progress() {
+        let start = Utc.with_ymd_and_hms(2024, 1, 15, 12, 0, 0).unwrap();
+        let mut tracker = EmergencyPowerTestTracker::new();
+        assert_eq!(tracker.update(1, 0, start), None);
+
+        assert_eq!(tracker.start(start).phase, "started");
+        let at = |seconds| start + Duration::seconds(seconds);
+        // Not picked up by the unit yet
+        assert_eq!(tracker.update(0, 0, at(5)).unwrap().phase, "progress");
+        assert_eq!(tracker.update(0, 0, at(10)), None);
+        assert_eq!(tracker.update(2, 0, at(15)).unwrap().phase, "progress");
+        let finished = tracker.update(0, 1, at(60)).unwrap();
+        assert_eq!(finished.phase, "finished");
+        assert_eq!(finished.result, Some(1));
+        assert_eq!(finished.duration_seconds, 60);
+        assert!(!tracker.is_running());
+
+        tracker.start(start);
+        assert_eq!(tracker.update(0, 0, at(900)).unwrap().phase, "timeout");
+        assert!(!tracker.is_running());
+    }
+
+    #[test]
+    fn test_emergency_power_transitions() {
+        let start = Utc.with_ymd_and_hms(2024, 1, 15, 12, 0, 0).unwrap();
+        let mut tracker = EmergencyPowerTracker::new();
+
+        assert_eq!(tracker.update(EmergencyPowerState::NotActive, start), None);
+        assert_eq!(
+            tracker.update(EmergencyPowerState::NotActive, start + Duration::seconds(5)),
+            None
+        );
+
+        let island = tracker
+            .update(EmergencyPowerState::Active, start + Duration::seconds(10))
+            .unwrap();
+        assert_eq!(island.from, "grid");
+        assert_eq!(island.to, "island");
+        assert!(island.island);
+        // Grid state started before the bridge, so its duration is unknown
+        assert_eq!(island.duration_seconds, None);
+
+        let grid = tracker
+            .update(
+                EmergencyPowerState::NotActive,
+                start + Duration::seconds(130),
+            )
+            .unwrap();
+        assert_eq!(grid.from, "island");
+        assert_eq!(grid.to, "grid");
+        assert!(!grid.island);
+        assert_eq!(grid.duration_seconds, Some(120));
+    }
+
+    #[test]
+    fn test_soc_alerts() {
+        let time = Utc.with_ymd_and_hms(2024, 1, 15, 12, 0, 0).unwrap();
+        let grid = EmergencyPowerState::NotActive;
+        let mut tracker = SocTracker::new(Some(10.0), Some(95.0), 2.0);
+
+        assert!(tracker.update(30.0, grid, time).is_empty());
+        let low = tracker.update(10.0, EmergencyPowerState::Active, time);
+        assert_eq!(low.len(), 1);
+        assert_eq!((low[0].limit, low[0].active), ("low", true));
+        assert!(low[0].island);
+
+        // Within the hysteresis band nothing changes
+        assert!(tracker.update(11.5, grid, time).is_empty());
+        assert!(tracker.update(9.0, grid, time).is_empty());
+        let cleared = tracker.update(12.0, grid, time);
+        assert_eq!((cleared[0].limit, cleared[0].active), ("low", false));
+
+        let high = tracker.update(95.0, grid, time);
+        assert_eq!((high[0].limit, high[0].active), ("high", true));
+        assert!(tracker.update(93.5, grid, time).is_empty());
+        assert!(!tracker.update(93.0, grid, time)[0].active);
+    }
+
+    #[test]
+    fn test_wallbox_session() {
+        let start = Utc.with_ymd_and_hms(2024, 6, 15, 12, 0, 0).unwrap();
+        let at = |minutes| start + Duration::minutes(minutes);
+        let mut tracker = WallboxSessionTracker::new();
+        assert_eq!(tracker.update(0.0, 0.0, at(0)), None);
+        assert_eq!(tracker.session_energy(), None);
+
+        // One hour at 11 kW, half of it from solar
+        assert_eq!(tracker.update(11000.0, 5500.0, at(1)), None);
+        assert_eq!(tracker.update(11000.0, 5500.0, at(31)), None);
+        assert_eq!(tracker.session_energy(), Some(5500.0));
+        assert_eq!(tracker.update(0.0, 0.0, at(61)), None);
+        // Short pause, the session continues
+        assert_eq!(tracker.update(0.0, 0.0, at(65)), None);
+
+        let event = tracker.update(0.0, 0.0, at(71)).unwrap();
+        assert_eq!(event.start, at(1));
+        assert_eq!(event.end, at(61));
+        assert_eq!(event.average_power, 11000.0);
+        assert_eq!(event.energy, 11000.0);
+        assert_eq!(event.solar_share, 50.0);
+        assert_eq!(tracker.session_energy(), Some(11000.0));
+    }
+
+    #[test]
+    fn test_grid_outage() {
+        let start = Utc.with_ymd_and_hms(2024, 6, 15, 12, 0, 0).unwrap();
+        let status = |minutes, grid, battery, state| Status {
+            time_stamp: start + Duration::minutes(minutes),
+            power_battery: Some(battery),
+            power_wb: Some(0.0),
+            power_wb_solar: Some(0.0),
+            power_home: Some(600.0),
+            power_pv: Some(0.0),
+            power_grid: Some(grid),
+            power_add: Some(0.0),
+            battery_soc: Some(50.0),
+            autarky: Some(100.0),
+            self_consumption: Some(100.0),
+            emergency_power_state: state,
+            missing: Vec::new(),
+        };
+        let mut tracker = GridOutageTracker::new(3);
+        assert_eq!(
+            tracker.update(&status(0, 0.0, 0.0, EmergencyPowerState::NotPossible)),
+            None
+        );
+        assert_eq!(
+            tracker.update(&status(1, 150.0, 0.0, EmergencyPowerState::NotPossible)),
+            None
+        );
+
+        // Zero grid power needs three polls, the battery takes over
+        for minutes in [2, 3] {
+            let poll = status(minutes, 0.0, -600.0, EmergencyPowerState::NotPossible);
+            assert_eq!(tracker.update(&poll), None);
+        }
+        let began = tracker
+            .update(&status(4, 0.0, -600.0, EmergencyPowerState::NotPossible))
+            .unwrap();
+        assert!(began.active);
+        assert_eq!(
+            (began.cause, began.start),
+            ("grid_power", start + Duration::minutes(2))
+        );
+        let ended = tracker
+            .update(&status(12, -50.0, 0.0, EmergencyPowerState::NotPossible))
+            .unwrap();
+        assert!(!ended.active);
+        assert_eq!(ended.duration_seconds, Some(600));
+        // 2 min at 600 W, 8 min ramping down to 0 W
+        assert_eq!(ended.battery_energy, Some(60.0));
+
+        // The island state is reported right away
+        let island = tracker
+            .update(&status(20, 0.0, -800.0, EmergencyPowerState::Active))
+            .unwrap();
+        assert_eq!((island.active, island.cause), (true, "island"));
+        let ended = tracker
+            .update(&status(21, -100.0, 0.0, EmergencyPowerState::NotActive))
+            .unwrap();
+        assert_eq!(ended.battery_energy, Some(7.0));
+    }
+
+    #[test]
+    fn test_emergency_power_usage() {
+        let start = Utc.with_ymd_and_hms(2024, 6, 15, 12, 0, 0).unwrap();
+        let status = |minutes, state| Status {
+            time_stamp: start + Duration::minutes(minutes),
+            power_battery: Some(-1000.0),
+            power_wb: Some(200.0),
+            power_wb_solar: Some(0.0),
+            power_home: Some(800.0),
+            power_pv: Some(0.0),
+            power_grid: Some(0.0),
+            power_add: Some(0.0),
+            battery_soc: Some(50.0),
+            autarky: Some(100.0),
+            self_consumption: Some(100.0),
+            emergency_power_state: state,
+            missing: Vec::new(),
+        };
+        let gap = Duration::minutes(5);
+        let mut usage = EmergencyPowerUsage::default();
+        assert!(!usage.update(&status(0, EmergencyPowerState::NotActive), gap));
+        assert!(usage.update(&status(1, EmergencyPowerState::Active), gap));
+        // Integrating doesn't ask for a save on every poll
+        for minutes in [2, 3] {
+            assert!(!usage.update(&status(minutes, EmergencyPowerState::Active), gap));
+        }
+        assert!(usage.is_island());
+        assert!(usage.update(&status(4, EmergencyPowerState::NotActive), gap));
+        assert_eq!(usage.switchovers, 1);
+        assert_eq!(usage.island_seconds, 120.0);
+        assert!((usage.island_energy - 1000.0 / 30.0).abs() < 1e-9);
+
+        // A gap (bridge restart) isn't integrated, nor counted as switchover
+        usage.update(&status(10, EmergencyPowerState::Active), gap);
+        usage.last = None;
+        usage.update(&status(11, EmergencyPowerState::Active), gap);
+        assert_eq!(usage.switchovers, 2);
+        assert_eq!(usage.island_seconds, 120.0);
+    }
+
+    #[test]
+    fn test_dcb_modules() {
+        let time = Utc.with_ymd_and_hms(2024, 6, 15, 12, 0, 0).unwrap();
+        let battery = |serials: &[&str]| BatteryData {
+            index: 0,
+            dcbs: serials
+                .iter()
+                .enumerate()
+                .map(|(index, serial)| DcbData {
+                    index: index as u64,
+                    serial_code: serial.to_string(),
+                    serial_no: 4711.0,
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        };
+        let mut modules = DcbModules::default();
+        assert_eq!(modules.update(time, &battery(&["A", "B"])), Some(vec![]));
+        assert_eq!(modules.update(time, &battery(&["A", "B"])), None);
+
+        let events = modules.update(time, &battery(&["A", "C", "D"])).unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].change, "swapped");
+        assert_eq!(events[0].dcb, 1);
+        assert_eq!(events[0].old_serial.as_deref(), Some("B"));
+        assert_eq!(events[0].new_serial.as_deref(), Some("C"));
+        assert_eq!(events[1].change, "added");
+        assert_eq!(events[1].old_serial, None);
+
+        // Without a serial code the serial number is used
+        let events = modules.update(time, &battery(&["A", ""])).unwrap();
+        assert_eq!(events[0].change, "swapped");
+        assert_eq!(events[0].new_serial.as_deref(), Some("4711"));
+        assert_eq!(events[1].change, "removed");
+        assert_eq!(events[1].new_serial, None);
+    }
+
+    #[test]
+    fn test_export_limit() {
+        let start = Utc.with_ymd_and_hms(2024, 6, 15, 12, 0, 0).unwrap();
+        let mut tracker = ExportLimitTracker::new(7000.0);
+        assert_eq!(tracker.update(5000.0, start), None);
+
+        let hit = tracker.update(6950.0, start).unwrap();
+        assert!(hit.active);
+        assert_eq!(tracker.update(7200.0, start + Duration::seconds(5)), None);
+        // Still close to the limit
+        assert_eq!(tracker.update(6800.0, start + Duration::seconds(10)), None);
+
+        let left = tracker
+            .update(6000.0, start + Duration::seconds(60))
+            .unwrap();
+        assert!(!left.active);
+        assert_eq!(left.peak, Some(7200.0));
+        assert_eq!(left.duration_seconds, Some(60));
+    }
+}
@@ -0,0 +1,246 @@
+//! PV forecast from [Forecast.Solar](https://forecast.solar) (`[forecast]`)
+//!
+//! Fetches the public estimate for the configured plane. The bridge has no
+//! HTTP client otherwise, so this is a plain HTTP/1.0 GET on native-tls (the
+//! TLS stack the MQTT connection already uses); HTTP/1.0 keeps the response
+//! free of chunked encoding.
+//!
+//! [`ForecastWorker`] fetches on a thread of its own: a request takes up to
+//! [`FETCH_TIMEOUT`] per step, which must not delay the E3DC polls.
+
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::thread;
+use std::time::Duration;
+
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime};
+use serde::Deserialize;
+use tracing::{debug, warn};
+
+use crate::config::ForecastConfig;
+use crate::errors::ForecastError;
+use crate::net;
+
+const HOST: &str = "api.forecast.solar";
+
+/// Connect, read and write timeout of a forecast request
+pub const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Wait before retrying a failed fetch, unless `interval` is shorter
+pub const RETRY_DELAY: Duration = Duration::from_secs(5 * 60);
+
+/// Estimate for the configured plane, times in the location's local time
+#[derive(Debug, Clone, PartialEq)]
+pub struct PvForecast {
+    /// Expected power (W) per period start
+    pub watts: BTreeMap<NaiveDateTime, f64>,
+    /// Expected energy (Wh) per day
+    pub energy_per_day: BTreeMap<NaiveDate, f64>,
+}
+
+#[derive(Deserialize)]
+struct Response {
+    result: ResponseResult,
+}
+
+#[derive(Deserialize)]
+struct ResponseResult {
+    watts: BTreeMap<String, f64>,
+    watt_hours_day: BTreeMap<String, f64>,
+}
+
+impl PvForecast {
+    /// Parse the JSON body of an `/estimate` response
+    pub fn parse(body: &str) -> Result<Self, ForecastError> {
+        let invalid = |e: String| ForecastError::ParseError(e);
+        let response: Response = serde_json::from_str(body).map_err(|e| invalid(e.to_string()))?;
+        let watts = response
+            .result
+            .watts
+            .iter()
+            .map(|(time, watts)| {
+                NaiveDateTime::parse_from_str(time, "%Y-%m-%d %H:%M:%S")
+                    .map(|time| (time, *watts))
+                    .map_err(|_| invalid(format!("invalid time '{}'", time)))
+            })
+            .collect::<Result<_, _>>()?;
+        let energy_per_day = response
+            .result
+            .watt_hours_day
+            .iter()
+            .map(|(day, energy)| {
+                day.parse::<NaiveDate>()
+                    .map(|day| (day, *energy))
+                    .map_err(|_| invalid(format!("invalid date '{}'", day)))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            watts,
+            energy_per_day,
+        })
+    }
+
+    /// Expected energy (Wh) of `day`, None if the forecast doesn't cover it
+    pub fn energy(&self, day: NaiveDate) -> Option<f64> {
+        self.energy_per_day.get(&day).copied()
+    }
+}
+
+/// The day whose production decides about charging from the grid at `now`
+///
+/// Before noon that's today (the night is over), afterwards tomorrow.
+pub fn upcoming_day(now: NaiveDateTime) -> NaiveDate {
+    let noon = NaiveTime::from_hms_opt(12, 0, 0).expect("noon is a valid time");
+    if now.time() < noon {
+        now.date()
+    } else {
+        now.date().succ_opt().unwrap_or(now.date())
+    }
+}
+
+/// Request path of the estimate for the configured plane
+pub fn estimate_path(config: &ForecastConfig) -> String {
+    format!(
+        "/estimate/{}/{}/{}/{}/{}",
+        config.latitude, config.longitude, config.declination, config.azimuth, config.kwp
+    )
+}
+
+/// Fetch the current estimate
+pub fn fetch(config: &ForecastConfig, timeout: Duration) -> Result<PvForecast, ForecastError> {
+    let response = https_get(
+        HOST,
+        &estimate_path(config),
+        timeout,
+        config.bind_interface.as_deref(),
+    )?;
+    PvForecast::parse(response_body(&response)?)
+}
+
+/// Handle of the forecast fetch thread
+pub struct ForecastWorker {
+    requests: SyncSender<()>,
+    results: Receiver<Result<PvForecast, ForecastError>>,
+}
+
+impl ForecastWorker {
+    pub fn spawn(config: &ForecastConfig) -> std::io::Result<Self> {
+        let (requests, pending) = mpsc::sync_channel(0);
+        let (completed, results) = mpsc::channel();
+        let config = config.clone();
+        thread::Builder::new()
+            .name("forecast".to_string())
+            .spawn(move || {
+                for () in pending {
+                    // Receiver is gone only during shutdown
+                    if completed.send(fetch(&config, FETCH_TIMEOUT)).is_err() {
+                        break;
+                    }
+                }
+            })?;
+        Ok(Self { requests, results })
+    }
+
+    /// Ask for a fetch, dropped while the previous one is still running
+    pub fn request(&self) {
+        match self.requests.try_send(()) {
+            Ok(()) => {}
+            Err(TrySendError::Full(())) => debug!("PV forecast fetch still running"),
+            Err(TrySendError::Disconnected(())) => {
+                warn!("Forecast worker stopped, the PV forecast is no longer fetched");
+            }
+        }
+    }
+
+    /// Result of a finished fetch, None while none is waiting
+    pub fn try_result(&self) -> Option<Result<PvForecast, ForecastError>> {
+        self.results.try_recv().ok()
+    }
+}
+
+fn https_get(
+    host: &str,
+    path: &str,
+    timeout: Duration,
+    interface: Option<&str>,
+) -> Result<String, ForecastError> {
+    let failed = |e: String| ForecastError::Request(format!("{}: {}", host, e));
+    let stream = net::connect(host, 443, timeout, interface).map_err(|e| failed(e.to_string()))?;
+    stream
+        .set_read_timeout(Some(timeout))
+        .and_then(|_| stream.set_write_timeout(Some(timeout)))
+        .map_err(|e| failed(e.to_string()))?;
+    let connector = native_tls::TlsConnector::new().map_err(|e| failed(e.to_string()))?;
+    let mut stream = connector
+        .connect(host, stream)
+        .map_err(|e| failed(e.to_string()))?;
+
+    let request = format!(
+        "GET {} HTTP/1.0\r\nHost: {}\r\nAccept: application/json\r\nUser-Agent: e3dc-mqtt-rs/{}\r\n\r\n",
+        path,
+        host,
+        env!("CARGO_PKG_VERSION")
+    );
+    stream
+        .write_all(request.as_bytes())
+        .map_err(|e| failed(e.to_string()))?;
+    let mut response = String::new();
+    stream
+        .read_to_string(&mut response)
+        .map_err(|e| failed(e.to_string()))?;
+    Ok(response)
+}
+
+/// Body of an HTTP response, an error unless the status is 200
+fn response_body(response: &str) -> Result<&str, ForecastError> {
+    let (head, body) = response
+        .split_once("\r\n\r\n")
+        .ok_or_else(|| ForecastError::ParseError("incomplete HTTP response".to_string()))?;
+    let status = head
+        .lines()
+        .next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .and_then(|status| status.parse::<u16>().ok())
+        .ok_or_else(|| ForecastError::ParseError("invalid HTTP status line".to_string()))?;
+    if status != 200 {
+        return Err(ForecastError::Http {
+            status,
+            message: body.trim().chars().take(200).collect(),
+        });
+    }
+    Ok(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_forecast() {
+        let response = "HTTP/1.0 200 OK\r\nContent-Type: application/json\r\n\r\n\
+            {\"result\": {\
+                \"watts\": {\"2024-03-01 07:00:00\": 0, \"2024-03-01 12:00:00\": 4210},\
+                \"watt_hours_period\": {},\
+                \"watt_hours\": {},\
+                \"watt_hours_day\": {\"2024-03-01\": 18400, \"2024-03-02\": 6300}\
+            }, \"message\": {\"code\": 0, \"type\": \"success\"}}";
+        let forecast = PvForecast::parse(response_body(response).unwrap()).unwrap();
+        let day = NaiveDate::from_ymd_opt(2024, 3, 1).unwrap();
+        assert_eq!(forecast.energy(day), Some(18400.0));
+        assert_eq!(forecast.energy(day.succ_opt().unwrap()), Some(6300.0));
+        assert_eq!(forecast.watts.values().copied().sum::<f64>(), 4210.0);
+
+        let limited = "HTTP/1.0 429 Too Many Requests\r\n\r\n{\"message\": \"rate limit\"}";
+        assert!(matches!(
+            response_body(limited),
+            Err(ForecastError::Http { status: 429, .. })
+        ));
+
+        assert_eq!(upcoming_day(day.and_hms_opt(3, 0, 0).unwrap()), day);
+        assert_eq!(
+            upcoming_day(day.and_hms_opt(22, 0, 0).unwrap()),
+            day.succ_opt().unwrap()
+        );
+    }
+}
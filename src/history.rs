@@ -0,0 +1,688 @@
+//! Local history store (`[history]`) and the `query` subcommand
+//!
+//! Every recorded snapshot is a row of `snapshots` in the SQLite database
+//! `{dir}/history.sqlite`, its numeric values are rows of `snapshot_values`:
+//!
+//! ```sql
+//! SELECT datetime(s.time / 1000, 'unixepoch'), v.value
+//! FROM snapshots s JOIN snapshot_values v ON v.snapshot = s.id
+//! WHERE s.kind = 'status' AND v.metric = 'solar_production';
+//! ```
+//!
+//! Times are Unix milliseconds (UTC). SQLite is compiled in (`rusqlite` with
+//! the bundled library) and the database runs in WAL mode, so the `sqlite3`
+//! shell or the `query` subcommand can read it while the bridge writes.
+//! Retention drops whole days, independent of the E3DC's internal database.
+//! Complete days can additionally be exported as Parquet files.
+//!
+//! The store is fed by [`HistorySink`] on the pipeline thread (see
+//! [`crate::pipeline`]).
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Duration, NaiveDate, NaiveTime, Utc};
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+
+use crate::config::HistoryConfig;
+use crate::mqtt::diff::Changes;
+use crate::parquet_export::{self, Column};
+use crate::pipeline::{Message, Sink};
+
+/// Database file below `[history] dir`
+pub const DB_FILE: &str = "history.sqlite";
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS snapshots (
+        id INTEGER PRIMARY KEY,
+        time INTEGER NOT NULL,
+        kind TEXT NOT NULL,
+        battery INTEGER
+    );
+    CREATE INDEX IF NOT EXISTS snapshots_time ON snapshots (time);
+    CREATE TABLE IF NOT EXISTS snapshot_values (
+        snapshot INTEGER NOT NULL REFERENCES snapshots (id) ON DELETE CASCADE,
+        metric TEXT NOT NULL,
+        value REAL NOT NULL,
+        PRIMARY KEY (snapshot, metric)
+    ) WITHOUT ROWID;
+    CREATE INDEX IF NOT EXISTS snapshot_values_metric ON snapshot_values (metric);
+    CREATE TABLE IF NOT EXISTS backfill (
+        kind TEXT NOT NULL,
+        start INTEGER NOT NULL,
+        span INTEGER NOT NULL,
+        done INTEGER NOT NULL DEFAULT 0,
+        PRIMARY KEY (kind, start)
+    ) WITHOUT ROWID;
+";
+
+/// Numeric values of one status, statistics or battery reading
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub time: DateTime<Utc>,
+    /// `status`, `statistics` or `battery`
+    pub kind: String,
+    /// Battery index for `battery` snapshots
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub index: Option<u64>,
+    pub values: BTreeMap<String, f64>,
+}
+
+impl Snapshot {
+    /// Numeric fields of `changes` (a diff against nothing holds all fields),
+    /// text and boolean fields are left out
+    pub fn from_changes(
+        kind: &str,
+        index: Option<u64>,
+        time: DateTime<Utc>,
+        changes: &Changes,
+    ) -> Self {
+        let values = changes
+            .iter()
+            .filter(|(name, _)| *name != "time")
+            .filter_map(|(name, value)| {
+                let value: f64 = value.to_payload().parse().ok()?;
+                Some((name.to_string(), value))
+            })
+            .collect();
+        Self {
+            time,
+            kind: kind.to_string(),
+            index,
+            values,
+        }
+    }
+}
+
+/// One value of a metric returned by [`HistoryStore::query`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sample {
+    pub time: DateTime<Utc>,
+    pub kind: String,
+    pub index: Option<u64>,
+    pub value: f64,
+}
+
+/// Stored Unix milliseconds as a time
+fn from_millis(millis: i64) -> rusqlite::Result<DateTime<Utc>> {
+    DateTime::from_timestamp_millis(millis)
+        .ok_or(rusqlite::Error::IntegralValueOutOfRange(0, millis))
+}
+
+/// Start of `day` in Unix milliseconds
+fn day_start(day: NaiveDate) -> i64 {
+    day.and_time(NaiveTime::MIN).and_utc().timestamp_millis()
+}
+
+/// Insert `snapshot` and its values
+fn insert(db: &Connection, snapshot: &Snapshot) -> rusqlite::Result<()> {
+    db.execute(
+        "INSERT INTO snapshots (time, kind, battery) VALUES (?1, ?2, ?3)",
+        params![
+            snapshot.time.timestamp_millis(),
+            snapshot.kind,
+            snapshot.index.map(|index| index as i64)
+        ],
+    )?;
+    let id = db.last_insert_rowid();
+    let mut insert = db.prepare_cached(
+        "INSERT INTO snapshot_values (snapshot, metric, value) VALUES (?1, ?2, ?3)",
+    )?;
+    for (metric, value) in &snapshot.values {
+        insert.execute(params![id, metric, value])?;
+    }
+    Ok(())
+}
+
+pub struct HistoryStore {
+    db: Connection,
+}
+
+impl HistoryStore {
+    /// Open the store, the directory and the database are created if missing
+    pub fn open(dir: &Path) -> anyhow::Result<Self> {
+        fs::create_dir_all(dir)?;
+        let db = Connection::open(dir.join(DB_FILE))?;
+        // Readers (the `query` subcommand) don't block the bridge and vice versa
+        db.busy_timeout(std::time::Duration::from_secs(5))?;
+        db.pragma_update(None, "journal_mode", "WAL")?;
+        db.pragma_update(None, "foreign_keys", true)?;
+        db.execute_batch(SCHEMA)?;
+        Ok(Self { db })
+    }
+
+    /// Days with snapshots, ascending
+    pub fn days(&self) -> anyhow::Result<Vec<NaiveDate>> {
+        let mut statement = self
+            .db
+            .prepare("SELECT DISTINCT date(time / 1000, 'unixepoch') FROM snapshots ORDER BY 1")?;
+        let days = statement
+            .query_map([], |row| row.get::<_, String>(0))?
+            .map(|day| Ok(day?.parse()?))
+            .collect::<anyhow::Result<Vec<NaiveDate>>>()?;
+        Ok(days)
+    }
+
+    /// Store a snapshot with its values
+    pub fn append(&mut self, snapshot: &Snapshot) -> anyhow::Result<()> {
+        let transaction = self.db.transaction()?;
+        insert(&transaction, snapshot)?;
+        transaction.commit()?;
+        Ok(())
+    }
+
+    /// Backfill spans still to query, oldest first
+    ///
+    /// The first call on an empty store plans `spans`, later calls return
+    /// what is left of that plan (`spans` is ignored then), so an interrupted
+    /// backfill resumes where it stopped. A store that has snapshots but no
+    /// plan is not backfilled.
+    pub fn pending_backfill(
+        &mut self,
+        spans: Vec<BackfillSpan>,
+    ) -> anyhow::Result<Vec<BackfillSpan>> {
+        let planned: i64 = self
+            .db
+            .query_row("SELECT COUNT(*) FROM backfill", [], |row| row.get(0))?;
+        if planned == 0 {
+            if !self.days()?.is_empty() {
+                return Ok(Vec::new());
+            }
+            let transaction = self.db.transaction()?;
+            {
+                let mut plan = transaction.prepare(
+                    "INSERT OR IGNORE INTO backfill (kind, start, span) VALUES (?1, ?2, ?3)",
+                )?;
+                for span in &spans {
+                    plan.execute(params![
+                        span.kind,
+                        span.start.timestamp_millis(),
+                        span.span.num_seconds()
+                    ])?;
+                }
+            }
+            transaction.commit()?;
+        }
+
+        let mut statement = self
+            .db
+            .prepare("SELECT kind, start, span FROM backfill WHERE done = 0")?;
+        let rows = statement
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    from_millis(row.get(1)?)?,
+                    Duration::seconds(row.get(2)?),
+                ))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        let mut pending: Vec<BackfillSpan> = rows
+            .into_iter()
+            .filter_map(|(kind, start, span)| {
+                let kind = BACKFILL_KINDS.into_iter().find(|known| *known == kind)?;
+                Some(BackfillSpan { kind, start, span })
+            })
+            .collect();
+        pending.sort_by_key(BackfillSpan::time);
+        Ok(pending)
+    }
+
+    /// Store the snapshot of a backfill span and mark the span done
+    pub fn append_backfill(
+        &mut self,
+        span: &BackfillSpan,
+        snapshot: &Snapshot,
+    ) -> anyhow::Result<()> {
+        let transaction = self.db.transaction()?;
+        insert(&transaction, snapshot)?;
+        transaction.execute(
+            "UPDATE backfill SET done = 1 WHERE kind = ?1 AND start = ?2",
+            params![span.kind, span.start.timestamp_millis()],
+        )?;
+        transaction.commit()?;
+        Ok(())
+    }
+
+    /// Delete the days older than `retention`, returns the number of days removed
+    pub fn prune(&self, now: DateTime<Utc>, retention: Duration) -> anyhow::Result<usize> {
+        let Some(oldest) = now.checked_sub_signed(retention) else {
+            return Ok(0);
+        };
+        let oldest = oldest.date_naive();
+        let removed = self.days()?.iter().filter(|day| **day < oldest).count();
+        if removed > 0 {
+            self.db.execute(
+                "DELETE FROM snapshots WHERE time < ?1",
+                params![day_start(oldest)],
+            )?;
+        }
+        Ok(removed)
+    }
+
+    /// Values of `metric` since `since`, optionally of one snapshot kind only
+    pub fn query(
+        &self,
+        since: DateTime<Utc>,
+        kind: Option<&str>,
+        metric: &str,
+    ) -> anyhow::Result<Vec<Sample>> {
+        let mut statement = self.db.prepare(
+            "SELECT s.time, s.kind, s.battery, v.value
+             FROM snapshots s JOIN snapshot_values v ON v.snapshot = s.id
+             WHERE v.metric = ?1 AND s.time >= ?2 AND (?3 IS NULL OR s.kind = ?3)
+             ORDER BY s.time, s.id",
+        )?;
+        let samples = statement
+            .query_map(params![metric, since.timestamp_millis(), kind], |row| {
+                Ok(Sample {
+                    time: from_millis(row.get(0)?)?,
+                    kind: row.get(1)?,
+                    index: row.get::<_, Option<i64>>(2)?.map(|index| index as u64),
+                    value: row.get(3)?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(samples)
+    }
+
+    /// Snapshots of `day`, oldest first
+    fn read_day(&self, day: NaiveDate) -> anyhow::Result<Vec<Snapshot>> {
+        let mut statement = self.db.prepare(
+            "SELECT s.id, s.time, s.kind, s.battery, v.metric, v.value
+             FROM snapshots s LEFT JOIN snapshot_values v ON v.snapshot = s.id
+             WHERE s.time >= ?1 AND s.time < ?2
+             ORDER BY s.time, s.id",
+        )?;
+        let end = day.succ_opt().map_or(i64::MAX, day_start);
+        let mut rows = statement.query(params![day_start(day), end])?;
+        let mut snapshots: Vec<(i64, Snapshot)> = Vec::new();
+        while let Some(row) = rows.next()? {
+            let id: i64 = row.get(0)?;
+            if snapshots.last().is_none_or(|(last, _)| *last != id) {
+                snapshots.push((
+                    id,
+                    Snapshot {
+                        time: from_millis(row.get(1)?)?,
+                        kind: row.get(2)?,
+                        index: row.get::<_, Option<i64>>(3)?.map(|index| index as u64),
+                        values: BTreeMap::new(),
+                    },
+                ));
+            }
+            let metric: Option<String> = row.get(4)?;
+            if let (Some(metric), Some((_, snapshot))) = (metric, snapshots.last_mut()) {
+                snapshot.values.insert(metric, row.get(5)?);
+            }
+        }
+        Ok(snapshots
+            .into_iter()
+            .map(|(_, snapshot)| snapshot)
+            .collect())
+    }
+
+    /// Complete days (before `today`) without a Parquet export in `dir`
+    pub fn pending_exports(&self, today: NaiveDate, dir: &Path) -> anyhow::Result<Vec<NaiveDate>> {
+        let exported = |day: NaiveDate| {
+            let partition = format!("date={}", day.format("%Y-%m-%d"));
+            fs::read_dir(dir).is_ok_and(|kinds| {
+                kinds
+                    .filter_map(Result::ok)
+                    .any(|kind| kind.path().join(&partition).exists())
+            })
+        };
+        Ok(self
+            .days()?
+            .into_iter()
+            .filter(|day| *day < today && !exported(*day))
+            .collect())
+    }
+
+    /// Write the snapshots of `day` as one Parquet file per kind to
+    /// `{dir}/{kind}/date={YYYY-MM-DD}/data.parquet` (Hive partitioning),
+    /// returns the files written
+    ///
+    /// Columns are `time`, `index` (battery snapshots) and one nullable
+    /// double per metric.
+    pub fn export_parquet(&self, day: NaiveDate, dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+        let mut by_kind: BTreeMap<String, Vec<Snapshot>> = BTreeMap::new();
+        for snapshot in self.read_day(day)? {
+            by_kind
+                .entry(snapshot.kind.clone())
+                .or_default()
+                .push(snapshot);
+        }
+
+        let mut written = Vec::new();
+        for (kind, snapshots) in by_kind {
+            let metrics: BTreeSet<&String> =
+                snapshots.iter().flat_map(|s| s.values.keys()).collect();
+            let mut columns = vec![(
+                "time".to_string(),
+                Column::TimestampMillis(
+                    snapshots
+                        .iter()
+                        .map(|s| s.time.timestamp_millis())
+                        .collect(),
+                ),
+            )];
+            if snapshots.iter().any(|s| s.index.is_some()) {
+                columns.push((
+                    "index".to_string(),
+                    Column::Int64(
+                        snapshots
+                            .iter()
+                            .map(|s| s.index.map(|i| i as i64))
+                            .collect(),
+                    ),
+                ));
+            }
+            for metric in metrics {
+                columns.push((
+                    metric.clone(),
+                    Column::Double(
+                        snapshots
+                            .iter()
+                            .map(|s| s.values.get(metric).copied())
+                            .collect(),
+                    ),
+                ));
+            }
+
+            let partition = dir
+                .join(&kind)
+                .join(format!("date={}", day.format("%Y-%m-%d")));
+            fs::create_dir_all(&partition)?;
+            let path = partition.join("data.parquet");
+            parquet_export::write(&path, &columns)?;
+            written.push(path);
+        }
+        Ok(written)
+    }
+}
+
+/// Snapshot kinds of [`BackfillSpan::kind`]
+const BACKFILL_KINDS: [&str; 2] = ["statistics", "statistics_15m"];
+
+/// Span of the E3DC's DB history to query for a backfill
+#[derive(Debug, Clone, PartialEq)]
+pub struct BackfillSpan {
+    /// `statistics` (day sums) or `statistics_15m` (quarter hour sums)
+    pub kind: &'static str,
+    pub start: DateTime<Utc>,
+    pub span: Duration,
+}
+
+impl BackfillSpan {
+    /// Time of the snapshot: the last second of a day, the start of a quarter hour
+    ///
+    /// A day sum then sorts like the last statistics reading of that day.
+    pub fn time(&self) -> DateTime<Utc> {
+        if self.kind == "statistics" {
+            self.start + self.span - Duration::seconds(1)
+        } else {
+            self.start
+        }
+    }
+}
+
+/// Spans of a backfill before `now`, oldest first: a day sum for each of the
+/// past `days` complete (UTC) days, quarter hour sums for the past `detail_days`
+pub fn backfill_spans(now: DateTime<Utc>, days: u32, detail_days: u32) -> Vec<BackfillSpan> {
+    let today = now.date_naive().and_time(NaiveTime::MIN).and_utc();
+    let mut spans: Vec<BackfillSpan> = (1..=i64::from(days))
+        .rev()
+        .map(|day| BackfillSpan {
+            kind: "statistics",
+            start: today - Duration::days(day),
+            span: Duration::days(1),
+        })
+        .collect();
+    let quarter = Duration::minutes(15);
+    let mut start = today - Duration::days(i64::from(detail_days));
+    while start < today {
+        spans.push(BackfillSpan {
+            kind: "statistics_15m",
+            start,
+            span: quarter,
+        });
+        start += quarter;
+    }
+    spans.sort_by_key(|span| span.time());
+    spans
+}
+
+/// Records the poll messages in a [`HistoryStore`] as configured in `[history]`
+///
+/// The status is recorded at most every `status_interval`, statistics and
+/// batteries with every message. Statistics messages also export the
+/// complete days to Parquet and prune the days past the retention.
+pub struct HistorySink {
+    store: HistoryStore,
+    status_interval: std::time::Duration,
+    retention: Option<Duration>,
+    parquet_dir: Option<PathBuf>,
+    last_status: Option<DateTime<Utc>>,
+}
+
+impl HistorySink {
+    pub fn new(store: HistoryStore, config: &HistoryConfig) -> Self {
+        Self {
+            store,
+            status_interval: config.status_interval,
+            // A retention beyond chrono's range never prunes anything
+            retention: config
+                .retention
+                .and_then(|retention| Duration::from_std(retention).ok()),
+            parquet_dir: config.parquet_dir.clone(),
+            last_status: None,
+        }
+    }
+
+    fn export_and_prune(&self, now: DateTime<Utc>) {
+        if let Some(dir) = &self.parquet_dir {
+            for day in self
+                .store
+                .pending_exports(now.date_naive(), dir)
+                .unwrap_or_default()
+            {
+                match self.store.export_parquet(day, dir) {
+                    Ok(files) => {
+                        tracing::info!(
+                            "Exported {} history as {} Parquet file(s)",
+                            day,
+                            files.len()
+                        )
+                    }
+                    Err(e) => {
+                        tracing::warn!("Failed to export {} history as Parquet: {}", day, e);
+                        break;
+                    }
+                }
+            }
+        }
+        if let Some(retention) = self.retention {
+            match self.store.prune(now, retention) {
+                Ok(0) => {}
+                Ok(removed) => tracing::info!("Removed {} day(s) of history", removed),
+                Err(e) => tracing::warn!("Failed to prune history: {}", e),
+            }
+        }
+    }
+}
+
+impl Sink for HistorySink {
+    fn name(&self) -> &'static str {
+        "history"
+    }
+
+    fn consume(&mut self, message: &Message) -> anyhow::Result<()> {
+        match message {
+            Message::Status(status) => {
+                let due = self.last_status.is_none_or(|last| {
+                    (status.time - last).to_std().unwrap_or_default() >= self.status_interval
+                });
+                if due {
+                    self.store.append(&Snapshot::from_changes(
+                        "status",
+                        None,
+                        status.time,
+                        &status.diff(None),
+                    ))?;
+                    self.last_status = Some(status.time);
+                }
+            }
+            Message::Statistics(stats) => {
+                self.store.append(&Snapshot::from_changes(
+                    "statistics",
+                    None,
+                    stats.time,
+                    &stats.diff(None),
+                ))?;
+                self.export_and_prune(Utc::now());
+            }
+            Message::Batteries(batteries) => {
+                for battery in batteries {
+                    self.store.append(&Snapshot::from_changes(
+                        "battery",
+                        Some(battery.index),
+                        battery.time,
+                        &battery.diff(None),
+                    ))?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_history_store() {
+        let dir = std::env::temp_dir().join(format!("e3dc-history-{}", std::process::id()));
+        let mut store = HistoryStore::open(&dir).unwrap();
+        let start = Utc.with_ymd_and_hms(2024, 3, 1, 23, 0, 0).unwrap();
+        for hours in 0..3 {
+            let time = start + Duration::hours(hours);
+            let mut values = BTreeMap::new();
+            values.insert("solar_production".to_string(), hours as f64 * 100.0);
+            store
+                .append(&Snapshot {
+                    time,
+                    kind: "status".to_string(),
+                    index: None,
+                    values,
+                })
+                .unwrap();
+        }
+        assert_eq!(store.days().unwrap().len(), 2);
+
+        let export = dir.join("parquet");
+        let today = start.date_naive().succ_opt().unwrap();
+        assert_eq!(
+            store.pending_exports(today, &export).unwrap(),
+            vec![start.date_naive()]
+        );
+        let files = store.export_parquet(start.date_naive(), &export).unwrap();
+        assert!(files[0].ends_with("status/date=2024-03-01/data.parquet"));
+        assert!(store.pending_exports(today, &export).unwrap().is_empty());
+
+        let samples = store
+            .query(
+                start + Duration::minutes(30),
+                Some("status"),
+                "solar_production",
+            )
+            .unwrap();
+        assert_eq!(
+            samples.iter().map(|s| s.value).collect::<Vec<_>>(),
+            vec![100.0, 200.0]
+        );
+        assert!(store
+            .query(start, Some("battery"), "solar_production")
+            .unwrap()
+            .is_empty());
+
+        assert_eq!(
+            store
+                .prune(start + Duration::days(1), Duration::hours(1))
+                .unwrap(),
+            1
+        );
+        assert_eq!(
+            store.query(start, None, "solar_production").unwrap().len(),
+            2
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_backfill_resumes() {
+        let dir = std::env::temp_dir().join(format!("e3dc-backfill-{}", std::process::id()));
+        let now = Utc.with_ymd_and_hms(2024, 3, 10, 8, 30, 0).unwrap();
+        let spans = backfill_spans(now, 3, 0);
+        let snapshot = |span: &BackfillSpan| Snapshot {
+            time: span.time(),
+            kind: span.kind.to_string(),
+            index: None,
+            values: BTreeMap::new(),
+        };
+
+        let mut store = HistoryStore::open(&dir).unwrap();
+        let pending = store.pending_backfill(spans.clone()).unwrap();
+        assert_eq!(pending, spans);
+        store
+            .append_backfill(&pending[0], &snapshot(&pending[0]))
+            .unwrap();
+        drop(store);
+
+        // Interrupted: the next start continues the plan of the first one
+        let mut store = HistoryStore::open(&dir).unwrap();
+        let later = backfill_spans(now + Duration::days(1), 3, 0);
+        assert_eq!(store.pending_backfill(later.clone()).unwrap(), spans[1..]);
+        for span in &spans[1..] {
+            store.append_backfill(span, &snapshot(span)).unwrap();
+        }
+        assert!(store.pending_backfill(later).unwrap().is_empty());
+        assert_eq!(store.days().unwrap().len(), 3);
+        fs::remove_dir_all(&dir).unwrap();
+
+        // Recorded before a backfill was configured: left as it is
+        let mut store = HistoryStore::open(&dir).unwrap();
+        store.append(&snapshot(&spans[0])).unwrap();
+        assert!(store.pending_backfill(spans).unwrap().is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_backfill_spans() {
+        let now = Utc.with_ymd_and_hms(2024, 3, 10, 8, 30, 0).unwrap();
+        let spans = backfill_spans(now, 3, 1);
+        assert_eq!(spans.len(), 3 + 96);
+        assert_eq!(
+            spans[0],
+            BackfillSpan {
+                kind: "statistics",
+                start: Utc.with_ymd_and_hms(2024, 3, 7, 0, 0, 0).unwrap(),
+                span: Duration::days(1),
+            }
+        );
+        // The day sum of yesterday follows its last quarter hour
+        let last = spans.last().unwrap();
+        assert_eq!(last.kind, "statistics");
+        assert_eq!(
+            last.time(),
+            Utc.with_ymd_and_hms(2024, 3, 9, 23, 59, 59).unwrap()
+        );
+        assert_eq!(
+            spans[spans.len() - 2].start,
+            last.start + Duration::minutes(23 * 60 + 45)
+        );
+        assert!(backfill_spans(now, 0, 0).is_empty());
+    }
+}
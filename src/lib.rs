@@ -2,11 +2,32 @@
 //!
 //! A Rust implementation of an E3DC to MQTT bridge using the RSCP protocol.
 
+pub mod alerts;
+pub mod bench;
+pub mod bridge;
 pub mod config;
+pub mod cycles;
 pub mod e3dc;
+pub mod energy;
 pub mod errors;
+pub mod events;
+pub mod forecast;
+#[cfg(feature = "history")]
+pub mod history;
+pub mod lifecycle;
 pub mod mqtt;
+pub mod net;
+#[cfg(feature = "history")]
+pub mod parquet_export;
+pub mod pipeline;
+pub mod rates;
+pub mod schedule;
+pub mod socks;
+pub mod state;
+pub mod stats;
+pub mod tasks;
+pub mod version;
 
 pub use config::Config;
 pub use e3dc::client::E3dcClient;
-pub use mqtt::publisher::MqttPublisher;
+pub use mqtt::publisher::{MqttPublisher, Publisher};
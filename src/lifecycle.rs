@@ -0,0 +1,198 @@
+//! Lifecycle of the bridge, published retained to `bridge/state`
+//!
+//! `online` only tells a running bridge from a dead one. The lifecycle adds
+//! why a running bridge publishes nothing new: it is still connecting, the
+//! broker connection is lost, or an E3DC or MQTT failure is being skipped per
+//! `[on_error]`.
+//!
+//! ```text
+//! connecting ─▶ running ◀─▶ degraded_e3dc
+//!                  ▲ │
+//!                  │ ▼
+//!             degraded_mqtt         (any) ─▶ shutting_down
+//! ```
+
+use std::fmt;
+
+/// State of the bridge
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BridgeState {
+    /// Started, the first poll hasn't run yet
+    Connecting,
+    Running,
+    /// E3DC unreachable, reconnecting (`[on_error] e3dc = "degrade"`)
+    DegradedE3dc,
+    /// The broker connection is lost, or a cycle was skipped because
+    /// publishing failed
+    DegradedMqtt,
+    /// Signal received, exiting
+    ShuttingDown,
+}
+
+impl BridgeState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BridgeState::Connecting => "connecting",
+            BridgeState::Running => "running",
+            BridgeState::DegradedE3dc => "degraded_e3dc",
+            BridgeState::DegradedMqtt => "degraded_mqtt",
+            BridgeState::ShuttingDown => "shutting_down",
+        }
+    }
+}
+
+impl fmt::Display for BridgeState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// What happened in the main loop
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LifecycleEvent {
+    /// A cycle succeeded
+    CycleSucceeded,
+    /// A cycle was skipped because publishing failed
+    MqttFailed,
+    /// The E3DC became unreachable (degraded mode entered)
+    E3dcLost,
+    /// The E3DC answers again
+    E3dcRecovered,
+    /// An event loop lost its broker connection
+    MqttLost,
+    /// All event loops are connected again
+    MqttRecovered,
+    Shutdown,
+}
+
+/// State machine of the bridge lifecycle
+#[derive(Debug)]
+pub struct Lifecycle {
+    state: BridgeState,
+    /// Between `MqttLost` and `MqttRecovered`
+    broker_lost: bool,
+}
+
+impl Default for Lifecycle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Lifecycle {
+    pub fn new() -> Self {
+        Self {
+            state: BridgeState::Connecting,
+            broker_lost: false,
+        }
+    }
+
+    pub fn state(&self) -> BridgeState {
+        self.state
+    }
+
+    /// Apply an event, returns the new state if it changed
+    ///
+    /// An unreachable E3DC outranks MQTT failures: without polls there is
+    /// nothing to publish, so only the E3DC recovering leaves that state.
+    /// A lost broker connection keeps `degraded_mqtt` until it is back, even
+    /// if cycles succeed because nothing changed.
+    pub fn handle(&mut self, event: LifecycleEvent) -> Option<BridgeState> {
+        use BridgeState::*;
+        use LifecycleEvent::*;
+        match event {
+            MqttLost => self.broker_lost = true,
+            MqttRecovered => self.broker_lost = false,
+            _ => {}
+        }
+        let next = match (self.state, event) {
+            (ShuttingDown, _) => ShuttingDown,
+            (_, Shutdown) => ShuttingDown,
+            (_, E3dcLost) => DegradedE3dc,
+            (DegradedE3dc, E3dcRecovered) if self.broker_lost => DegradedMqtt,
+            (DegradedE3dc, E3dcRecovered) => Running,
+            (DegradedE3dc, _) => DegradedE3dc,
+            (_, MqttFailed | MqttLost) => DegradedMqtt,
+            (_, CycleSucceeded) if self.broker_lost => DegradedMqtt,
+            (_, CycleSucceeded) => Running,
+            (DegradedMqtt, MqttRecovered) => Running,
+            (state, E3dcRecovered | MqttRecovered) => state,
+        };
+        (next != self.state).then(|| {
+            self.state = next;
+            next
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lifecycle() {
+        let mut lifecycle = Lifecycle::new();
+        assert_eq!(lifecycle.state(), BridgeState::Connecting);
+        assert_eq!(
+            lifecycle.handle(LifecycleEvent::CycleSucceeded),
+            Some(BridgeState::Running)
+        );
+        assert_eq!(lifecycle.handle(LifecycleEvent::CycleSucceeded), None);
+
+        assert_eq!(
+            lifecycle.handle(LifecycleEvent::MqttFailed),
+            Some(BridgeState::DegradedMqtt)
+        );
+        // The E3DC outranks MQTT, a succeeding cycle doesn't leave it
+        assert_eq!(
+            lifecycle.handle(LifecycleEvent::E3dcLost),
+            Some(BridgeState::DegradedE3dc)
+        );
+        assert_eq!(lifecycle.handle(LifecycleEvent::MqttFailed), None);
+        assert_eq!(lifecycle.handle(LifecycleEvent::CycleSucceeded), None);
+        assert_eq!(
+            lifecycle.handle(LifecycleEvent::E3dcRecovered),
+            Some(BridgeState::Running)
+        );
+
+        assert_eq!(
+            lifecycle.handle(LifecycleEvent::Shutdown),
+            Some(BridgeState::ShuttingDown)
+        );
+        assert_eq!(lifecycle.handle(LifecycleEvent::CycleSucceeded), None);
+        assert_eq!(lifecycle.state().as_str(), "shutting_down");
+    }
+
+    #[test]
+    fn test_lifecycle_broker_outage() {
+        let mut lifecycle = Lifecycle::new();
+        lifecycle.handle(LifecycleEvent::CycleSucceeded);
+
+        assert_eq!(
+            lifecycle.handle(LifecycleEvent::MqttLost),
+            Some(BridgeState::DegradedMqtt)
+        );
+        // Nothing changed, nothing published: still no broker
+        assert_eq!(lifecycle.handle(LifecycleEvent::CycleSucceeded), None);
+        assert_eq!(
+            lifecycle.handle(LifecycleEvent::MqttRecovered),
+            Some(BridgeState::Running)
+        );
+        assert_eq!(lifecycle.handle(LifecycleEvent::MqttRecovered), None);
+
+        // The E3DC outranks the broker, the broker outage outlasts it
+        lifecycle.handle(LifecycleEvent::MqttLost);
+        assert_eq!(
+            lifecycle.handle(LifecycleEvent::E3dcLost),
+            Some(BridgeState::DegradedE3dc)
+        );
+        assert_eq!(
+            lifecycle.handle(LifecycleEvent::E3dcRecovered),
+            Some(BridgeState::DegradedMqtt)
+        );
+        assert_eq!(
+            lifecycle.handle(LifecycleEvent::MqttRecovered),
+            Some(BridgeState::Running)
+        );
+    }
+}
@@ -1,18 +1,24 @@
-mod config;
-mod e3dc;
-mod errors;
-mod mqtt;
-
-use std::cmp::{max, min};
-
-use chrono::{DateTime, Duration, TimeDelta, Utc};
-use clap::Parser;
-use config::Config;
-use e3dc::E3dcClient;
-use mqtt::MqttPublisher;
-use tracing::{debug, error, info};
-
-use crate::mqtt::DailyStatistics;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use chrono::{Duration, NaiveDate, Utc};
+use clap::{Parser, Subcommand};
+use e3dc_mqtt_rs::bench::Latencies;
+use e3dc_mqtt_rs::bridge::{self, publish_idle_periods, Shutdown};
+use e3dc_mqtt_rs::config::Config;
+use e3dc_mqtt_rs::e3dc::capture::FrameRecorder;
+use e3dc_mqtt_rs::e3dc::items::empty_item;
+use e3dc_mqtt_rs::e3dc::{Retry, RetryPolicy};
+use e3dc_mqtt_rs::errors::E3dcError;
+#[cfg(feature = "history")]
+use e3dc_mqtt_rs::history::HistoryStore;
+use e3dc_mqtt_rs::mqtt::migrate::{self, Migration};
+use e3dc_mqtt_rs::mqtt::selftest;
+use e3dc_mqtt_rs::mqtt::{self, MqttPublisher, Publisher};
+use e3dc_mqtt_rs::stats::{stats_spans, write_rows, Granularity, StatsFormat, StatsRow};
+use e3dc_mqtt_rs::version::BuildInfo;
+use tracing::{error, info, warn};
 
 /// E3DC MQTT Bridge - Publishes E3DC solar system data to MQTT
 #[derive(Parser)]
@@ -23,13 +29,324 @@ struct Cli {
     /// Path to configuration file
     #[arg(short, long, default_value = "config.toml")]
     config: String,
+
+    #[command(subcommand)]
+    command: Option<CliCommand>,
 }
 
-/// Round timestamp to next modulo seconds (Python-style precise timing)
-/// Example: round_to_next_modulo_seconds(12.3, 5.0) -> 15.0
-fn next_interval(time: DateTime<Utc>, interval: Duration) -> DateTime<Utc> {
-    let duration_since_last_interval = Duration::seconds(time.timestamp() % interval.num_seconds());
-    time - duration_since_last_interval + interval
+#[derive(Subcommand)]
+enum CliCommand {
+    /// Clear retained topics left behind by an old root topic or device id, or
+    /// by groups no longer retained
+    ///
+    /// Stop the bridge first, the new layout is derived from the config and the E3DC.
+    Migrate {
+        /// Old root topic, e.g. "e3dc" or "e3dc/S10E-123"
+        #[arg(long)]
+        from_root: String,
+
+        /// Publish the current values under the new layout afterwards
+        #[arg(long)]
+        republish: bool,
+
+        /// Only list the obsolete topics
+        #[arg(long)]
+        dry_run: bool,
+    },
+
+    /// Run one full poll and write the anonymized RSCP frames for a bug report
+    ///
+    /// Serial numbers and MAC addresses are replaced, the files can be attached
+    /// to an issue as they are.
+    Capture {
+        /// Directory for the frame files, created if missing
+        #[arg(long)]
+        output: PathBuf,
+    },
+
+    /// Measure the round trip time of each query type and print percentiles
+    Bench {
+        /// Runs per query type
+        #[arg(long, default_value_t = 20)]
+        runs: u32,
+    },
+
+    /// Check the broker connection, ACLs and last will without touching the E3DC
+    Selftest {
+        /// Seconds to wait for each broker response
+        #[arg(long, default_value_t = 5)]
+        timeout: u64,
+    },
+
+    /// Print the recorded values of one metric from the `[history]` store
+    #[cfg(feature = "history")]
+    Query {
+        /// Metric (topic) name, e.g. "solar_production" or "rsoc"
+        #[arg(long)]
+        metric: String,
+
+        /// Age of the oldest value, e.g. "7d" or "12h"
+        #[arg(long, default_value = "1d")]
+        since: String,
+
+        /// Only snapshots of this kind: "status", "statistics" or "battery"
+        #[arg(long)]
+        kind: Option<String>,
+    },
+
+    /// Export the E3DC's DB sums of a range of UTC days as CSV or JSON
+    Stats {
+        /// First day, e.g. "2024-01-01"
+        #[arg(long)]
+        from: NaiveDate,
+
+        /// Last day (inclusive), defaults to today
+        #[arg(long)]
+        to: Option<NaiveDate>,
+
+        /// Span of a row: "quarter_hour", "hour", "day" or "month"
+        #[arg(long, default_value = "day")]
+        granularity: Granularity,
+
+        /// "csv" or "json"
+        #[arg(long, default_value = "csv")]
+        format: StatsFormat,
+
+        /// Output file instead of stdout
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+}
+
+/// Time without new retained messages after which the old tree is complete
+const MIGRATE_SETTLE: std::time::Duration = std::time::Duration::from_secs(2);
+
+/// Clear obsolete retained topics below `from_root`, optionally republish
+fn migrate(config: &Config, from_root: &str, republish: bool, dry_run: bool) -> anyhow::Result<()> {
+    let mut e3dc_client = bridge::connect_e3dc(config)?;
+    let system_info = e3dc_client.get_system_info()?;
+    let device_id = format!("{}-{}", system_info.model, system_info.serial_number);
+    let root_topic = format!("{}/{}", config.mqtt.root, device_id);
+    info!("Current layout: {}", root_topic);
+
+    let migration = Migration::connect(config, from_root)?;
+    let retained = migration.collect(MIGRATE_SETTLE);
+    let mut obsolete = migrate::obsolete_topics(&retained, &root_topic);
+    obsolete.extend(migrate::unretained_topics(
+        &retained,
+        &root_topic,
+        &config.topics,
+    ));
+    info!(
+        "Found {} retained topics below {}, {} obsolete",
+        retained.len(),
+        from_root,
+        obsolete.len()
+    );
+    for topic in &obsolete {
+        info!("  {}", topic);
+    }
+
+    if dry_run {
+        info!("Dry run, nothing cleared");
+        return Ok(());
+    }
+    let cleared = migration.clear(&obsolete, MIGRATE_SETTLE * 5)?;
+    if cleared < obsolete.len() {
+        warn!("Only {} of {} clears acknowledged", cleared, obsolete.len());
+    }
+    info!("✓ Cleared {} retained topics", cleared);
+
+    if republish {
+        let mqtt_publisher = MqttPublisher::new(config, device_id)?;
+        let statistic_interval = Duration::from_std(config.e3dc.statistic_update_interval)?;
+        let snapshot = e3dc_client.poll_snapshot(statistic_interval)?;
+        mqtt_publisher.publish_system_info(&mqtt::SystemInfo::from_e3dc(&system_info))?;
+        mqtt_publisher.publish_status(&mqtt::Status::from_e3dc(&snapshot.status), None)?;
+        mqtt_publisher.publish_daily_statistics(
+            &mqtt::DailyStatistics::from_e3dc(&snapshot.statistics),
+            None,
+        )?;
+        let batteries: Vec<mqtt::BatteryData> = snapshot
+            .batteries
+            .iter()
+            .map(mqtt::BatteryData::from_e3dc)
+            .collect();
+        mqtt_publisher.publish_battery_data(&batteries, &[])?;
+        publish_idle_periods(&mut e3dc_client, &mqtt_publisher)?;
+        // Let the event loop flush the publishes before disconnecting
+        std::thread::sleep(MIGRATE_SETTLE);
+        info!("✓ Republished current values below {}", root_topic);
+    }
+    Ok(())
+}
+
+/// Query everything the bridge polls once with a [`FrameRecorder`] attached
+fn capture(config: &Config, output: &Path) -> anyhow::Result<()> {
+    let mut e3dc_client = bridge::connect_e3dc(config)?;
+    let recorder = FrameRecorder::new();
+    e3dc_client.add_observer(Box::new(recorder.clone()));
+
+    let statistic_interval = Duration::from_std(config.e3dc.statistic_update_interval)?;
+    let mut poll = || -> Result<(), E3dcError> {
+        e3dc_client.get_system_info()?;
+        e3dc_client.poll_snapshot(statistic_interval)?;
+        e3dc_client.get_idle_periods()?;
+        if config.e3dc.poll_charge_limits {
+            e3dc_client.get_charge_limits()?;
+        }
+        if config.e3dc.poll_phases {
+            e3dc_client.get_phase_balance()?;
+        }
+        if !e3dc_client.pvis().is_empty() {
+            e3dc_client.get_pvi_data()?;
+        }
+        if config.e3dc.poll_dcdc {
+            e3dc_client.get_dcdc_data()?;
+        }
+        Ok(())
+    };
+    // A failing query is usually what the report is about, keep what was captured
+    let result = poll();
+    if let Err(e) = &result {
+        warn!("Poll failed, writing the frames captured so far: {}", e);
+    }
+
+    let written = recorder
+        .write(output)
+        .with_context(|| format!("Failed to write capture to {}", output.display()))?;
+    info!("✓ Wrote {} frame files to {}", written, output.display());
+    Ok(result?)
+}
+
+/// Run every query type `runs` times and log the latency percentiles
+fn bench(config: &Config, runs: u32) -> anyhow::Result<()> {
+    let mut e3dc_client = bridge::connect_e3dc(config)?;
+    // Retries would hide the latency of failed attempts
+    e3dc_client.set_retry_policy(RetryPolicy {
+        status: Retry::NONE,
+        statistics: Retry::NONE,
+        battery: Retry::NONE,
+        other: Retry::NONE,
+    });
+    let statistic_interval = Duration::from_std(config.e3dc.statistic_update_interval)?;
+    let first_dcb = e3dc_client
+        .batteries()
+        .iter()
+        .find(|battery| battery.dcb_count > 0)
+        .map(|battery| battery.index);
+    info!("Running each query {} times...", runs);
+
+    let mut results = vec![
+        Latencies::measure("status", runs, || e3dc_client.get_status()),
+        Latencies::measure("power settings", runs, || {
+            let mut frame = rscp::Frame::new();
+            frame.push_item(empty_item(rscp::tags::EMS::GET_POWER_SETTINGS.into()));
+            e3dc_client.send_request(frame)
+        }),
+        Latencies::measure("battery", runs, || e3dc_client.get_battery_data()),
+    ];
+    if let Some(battery) = first_dcb {
+        results.push(Latencies::measure("dcb", runs, || {
+            e3dc_client.get_dcb_data(battery, 0)
+        }));
+    }
+    results.push(Latencies::measure("db history", runs, || {
+        e3dc_client.get_daily_statistics(statistic_interval)
+    }));
+
+    for latencies in &results {
+        info!("{}", latencies);
+    }
+    Ok(())
+}
+
+/// Print the values of `metric` from the history store, one per line
+#[cfg(feature = "history")]
+fn query(config: &Config, metric: &str, since: &str, kind: Option<&str>) -> anyhow::Result<()> {
+    let history = config
+        .history
+        .as_ref()
+        .context("No [history] store configured")?;
+    let age = humantime_serde::re::humantime::parse_duration(since)
+        .with_context(|| format!("Invalid --since '{}'", since))?;
+    let store = HistoryStore::open(&history.dir)
+        .with_context(|| format!("Failed to open {}", history.dir.display()))?;
+    let samples = store.query(Utc::now() - Duration::from_std(age)?, kind, metric)?;
+    for sample in &samples {
+        match sample.index {
+            Some(index) => println!(
+                "{}\t{}[{}]\t{}",
+                sample.time.to_rfc3339(),
+                sample.kind,
+                index,
+                sample.value
+            ),
+            None => println!(
+                "{}\t{}\t{}",
+                sample.time.to_rfc3339(),
+                sample.kind,
+                sample.value
+            ),
+        }
+    }
+    info!("{} values of {}", samples.len(), metric);
+    Ok(())
+}
+
+/// Query the DB sums of every span from `from` to `to` and write them
+///
+/// Stops at the first failed query, nothing is written then.
+fn stats(
+    config: &Config,
+    from: NaiveDate,
+    to: Option<NaiveDate>,
+    granularity: Granularity,
+    format: StatsFormat,
+    output: Option<&Path>,
+) -> anyhow::Result<()> {
+    let to = to.unwrap_or_else(|| Utc::now().date_naive());
+    anyhow::ensure!(from <= to, "--from {} is after --to {}", from, to);
+    let spans = stats_spans(from, to, granularity);
+    let mut e3dc_client = bridge::connect_e3dc(config)?;
+    info!("Querying {} spans from {} to {}", spans.len(), from, to);
+    let mut rows = Vec::with_capacity(spans.len());
+    for (start, span) in spans {
+        let stat = e3dc_client
+            .get_db_data_timestamp(start, span)
+            .with_context(|| format!("Query for {} failed", start))?;
+        rows.push(StatsRow::from_e3dc(start, span, &stat));
+    }
+    match output {
+        Some(path) => {
+            let file = std::fs::File::create(path)
+                .with_context(|| format!("Failed to create {}", path.display()))?;
+            let mut out = std::io::BufWriter::new(file);
+            write_rows(&mut out, &rows, format)?;
+            out.flush()?;
+            info!("{} rows written to {}", rows.len(), path.display());
+        }
+        None => write_rows(&mut std::io::stdout().lock(), &rows, format)?,
+    }
+    Ok(())
+}
+
+/// Run the broker self-test and log every check
+fn run_selftest(config: &Config, timeout: u64) -> anyhow::Result<()> {
+    let checks = selftest::run(config, std::time::Duration::from_secs(timeout));
+    for check in &checks {
+        if check.passed {
+            info!("{}", check);
+        } else {
+            error!("{}", check);
+        }
+    }
+    let failed = checks.iter().filter(|check| !check.passed).count();
+    if failed > 0 {
+        anyhow::bail!("{} of {} MQTT checks failed", failed, checks.len());
+    }
+    info!("✓ All {} MQTT checks passed", checks.len());
+    Ok(())
 }
 
 fn main() -> anyhow::Result<()> {
@@ -37,169 +354,75 @@ fn main() -> anyhow::Result<()> {
     let cli = Cli::parse();
 
     // Load configuration first (to get log level)
-    let config_path = cli.config;
-    let config = Config::from_file(&config_path)?;
+    let config_path = &cli.config;
+    let config = Config::from_file(config_path)?;
 
     // Initialize tracing with log level from config
     let app_log_level = config.default.log_level.as_str();
-    tracing_subscriber::fmt()
-        .with_env_filter(
-            tracing_subscriber::EnvFilter::from_default_env()
-                .add_directive(format!("e3dc_mqtt_rs={}", app_log_level).parse()?)
-                .add_directive("rscp=warn".parse()?), // Only show warnings/errors from rscp
-        )
-        .init();
-
-    let interval = Duration::from_std(config.e3dc.interval)?;
-    let statistic_interval = Duration::from_std(config.e3dc.statistic_update_interval)?;
-
-    info!("Configuration loaded from: {}", config_path);
-    info!("Log level: {}", config.default.log_level);
-    debug!("Debug logging is enabled");
-
-    info!("Configuration loaded successfully!");
-    info!("  E3DC Host: {}", config.e3dc.host);
-    info!("  MQTT Root: {}", config.mqtt.root);
-    info!("  Interval: {:?}", interval);
-    info!("  Statistics Interval: {:?}", statistic_interval);
-
-    // Create E3DC client
-    info!("Creating E3DC client...");
-    let mut e3dc_client = E3dcClient::new(
-        config.e3dc.host.clone(),
-        config.e3dc.key.clone(),
-        config.e3dc.username.clone(),
-        config.e3dc.password.clone(),
-    )?;
-
-    let batteries = e3dc_client.batteries().clone();
-
-    let system_info = e3dc_client.get_system_info()?;
-    let device_id = format!("{}-{}", system_info.model, system_info.serial_number);
-    info!("Device ID: {}", device_id);
-
-    // Query batteries at startup to know how many we have and their DCB counts
-    info!("Querying batteries...");
-    info!("Found {} battery/batteries", batteries.len());
-    for battery in batteries.iter() {
-        info!(
-            "  Battery {}: {} DCB modules",
-            battery.index, battery.dcb_count
-        );
+    let mut env_filter = tracing_subscriber::EnvFilter::from_default_env()
+        .add_directive(format!("e3dc_mqtt_rs={}", app_log_level).parse()?)
+        .add_directive("rscp=warn".parse()?); // Only show warnings/errors from rscp
+    if config.debug.rscp {
+        env_filter = env_filter.add_directive("e3dc_mqtt_rs::rscp=debug".parse()?);
+    }
+    // Logs go to stderr while stdout carries the export
+    if matches!(cli.command, Some(CliCommand::Stats { output: None, .. })) {
+        tracing_subscriber::fmt()
+            .with_env_filter(env_filter)
+            .with_writer(std::io::stderr)
+            .init();
+    } else {
+        tracing_subscriber::fmt().with_env_filter(env_filter).init();
     }
 
-    // Create MQTT publisher (blocking)
-    info!("Creating MQTT publisher...");
-    let mqtt_publisher = MqttPublisher::new(&config, device_id.clone())?;
-    info!("✓ MQTT publisher created successfully!");
-
-    // Give MQTT a moment to connect
-    std::thread::sleep(Duration::milliseconds(500).to_std()?);
+    match &cli.command {
+        Some(CliCommand::Migrate {
+            from_root,
+            republish,
+            dry_run,
+        }) => return migrate(&config, from_root, *republish, *dry_run),
+        Some(CliCommand::Capture { output }) => return capture(&config, output),
+        Some(CliCommand::Selftest { timeout }) => return run_selftest(&config, *timeout),
+        Some(CliCommand::Bench { runs }) => return bench(&config, *runs),
+        #[cfg(feature = "history")]
+        Some(CliCommand::Query {
+            metric,
+            since,
+            kind,
+        }) => return query(&config, metric, since, kind.as_deref()),
+        Some(CliCommand::Stats {
+            from,
+            to,
+            granularity,
+            format,
+            output,
+        }) => {
+            return stats(
+                &config,
+                *from,
+                *to,
+                *granularity,
+                *format,
+                output.as_deref(),
+            )
+        }
+        None => {}
+    }
 
-    // Publish online status
-    mqtt_publisher.publish_online_status(true)?;
-    info!("✓ Published online status");
+    info!("e3dc-mqtt-rs {}", BuildInfo::current());
+    info!("Configuration loaded from: {}", config_path);
 
-    // Setup signal handler for graceful shutdown
+    // Setup signal handler for graceful shutdown, a second signal exits right away
+    let shutdown = Shutdown::new();
+    let signal = shutdown.clone();
     ctrlc::set_handler(move || {
+        if signal.is_requested() {
+            std::process::exit(0);
+        }
         info!("Received shutdown signal (SIGTERM/SIGINT), exiting...");
-        std::process::exit(0);
+        signal.request();
     })
     .expect("Error setting signal handler");
 
-    // Publish initial system info
-    mqtt_publisher.publish_system_info(&mqtt::SystemInfo::from_e3dc(&system_info))?;
-    info!("✓ Published system info");
-
-    // Python-style timing: track next loop times
-    let mut next_loop = Utc::now();
-    let mut next_statistic_loop = Utc::now();
-
-    let mut last_status: Option<mqtt::Status> = None;
-    let mut last_battery_data: Vec<mqtt::BatteryData> = Vec::new();
-    let mut last_daily_stats: Option<DailyStatistics> = None;
-    info!("Starting main loop...");
-
-    loop {
-        let now = Utc::now();
-        if now >= next_loop {
-            next_loop = next_interval(now, interval);
-
-            // Get and publish current status (always)
-            let status = e3dc_client.get_status()?;
-            // Publish to MQTT (per-field change detection inside publish_status)
-            let mqtt_status = mqtt::Status::from_e3dc(&status);
-            if let Err(e) = mqtt_publisher.publish_status(&mqtt_status, last_status) {
-                error!("Failed to publish status: {:?}", e);
-                // Let it crash on MQTT errors
-                return Err(e.into());
-            }
-
-            debug!(
-                "Status: Solar={:.0}W Battery={:.0}W Grid={:.0}W Home={:.0}W SOC={:.1}%",
-                status.power_pv,
-                status.power_battery,
-                status.power_grid,
-                status.power_home,
-                status.battery_soc
-            );
-            last_status = Some(mqtt_status);
-        }
-
-        // Get and publish statistics (only when interval has elapsed)
-        if now >= next_statistic_loop {
-            next_statistic_loop = next_interval(now, statistic_interval);
-
-            // Publish daily statistics
-            let interval = TimeDelta::from_std(config.e3dc.statistic_update_interval)?;
-            let e3dc_stats = e3dc_client.get_daily_statistics(interval)?;
-            let stats = mqtt::DailyStatistics::from_e3dc(&e3dc_stats);
-            if let Err(e) = mqtt_publisher.publish_daily_statistics(&stats, last_daily_stats) {
-                error!("Failed to publish daily statistics: {:?}", e);
-                return Err(e.into());
-            }
-            info!(
-                "Statistics: Autarky={:.1}% SelfCons={:.1}% Solar={}Wh Consumption={}Wh",
-                e3dc_stats.autarky,
-                e3dc_stats.consumed_production,
-                e3dc_stats.solar_production,
-                e3dc_stats.consumption
-            );
-
-            last_daily_stats = Some(stats);
-
-            // Publish battery data for all known batteries with change detection
-            // Battery data now includes DCBs, much simpler!
-            let battery_data = e3dc_client.get_battery_data()?;
-            let bat_data: Vec<mqtt::BatteryData> = battery_data
-                .iter()
-                .map(mqtt::BatteryData::from_e3dc)
-                .collect();
-            mqtt_publisher.publish_battery_data(&bat_data, &last_battery_data)?;
-
-            for battery in &bat_data {
-                debug!(
-                    "Battery {}: SOC={:.1}%, {} DCBs with {} cells each",
-                    battery.index,
-                    battery.rsoc_real,
-                    battery.dcb_count,
-                    battery.dcbs.first().map(|d| d.voltages.len()).unwrap_or(0)
-                );
-            }
-
-            last_battery_data = bat_data;
-        }
-
-        // Python-style sleep: compensate for execution time
-        let sleep_duration = max(
-            min(next_loop, next_statistic_loop) - Utc::now(),
-            Duration::milliseconds(100),
-        );
-
-        std::thread::sleep(
-            sleep_duration
-                .to_std()
-                .expect("Sleep duration invalid - this is a bug in timing calculation"),
-        );
-    }
+    bridge::run(config, shutdown)
 }
@@ -0,0 +1,708 @@
+//! MQTT commands
+//!
+//! Commands are received on `{root}/{device-id}/command/{name}` and validated
+//! before anything is written to the E3DC. [`SettingsCommands`] is the
+//! [`CommandHandler`] for the EMS settings below, [`ClockCommands`] sets the
+//! unit's clock, [`EmergencyPowerTestCommands`] starts the emergency power
+//! self-test, [`ActionCommands`] triggers one-shot actions (refresh,
+//! manual charge) and [`WallboxCommands`] switches the wallbox phases.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+
+use crate::config::CommandsConfig;
+use crate::e3dc::{self, E3dcClient, PowerSettingsUpdate, WeatherForecastMode};
+use crate::errors::{CommandError, E3dcError};
+use crate::events::EmergencyPowerTestTracker;
+use crate::mqtt::subscriber::CommandHandler;
+use crate::mqtt::{DeviceClock, EmergencyPowerCapabilities, IdlePeriod, Publisher, SystemInfo};
+use crate::version::BuildInfo;
+
+/// Upper bound for power values accepted from MQTT (catches typos like "50000000")
+const MAX_POWER_SETTING_W: u64 = 100_000;
+
+/// Upper bound for the manual charge energy accepted from MQTT
+const MAX_MANUAL_CHARGE_WH: u64 = 100_000;
+
+/// Raw command as received from the broker
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IncomingCommand {
+    /// Topic suffix after `command/`
+    pub name: String,
+    pub payload: String,
+}
+
+/// Validated command ready to be executed
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    /// Maximum battery charge power (W), enables the power limits
+    MaxChargePower(u64),
+    /// Maximum battery discharge power (W), enables the power limits
+    MaxDischargePower(u64),
+    /// Battery only discharges when house consumption exceeds this value (W)
+    DischargeStartPower(u64),
+    /// EMS power save mode
+    PowerSaveEnabled(bool),
+    /// Weather regulated charging
+    WeatherRegulatedChargeEnabled(bool),
+    WeatherForecastMode(WeatherForecastMode),
+    /// Emergency power reserve (% of the usable battery energy)
+    EmergencyPowerReserve(f64),
+    /// Replace the weekly idle period schedule
+    IdlePeriods(Vec<e3dc::IdlePeriod>),
+}
+
+fn parse_idle_periods(command: &str, payload: &str) -> Result<Vec<e3dc::IdlePeriod>, CommandError> {
+    let invalid = |reason: String| CommandError::InvalidPayload {
+        command: command.to_string(),
+        reason,
+    };
+    let periods: Vec<IdlePeriod> =
+        serde_json::from_str(payload).map_err(|e| invalid(e.to_string()))?;
+    let periods = periods
+        .iter()
+        .map(IdlePeriod::to_e3dc)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(invalid)?;
+    e3dc::IdlePeriod::validate_schedule(&periods).map_err(invalid)?;
+    Ok(periods)
+}
+
+fn parse_bool(command: &str, payload: &str) -> Result<bool, CommandError> {
+    match payload.trim().to_lowercase().as_str() {
+        "true" | "on" | "1" => Ok(true),
+        "false" | "off" | "0" => Ok(false),
+        other => Err(CommandError::InvalidPayload {
+            command: command.to_string(),
+            reason: format!("'{}' is not a boolean (true/false, on/off, 1/0)", other),
+        }),
+    }
+}
+
+fn parse_percent(command: &str, payload: &str) -> Result<f64, CommandError> {
+    let value: f64 = payload
+        .trim()
+        .parse()
+        .map_err(|_| CommandError::InvalidPayload {
+            command: command.to_string(),
+            reason: format!("'{}' is not a number", payload.trim()),
+        })?;
+    if !(0.0..=100.0).contains(&value) {
+        return Err(CommandError::InvalidPayload {
+            command: command.to_string(),
+            reason: format!("{} % is outside 0-100 %", value),
+        });
+    }
+    Ok(value)
+}
+
+fn parse_power(command: &str, payload: &str) -> Result<u64, CommandError> {
+    let value: u64 = payload
+        .trim()
+        .parse()
+        .map_err(|_| CommandError::InvalidPayload {
+            command: command.to_string(),
+            reason: format!("'{}' is not a non-negative integer", payload.trim()),
+        })?;
+    if value > MAX_POWER_SETTING_W {
+        return Err(CommandError::InvalidPayload {
+            command: command.to_string(),
+            reason: format!("{} W exceeds maximum of {} W", value, MAX_POWER_SETTING_W),
+        });
+    }
+    Ok(value)
+}
+
+impl Command {
+    /// Names accepted by [`Command::parse`]
+    pub const NAMES: [&'static str; 8] = [
+        "discharge_start_power",
+        "max_charge_power",
+        "max_discharge_power",
+        "power_save_enabled",
+        "weather_regulated_charge_enabled",
+        "idle_periods",
+        "weather_forecast_mode",
+        "ep_reserve",
+    ];
+
+    /// Parse and validate a command received on `command/{name}`
+    pub fn parse(name: &str, payload: &str) -> Result<Self, CommandError> {
+        match name {
+            "discharge_start_power" => {
+                Ok(Command::DischargeStartPower(parse_power(name, payload)?))
+            }
+            "max_charge_power" => Ok(Command::MaxChargePower(parse_power(name, payload)?)),
+            "max_discharge_power" => Ok(Command::MaxDischargePower(parse_power(name, payload)?)),
+            "power_save_enabled" => Ok(Command::PowerSaveEnabled(parse_bool(name, payload)?)),
+            "weather_regulated_charge_enabled" => Ok(Command::WeatherRegulatedChargeEnabled(
+                parse_bool(name, payload)?,
+            )),
+            "idle_periods" => Ok(Command::IdlePeriods(parse_idle_periods(name, payload)?)),
+            "weather_forecast_mode" => WeatherForecastMode::parse(payload)
+                .map(Command::WeatherForecastMode)
+                .ok_or_else(|| CommandError::InvalidPayload {
+                    command: name.to_string(),
+                    reason: format!(
+                        "'{}' is not one of {}",
+                        payload.trim(),
+                        WeatherForecastMode::ALL
+                            .iter()
+                            .map(|mode| mode.as_str())
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    ),
+                }),
+            "ep_reserve" => Ok(Command::EmergencyPowerReserve(parse_percent(
+                name, payload,
+            )?)),
+            _ => Err(CommandError::UnknownCommand(name.to_string())),
+        }
+    }
+
+    /// Write the command to the E3DC
+    pub fn execute(&self, client: &mut E3dcClient) -> Result<(), E3dcError> {
+        match self {
+            Command::MaxChargePower(value) => client.set_power_settings(&PowerSettingsUpdate {
+                power_limits_used: Some(true),
+                max_charge_power: Some(*value),
+                ..Default::default()
+            }),
+            Command::MaxDischargePower(value) => client.set_power_settings(&PowerSettingsUpdate {
+                power_limits_used: Some(true),
+                max_discharge_power: Some(*value),
+                ..Default::default()
+            }),
+            Command::DischargeStartPower(value) => {
+                client.set_power_settings(&PowerSettingsUpdate {
+                    discharge_start_power: Some(*value),
+                    ..Default::default()
+                })
+            }
+            Command::WeatherForecastMode(mode) => client.set_power_settings(&PowerSettingsUpdate {
+                weather_forecast_mode: Some(*mode),
+                ..Default::default()
+            }),
+            Command::PowerSaveEnabled(enabled) => client.set_power_settings(&PowerSettingsUpdate {
+                power_save_enabled: Some(*enabled),
+                ..Default::default()
+            }),
+            Command::WeatherRegulatedChargeEnabled(enabled) => {
+                client.set_power_settings(&PowerSettingsUpdate {
+                    weather_regulated_charge_enabled: Some(*enabled),
+                    ..Default::default()
+                })
+            }
+            Command::EmergencyPowerReserve(percent) => client.set_emergency_power_reserve(*percent),
+            Command::IdlePeriods(periods) => client.set_idle_periods(periods),
+        }
+    }
+}
+
+/// Writes the EMS settings and republishes what they affect
+pub struct SettingsCommands;
+
+impl CommandHandler<E3dcClient> for SettingsCommands {
+    fn names(&self) -> &[&'static str] {
+        &Command::NAMES
+    }
+
+    fn handle(
+        &mut self,
+        command: &IncomingCommand,
+        client: &mut E3dcClient,
+        publisher: &dyn Publisher,
+    ) -> Result<String, CommandError> {
+        let parsed = Command::parse(&command.name, &command.payload)?;
+        tracing::info!("Executing command {:?}", parsed);
+        let failed = |reason: String| CommandError::Failed {
+            command: command.name.clone(),
+            reason,
+        };
+        parsed.execute(client).map_err(|e| failed(e.to_string()))?;
+
+        // Republish the affected data so the new settings are visible
+        let republished = match parsed {
+            Command::IdlePeriods(_) => client.get_idle_periods().map(|periods| {
+                let periods: Vec<IdlePeriod> = periods.iter().map(IdlePeriod::from_e3dc).collect();
+                publisher.publish_idle_periods(&periods)
+            }),
+            Command::EmergencyPowerReserve(_) => client.get_system_info().and_then(|info| {
+                let capacity = info
+                    .installed_battery_capacity
+                    .map(|capacity| capacity as f64);
+                client
+                    .get_emergency_power_capabilities(capacity)
+                    .map(|capabilities| {
+                        publisher.publish_emergency_power_capabilities(
+                            &EmergencyPowerCapabilities::from_e3dc(&capabilities),
+                            None,
+                        )
+                    })
+            }),
+            _ => client
+                .get_system_info()
+                .map(|info| publisher.publish_system_info(&SystemInfo::from_e3dc(&info))),
+        };
+        match republished {
+            Ok(Ok(())) => Ok(format!("{} = {}", command.name, command.payload.trim())),
+            Ok(Err(e)) => Err(failed(format!("written, but republishing failed: {}", e))),
+            Err(e) => Err(failed(format!("written, but reading back failed: {}", e))),
+        }
+    }
+}
+
+/// Payload of `command/set_time`: `now`, or an IANA time zone to set as well
+#[derive(Debug, Clone, PartialEq)]
+pub struct SetTime {
+    pub time_zone: Option<chrono_tz::Tz>,
+}
+
+impl SetTime {
+    pub const NAME: &'static str = "set_time";
+
+    /// Parse the payload and check the host clock it would write
+    ///
+    /// A host clock before the bridge's build time was never synchronized
+    /// (e.g. a board without RTC), writing it would break the statistics.
+    pub fn parse(payload: &str, now: DateTime<Utc>) -> Result<Self, CommandError> {
+        let invalid = |reason: String| CommandError::InvalidPayload {
+            command: Self::NAME.to_string(),
+            reason,
+        };
+        if let Some(build_time) = BuildInfo::current().build_time {
+            if now < build_time {
+                return Err(invalid(format!(
+                    "host clock {} is before the build time {}, not synchronized",
+                    now, build_time
+                )));
+            }
+        }
+        let time_zone = match payload.trim() {
+            "now" => None,
+            name => Some(name.parse::<chrono_tz::Tz>().map_err(|_| {
+                invalid(format!("'{}' is neither 'now' nor an IANA time zone", name))
+            })?),
+        };
+        Ok(Self { time_zone })
+    }
+}
+
+/// Writes the host clock to the unit (`command/set_time`)
+///
+/// A wrong clock shifts the unit's statistics days, so the command must be
+/// listed in `[commands] allow` explicitly; enabling all commands isn't enough.
+pub struct ClockCommands {
+    explicitly_allowed: bool,
+}
+
+impl ClockCommands {
+    pub fn new(acl: &CommandsConfig) -> Self {
+        Self {
+            explicitly_allowed: acl.allow.iter().any(|name| name == SetTime::NAME),
+        }
+    }
+}
+
+impl CommandHandler<E3dcClient> for ClockCommands {
+    fn names(&self) -> &[&'static str] {
+        &[SetTime::NAME]
+    }
+
+    fn handle(
+        &mut self,
+        command: &IncomingCommand,
+        client: &mut E3dcClient,
+        publisher: &dyn Publisher,
+    ) -> Result<String, CommandError> {
+        if !self.explicitly_allowed {
+            tracing::warn!(
+                "{} must be listed in [commands] allow explicitly",
+                SetTime::NAME
+            );
+            return Err(CommandError::NotAllowed(command.name.clone()));
+        }
+        let now = Utc::now();
+        let set_time = SetTime::parse(&command.payload, now)?;
+        let time_zone = set_time.time_zone.map(|tz| tz.name());
+        tracing::info!("Setting E3DC clock to {} ({:?})", now, time_zone);
+        let failed = |reason: String| CommandError::Failed {
+            command: command.name.clone(),
+            reason,
+        };
+        client
+            .set_time(now, time_zone)
+            .map_err(|e| failed(e.to_string()))?;
+
+        let clock = client
+            .get_device_clock()
+            .map_err(|e| failed(format!("written, but reading back failed: {}", e)))?;
+        let clock = DeviceClock::from_e3dc(&clock);
+        publisher
+            .publish_device_clock(&clock, None)
+            .map_err(|e| failed(format!("written, but republishing failed: {}", e)))?;
+        Ok(format!(
+            "clock set, {} {} (offset {} s)",
+            clock.local_time, clock.time_zone, clock.offset
+        ))
+    }
+}
+
+/// Time between `arm` and `confirm` of the emergency power test
+const EMERGENCY_POWER_TEST_CONFIRM: Duration = Duration::from_secs(60);
+
+/// Starts the emergency power self-test (`command/emergency_power_test`)
+///
+/// The test switches the house to island operation, so it needs two commands:
+/// `arm`, then `confirm` within a minute. The main loop follows the started
+/// test through the shared tracker.
+pub struct EmergencyPowerTestCommands {
+    armed: Option<Instant>,
+    tracker: Arc<Mutex<EmergencyPowerTestTracker>>,
+}
+
+impl EmergencyPowerTestCommands {
+    pub const NAME: &'static str = "emergency_power_test";
+
+    pub fn new(tracker: Arc<Mutex<EmergencyPowerTestTracker>>) -> Self {
+        Self {
+            armed: None,
+            tracker,
+        }
+    }
+}
+
+impl CommandHandler<E3dcClient> for EmergencyPowerTestCommands {
+    fn names(&self) -> &[&'static str] {
+        &[Self::NAME]
+    }
+
+    fn handle(
+        &mut self,
+        command: &IncomingCommand,
+        client: &mut E3dcClient,
+        publisher: &dyn Publisher,
+    ) -> Result<String, CommandError> {
+        let invalid = |reason: &str| CommandError::InvalidPayload {
+            command: command.name.clone(),
+            reason: reason.to_string(),
+        };
+        match command.payload.trim() {
+            "arm" => {
+                self.armed = Some(Instant::now());
+                Ok(format!(
+                    "armed, send 'confirm' within {} s to start the test",
+                    EMERGENCY_POWER_TEST_CONFIRM.as_secs()
+                ))
+            }
+            "confirm" => {
+                let armed = self.armed.take();
+                if armed.is_none_or(|at| at.elapsed() > EMERGENCY_POWER_TEST_CONFIRM) {
+                    return Err(invalid("not armed, send 'arm' first"));
+                }
+                let mut tracker = self.tracker.lock().unwrap_or_else(|e| e.into_inner());
+                if tracker.is_running() {
+                    return Err(invalid("a test is already running"));
+                }
+                tracing::info!("Starting emergency power test");
+                client
+                    .start_emergency_power_test()
+                    .map_err(|e| CommandError::Failed {
+                        command: command.name.clone(),
+                        reason: e.to_string(),
+                    })?;
+                let event = tracker.start(Utc::now());
+                publisher
+                    .publish_emergency_power_test(&event)
+                    .map_err(|e| CommandError::Failed {
+                        command: command.name.clone(),
+                        reason: format!("started, but publishing the event failed: {}", e),
+                    })?;
+                Ok("emergency power test started".to_string())
+            }
+            _ => Err(invalid("expected 'arm' or 'confirm'")),
+        }
+    }
+}
+
+/// One-shot actions: `command/refresh` polls status and statistics right
+/// away, `command/manual_charge` starts a manual charge of the given Wh (0 stops it)
+///
+/// The refresh is done by the main loop, which takes the shared flag.
+pub struct ActionCommands {
+    refresh: Arc<AtomicBool>,
+}
+
+impl ActionCommands {
+    pub const REFRESH: &'static str = "refresh";
+    pub const MANUAL_CHARGE: &'static str = "manual_charge";
+
+    pub fn new(refresh: Arc<AtomicBool>) -> Self {
+        Self { refresh }
+    }
+
+    /// Parse the energy of `command/manual_charge` in Wh
+    pub fn parse_manual_charge(payload: &str) -> Result<u64, CommandError> {
+        let invalid = |reason: String| CommandError::InvalidPayload {
+            command: Self::MANUAL_CHARGE.to_string(),
+            reason,
+        };
+        let energy: u64 = payload.trim().parse().map_err(|_| {
+            invalid(format!(
+                "'{}' is not a non-negative integer",
+                payload.trim()
+            ))
+        })?;
+        if energy > MAX_MANUAL_CHARGE_WH {
+            return Err(invalid(format!(
+                "{} Wh exceeds maximum of {} Wh",
+                energy, MAX_MANUAL_CHARGE_WH
+            )));
+        }
+        Ok(energy)
+    }
+}
+
+impl CommandHandler<E3dcClient> for ActionCommands {
+    fn names(&self) -> &[&'static str] {
+        &[Self::REFRESH, Self::MANUAL_CHARGE]
+    }
+
+    fn handle(
+        &mut self,
+        command: &IncomingCommand,
+        client: &mut E3dcClient,
+        _publisher: &dyn Publisher,
+    ) -> Result<String, CommandError> {
+        if command.name == Self::REFRESH {
+            self.refresh.store(true, Ordering::Relaxed);
+            return Ok("refresh requested".to_string());
+        }
+        let energy = Self::parse_manual_charge(&command.payload)?;
+        tracing::info!("Starting manual charge of {} Wh", energy);
+        client
+            .start_manual_charge(energy)
+            .map_err(|e| CommandError::Failed {
+                command: command.name.clone(),
+                reason: e.to_string(),
+            })?;
+        Ok(if energy == 0 {
+            "manual charge stopped".to_string()
+        } else {
+            format!("manual charge of {} Wh started", energy)
+        })
+    }
+}
+
+/// Switches the wallbox between 1- and 3-phase charging (`command/wb_phases`)
+///
+/// Surplus charging drops to one phase when the PV output can't cover the
+/// minimum current on three.
+pub struct WallboxCommands {
+    index: u8,
+}
+
+impl WallboxCommands {
+    pub const NAME: &'static str = "wb_phases";
+
+    pub fn new(index: u8) -> Self {
+        Self { index }
+    }
+
+    /// Parse the number of phases, 1 or 3
+    pub fn parse_phases(payload: &str) -> Result<u8, CommandError> {
+        match payload.trim() {
+            "1" => Ok(1),
+            "3" => Ok(3),
+            other => Err(CommandError::InvalidPayload {
+                command: Self::NAME.to_string(),
+                reason: format!("'{}' is not 1 or 3 phases", other),
+            }),
+        }
+    }
+}
+
+impl CommandHandler<E3dcClient> for WallboxCommands {
+    fn names(&self) -> &[&'static str] {
+        &[Self::NAME]
+    }
+
+    fn handle(
+        &mut self,
+        command: &IncomingCommand,
+        client: &mut E3dcClient,
+        publisher: &dyn Publisher,
+    ) -> Result<String, CommandError> {
+        let phases = Self::parse_phases(&command.payload)?;
+        let failed = |reason: String| CommandError::Failed {
+            command: command.name.clone(),
+            reason,
+        };
+        // Writing to a wallbox without phase switching may be accepted and ignored
+        if client
+            .get_wallbox_phases(self.index)
+            .map_err(|e| failed(e.to_string()))?
+            .is_none()
+        {
+            return Err(failed(format!(
+                "wallbox {} doesn't support phase switching",
+                self.index
+            )));
+        }
+        tracing::info!("Switching wallbox {} to {} phases", self.index, phases);
+        client
+            .set_wallbox_phases(self.index, phases)
+            .map_err(|e| failed(e.to_string()))?;
+
+        let current = client
+            .get_wallbox_phases(self.index)
+            .map_err(|e| failed(format!("written, but reading back failed: {}", e)))?
+            .ok_or_else(|| failed("written, but reading back failed".to_string()))?;
+        publisher
+            .publish_wallbox_phases(current)
+            .map_err(|e| failed(format!("written, but republishing failed: {}", e)))?;
+        Ok(format!(
+            "wallbox {} charges with {} phases",
+            self.index, current
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_discharge_start_power() {
+        assert_eq!(
+            Command::parse("discharge_start_power", " 65 ").unwrap(),
+            Command::DischargeStartPower(65)
+        );
+        assert!(Command::parse("discharge_start_power", "-5").is_err());
+        assert!(Command::parse("discharge_start_power", "abc").is_err());
+        assert!(Command::parse("discharge_start_power", "200000").is_err());
+    }
+
+    #[test]
+    fn test_parse_switches() {
+        assert_eq!(
+            Command::parse("power_save_enabled", "ON").unwrap(),
+            Command::PowerSaveEnabled(true)
+        );
+        assert_eq!(
+            Command::parse("weather_regulated_charge_enabled", "false").unwrap(),
+            Command::WeatherRegulatedChargeEnabled(false)
+        );
+        assert!(Command::parse("power_save_enabled", "maybe").is_err());
+    }
+
+    #[test]
+    fn test_parse_weather_forecast_mode() {
+        assert_eq!(
+            Command::parse("weather_forecast_mode", "off").unwrap(),
+            Command::WeatherForecastMode(WeatherForecastMode::Off)
+        );
+        assert_eq!(
+            Command::parse("weather_forecast_mode", "Forecast").unwrap(),
+            Command::WeatherForecastMode(WeatherForecastMode::Forecast)
+        );
+        assert_eq!(
+            Command::parse("weather_forecast_mode", "2").unwrap(),
+            Command::WeatherForecastMode(WeatherForecastMode::ForecastOptimized)
+        );
+        assert!(Command::parse("weather_forecast_mode", "7").is_err());
+        assert!(Command::parse("weather_forecast_mode", "sunny").is_err());
+    }
+
+    #[test]
+    fn test_parse_ep_reserve() {
+        assert_eq!(
+            Command::parse("ep_reserve", " 20.5 ").unwrap(),
+            Command::EmergencyPowerReserve(20.5)
+        );
+        assert!(Command::parse("ep_reserve", "101").is_err());
+        assert!(Command::parse("ep_reserve", "-1").is_err());
+        assert!(Command::parse("ep_reserve", "NaN").is_err());
+    }
+
+    #[test]
+    fn test_parse_idle_periods() {
+        let payload = r#"[
+            {"type": "charge", "day": "monday", "start": "08:00", "end": "12:00"},
+            {"type": "discharge", "day": "monday", "start": "10:00", "end": "14:00"},
+            {"type": "charge", "day": "sun", "start": "00:00", "end": "06:30", "active": false}
+        ]"#;
+        let Command::IdlePeriods(periods) = Command::parse("idle_periods", payload).unwrap() else {
+            panic!("expected idle periods");
+        };
+        assert_eq!(periods.len(), 3);
+        assert_eq!(periods[2].day, chrono::Weekday::Sun);
+        assert!(!periods[2].active);
+
+        // Overlapping windows of the same type
+        let overlapping = r#"[
+            {"type": "charge", "day": "monday", "start": "08:00", "end": "12:00"},
+            {"type": "charge", "day": "monday", "start": "11:00", "end": "13:00"}
+        ]"#;
+        assert!(Command::parse("idle_periods", overlapping).is_err());
+
+        // Invalid times
+        let invalid = r#"[{"type": "charge", "day": "monday", "start": "25:00", "end": "26:00"}]"#;
+        assert!(Command::parse("idle_periods", invalid).is_err());
+        let reversed = r#"[{"type": "charge", "day": "monday", "start": "12:00", "end": "08:00"}]"#;
+        assert!(Command::parse("idle_periods", reversed).is_err());
+        assert!(Command::parse("idle_periods", "not json").is_err());
+    }
+
+    #[test]
+    fn test_names_parse() {
+        // Every advertised name is known to the parser (payload errors only)
+        for name in Command::NAMES {
+            assert!(!matches!(
+                Command::parse(name, ""),
+                Err(CommandError::UnknownCommand(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_parse_set_time() {
+        let now = Utc::now();
+        assert_eq!(SetTime::parse(" now ", now).unwrap().time_zone, None);
+        assert_eq!(
+            SetTime::parse("Europe/Berlin", now).unwrap().time_zone,
+            Some(chrono_tz::Europe::Berlin)
+        );
+        assert!(SetTime::parse("Mars/Olympus", now).is_err());
+        // Unsynchronized host clock
+        let epoch = DateTime::from_timestamp(0, 0).unwrap();
+        assert!(SetTime::parse("now", epoch).is_err());
+    }
+
+    #[test]
+    fn test_parse_manual_charge() {
+        assert_eq!(ActionCommands::parse_manual_charge(" 5000 ").unwrap(), 5000);
+        assert_eq!(ActionCommands::parse_manual_charge("0").unwrap(), 0);
+        assert!(ActionCommands::parse_manual_charge("-1").is_err());
+        assert!(ActionCommands::parse_manual_charge("500000").is_err());
+    }
+
+    #[test]
+    fn test_parse_wallbox_phases() {
+        assert_eq!(WallboxCommands::parse_phases(" 1 ").unwrap(), 1);
+        assert_eq!(WallboxCommands::parse_phases("3").unwrap(), 3);
+        assert!(WallboxCommands::parse_phases("2").is_err());
+        assert!(WallboxCommands::parse_phases("three").is_err());
+    }
+
+    #[test]
+    fn test_parse_unknown_command() {
+        assert!(matches!(
+            Command::parse("self_destruct", "1"),
+            Err(CommandError::UnknownCommand(_))
+        ));
+    }
+}
@@ -1,16 +1,164 @@
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+
 use chrono::{DateTime, Duration, Utc};
 use rumqttc::{Client, QoS};
+use serde::Serialize;
 
+use crate::config::{PayloadConfig, PayloadEncoding, Timezone};
+use crate::e3dc::EmergencyPowerState;
 use crate::errors::MqttError;
+use crate::events::{
+    BatteryErrorEvent, CellImbalanceEvent, DcbCountChangedEvent, DcbModuleChangedEvent,
+    EmergencyPowerEvent, EmergencyPowerTestEvent, EnergyBalanceEvent, ExportLimitEvent,
+    GridOutageEvent, RejectedCommandEvent, SocAlertEvent, TemperatureAlertEvent,
+    WallboxSessionEvent,
+};
+use crate::lifecycle::BridgeState;
+use crate::mqtt::diff::Changes;
+use crate::mqtt::subscriber::CommandAck;
+use crate::mqtt::SystemInfo;
+
+/// How floats are rendered: fixed decimals, never scientific notation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FloatFormat {
+    pub decimals: usize,
+    pub trim_trailing_zeros: bool,
+}
+
+impl FloatFormat {
+    pub const DEFAULT: FloatFormat = FloatFormat {
+        decimals: 3,
+        trim_trailing_zeros: true,
+    };
+
+    pub fn format(&self, value: f64) -> String {
+        // inf/NaN have no fixed-point form
+        if !value.is_finite() {
+            return value.to_string();
+        }
+        let mut text = format!("{:.*}", self.decimals, value);
+        if self.trim_trailing_zeros && text.contains('.') {
+            let len = text.trim_end_matches('0').trim_end_matches('.').len();
+            text.truncate(len);
+        }
+        // Values rounding to zero shouldn't flip between "0" and "-0"
+        if text.starts_with('-') && text[1..].chars().all(|c| c == '0' || c == '.') {
+            text.remove(0);
+        }
+        text
+    }
+}
+
+impl Default for FloatFormat {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+impl Timezone {
+    /// RFC3339 in this timezone, the offset keeps the value unambiguous
+    pub fn format(&self, time: &DateTime<Utc>) -> String {
+        match self {
+            Timezone::Utc => time.to_rfc3339(),
+            Timezone::Local => time.with_timezone(&chrono::Local).to_rfc3339(),
+            Timezone::Named(tz) => time.with_timezone(tz).to_rfc3339(),
+        }
+    }
+}
+
+/// Float formatting policy with per-field overrides (keyed by topic name)
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PayloadFormat {
+    pub float: FloatFormat,
+    pub field_decimals: BTreeMap<String, usize>,
+    pub timezone: Timezone,
+    pub encoding: PayloadEncoding,
+}
+
+impl PayloadFormat {
+    pub const DEFAULT: PayloadFormat = PayloadFormat {
+        float: FloatFormat::DEFAULT,
+        field_decimals: BTreeMap::new(),
+        timezone: Timezone::Utc,
+        encoding: PayloadEncoding::Json,
+    };
+
+    pub fn from_config(config: &PayloadConfig) -> Self {
+        Self {
+            float: FloatFormat {
+                decimals: config.float_decimals,
+                trim_trailing_zeros: config.trim_trailing_zeros,
+            },
+            field_decimals: config.decimals.clone(),
+            timezone: config.timezone,
+            encoding: config.encoding,
+        }
+    }
+
+    /// Float format for a field, applying its decimals override if configured
+    pub fn for_field(&self, field: &str) -> FloatFormat {
+        match self.field_decimals.get(field) {
+            Some(&decimals) => FloatFormat {
+                decimals,
+                ..self.float
+            },
+            None => self.float,
+        }
+    }
+}
+
+pub(crate) static DEFAULT_PAYLOAD_FORMAT: PayloadFormat = PayloadFormat::DEFAULT;
+
+pub(crate) static NO_RENAMES: BTreeMap<String, String> = BTreeMap::new();
+
+/// `name` with its last level renamed per `[mqtt.rename]`
+pub fn renamed_topic<'a>(renames: &BTreeMap<String, String>, name: &'a str) -> Cow<'a, str> {
+    let (path, leaf) = match name.rsplit_once('/') {
+        Some((path, leaf)) => (Some(path), leaf),
+        None => (None, name),
+    };
+    match (renames.get(leaf), path) {
+        (None, _) => Cow::Borrowed(name),
+        (Some(leaf), None) => Cow::Owned(leaf.clone()),
+        (Some(leaf), Some(path)) => Cow::Owned(format!("{}/{}", path, leaf)),
+    }
+}
 
 pub trait MqttPayload {
     fn to_payload(&self) -> String;
+
+    /// Render with an explicit float format, only float payloads use it
+    fn to_payload_with(&self, _format: FloatFormat) -> String {
+        self.to_payload()
+    }
+
+    /// Render the topic `field` with the full payload policy
+    fn to_payload_for(&self, format: &PayloadFormat, field: &str) -> String {
+        self.to_payload_with(format.for_field(field))
+    }
+
+    /// Binary form for `payload.encoding = "cbor"`, only aggregate payloads have one
+    fn to_cbor(&self, _format: FloatFormat) -> Option<Result<Vec<u8>, MqttError>> {
+        None
+    }
+}
+
+/// Encode `value` as CBOR
+pub fn to_cbor<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, MqttError> {
+    let mut bytes = Vec::new();
+    ciborium::into_writer(value, &mut bytes).map_err(|e| MqttError::CborError(e.to_string()))?;
+    Ok(bytes)
 }
 
 impl MqttPayload for DateTime<Utc> {
     fn to_payload(&self) -> String {
         self.to_rfc3339()
     }
+
+    fn to_payload_for(&self, format: &PayloadFormat, _field: &str) -> String {
+        format.timezone.format(self)
+    }
 }
 
 impl MqttPayload for Duration {
@@ -21,14 +169,28 @@ impl MqttPayload for Duration {
 
 impl MqttPayload for Vec<f64> {
     fn to_payload(&self) -> String {
+        self.to_payload_with(FloatFormat::DEFAULT)
+    }
+
+    fn to_payload_with(&self, format: FloatFormat) -> String {
         format!(
             "[{}]",
             self.iter()
-                .map(|v| v.to_string())
+                .map(|v| format.format(*v))
                 .collect::<Vec<_>>()
                 .join(",")
         )
     }
+
+    fn to_cbor(&self, format: FloatFormat) -> Option<Result<Vec<u8>, MqttError>> {
+        // Cell values are rounded to the configured decimals anyway, f32 halves the size
+        let factor = 10_f64.powi(format.decimals as i32);
+        let values: Vec<f32> = self
+            .iter()
+            .map(|v| ((v * factor).round() / factor) as f32)
+            .collect();
+        Some(to_cbor(&values))
+    }
 }
 
 impl MqttPayload for String {
@@ -45,7 +207,11 @@ impl MqttPayload for bool {
 
 impl MqttPayload for f64 {
     fn to_payload(&self) -> String {
-        self.to_string()
+        self.to_payload_with(FloatFormat::DEFAULT)
+    }
+
+    fn to_payload_with(&self, format: FloatFormat) -> String {
+        format.format(*self)
     }
 }
 
@@ -55,29 +221,215 @@ impl MqttPayload for u64 {
     }
 }
 
+impl MqttPayload for EmergencyPowerState {
+    fn to_payload(&self) -> String {
+        self.as_str().to_string()
+    }
+}
+
+impl MqttPayload for BridgeState {
+    fn to_payload(&self) -> String {
+        self.as_str().to_string()
+    }
+}
+
+/// Data with the time it was read from the E3DC (or the event occurred)
+pub trait Timestamped {
+    fn time_stamp(&self) -> DateTime<Utc>;
+}
+
+impl Timestamped for SystemInfo<'_> {
+    fn time_stamp(&self) -> DateTime<Utc> {
+        self.time
+    }
+}
+
+/// `Timestamped` for types with a `time: DateTime<Utc>` field
+macro_rules! impl_timestamped {
+    ($($ty:ty),+ $(,)?) => {
+        $(
+            impl Timestamped for $ty {
+                fn time_stamp(&self) -> DateTime<Utc> {
+                    self.time
+                }
+            }
+        )+
+    };
+}
+
+impl_timestamped!(
+    CellImbalanceEvent,
+    BatteryErrorEvent,
+    EmergencyPowerEvent,
+    DcbModuleChangedEvent,
+    DcbCountChangedEvent,
+    ExportLimitEvent,
+    EnergyBalanceEvent,
+    GridOutageEvent,
+    WallboxSessionEvent,
+    SocAlertEvent,
+    TemperatureAlertEvent,
+    CommandAck,
+    EmergencyPowerTestEvent,
+    RejectedCommandEvent,
+);
+
+/// JSON document with its source `time_stamp` and the bridge's `published_at`,
+/// so late or replayed messages can be ordered downstream
+#[derive(Serialize)]
+pub struct JsonDocument<'a, T: Serialize> {
+    #[serde(flatten)]
+    pub data: &'a T,
+    pub time_stamp: DateTime<Utc>,
+    pub published_at: DateTime<Utc>,
+}
+
+impl<'a, T: Serialize + Timestamped> JsonDocument<'a, T> {
+    pub fn new(data: &'a T) -> Self {
+        Self {
+            data,
+            time_stamp: data.time_stamp(),
+            published_at: Utc::now(),
+        }
+    }
+}
+
+/// Sends a rendered payload, implemented by the MQTT client and test doubles
+pub trait Transport {
+    fn send(&self, topic: &str, payload: String, qos: QoS, retain: bool) -> Result<(), MqttError>;
+
+    /// Send a binary payload (CBOR encoding), text-only transports reject it
+    fn send_binary(
+        &self,
+        topic: &str,
+        _payload: Vec<u8>,
+        _qos: QoS,
+        _retain: bool,
+    ) -> Result<(), MqttError> {
+        Err(MqttError::PublishFailed {
+            topic: topic.to_string(),
+            reason: "transport does not support binary payloads".to_string(),
+        })
+    }
+}
+
+impl Transport for Client {
+    fn send(&self, topic: &str, payload: String, qos: QoS, retain: bool) -> Result<(), MqttError> {
+        self.send_binary(topic, payload.into_bytes(), qos, retain)
+    }
+
+    fn send_binary(
+        &self,
+        topic: &str,
+        payload: Vec<u8>,
+        qos: QoS,
+        retain: bool,
+    ) -> Result<(), MqttError> {
+        self.publish(topic, qos, retain, payload)
+            .map_err(|e| MqttError::PublishFailed {
+                topic: topic.to_string(),
+                reason: e.to_string(),
+            })
+    }
+}
+
 pub struct PublishContext<'a> {
-    client: &'a Client,
+    transport: &'a dyn Transport,
     pub topic: String,
     pub qos: QoS,
     pub retain: bool,
+    pub format: &'a PayloadFormat,
+    /// Leaf renames of `publish` and `clear`, documents keep their names
+    pub renames: &'a BTreeMap<String, String>,
 }
 
 impl<'a> PublishContext<'a> {
-    pub fn new(client: &'a Client, topic: impl Into<String>) -> Self {
+    pub fn new(transport: &'a dyn Transport, topic: impl Into<String>) -> Self {
         Self {
-            client,
+            transport,
             topic: topic.into(),
             qos: QoS::AtLeastOnce,
             retain: true,
+            format: &DEFAULT_PAYLOAD_FORMAT,
+            renames: &NO_RENAMES,
         }
     }
-    pub fn publish<T: MqttPayload>(&self, topic: &str, payload: &T) -> Result<(), MqttError> {
+    pub fn publish<T: MqttPayload + ?Sized>(
+        &self,
+        topic: &str,
+        payload: &T,
+    ) -> Result<(), MqttError> {
+        let full_topic = format!("{}/{}", self.topic, renamed_topic(self.renames, topic));
+        if self.format.encoding == PayloadEncoding::Cbor {
+            if let Some(bytes) = payload.to_cbor(self.format.for_field(topic)) {
+                return self
+                    .transport
+                    .send_binary(&full_topic, bytes?, self.qos, self.retain);
+            }
+        }
+        let payload = payload.to_payload_for(self.format, topic);
+        self.transport
+            .send(&full_topic, payload, self.qos, self.retain)
+    }
+
+    /// Publish an aggregate data document as JSON, or CBOR if configured
+    pub fn publish_document<T: Serialize + ?Sized>(
+        &self,
+        topic: &str,
+        document: &T,
+    ) -> Result<(), MqttError> {
+        match self.format.encoding {
+            PayloadEncoding::Json => self.publish_json(topic, document),
+            PayloadEncoding::Cbor => {
+                let full_topic = format!("{}/{}", self.topic, topic);
+                self.transport
+                    .send_binary(&full_topic, to_cbor(document)?, self.qos, self.retain)
+            }
+        }
+    }
+
+    /// Publish a document as JSON regardless of `[payload] encoding`, for
+    /// events, acknowledgements and settings that automations parse
+    pub fn publish_json<T: Serialize + ?Sized>(
+        &self,
+        topic: &str,
+        document: &T,
+    ) -> Result<(), MqttError> {
         let full_topic = format!("{}/{}", self.topic, topic);
-        self.client
-            .publish(&full_topic, self.qos, self.retain, payload.to_payload())
-            .map_err(|e| MqttError::PublishFailed {
-                topic: full_topic,
-                reason: e.to_string(),
-            })
+        let json = serde_json::to_string(document)
+            .map_err(|error| MqttError::SerializationError { error })?;
+        self.transport
+            .send(&full_topic, json, self.qos, self.retain)
+    }
+
+    /// Remove the retained message of `topic` (empty retained payload)
+    pub fn clear(&self, topic: &str) -> Result<(), MqttError> {
+        let full_topic = format!("{}/{}", self.topic, renamed_topic(self.renames, topic));
+        self.transport
+            .send(&full_topic, String::new(), self.qos, true)
+    }
+
+    /// Publish all changed fields below this context's topic
+    ///
+    /// A failed field doesn't stop the others, the error names the number
+    /// of failed fields and the first failure.
+    pub fn publish_changes(&self, changes: &Changes) -> Result<(), MqttError> {
+        let mut total = 0;
+        let mut failed = 0;
+        let mut first_error = None;
+        for (name, value) in changes.iter() {
+            total += 1;
+            if let Err(e) = self.publish(name, value) {
+                failed += 1;
+                first_error.get_or_insert(e);
+            }
+        }
+        match first_error {
+            None => Ok(()),
+            Some(e) => Err(MqttError::PublishFailed {
+                topic: self.topic.clone(),
+                reason: format!("{} of {} fields failed, first: {}", failed, total, e),
+            }),
+        }
     }
 }
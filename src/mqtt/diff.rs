@@ -0,0 +1,506 @@
+//! Transport-agnostic change detection
+//!
+//! Compares a freshly converted value with the previously published one and
+//! collects the fields that changed, so only those are sent.
+
+use chrono::{DateTime, Duration, Utc};
+
+use crate::mqtt::context::MqttPayload;
+use crate::mqtt::{
+    BatteryData, BatteryRates, ChargeLimits, CycleTrend, DailyStatistics, DcbData, DcdcData,
+    DeviceClock, EmergencyPowerCapabilities, EmergencyPowerUsage, ExportLimitUsage,
+    IntegratedEnergy, NetworkConfig, PeriodStatistics, PhaseBalance, PvForecast, PviData,
+    PviString, Status,
+};
+
+/// Changed fields as (topic name, value) pairs, in publish order
+#[derive(Default)]
+pub struct Changes<'a> {
+    fields: Vec<(&'static str, &'a dyn MqttPayload)>,
+}
+
+impl<'a> Changes<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, name: &'static str, value: &'a dyn MqttPayload) {
+        self.fields.push((name, value));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    /// Leave out the fields named in `unknown`, they have no value to publish
+    fn without(mut self, unknown: &[&str]) -> Self {
+        self.fields.retain(|(name, _)| !unknown.contains(name));
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.fields.len()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&'static str, &'a dyn MqttPayload)> + '_ {
+        self.fields.iter().copied()
+    }
+
+    /// Topic names of the changed fields
+    pub fn names(&self) -> Vec<&'static str> {
+        self.fields.iter().map(|(name, _)| *name).collect()
+    }
+}
+
+/// Forces a full republish of unchanged values after a maximum age
+///
+/// Change detection alone never republishes a stable value, so consumers
+/// joining later (or non-retained setups) would never see it.
+#[derive(Debug, Default)]
+pub struct Heartbeat {
+    max_age: Option<Duration>,
+    last_full: Option<DateTime<Utc>>,
+    /// Set by [`Heartbeat::force`]
+    forced: bool,
+}
+
+impl Heartbeat {
+    /// `None` disables the heartbeat, only changes are published
+    pub fn new(max_age: Option<Duration>) -> Self {
+        Self {
+            max_age,
+            last_full: None,
+            forced: false,
+        }
+    }
+
+    /// Publish everything at the next check, even without a heartbeat
+    pub fn force(&mut self) {
+        self.forced = true;
+    }
+
+    /// Whether everything has to be published at `now`, restarts the period if so
+    pub fn due(&mut self, now: DateTime<Utc>) -> bool {
+        if std::mem::take(&mut self.forced) {
+            self.last_full = Some(now);
+            return true;
+        }
+        let Some(max_age) = self.max_age else {
+            return false;
+        };
+        match self.last_full {
+            Some(last) if now - last < max_age => false,
+            _ => {
+                self.last_full = Some(now);
+                true
+            }
+        }
+    }
+
+    /// Previous value to diff against, `None` when a full republish is due
+    pub fn previous<'a, T>(&mut self, now: DateTime<Utc>, old: Option<&'a T>) -> Option<&'a T> {
+        if self.due(now) {
+            None
+        } else {
+            old
+        }
+    }
+}
+
+/// Push every listed field whose value differs from `$old` (all of them without `$old`)
+macro_rules! diff_fields {
+    ($changes:ident, $new:ident, $old:ident, $($field:ident),+ $(,)?) => {
+        $(
+            if $old.map_or(true, |o| o.$field != $new.$field) {
+                $changes.push(stringify!($field), &$new.$field);
+            }
+        )+
+    };
+}
+
+impl Status {
+    pub fn diff<'a>(&'a self, old: Option<&Status>) -> Changes<'a> {
+        // A field that got its first value is compared against nothing
+        let old = old.filter(|old| old.unknown.iter().all(|name| self.unknown.contains(name)));
+        let mut changes = Changes::new();
+        diff_fields!(
+            changes,
+            self,
+            old,
+            time,
+            additional,
+            autarky,
+            battery_charge,
+            battery_discharge,
+            battery_consumption,
+            consumption_from_grid,
+            export_to_grid,
+            grid_production,
+            house_consumption,
+            self_consumption,
+            solar_production,
+            solar_production_excess,
+            state_of_charge,
+            wb_consumption,
+            emergency_power,
+        );
+        changes.without(&self.unknown)
+    }
+}
+
+impl DailyStatistics {
+    pub fn diff<'a>(&'a self, old: Option<&DailyStatistics>) -> Changes<'a> {
+        let mut changes = Changes::new();
+        diff_fields!(
+            changes,
+            self,
+            old,
+            time,
+            autarky_today,
+            self_consumption_today,
+            solar_production_today,
+            house_consumption_today,
+            battery_charge_today,
+            battery_discharge_today,
+            export_to_grid_today,
+            consumption_from_grid_today,
+            state_of_charge_today,
+            start,
+            timespan,
+        );
+        changes
+    }
+}
+
+impl ChargeLimits {
+    pub fn diff<'a>(&'a self, old: Option<&ChargeLimits>) -> Changes<'a> {
+        // A field that got its first value is compared against nothing
+        let old = old.filter(|old| old.unknown.iter().all(|name| self.unknown.contains(name)));
+        let mut changes = Changes::new();
+        diff_fields!(
+            changes,
+            self,
+            old,
+            time,
+            used_charge_limit,
+            bat_charge_limit,
+            dcdc_charge_limit,
+            user_charge_limit,
+            used_discharge_limit,
+            bat_discharge_limit,
+            dcdc_discharge_limit,
+            user_discharge_limit,
+            remaining_bat_charge_power,
+            remaining_bat_discharge_power,
+            charge_limited,
+            discharge_limited,
+        );
+        changes.without(&self.unknown)
+    }
+}
+
+impl PviData {
+    /// Inverter level fields only, strings are diffed separately
+    pub fn diff<'a>(&'a self, old: Option<&PviData>) -> Changes<'a> {
+        let mut changes = Changes::new();
+        diff_fields!(changes, self, old, time, on_grid, ac_power, dc_power);
+        changes
+    }
+}
+
+impl DcdcData {
+    pub fn diff<'a>(&'a self, old: Option<&DcdcData>) -> Changes<'a> {
+        let mut changes = Changes::new();
+        diff_fields!(
+            changes,
+            self,
+            old,
+            time,
+            battery_current,
+            battery_voltage,
+            battery_power,
+            dcl_current,
+            dcl_voltage,
+            dcl_power,
+            status,
+            state,
+            substate,
+            error_code,
+            warning_code,
+        );
+        changes
+    }
+}
+
+impl PviString {
+    pub fn diff<'a>(&'a self, old: Option<&PviString>) -> Changes<'a> {
+        let mut changes = Changes::new();
+        diff_fields!(changes, self, old, dc_power, dc_voltage, dc_current);
+        changes
+    }
+}
+
+impl PhaseBalance {
+    pub fn diff<'a>(&'a self, old: Option<&PhaseBalance>) -> Changes<'a> {
+        let mut changes = Changes::new();
+        diff_fields!(
+            changes,
+            self,
+            old,
+            time,
+            l1_balanced,
+            l2_balanced,
+            l3_balanced,
+            power_l1,
+            power_l2,
+            power_l3,
+        );
+        changes
+    }
+}
+
+impl ExportLimitUsage {
+    pub fn diff<'a>(&'a self, old: Option<&ExportLimitUsage>) -> Changes<'a> {
+        let mut changes = Changes::new();
+        diff_fields!(changes, self, old, export_limit, export_limit_usage);
+        changes
+    }
+}
+
+impl BatteryRates {
+    pub fn diff<'a>(&'a self, old: Option<&BatteryRates>) -> Changes<'a> {
+        let mut changes = Changes::new();
+        diff_fields!(changes, self, old, soc_rate, time_to_full, time_to_empty);
+        changes
+    }
+}
+
+impl IntegratedEnergy {
+    pub fn diff<'a>(&'a self, old: Option<&IntegratedEnergy>) -> Changes<'a> {
+        let mut changes = Changes::new();
+        diff_fields!(
+            changes,
+            self,
+            old,
+            solar_production_energy_integrated,
+            house_consumption_energy_integrated,
+            battery_charge_energy_integrated,
+            battery_discharge_energy_integrated,
+            export_to_grid_energy_integrated,
+            consumption_from_grid_energy_integrated,
+        );
+        changes
+    }
+}
+
+impl EmergencyPowerUsage {
+    pub fn diff<'a>(&'a self, old: Option<&EmergencyPowerUsage>) -> Changes<'a> {
+        let mut changes = Changes::new();
+        diff_fields!(changes, self, old, switchovers, island_time, island_energy);
+        changes
+    }
+}
+
+impl EmergencyPowerCapabilities {
+    pub fn diff<'a>(&'a self, old: Option<&EmergencyPowerCapabilities>) -> Changes<'a> {
+        let old = old.filter(|old| old.unknown.iter().all(|name| self.unknown.contains(name)));
+        let mut changes = Changes::new();
+        diff_fields!(
+            changes,
+            self,
+            old,
+            possible,
+            ready_for_switch,
+            power_off_possible,
+            reserve_configured,
+            reserve_percent,
+            reserve_energy,
+            reserve_held_energy,
+        );
+        changes.without(&self.unknown)
+    }
+}
+
+impl NetworkConfig {
+    pub fn diff<'a>(&'a self, old: Option<&NetworkConfig>) -> Changes<'a> {
+        let mut changes = Changes::new();
+        diff_fields!(
+            changes,
+            self,
+            old,
+            dhcp,
+            ip_address,
+            subnet_mask,
+            gateway,
+            dns
+        );
+        changes
+    }
+}
+
+impl PvForecast {
+    pub fn diff<'a>(&'a self, old: Option<&PvForecast>) -> Changes<'a> {
+        let mut changes = Changes::new();
+        diff_fields!(changes, self, old, energy_today, energy_tomorrow);
+        changes
+    }
+}
+
+impl DeviceClock {
+    pub fn diff<'a>(&'a self, old: Option<&DeviceClock>) -> Changes<'a> {
+        let mut changes = Changes::new();
+        diff_fields!(changes, self, old, utc_time, local_time, time_zone, offset);
+        changes
+    }
+}
+
+impl CycleTrend {
+    pub fn diff<'a>(&'a self, old: Option<&CycleTrend>) -> Changes<'a> {
+        let mut changes = Changes::new();
+        diff_fields!(changes, self, old, cycles_per_day, cycles_per_month);
+        changes
+    }
+}
+
+impl PeriodStatistics {
+    pub fn diff<'a>(&'a self, old: Option<&PeriodStatistics>) -> Changes<'a> {
+        let mut changes = Changes::new();
+        diff_fields!(
+            changes,
+            self,
+            old,
+            time,
+            autarky,
+            self_consumption,
+            solar_production,
+            house_consumption,
+            battery_charge,
+            battery_discharge,
+            battery_throughput,
+            export_to_grid,
+            consumption_from_grid,
+            start,
+        );
+        changes
+    }
+}
+
+impl BatteryData {
+    /// Battery level fields only, DCBs are diffed separately
+    pub fn diff<'a>(&'a self, old: Option<&BatteryData>) -> Changes<'a> {
+        // A field that got its first value is compared against nothing
+        let old = old.filter(|old| old.unknown.iter().all(|name| self.unknown.contains(name)));
+        let mut changes = Changes::new();
+        diff_fields!(
+            changes,
+            self,
+            old,
+            time,
+            asoc,
+            cell_imbalance,
+            charge_cycles,
+            current,
+            dcb_count,
+            design_capacity,
+            device_name,
+            eod_voltage,
+            error_code,
+            fcc,
+            index,
+            max_battery_voltage,
+            max_charge_current,
+            max_discharge_current,
+            max_dcb_cell_temp,
+            min_dcb_cell_temp,
+            module_voltage,
+            rc,
+            ready_for_shutdown,
+            rsoc,
+            rsoc_real,
+            status_code,
+            terminal_voltage,
+            total_use_time,
+            total_discharge_time,
+            training_mode,
+            usable_capacity,
+            usable_remaining_capacity,
+        );
+        changes.without(&self.unknown)
+    }
+}
+
+impl DcbData {
+    pub fn diff<'a>(&'a self, old: Option<&DcbData>) -> Changes<'a> {
+        let mut changes = Changes::new();
+        diff_fields!(
+            changes,
+            self,
+            old,
+            current,
+            current_avg_30s,
+            cycle_count,
+            design_capacity,
+            design_voltage,
+            device_name,
+            end_of_discharge,
+            error,
+            full_charge_capacity,
+            fw_version,
+            manufacture_date,
+            manufacture_name,
+            max_charge_current,
+            max_charge_temperature,
+            max_charge_voltage,
+            max_discharge_current,
+            min_charge_temperature,
+            parallel_cell_count,
+            sensor_count,
+            series_cell_count,
+            pcb_version,
+            protocol_version,
+            remaining_capacity,
+            serial_no,
+            serial_code,
+            soc,
+            soh,
+            status,
+            temperatures,
+            voltage,
+            voltage_avg_30s,
+            voltages,
+            cell_imbalance,
+            warning,
+        );
+        changes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_heartbeat() {
+        let start = Utc.with_ymd_and_hms(2024, 1, 15, 12, 0, 0).unwrap();
+        let mut disabled = Heartbeat::new(None);
+        assert!(!disabled.due(start));
+        disabled.force();
+        assert!(disabled.due(start));
+        assert!(!disabled.due(start));
+
+        let mut heartbeat = Heartbeat::new(Some(Duration::minutes(10)));
+        // The first publish is a full one anyway
+        assert!(heartbeat.due(start));
+        assert!(!heartbeat.due(start + Duration::minutes(9)));
+        assert!(heartbeat.due(start + Duration::minutes(10)));
+        assert_eq!(
+            heartbeat.previous(start + Duration::minutes(11), Some(&1)),
+            Some(&1)
+        );
+        assert_eq!(
+            heartbeat.previous(start + Duration::minutes(20), Some(&1)),
+            None
+        );
+    }
+}
@@ -0,0 +1,619 @@
+//! Home Assistant MQTT discovery
+//!
+//! Entity configs are published retained to
+//! `{discovery_prefix}/{component}/{device-id}/{object-id}/config`.
+
+use std::collections::BTreeMap;
+
+use serde_json::{json, Value};
+
+use crate::config::TopicsConfig;
+use crate::e3dc::WeatherForecastMode;
+use crate::mqtt::context::renamed_topic;
+use crate::mqtt::SystemInfo;
+use crate::version::BuildInfo;
+
+/// Slider maximum when the unit doesn't report its power specs
+const DEFAULT_MAX_POWER_W: u64 = 10_000;
+
+/// One discovery config for a Home Assistant entity
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiscoveryEntity {
+    pub component: &'static str,
+    pub object_id: String,
+    pub config: Value,
+}
+
+/// Builds discovery configs bound to the bridge's topics
+pub struct Discovery {
+    root_topic: String,
+    info_topic: String,
+    topics: TopicsConfig,
+    device_id: String,
+    device: Value,
+    origin: Value,
+    expire_after: Option<u64>,
+    renames: BTreeMap<String, String>,
+}
+
+impl Discovery {
+    pub fn new(
+        root_topic: &str,
+        device_id: &str,
+        info: &SystemInfo,
+        topics: &TopicsConfig,
+    ) -> Self {
+        let build = BuildInfo::current();
+        Self {
+            root_topic: root_topic.to_string(),
+            info_topic: format!("{}/{}", root_topic, topics.info),
+            topics: topics.clone(),
+            device_id: device_id.to_string(),
+            device: json!({
+                "identifiers": [device_id],
+                "name": format!("E3DC {}", info.model),
+                "manufacturer": "E3/DC",
+                "model": info.model,
+                "serial_number": info.serial,
+                "sw_version": info.release,
+            }),
+            // The bridge publishing the entities, shown next to the device
+            origin: json!({
+                "name": "e3dc-mqtt-rs",
+                "sw_version": build.to_string(),
+                "support_url": env!("CARGO_PKG_HOMEPAGE"),
+            }),
+            expire_after: None,
+            renames: BTreeMap::new(),
+        }
+    }
+
+    /// Follow the topic leaf renames of `[mqtt.rename]`
+    pub fn with_renames(mut self, renames: &BTreeMap<String, String>) -> Self {
+        self.renames = renames.clone();
+        self
+    }
+
+    /// Mark sensor entities unavailable after `expire_after` without an update
+    pub fn with_expire_after(mut self, expire_after: Option<std::time::Duration>) -> Self {
+        self.expire_after = expire_after.map(|expire_after| expire_after.as_secs().max(1));
+        self
+    }
+
+    /// Common fields (identity, availability, device) merged into `config`
+    fn entity(
+        &self,
+        component: &'static str,
+        object_id: &str,
+        name: &str,
+        mut config: Value,
+    ) -> DiscoveryEntity {
+        let common = json!({
+            "name": name,
+            "unique_id": format!("{}_{}", self.device_id, object_id),
+            "availability_topic": format!("{}/online", self.root_topic),
+            "payload_available": "true",
+            "payload_not_available": "false",
+            "device": self.device,
+            "origin": self.origin,
+        });
+        if let (Value::Object(config), Value::Object(common)) = (&mut config, common) {
+            config.extend(common);
+        }
+        // Only sensors support it, the settings entities read the retained `info`
+        if let Some(expire_after) = self.expire_after {
+            if matches!(component, "sensor" | "binary_sensor") {
+                config["expire_after"] = json!(expire_after);
+            }
+        }
+        DiscoveryEntity {
+            component,
+            object_id: object_id.to_string(),
+            config,
+        }
+    }
+
+    /// `number` entities (sliders) for the writable power settings and the
+    /// emergency power reserve
+    pub fn number_entities(&self, info: &SystemInfo) -> Vec<DiscoveryEntity> {
+        let max_charge = info.max_battery_charge_power.unwrap_or(DEFAULT_MAX_POWER_W);
+        let max_discharge = info
+            .max_battery_discharge_power
+            .unwrap_or(DEFAULT_MAX_POWER_W);
+
+        [
+            ("max_charge_power", "Max charge power", max_charge, 10),
+            (
+                "max_discharge_power",
+                "Max discharge power",
+                max_discharge,
+                10,
+            ),
+            (
+                "discharge_start_power",
+                "Discharge start power",
+                max_discharge,
+                1,
+            ),
+        ]
+        .into_iter()
+        .map(|(object_id, name, max, step)| {
+            self.entity(
+                "number",
+                object_id,
+                name,
+                json!({
+                    "command_topic": format!("{}/command/{}", self.root_topic, object_id),
+                    "state_topic": self.info_topic,
+                    "value_template": format!("{{{{ value_json.{} }}}}", object_id),
+                    "min": 0,
+                    "max": max,
+                    "step": step,
+                    "mode": "slider",
+                    "unit_of_measurement": "W",
+                    "device_class": "power",
+                    "entity_category": "config",
+                }),
+            )
+        })
+        .chain(std::iter::once(self.entity(
+            "number",
+            "ep_reserve",
+            "Emergency power reserve",
+            json!({
+                "command_topic": format!("{}/command/ep_reserve", self.root_topic),
+                "state_topic": format!(
+                    "{}/emergency_power/{}",
+                    self.root_topic,
+                    renamed_topic(&self.renames, "reserve_percent")
+                ),
+                "min": 0,
+                "max": 100,
+                "step": 1,
+                "mode": "slider",
+                "unit_of_measurement": "%",
+                "entity_category": "config",
+            }),
+        )))
+        .collect()
+    }
+
+    /// `switch` and `select` entities for the boolean and enum settings
+    ///
+    /// Only settings with a command get an entity: grid charging, the
+    /// wallbox sun mode and the EMS power mode are neither read nor written.
+    pub fn mode_entities(&self) -> Vec<DiscoveryEntity> {
+        let mut entities: Vec<DiscoveryEntity> = [
+            ("power_save_enabled", "Power save"),
+            (
+                "weather_regulated_charge_enabled",
+                "Weather regulated charging",
+            ),
+        ]
+        .into_iter()
+        .map(|(object_id, name)| {
+            self.entity(
+                "switch",
+                object_id,
+                name,
+                json!({
+                    "command_topic": format!("{}/command/{}", self.root_topic, object_id),
+                    "state_topic": self.info_topic,
+                    "value_template": format!(
+                        "{{{{ 'true' if value_json.{} else 'false' }}}}",
+                        object_id
+                    ),
+                    "payload_on": "true",
+                    "payload_off": "false",
+                    "state_on": "true",
+                    "state_off": "false",
+                    "entity_category": "config",
+                }),
+            )
+        })
+        .collect();
+
+        // Info carries the raw mode value, map it to the option names
+        let modes = WeatherForecastMode::ALL
+            .iter()
+            .map(|mode| format!("{}: '{}'", mode.as_raw(), mode.as_str()))
+            .collect::<Vec<_>>()
+            .join(", ");
+        entities.push(self.entity(
+            "select",
+            "weather_forecast_mode",
+            "Weather forecast mode",
+            json!({
+                "command_topic": format!("{}/command/weather_forecast_mode", self.root_topic),
+                "state_topic": self.info_topic,
+                "value_template": format!(
+                    "{{% set modes = {{{}}} %}}{{{{ modes[value_json.weather_forecast_mode] }}}}",
+                    modes
+                ),
+                "options": WeatherForecastMode::ALL.iter().map(|mode| mode.as_str()).collect::<Vec<_>>(),
+                "entity_category": "config",
+            }),
+        ));
+
+        entities
+    }
+
+    /// `button` entities for the one-shot commands whose name passes `allowed`,
+    /// each with an `event` entity firing on its `command_ack` (applied,
+    /// rejected or failed)
+    ///
+    /// The emergency power test gets two buttons, arm and confirm.
+    pub fn button_entities(
+        &self,
+        manual_charge_wh: u64,
+        allowed: impl Fn(&str) -> bool,
+    ) -> Vec<DiscoveryEntity> {
+        let manual_charge = manual_charge_wh.to_string();
+        let buttons = [
+            ("refresh", "refresh", "Refresh now", "now", None),
+            (
+                "manual_charge",
+                "manual_charge",
+                "Start manual charge",
+                manual_charge.as_str(),
+                None,
+            ),
+            (
+                "emergency_power_test_arm",
+                "emergency_power_test",
+                "Arm emergency power test",
+                "arm",
+                Some("config"),
+            ),
+            (
+                "emergency_power_test_confirm",
+                "emergency_power_test",
+                "Confirm emergency power test",
+                "confirm",
+                Some("config"),
+            ),
+        ];
+
+        let mut entities = Vec::new();
+        let mut acknowledged: Vec<&str> = Vec::new();
+        for (object_id, command, name, payload, category) in buttons {
+            if !allowed(command) {
+                continue;
+            }
+            let mut config = json!({
+                "command_topic": format!("{}/command/{}", self.root_topic, command),
+                "payload_press": payload,
+            });
+            if let Some(category) = category {
+                config["entity_category"] = json!(category);
+            }
+            entities.push(self.entity("button", object_id, name, config));
+            if !acknowledged.contains(&command) {
+                acknowledged.push(command);
+            }
+        }
+        for command in acknowledged {
+            let mut name = format!("{} result", command.replace('_', " "));
+            name[..1].make_ascii_uppercase();
+            entities.push(self.entity(
+                "event",
+                &format!("{}_result", command),
+                &name,
+                json!({
+                    "state_topic": format!("{}/command_ack/{}", self.root_topic, command),
+                    "value_template": "{\"event_type\": \"{{ value_json.status }}\", \"message\": {{ value_json.message | tojson }}}",
+                    "event_types": ["applied", "rejected", "failed"],
+                    "entity_category": "diagnostic",
+                }),
+            ));
+        }
+        entities
+    }
+
+    /// `update` entity with the installed firmware release from `info`
+    ///
+    /// E3DC doesn't publish its releases anywhere the bridge could query, the
+    /// latest version is `latest` if known and the installed one otherwise, so
+    /// Home Assistant only reports an update when `latest` differs.
+    pub fn firmware_update_entity(&self, latest: Option<&str>) -> DiscoveryEntity {
+        let latest_template = match latest {
+            Some(latest) => format!("{{{{ {} }}}}", Value::from(latest)),
+            None => "{{ value_json.release }}".to_string(),
+        };
+        self.entity(
+            "update",
+            "firmware",
+            "Firmware",
+            json!({
+                "state_topic": self.info_topic,
+                "value_template": "{{ value_json.release }}",
+                "latest_version_topic": self.info_topic,
+                "latest_version_template": latest_template,
+                "title": "E3DC firmware",
+                "device_class": "firmware",
+                "entity_category": "diagnostic",
+            }),
+        )
+    }
+
+    /// Device trigger firing when `value_template` renders `payload` for a
+    /// message on `events/{event}`
+    ///
+    /// Events aren't retained, so triggers never fire for old messages.
+    fn trigger(
+        &self,
+        event: &str,
+        trigger_type: &str,
+        subtype: &str,
+        payload: &str,
+        value_template: &str,
+    ) -> DiscoveryEntity {
+        DiscoveryEntity {
+            component: "device_automation",
+            object_id: format!("{}_{}", trigger_type, subtype),
+            config: json!({
+                "automation_type": "trigger",
+                "topic": format!("{}/events/{}", self.root_topic, event),
+                "type": trigger_type,
+                "subtype": subtype,
+                "payload": payload,
+                "value_template": value_template,
+                "device": self.device,
+                "origin": self.origin,
+            }),
+        }
+    }
+
+    /// Triggers for the start and end of a grid outage (`events/emergency_power`)
+    pub fn grid_outage_triggers(&self) -> Vec<DiscoveryEntity> {
+        let template = "{{ 'started' if value_json.to == 'island' \
+                        else 'ended' if value_json.from == 'island' else '' }}";
+        ["started", "ended"]
+            .into_iter()
+            .map(|phase| {
+                self.trigger(
+                    "emergency_power",
+                    &format!("grid_outage_{}", phase),
+                    "grid",
+                    phase,
+                    template,
+                )
+            })
+            .collect()
+    }
+
+    /// Triggers for the export reaching and leaving the limit (`events/export_limit`),
+    /// the derating of the feed-in
+    pub fn derating_triggers(&self) -> Vec<DiscoveryEntity> {
+        ["started", "ended"]
+            .into_iter()
+            .map(|phase| {
+                self.trigger(
+                    "export_limit",
+                    &format!("derating_{}", phase),
+                    "export",
+                    phase,
+                    "{{ 'started' if value_json.active else 'ended' }}",
+                )
+            })
+            .collect()
+    }
+
+    /// Triggers per battery for an alert event with `battery` and `active`
+    /// (`battery_error`, `cell_imbalance`), raised and cleared
+    ///
+    /// Events of single DCBs are left out.
+    pub fn battery_triggers(&self, batteries: &[u64], event: &str) -> Vec<DiscoveryEntity> {
+        let mut triggers = Vec::new();
+        for index in batteries {
+            let template = format!(
+                "{{% if value_json.battery == {} and value_json.get('dcb') is none %}}\
+                 {{{{ 'raised' if value_json.active else 'cleared' }}}}{{% endif %}}",
+                index
+            );
+            for (suffix, payload) in [("", "raised"), ("_cleared", "cleared")] {
+                triggers.push(self.trigger(
+                    event,
+                    &format!("{}{}", event, suffix),
+                    &format!("battery_{}", index),
+                    payload,
+                    &template,
+                ));
+            }
+        }
+        triggers
+    }
+
+    /// Read-only `binary_sensor` entity per battery for the alert topic `alert`,
+    /// e.g. `cell_imbalance_alert`
+    pub fn battery_alert_entities(
+        &self,
+        batteries: &[u64],
+        alert: &str,
+        name: &str,
+        device_class: &str,
+    ) -> Vec<DiscoveryEntity> {
+        let object = alert.trim_end_matches("_alert");
+        batteries
+            .iter()
+            .map(|index| {
+                self.entity(
+                    "binary_sensor",
+                    &format!("battery_{}_{}", index, object),
+                    &format!("Battery {} {}", index, name),
+                    json!({
+                        "state_topic": format!(
+                            "{}/{}/{}",
+                            self.root_topic,
+                            self.topics.battery(*index),
+                            renamed_topic(&self.renames, alert)
+                        ),
+                        "payload_on": "true",
+                        "payload_off": "false",
+                        "device_class": device_class,
+                        "entity_category": "diagnostic",
+                    }),
+                )
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    #[test]
+    fn test_number_entities() {
+        let text = "x".to_string();
+        let info = SystemInfo {
+            time: Utc::now(),
+            derate_percent: 70.0,
+            derate_power: 7000,
+            external_source_available: false,
+            installed_battery_capacity: Some(13000),
+            installed_peak_power: 10000,
+            ip_address: &text,
+            max_ac_power: Some(12000),
+            mac_address: &text,
+            max_battery_charge_power: Some(3000),
+            max_battery_discharge_power: None,
+            model: "S10E",
+            release: &text,
+            serial: &text,
+            discharge_start_power: 65,
+            max_charge_power: 3000,
+            max_discharge_power: 3000,
+            power_limits_used: false,
+            power_save_enabled: true,
+            weather_forecast_mode: 0,
+            weather_regulated_charge_enabled: true,
+            production_date: None,
+            platform_type: None,
+            assembly_serial_number: None,
+        };
+        let discovery =
+            Discovery::new("e3dc/S10E-123", "S10E-123", &info, &TopicsConfig::default());
+        let entities = discovery.number_entities(&info);
+
+        assert_eq!(entities.len(), 4);
+        let charge = &entities[0];
+        assert_eq!(charge.component, "number");
+        assert_eq!(charge.config["unique_id"], "S10E-123_max_charge_power");
+        assert_eq!(
+            charge.config["command_topic"],
+            "e3dc/S10E-123/command/max_charge_power"
+        );
+        assert_eq!(
+            charge.config["value_template"],
+            "{{ value_json.max_charge_power }}"
+        );
+        assert_eq!(charge.config["state_topic"], "e3dc/S10E-123/info");
+        assert_eq!(charge.config["max"], 3000);
+        // Falls back when the unit doesn't report a discharge power spec
+        assert_eq!(entities[1].config["max"], DEFAULT_MAX_POWER_W);
+        assert_eq!(charge.config["device"]["identifiers"][0], "S10E-123");
+        assert_eq!(charge.config["origin"]["name"], "e3dc-mqtt-rs");
+        // The reserve reads its own topic, not `info`
+        let reserve = &entities[3];
+        assert_eq!(
+            reserve.config["command_topic"],
+            "e3dc/S10E-123/command/ep_reserve"
+        );
+        assert_eq!(
+            reserve.config["state_topic"],
+            "e3dc/S10E-123/emergency_power/reserve_percent"
+        );
+
+        let modes = discovery.mode_entities();
+        assert_eq!(modes.len(), 3);
+        assert_eq!(modes[0].component, "switch");
+        assert_eq!(
+            modes[0].config["value_template"],
+            "{{ 'true' if value_json.power_save_enabled else 'false' }}"
+        );
+        let select = &modes[2];
+        assert_eq!(select.component, "select");
+        assert_eq!(
+            select.config["value_template"],
+            "{% set modes = {0: 'off', 1: 'forecast', 2: 'forecast_optimized'} %}\
+             {{ modes[value_json.weather_forecast_mode] }}"
+        );
+        assert_eq!(select.config["options"][1], "forecast");
+
+        let buttons = discovery.button_entities(3000, |command| command != "refresh");
+        assert_eq!(buttons.len(), 5);
+        assert_eq!(buttons[0].component, "button");
+        assert_eq!(buttons[0].object_id, "manual_charge");
+        assert_eq!(buttons[0].config["payload_press"], "3000");
+        assert_eq!(
+            buttons[2].config["command_topic"],
+            "e3dc/S10E-123/command/emergency_power_test"
+        );
+        assert_eq!(buttons[2].config["payload_press"], "confirm");
+        let result = &buttons[4];
+        assert_eq!(result.component, "event");
+        assert_eq!(result.object_id, "emergency_power_test_result");
+        assert_eq!(result.config["name"], "Emergency power test result");
+        assert_eq!(
+            result.config["state_topic"],
+            "e3dc/S10E-123/command_ack/emergency_power_test"
+        );
+
+        let firmware = discovery.firmware_update_entity(None);
+        assert_eq!(firmware.component, "update");
+        assert_eq!(
+            firmware.config["latest_version_template"],
+            "{{ value_json.release }}"
+        );
+        let firmware = discovery.firmware_update_entity(Some("S10_2024_04"));
+        assert_eq!(
+            firmware.config["latest_version_template"],
+            "{{ \"S10_2024_04\" }}"
+        );
+
+        let outage = discovery.grid_outage_triggers();
+        assert_eq!(outage[0].component, "device_automation");
+        assert_eq!(outage[0].object_id, "grid_outage_started_grid");
+        assert_eq!(
+            outage[0].config["topic"],
+            "e3dc/S10E-123/events/emergency_power"
+        );
+        assert_eq!(outage[1].config["payload"], "ended");
+        assert_eq!(
+            discovery.derating_triggers()[0].config["type"],
+            "derating_started"
+        );
+        let errors = discovery.battery_triggers(&[0, 1], "battery_error");
+        assert_eq!(errors.len(), 4);
+        assert_eq!(errors[3].object_id, "battery_error_cleared_battery_1");
+        assert_eq!(
+            errors[2].config["value_template"],
+            "{% if value_json.battery == 1 and value_json.get('dcb') is none %}\
+             {{ 'raised' if value_json.active else 'cleared' }}{% endif %}"
+        );
+
+        let alerts = discovery.battery_alert_entities(
+            &[0],
+            "cell_imbalance_alert",
+            "cell imbalance",
+            "problem",
+        );
+        assert_eq!(alerts[0].component, "binary_sensor");
+        assert_eq!(alerts[0].object_id, "battery_0_cell_imbalance");
+        assert_eq!(
+            alerts[0].config["state_topic"],
+            "e3dc/S10E-123/status/battery:0/cell_imbalance_alert"
+        );
+        assert!(alerts[0].config.get("expire_after").is_none());
+
+        let discovery = discovery.with_expire_after(Some(std::time::Duration::from_secs(1800)));
+        let alerts = discovery.battery_alert_entities(&[0], "cell_imbalance_alert", "", "problem");
+        assert_eq!(alerts[0].config["expire_after"], 1800);
+        assert!(discovery.number_entities(&info)[0]
+            .config
+            .get("expire_after")
+            .is_none());
+    }
+}
@@ -0,0 +1,510 @@
+//! Homie 4 convention output
+//!
+//! Publishes the same values as the default layout below
+//! `{prefix}/{device-id}/{node}/{property}` together with the Homie
+//! description (`$homie`, `$nodes`, `$properties`, datatypes and units), so
+//! controllers like openHAB discover the device without HA-specific configs.
+//!
+//! Node and property ids are derived from the default topics: `status_sums`
+//! becomes node `status-sums`, `status/battery:0/dcb:1` becomes node
+//! `status-battery-0-dcb-1`.
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::rc::Rc;
+use std::thread;
+
+use rumqttc::{Client, LastWill, QoS};
+
+use crate::config::Config;
+use crate::errors::MqttError;
+use crate::mqtt::context::Transport;
+use crate::mqtt::link::{self, ConnectionEvent, Link};
+use crate::mqtt::publisher::{mqtt_client, mqtt_options, Publisher};
+
+pub const HOMIE_VERSION: &str = "4.0";
+
+/// Values of the `emergency_power` enum property
+const EMERGENCY_POWER_FORMAT: &str =
+    "not_possible,island,grid,not_available,switch_in_island,unknown";
+
+/// Homie topic id: lowercase `[a-z0-9-]`, other characters become `-`
+pub fn homie_id(segment: &str) -> String {
+    let id: String = segment
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_lowercase()
+            } else {
+                '-'
+            }
+        })
+        .collect();
+    id.split('-')
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// Homie property attributes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PropertyMeta {
+    pub name: String,
+    pub datatype: &'static str,
+    pub unit: Option<&'static str>,
+    pub format: Option<&'static str>,
+}
+
+impl PropertyMeta {
+    /// Attributes of a property by its field name and default-layout node path
+    pub fn for_field(node: &str, field: &str) -> Self {
+        let datatype = match field {
+            "time" | "start" => "datetime",
+            "timespan" => "duration",
+            "emergency_power" => "enum",
+            "ready_for_shutdown" | "training_mode" => "boolean",
+            "device_name" | "manufacture_name" | "serial_code" | "temperatures" | "voltages" => {
+                "string"
+            }
+            "index"
+            | "dcb_count"
+            | "series_cell_count"
+            | "parallel_cell_count"
+            | "sensor_count"
+            | "total_use_time"
+            | "total_discharge_time" => "integer",
+            _ => "float",
+        };
+
+        let numeric =
+            matches!(datatype, "float" | "integer") || matches!(field, "temperatures" | "voltages");
+        let unit = if !numeric {
+            None
+        } else if field.contains("temp") {
+            Some("°C")
+        } else if field.contains("voltage") || field == "end_of_discharge" {
+            Some("V")
+        } else if field.contains("current") {
+            Some("A")
+        } else if field.contains("capacity") || field == "fcc" || field == "rc" {
+            Some("Ah")
+        } else if field.contains("soc")
+            || field == "soh"
+            || field.starts_with("autarky")
+            || field.starts_with("self_consumption")
+            || field.starts_with("state_of_charge")
+        {
+            Some("%")
+        } else if field.starts_with("total_") {
+            Some("s")
+        } else if field.ends_with("_today") {
+            Some("Wh")
+        } else if node == "status" && datatype == "float" {
+            Some("W")
+        } else {
+            None
+        };
+
+        Self {
+            name: field.replace('_', " "),
+            datatype,
+            unit,
+            format: (field == "emergency_power").then_some(EMERGENCY_POWER_FORMAT),
+        }
+    }
+}
+
+/// Homie node with its properties by id
+struct HomieNode {
+    name: String,
+    properties: BTreeMap<String, PropertyMeta>,
+}
+
+/// Rewrites default-layout topics to Homie ids and records the description
+struct HomieTransport {
+    inner: Box<dyn Transport>,
+    root: String,
+    nodes: RefCell<BTreeMap<String, HomieNode>>,
+    described: RefCell<bool>,
+}
+
+impl Transport for HomieTransport {
+    fn send(&self, topic: &str, payload: String, qos: QoS, _retain: bool) -> Result<(), MqttError> {
+        let Some(path) = topic
+            .strip_prefix(&self.root)
+            .and_then(|t| t.strip_prefix('/'))
+        else {
+            return Ok(());
+        };
+        let segments: Vec<&str> = path.split('/').collect();
+        // Device level topics (online, info, events) have no Homie node
+        let Some((field, node_path)) = segments.split_last() else {
+            return Ok(());
+        };
+        if node_path.is_empty() || node_path[0] == "events" {
+            return Ok(());
+        }
+
+        let node = homie_id(&node_path.join("-"));
+        let property = homie_id(field);
+        if payload.is_empty() {
+            // Cleared topic (removed hardware): drop it from the description
+            let mut nodes = self.nodes.borrow_mut();
+            if let Some(entry) = nodes.get_mut(&node) {
+                if entry.properties.remove(&property).is_some() {
+                    *self.described.borrow_mut() = false;
+                }
+                if entry.properties.is_empty() {
+                    nodes.remove(&node);
+                }
+            }
+        } else {
+            let mut nodes = self.nodes.borrow_mut();
+            let path = node_path.join("/");
+            let entry = nodes.entry(node.clone()).or_insert_with(|| HomieNode {
+                name: path.clone(),
+                properties: BTreeMap::new(),
+            });
+            if !entry.properties.contains_key(&property) {
+                entry
+                    .properties
+                    .insert(property.clone(), PropertyMeta::for_field(&path, field));
+                *self.described.borrow_mut() = false;
+            }
+        }
+
+        // Homie property values are always retained
+        self.inner.send(
+            &format!("{}/{}/{}", self.root, node, property),
+            payload,
+            qos,
+            true,
+        )
+    }
+}
+
+/// Publisher producing the Homie 4 layout on its own connection
+pub struct HomiePublisher {
+    transport: HomieTransport,
+    device_id: String,
+    name: String,
+    /// Event loop of the connection, None on an existing transport
+    link: Option<Rc<Link>>,
+}
+
+/// Homie client, fails fast instead of blocking on a full queue while the
+/// broker is unreachable
+struct LinkedClient {
+    client: Client,
+    link: Rc<Link>,
+}
+
+impl Transport for LinkedClient {
+    fn send(&self, topic: &str, payload: String, qos: QoS, retain: bool) -> Result<(), MqttError> {
+        self.send_binary(topic, payload.into_bytes(), qos, retain)
+    }
+
+    fn send_binary(
+        &self,
+        topic: &str,
+        payload: Vec<u8>,
+        qos: QoS,
+        retain: bool,
+    ) -> Result<(), MqttError> {
+        if !self.link.is_lost() {
+            return self.client.send_binary(topic, payload, qos, retain);
+        }
+        self.client
+            .try_publish(topic, qos, retain, payload)
+            .map_err(|e| MqttError::PublishFailed {
+                topic: topic.to_string(),
+                reason: e.to_string(),
+            })
+    }
+}
+
+impl HomiePublisher {
+    /// Connect with `$state = lost` as last will
+    pub fn new(config: &Config, device_id: &str, name: String) -> Result<Self, MqttError> {
+        let client_id = match &config.mqtt.client_id {
+            Some(client_id) => format!("{}-homie", client_id),
+            None => format!("e3dc-mqtt-rs-{}-homie", device_id),
+        };
+        let root = format!("{}/{}", config.homie.prefix, homie_id(device_id));
+
+        let mut mqtt_options = mqtt_options(config, client_id)?;
+        mqtt_options.set_last_will(LastWill {
+            topic: format!("{}/$state", root),
+            message: b"lost".to_vec().into(),
+            qos: QoS::AtLeastOnce,
+            retain: true,
+        });
+        let (client, mut connection) = mqtt_client(config, mqtt_options, 10);
+
+        // Same as the main connection: errors go to `connection_events`
+        let (reporter, link) = link::link("MQTT (Homie)");
+        thread::Builder::new()
+            .name("mqtt-homie".to_string())
+            .spawn(move || {
+                for notification in connection.iter() {
+                    if reporter.observe(&notification) {
+                        thread::sleep(link::RECONNECT_DELAY);
+                    }
+                }
+            })
+            .map_err(|e| MqttError::ConnectFailed {
+                broker: format!("{}:{}", config.mqtt.host, config.mqtt.port),
+                reason: format!("failed to spawn the Homie event loop thread: {}", e),
+            })?;
+
+        let link = Rc::new(link);
+        let mut homie = Self::with_transport(
+            Box::new(LinkedClient {
+                client,
+                link: Rc::clone(&link),
+            }),
+            root,
+            device_id,
+            name,
+        );
+        homie.link = Some(link);
+        Ok(homie)
+    }
+
+    /// Connection changes of the event loop since the last call
+    pub fn connection_events(&self) -> Vec<ConnectionEvent> {
+        self.link
+            .as_ref()
+            .map(|link| link.events())
+            .unwrap_or_default()
+    }
+
+    /// Whether the event loop is currently without a broker connection
+    pub fn connection_lost(&self) -> bool {
+        self.link.as_ref().is_some_and(|link| link.is_lost())
+    }
+
+    /// Publisher on an existing transport, `root` is `{prefix}/{homie-device-id}`
+    pub fn with_transport(
+        transport: Box<dyn Transport>,
+        root: String,
+        device_id: &str,
+        name: String,
+    ) -> Self {
+        Self {
+            transport: HomieTransport {
+                inner: transport,
+                root,
+                nodes: RefCell::new(BTreeMap::new()),
+                described: RefCell::new(false),
+            },
+            device_id: device_id.to_string(),
+            name,
+            link: None,
+        }
+    }
+
+    fn send_attribute(&self, topic: &str, value: &str) -> Result<(), MqttError> {
+        self.transport.inner.send(
+            &format!("{}/{}", self.transport.root, topic),
+            value.to_string(),
+            QoS::AtLeastOnce,
+            true,
+        )
+    }
+
+    /// Publish the device description if properties were added since the last call
+    ///
+    /// Call after publishing values; the first call announces the device,
+    /// later calls only republish when new nodes or properties appeared.
+    pub fn announce(&self) -> Result<(), MqttError> {
+        if *self.transport.described.borrow() {
+            return Ok(());
+        }
+        let nodes = self.transport.nodes.borrow();
+
+        self.send_attribute("$state", "init")?;
+        self.send_attribute("$homie", HOMIE_VERSION)?;
+        self.send_attribute("$name", &self.name)?;
+        self.send_attribute("$extensions", "")?;
+        self.send_attribute(
+            "$nodes",
+            &nodes.keys().cloned().collect::<Vec<_>>().join(","),
+        )?;
+        for (node, HomieNode { name, properties }) in nodes.iter() {
+            self.send_attribute(&format!("{}/$name", node), name)?;
+            self.send_attribute(&format!("{}/$type", node), "")?;
+            self.send_attribute(
+                &format!("{}/$properties", node),
+                &properties.keys().cloned().collect::<Vec<_>>().join(","),
+            )?;
+            for (property, meta) in properties {
+                let prefix = format!("{}/{}", node, property);
+                self.send_attribute(&format!("{}/$name", prefix), &meta.name)?;
+                self.send_attribute(&format!("{}/$datatype", prefix), meta.datatype)?;
+                if let Some(unit) = meta.unit {
+                    self.send_attribute(&format!("{}/$unit", prefix), unit)?;
+                }
+                if let Some(format) = meta.format {
+                    self.send_attribute(&format!("{}/$format", prefix), format)?;
+                }
+            }
+        }
+        self.send_attribute("$state", "ready")?;
+
+        *self.transport.described.borrow_mut() = true;
+        Ok(())
+    }
+
+    /// Switch `$state` to `alert` while the E3DC is unreachable, back to `ready` after
+    pub fn set_alert(&self, alert: bool) -> Result<(), MqttError> {
+        self.send_attribute("$state", if alert { "alert" } else { "ready" })
+    }
+}
+
+impl Publisher for HomiePublisher {
+    fn transport(&self) -> &dyn Transport {
+        &self.transport
+    }
+
+    fn root_topic(&self) -> &str {
+        &self.transport.root
+    }
+
+    fn device_id(&self) -> &str {
+        &self.device_id
+    }
+}
+
+impl Drop for HomiePublisher {
+    fn drop(&mut self) {
+        if let Err(e) = self.send_attribute("$state", "disconnected") {
+            tracing::warn!("Failed to publish Homie state during shutdown: {:?}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::rc::Rc;
+
+    #[derive(Clone, Default)]
+    struct Recorder(Rc<RefCell<Vec<(String, String)>>>);
+
+    impl Transport for Recorder {
+        fn send(
+            &self,
+            topic: &str,
+            payload: String,
+            _qos: QoS,
+            _retain: bool,
+        ) -> Result<(), MqttError> {
+            self.0.borrow_mut().push((topic.to_string(), payload));
+            Ok(())
+        }
+    }
+
+    impl Recorder {
+        fn get(&self, topic: &str) -> Option<String> {
+            self.0
+                .borrow()
+                .iter()
+                .rev()
+                .find(|(t, _)| t == topic)
+                .map(|(_, payload)| payload.clone())
+        }
+    }
+
+    #[test]
+    fn test_homie_id() {
+        assert_eq!(homie_id("status_sums"), "status-sums");
+        assert_eq!(homie_id("battery:0"), "battery-0");
+        assert_eq!(homie_id("S10E-123456"), "s10e-123456");
+        assert_eq!(homie_id("_time_"), "time");
+    }
+
+    #[test]
+    fn test_property_meta() {
+        let soc = PropertyMeta::for_field("status", "state_of_charge");
+        assert_eq!((soc.datatype, soc.unit), ("float", Some("%")));
+        let solar = PropertyMeta::for_field("status", "solar_production");
+        assert_eq!(solar.unit, Some("W"));
+        let today = PropertyMeta::for_field("status_sums", "solar_production_today");
+        assert_eq!(today.unit, Some("Wh"));
+        let temp = PropertyMeta::for_field("status/battery:0", "max_dcb_cell_temp");
+        assert_eq!(temp.unit, Some("°C"));
+        // Battery codes are no power values
+        let code = PropertyMeta::for_field("status/battery:0", "status_code");
+        assert_eq!(code.unit, None);
+        let time = PropertyMeta::for_field("status", "time");
+        assert_eq!((time.datatype, time.unit), ("datetime", None));
+        assert_eq!(
+            PropertyMeta::for_field("status", "emergency_power").format,
+            Some(EMERGENCY_POWER_FORMAT)
+        );
+    }
+
+    #[test]
+    fn test_homie_layout() {
+        let recorder = Recorder::default();
+        let publisher = HomiePublisher::with_transport(
+            Box::new(recorder.clone()),
+            "homie/s10e-123".to_string(),
+            "S10E-123",
+            "E3DC S10E".to_string(),
+        );
+        let context = publisher.context("status/battery:0");
+        context.publish("rsoc_real", &55.5).unwrap();
+        publisher
+            .context("status")
+            .publish("solar_production", &1200.0)
+            .unwrap();
+        publisher.publish_online_status(true).unwrap();
+        publisher.announce().unwrap();
+
+        assert_eq!(
+            recorder.get("homie/s10e-123/status-battery-0/rsoc-real"),
+            Some("55.5".to_string())
+        );
+        assert_eq!(
+            recorder.get("homie/s10e-123/$homie"),
+            Some("4.0".to_string())
+        );
+        assert_eq!(
+            recorder.get("homie/s10e-123/$nodes"),
+            Some("status,status-battery-0".to_string())
+        );
+        assert_eq!(
+            recorder.get("homie/s10e-123/status-battery-0/$properties"),
+            Some("rsoc-real".to_string())
+        );
+        assert_eq!(
+            recorder.get("homie/s10e-123/status-battery-0/rsoc-real/$unit"),
+            Some("%".to_string())
+        );
+        assert_eq!(
+            recorder.get("homie/s10e-123/$state"),
+            Some("ready".to_string())
+        );
+        // Device level topics are not part of the Homie tree
+        assert_eq!(recorder.get("homie/s10e-123/online"), None);
+
+        // Nothing new, no second description
+        let published = recorder.0.borrow().len();
+        publisher.announce().unwrap();
+        assert_eq!(recorder.0.borrow().len(), published);
+
+        // Removed hardware drops its node from the description
+        context.clear("rsoc_real").unwrap();
+        publisher.announce().unwrap();
+        assert_eq!(
+            recorder.get("homie/s10e-123/status-battery-0/rsoc-real"),
+            Some(String::new())
+        );
+        assert_eq!(
+            recorder.get("homie/s10e-123/$nodes"),
+            Some("status".to_string())
+        );
+    }
+}
@@ -0,0 +1,128 @@
+//! Connection state of an MQTT event loop thread
+//!
+//! rumqttc reconnects on the next poll after a connection error. The event
+//! loop threads report the state changes through a [`LinkReporter`] instead of
+//! ending the process, the owner of the [`Link`] decides what a lost broker
+//! means (`[on_error] mqtt`). While the link is down publishes fail fast
+//! instead of waiting for room in the request queue.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::time::Duration;
+
+use rumqttc::{ConnectionError, Event, Packet};
+
+/// Wait before the event loop polls (and so reconnects) after an error
+pub const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// Connection change reported by an event loop
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConnectionEvent {
+    /// The broker acknowledged the connection (again)
+    Connected,
+    /// The connection failed, the event loop retries after [`RECONNECT_DELAY`]
+    Lost(String),
+}
+
+/// Event loop side of a link
+pub struct LinkReporter {
+    name: &'static str,
+    lost: Arc<AtomicBool>,
+    events: Sender<ConnectionEvent>,
+}
+
+/// Owner side of a link
+pub struct Link {
+    lost: Arc<AtomicBool>,
+    events: Receiver<ConnectionEvent>,
+}
+
+/// New link for the event loop `name` (used in the log)
+pub fn link(name: &'static str) -> (LinkReporter, Link) {
+    let lost = Arc::new(AtomicBool::new(false));
+    let (events_tx, events) = mpsc::channel();
+    (
+        LinkReporter {
+            name,
+            lost: Arc::clone(&lost),
+            events: events_tx,
+        },
+        Link { lost, events },
+    )
+}
+
+impl LinkReporter {
+    /// Track a notification of the event loop, returns whether it was an error
+    ///
+    /// Only changes are sent: the first error after a connection, the first
+    /// CONNACK after an error (or at startup).
+    pub fn observe(&self, notification: &Result<Event, ConnectionError>) -> bool {
+        match notification {
+            Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                self.lost.store(false, Ordering::Relaxed);
+                tracing::info!("{} connected", self.name);
+                // The owner is gone only during shutdown
+                let _ = self.events.send(ConnectionEvent::Connected);
+                false
+            }
+            Ok(_) => false,
+            Err(e) => {
+                if !self.lost.swap(true, Ordering::Relaxed) {
+                    tracing::error!("{} connection error: {:?}", self.name, e);
+                    let _ = self.events.send(ConnectionEvent::Lost(e.to_string()));
+                } else {
+                    tracing::debug!("{} still unreachable: {:?}", self.name, e);
+                }
+                true
+            }
+        }
+    }
+}
+
+impl Link {
+    /// Whether the last notification of the event loop was an error
+    pub fn is_lost(&self) -> bool {
+        self.lost.load(Ordering::Relaxed)
+    }
+
+    /// Changes since the last call, oldest first
+    pub fn events(&self) -> Vec<ConnectionEvent> {
+        self.events.try_iter().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rumqttc::{ConnAck, ConnectReturnCode};
+
+    #[test]
+    fn test_link_reports_changes() {
+        let (reporter, link) = link("MQTT");
+        let connack = Ok(Event::Incoming(Packet::ConnAck(ConnAck::new(
+            ConnectReturnCode::Success,
+            false,
+        ))));
+        let error: Result<Event, _> = Err(ConnectionError::NetworkTimeout);
+
+        assert!(!reporter.observe(&connack));
+        assert!(!link.is_lost());
+        assert!(reporter.observe(&error));
+        assert!(link.is_lost());
+        // Reconnect attempts failing again are no new change
+        assert!(reporter.observe(&error));
+        assert_eq!(
+            link.events(),
+            vec![
+                ConnectionEvent::Connected,
+                ConnectionEvent::Lost("Network timeout".to_string()),
+            ]
+        );
+
+        reporter.observe(&connack);
+        assert!(!link.is_lost());
+        assert_eq!(link.events(), vec![ConnectionEvent::Connected]);
+        assert!(link.events().is_empty());
+    }
+}
@@ -0,0 +1,117 @@
+//! Publish counters behind the `bridge/publish_*` and `bridge/queue_depth` topics
+//!
+//! rumqttc queues publishes for its event loop thread, a full queue or a
+//! lost connection makes a publish fail without the caller seeing more
+//! than an error per field. The counters are shared between the transport
+//! and the event loop and taken once per status cycle, so a cycle with
+//! failures logs one warning and the broker shows the publish health.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+/// Counters shared by the transport and the MQTT event loop
+#[derive(Debug, Default)]
+pub struct PublishMetrics {
+    attempts: AtomicU64,
+    failures: AtomicU64,
+    retries: AtomicU64,
+    /// Publishes handed to the event loop since the start
+    queued: AtomicU64,
+    /// Publishes written to the broker since the start
+    written: AtomicU64,
+    first_error: Mutex<Option<String>>,
+}
+
+/// Publish counters of one cycle
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PublishStats {
+    pub attempts: u64,
+    pub failures: u64,
+    /// Publishes that found the queue full and waited for room
+    pub retries: u64,
+    /// Publishes queued but not yet written to the broker
+    pub queue_depth: u64,
+    /// Error of the first failed publish
+    pub first_error: Option<String>,
+}
+
+impl PublishMetrics {
+    pub fn attempt(&self) {
+        self.attempts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn retry(&self) {
+        self.retries.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn queued(&self) {
+        self.queued.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Called by the event loop for every publish it wrote
+    pub fn written(&self) {
+        self.written.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn failure(&self, error: &str) {
+        self.failures.fetch_add(1, Ordering::Relaxed);
+        let mut first_error = self.first_error.lock().unwrap_or_else(|e| e.into_inner());
+        first_error.get_or_insert_with(|| error.to_string());
+    }
+
+    /// Counters since the last call, the queue depth is the current one
+    pub fn take(&self) -> PublishStats {
+        let queued = self.queued.load(Ordering::Relaxed);
+        let written = self.written.load(Ordering::Relaxed);
+        PublishStats {
+            attempts: self.attempts.swap(0, Ordering::Relaxed),
+            failures: self.failures.swap(0, Ordering::Relaxed),
+            retries: self.retries.swap(0, Ordering::Relaxed),
+            queue_depth: queued.saturating_sub(written),
+            first_error: self
+                .first_error
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .take(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_publish_metrics() {
+        let metrics = PublishMetrics::default();
+        for _ in 0..3 {
+            metrics.attempt();
+            metrics.queued();
+        }
+        metrics.retry();
+        metrics.written();
+        metrics.attempt();
+        metrics.failure("queue closed");
+        metrics.failure("connection lost");
+
+        assert_eq!(
+            metrics.take(),
+            PublishStats {
+                attempts: 4,
+                failures: 2,
+                retries: 1,
+                queue_depth: 2,
+                first_error: Some("queue closed".to_string()),
+            }
+        );
+        // Per-cycle counters restart, the queue depth stays until written
+        metrics.written();
+        assert_eq!(
+            metrics.take(),
+            PublishStats {
+                queue_depth: 1,
+                ..PublishStats::default()
+            }
+        );
+    }
+}
@@ -0,0 +1,219 @@
+//! Retained topic migration
+//!
+//! When the root topic or device id changes, the old tree stays on the broker
+//! as retained messages. [`Migration`] collects the retained topics below an
+//! old root and clears the ones that are not part of the current layout,
+//! and the ones of groups that are no longer retained (`[topics.retain]`).
+
+use crate::config::{Config, TopicsConfig};
+use crate::errors::MqttError;
+use crate::mqtt::context::Transport;
+use crate::mqtt::publisher::{mqtt_client, mqtt_options};
+use rumqttc::{Client, Event, Outgoing, Packet, QoS};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+enum MigrationEvent {
+    /// Retained message with a non-empty payload
+    Retained(String),
+    /// Broker acknowledged one of our publishes
+    PubAck,
+}
+
+/// Connection to the broker subscribed to an old topic tree
+pub struct Migration {
+    client: Client,
+    events: Receiver<MigrationEvent>,
+}
+
+impl Migration {
+    /// Connect and subscribe to `{from_root}/#`
+    pub fn connect(config: &Config, from_root: &str) -> Result<Self, MqttError> {
+        let client_id = format!("e3dc-mqtt-rs-migrate-{}", std::process::id());
+        let (client, mut connection) = mqtt_client(config, mqtt_options(config, client_id)?, 100);
+        let (event_tx, events) = mpsc::channel();
+
+        thread::Builder::new()
+            .name("mqtt-migrate".to_string())
+            .spawn(move || {
+                for notification in connection.iter() {
+                    let event = match notification {
+                        Ok(Event::Incoming(Packet::Publish(publish)))
+                            if publish.retain && !publish.payload.is_empty() =>
+                        {
+                            MigrationEvent::Retained(publish.topic)
+                        }
+                        Ok(Event::Incoming(Packet::PubAck(_))) => MigrationEvent::PubAck,
+                        Ok(Event::Outgoing(Outgoing::Disconnect)) => break,
+                        Ok(_) => continue,
+                        Err(e) => {
+                            tracing::error!("MQTT connection error: {:?}", e);
+                            break;
+                        }
+                    };
+                    if event_tx.send(event).is_err() {
+                        break;
+                    }
+                }
+            })
+            .expect("Failed to spawn MQTT migration thread");
+
+        let topic = format!("{}/#", from_root.trim_end_matches('/'));
+        client
+            .subscribe(&topic, QoS::AtLeastOnce)
+            .map_err(|e| MqttError::SubscribeFailed {
+                topic,
+                reason: e.to_string(),
+            })?;
+
+        Ok(Self { client, events })
+    }
+
+    /// Collect retained topics until the broker stays quiet for `settle`
+    pub fn collect(&self, settle: Duration) -> Vec<String> {
+        let mut topics = Vec::new();
+        loop {
+            match self.events.recv_timeout(settle) {
+                Ok(MigrationEvent::Retained(topic)) => topics.push(topic),
+                Ok(MigrationEvent::PubAck) => {}
+                Err(RecvTimeoutError::Timeout | RecvTimeoutError::Disconnected) => break,
+            }
+        }
+        topics.sort();
+        topics.dedup();
+        topics
+    }
+
+    /// Clear retained `topics` by publishing empty retained payloads
+    ///
+    /// Waits up to `timeout` for the broker to acknowledge all of them and
+    /// returns the number of acknowledged clears.
+    pub fn clear(&self, topics: &[String], timeout: Duration) -> Result<usize, MqttError> {
+        for topic in topics {
+            self.client
+                .send(topic, String::new(), QoS::AtLeastOnce, true)?;
+        }
+
+        let deadline = Instant::now() + timeout;
+        let mut acknowledged = 0;
+        while acknowledged < topics.len() {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match self.events.recv_timeout(remaining) {
+                Ok(MigrationEvent::PubAck) => acknowledged += 1,
+                // Our own clears are not retained, anything else is late
+                Ok(MigrationEvent::Retained(_)) => {}
+                Err(_) => break,
+            }
+        }
+        Ok(acknowledged)
+    }
+}
+
+impl Drop for Migration {
+    fn drop(&mut self) {
+        let _ = self.client.disconnect();
+    }
+}
+
+/// Retained topics that are not below `keep_root`
+///
+/// With the same root for old and new layout this keeps the current device
+/// tree and only returns leftovers such as topics of an old device id.
+pub fn obsolete_topics(retained: &[String], keep_root: &str) -> Vec<String> {
+    let keep_root = keep_root.trim_end_matches('/');
+    let prefix = format!("{}/", keep_root);
+    retained
+        .iter()
+        .filter(|topic| topic.as_str() != keep_root && !topic.starts_with(&prefix))
+        .cloned()
+        .collect()
+}
+
+/// Retained topics below `root` in groups that `topics.retain` excludes
+///
+/// Publishing without the retain flag leaves an existing retained message on
+/// the broker, values retained by older versions have to be cleared.
+pub fn unretained_topics(retained: &[String], root: &str, topics: &TopicsConfig) -> Vec<String> {
+    let root = root.trim_end_matches('/');
+    let status = format!("{}/{}/", root, topics.status);
+    let battery = format!("{}{}", status, topics.battery);
+    let status_sums = format!("{}/{}", root, topics.status_sums);
+    let info = format!("{}/{}", root, topics.info);
+    retained
+        .iter()
+        .filter(|topic| {
+            if topic.starts_with(&battery) {
+                !topics.retain.battery
+            } else if topic.starts_with(&status) {
+                !topics.retain.status
+            } else if let Some(rest) = topic.strip_prefix(&status_sums) {
+                !topics.retain.status_sums && (rest.starts_with('/') || rest.starts_with('_'))
+            } else {
+                !topics.retain.info && topic.as_str() == info
+            }
+        })
+        .cloned()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_obsolete_topics() {
+        let retained: Vec<String> = [
+            "e3dc/S10-123/online",
+            "e3dc/S10-123/status/autarky",
+            "e3dc/S10-1234/online",
+            "e3dc/old/status/autarky",
+        ]
+        .iter()
+        .map(|topic| topic.to_string())
+        .collect();
+
+        // Same root, only the device id changed
+        assert_eq!(
+            obsolete_topics(&retained, "e3dc/S10-123"),
+            vec!["e3dc/S10-1234/online", "e3dc/old/status/autarky"]
+        );
+        // Moved to a new root, everything below the old one goes
+        assert_eq!(obsolete_topics(&retained, "home/e3dc/S10-123").len(), 4);
+    }
+
+    #[test]
+    fn test_unretained_topics() {
+        let retained: Vec<String> = [
+            "e3dc/S10-123/info",
+            "e3dc/S10-123/status/autarky",
+            "e3dc/S10-123/status/pvi:0/dc_power",
+            "e3dc/S10-123/status/battery:0/soc",
+            "e3dc/S10-123/status_sums/autarky",
+            "e3dc/S10-123/status_sums_month/autarky",
+        ]
+        .iter()
+        .map(|topic| topic.to_string())
+        .collect();
+
+        let mut topics = TopicsConfig::default();
+        assert_eq!(
+            unretained_topics(&retained, "e3dc/S10-123", &topics),
+            vec![
+                "e3dc/S10-123/status/autarky",
+                "e3dc/S10-123/status/pvi:0/dc_power"
+            ]
+        );
+        topics.retain.status = true;
+        topics.retain.status_sums = false;
+        topics.retain.info = false;
+        assert_eq!(
+            unretained_topics(&retained, "e3dc/S10-123", &topics),
+            vec![
+                "e3dc/S10-123/info",
+                "e3dc/S10-123/status_sums/autarky",
+                "e3dc/S10-123/status_sums_month/autarky"
+            ]
+        );
+    }
+}
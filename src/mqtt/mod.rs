@@ -1,6 +1,16 @@
+pub mod commands;
 pub mod context;
+pub mod diff;
+pub mod discovery;
+pub mod homie;
+pub mod link;
+pub mod metrics;
+pub mod migrate;
 pub mod publisher;
+pub mod selftest;
+pub mod subscriber;
+pub mod suppression;
 pub mod types;
 
-pub use publisher::MqttPublisher;
+pub use publisher::{MqttPublisher, Publisher};
 pub use types::*;
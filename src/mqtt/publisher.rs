@@ -1,251 +1,868 @@
-use crate::config::Config;
+use crate::alerts::BatteryAlert;
+use crate::config::{Config, TopicsConfig};
+use crate::e3dc::debug::DebugSink;
+use crate::e3dc::StatisticsPeriod;
 use crate::errors::MqttError;
-use crate::mqtt::context::PublishContext;
-use crate::mqtt::{BatteryData, DailyStatistics, DcbData, Status, SystemInfo};
-use rumqttc::{Client, Event, MqttOptions, Packet, QoS};
+use crate::events::EmergencyPowerTestEvent;
+use crate::lifecycle::BridgeState;
+use crate::mqtt::commands::IncomingCommand;
+use crate::mqtt::context::{
+    JsonDocument, PayloadFormat, PublishContext, Timestamped, Transport, DEFAULT_PAYLOAD_FORMAT,
+    NO_RENAMES,
+};
+use crate::mqtt::discovery::DiscoveryEntity;
+use crate::mqtt::link::{self, ConnectionEvent, Link, LinkReporter};
+use crate::mqtt::metrics::{PublishMetrics, PublishStats};
+use crate::mqtt::subscriber::{self, CommandAck};
+use crate::mqtt::{
+    BatteryData, BatteryRates, ChargeLimits, CycleTrend, DailyStatistics, DayCurve, DayStatistics,
+    DcdcData, DeviceClock, EmergencyPowerCapabilities, EmergencyPowerUsage, ExportLimitUsage,
+    IdlePeriod, IntegratedEnergy, NetworkConfig, PeriodStatistics, PhaseBalance, PvForecast,
+    PviData, Status, SystemInfo,
+};
+use crate::net;
+use crate::socks;
+use crate::version::BuildInfo;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use rumqttc::{
+    Client, ClientError, Connection, ConnectionError, Event, MqttOptions, NetworkOptions, Outgoing,
+    Packet, QoS, Request,
+};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, LazyLock};
 use std::thread;
 use std::time::Duration;
 
 pub struct MqttPublisher {
-    client: Client,
+    client: MeteredClient,
     root_topic: String,
+    device_id: String,
+    commands: Receiver<IncomingCommand>,
+    payload_format: PayloadFormat,
+    topics: TopicsConfig,
+    renames: BTreeMap<String, String>,
 }
 
-macro_rules! publish_if_changed {
-    ($context:expr, $src:ident , $old:ident, $field:ident) => {
-        if $old.as_ref().map_or(true, |o| o.$field != $src.$field) {
-            $context.publish(stringify!($field), &$src.$field)?;
-        }
-    };
-}
+static DEFAULT_TOPICS: LazyLock<TopicsConfig> = LazyLock::new(TopicsConfig::default);
 
-impl MqttPublisher {
-    pub fn new(config: &Config, device_id: String) -> Result<Self, MqttError> {
-        // Use custom client_id if provided, otherwise default to e3dc-mqtt-rs-{device_id}
-        let client_id = config
-            .mqtt
-            .client_id
-            .clone()
-            .unwrap_or_else(|| format!("e3dc-mqtt-rs-{}", device_id));
-
-        let host = &config.mqtt.host;
-        tracing::info!("Connecting to MQTT broker at {}:{} with client ID '{}'", host, config.mqtt.port, client_id);
-        let mut mqtt_options = MqttOptions::new(client_id, host, config.mqtt.port);
+/// Publishing of the converted data on top of a [`Transport`]
+///
+/// Implementors only provide the transport and topic layout; the default
+/// methods render payloads and publish changed fields (see [`crate::mqtt::diff`]).
+/// Alternative transports and tests can implement this to capture the
+/// generated topic/payload pairs.
+pub trait Publisher {
+    fn transport(&self) -> &dyn Transport;
 
-        if !config.mqtt.username.is_empty() {
-            mqtt_options.set_credentials(&config.mqtt.username, &config.mqtt.password);
-        }
+    /// Device root topic (`{root}/{device-id}`)
+    fn root_topic(&self) -> &str;
 
-        mqtt_options.set_keep_alive(Duration::from_secs(60));
+    fn device_id(&self) -> &str;
 
-        // Set Last Will and Testament - publish "false" to online topic when connection is lost
-        let online_topic = format!("{}/{}/online", config.mqtt.root, device_id);
-        mqtt_options.set_last_will(rumqttc::LastWill {
-            topic: online_topic.clone(),
-            message: b"false".to_vec().into(),
-            qos: QoS::AtLeastOnce,
-            retain: true,
-        });
-
-        // Create blocking client (no async!)
-        let (client, mut connection) = Client::new(mqtt_options, 10);
+    fn payload_format(&self) -> &PayloadFormat {
+        &DEFAULT_PAYLOAD_FORMAT
+    }
 
-        // Spawn event loop in background thread (not tokio task!)
-        // Note: This thread will be forcibly terminated when the main thread exits.
-        // This is intentional for "let it crash" philosophy - no graceful shutdown needed.
-        thread::Builder::new()
-            .name("mqtt-event-loop".to_string())
-            .spawn(move || {
-                for notification in connection.iter() {
-                    match notification {
-                        Ok(Event::Incoming(Packet::ConnAck(_))) => {
-                            tracing::info!("MQTT connected");
-                        }
-                        Ok(_) => {}
-                        Err(e) => {
-                            // On connection error, crash the process (let it crash philosophy)
-                            tracing::error!("MQTT connection error: {:?}", e);
-                            std::process::exit(1);
-                        }
-                    }
-                }
-            })
-            .expect("Failed to spawn MQTT event loop thread");
-        let root_topic = format!("{}/{}", config.mqtt.root, device_id);
+    /// Path segments of the metric groups
+    fn topics(&self) -> &TopicsConfig {
+        &DEFAULT_TOPICS
+    }
 
-        Ok(Self { client, root_topic })
+    /// Custom topic leaf names by field name (`[mqtt.rename]`)
+    fn topic_renames(&self) -> &BTreeMap<String, String> {
+        &NO_RENAMES
     }
 
-    pub fn context(&'_ self, topic: &str) -> PublishContext<'_> {
+    fn context(&self, topic: &str) -> PublishContext<'_> {
         let full_topic = if topic.is_empty() {
-            self.root_topic.clone()
+            self.root_topic().to_string()
         } else {
-            format!("{}/{}", self.root_topic, topic)
+            format!("{}/{}", self.root_topic(), topic)
         };
-        PublishContext::new(&self.client, full_topic)
+        let mut context = PublishContext::new(self.transport(), full_topic);
+        context.format = self.payload_format();
+        context.renames = self.topic_renames();
+        context
+    }
+
+    /// Context of a topic in a metric group, `retain` as configured in `[topics.retain]`
+    fn group_context(&self, topic: &str, retain: bool) -> PublishContext<'_> {
+        let mut context = self.context(topic);
+        context.retain = retain;
+        context
     }
 
-    pub fn publish_online_status(&self, online: bool) -> Result<(), MqttError> {
+    fn publish_online_status(&self, online: bool) -> Result<(), MqttError> {
         let context = self.context("");
         context.publish("online", &online)
     }
 
-    /// Publish system info as JSON
-    pub fn publish_system_info(&self, info: &SystemInfo) -> Result<(), MqttError> {
+    /// Publish a Home Assistant discovery config (retained)
+    fn publish_discovery(
+        &self,
+        discovery_prefix: &str,
+        entity: &DiscoveryEntity,
+    ) -> Result<(), MqttError> {
+        let context = PublishContext::new(
+            self.transport(),
+            format!(
+                "{}/{}/{}",
+                discovery_prefix,
+                entity.component,
+                self.device_id()
+            ),
+        );
+        let json = serde_json::to_string(&entity.config)
+            .map_err(|error| MqttError::SerializationError { error })?;
+        context.publish(&format!("{}/config", entity.object_id), &json)
+    }
+
+    /// Publish system info as JSON document
+    fn publish_system_info(&self, info: &SystemInfo) -> Result<(), MqttError> {
+        let context = self.group_context("", self.topics().retain.info);
+        context.publish_document(&self.topics().info, &JsonDocument::new(info))
+    }
+
+    /// Publish the effective idle period schedule as array document
+    fn publish_idle_periods(&self, periods: &[IdlePeriod]) -> Result<(), MqttError> {
         let context = self.context("");
-        let json =
-            serde_json::to_string(info).map_err(|error| MqttError::SerializationError { error })?;
-        // Manual JSON formatting (no serde_json needed for simple structure)
+        context.publish_json("idle_periods", periods)
+    }
+
+    /// Publish month- or year-to-date totals to `{status_sums}_{period}`
+    fn publish_period_statistics(
+        &self,
+        period: StatisticsPeriod,
+        stats: &PeriodStatistics,
+        old: Option<&PeriodStatistics>,
+    ) -> Result<(), MqttError> {
+        self.group_context(
+            &format!("{}_{}", self.topics().status_sums, period.name()),
+            self.topics().retain.status_sums,
+        )
+        .publish_changes(&stats.diff(old))
+    }
+
+    /// Publish the sums of a statistics window to `{status_sums}_{name}`
+    fn publish_window_statistics(
+        &self,
+        name: &str,
+        stats: &PeriodStatistics,
+        old: Option<&PeriodStatistics>,
+    ) -> Result<(), MqttError> {
+        self.group_context(
+            &format!("{}_{}", self.topics().status_sums, name),
+            self.topics().retain.status_sums,
+        )
+        .publish_changes(&stats.diff(old))
+    }
+
+    /// Publish the lifetime totals to `{status_sums}_lifetime`
+    fn publish_lifetime_statistics(
+        &self,
+        stats: &PeriodStatistics,
+        old: Option<&PeriodStatistics>,
+    ) -> Result<(), MqttError> {
+        self.group_context(
+            &format!("{}_lifetime", self.topics().status_sums),
+            self.topics().retain.status_sums,
+        )
+        .publish_changes(&stats.diff(old))
+    }
+
+    /// Publish today's curve as JSON arrays to `{status_sums}/curve`
+    fn publish_day_curve(&self, curve: &DayCurve) -> Result<(), MqttError> {
+        self.group_context(&self.topics().status_sums, self.topics().retain.status_sums)
+            .publish_document("curve", curve)
+    }
 
-        context.publish("info", &json)
+    /// Publish past daily statistics as JSON array to `{status_sums}/history`
+    fn publish_statistics_history(&self, days: &[DayStatistics]) -> Result<(), MqttError> {
+        self.group_context(&self.topics().status_sums, self.topics().retain.status_sums)
+            .publish_document("history", days)
     }
 
     /// Publish real-time status data
-    /// Only publishes fields that have changed compared to prev_status
-    pub fn publish_status(&self, status: &Status, old: Option<Status>) -> Result<(), MqttError> {
-        let context = self.context("status");
-        publish_if_changed!(context, status, old, time);
-        publish_if_changed!(context, status, old, additional);
-        publish_if_changed!(context, status, old, autarky);
-        publish_if_changed!(context, status, old, battery_charge);
-        publish_if_changed!(context, status, old, battery_discharge);
-        publish_if_changed!(context, status, old, battery_consumption);
-        publish_if_changed!(context, status, old, consumption_from_grid);
-        publish_if_changed!(context, status, old, export_to_grid);
-        publish_if_changed!(context, status, old, grid_production);
-        publish_if_changed!(context, status, old, house_consumption);
-        publish_if_changed!(context, status, old, self_consumption);
-        publish_if_changed!(context, status, old, solar_production);
-        publish_if_changed!(context, status, old, solar_production_excess);
-        publish_if_changed!(context, status, old, state_of_charge);
-        publish_if_changed!(context, status, old, wb_consumption);
+    /// Only publishes fields that have changed compared to `old`
+    fn publish_status(&self, status: &Status, old: Option<&Status>) -> Result<(), MqttError> {
+        self.group_context(&self.topics().status, self.topics().retain.status)
+            .publish_changes(&status.diff(old))
+    }
+
+    /// Publish the currently applied charge limits to `{status}/limits`
+    fn publish_charge_limits(
+        &self,
+        limits: &ChargeLimits,
+        old: Option<&ChargeLimits>,
+    ) -> Result<(), MqttError> {
+        self.group_context(
+            &format!("{}/limits", self.topics().status),
+            self.topics().retain.status,
+        )
+        .publish_changes(&limits.diff(old))
+    }
+
+    /// Publish the phase balancing state to `{status}/phases`
+    fn publish_phase_balance(
+        &self,
+        balance: &PhaseBalance,
+        old: Option<&PhaseBalance>,
+    ) -> Result<(), MqttError> {
+        self.group_context(
+            &format!("{}/phases", self.topics().status),
+            self.topics().retain.status,
+        )
+        .publish_changes(&balance.diff(old))
+    }
+
+    /// Publish the energy of the current (or last) wallbox charging session in Wh
+    fn publish_wallbox_session_energy(&self, energy: f64) -> Result<(), MqttError> {
+        self.group_context(&self.topics().status, self.topics().retain.status)
+            .publish("wb_session_energy", &energy)
+    }
+
+    /// Publish the number of phases the wallbox charges with to `{status}/wb_phases`
+    fn publish_wallbox_phases(&self, phases: u8) -> Result<(), MqttError> {
+        self.group_context(&self.topics().status, self.topics().retain.status)
+            .publish("wb_phases", &u64::from(phases))
+    }
+
+    /// Publish the energy balance residual in W to `{status}/energy_balance_residual`
+    fn publish_energy_balance(&self, residual: f64) -> Result<(), MqttError> {
+        self.group_context(&self.topics().status, self.topics().retain.status)
+            .publish("energy_balance_residual", &residual)
+    }
+
+    /// Publish the smoothed SOC to `status/state_of_charge_smoothed`
+    fn publish_smoothed_soc(&self, soc: f64) -> Result<(), MqttError> {
+        self.group_context(&self.topics().status, self.topics().retain.status)
+            .publish("state_of_charge_smoothed", &soc)
+    }
+
+    /// Publish the grid export against the configured limit below the status topic
+    fn publish_export_limit(
+        &self,
+        usage: &ExportLimitUsage,
+        old: Option<&ExportLimitUsage>,
+    ) -> Result<(), MqttError> {
+        self.group_context(&self.topics().status, self.topics().retain.status)
+            .publish_changes(&usage.diff(old))
+    }
+
+    /// Publish the SOC change per hour and time to full/empty below the status topic
+    fn publish_battery_rates(
+        &self,
+        rates: &BatteryRates,
+        old: Option<&BatteryRates>,
+    ) -> Result<(), MqttError> {
+        self.group_context(&self.topics().status, self.topics().retain.status)
+            .publish_changes(&rates.diff(old))
+    }
 
+    /// Publish the emergency power capabilities and reserve below `emergency_power`
+    fn publish_emergency_power_capabilities(
+        &self,
+        capabilities: &EmergencyPowerCapabilities,
+        old: Option<&EmergencyPowerCapabilities>,
+    ) -> Result<(), MqttError> {
+        self.context("emergency_power")
+            .publish_changes(&capabilities.diff(old))
+    }
+
+    /// Publish the emergency power usage counters below `{status_sums}/emergency_power`
+    fn publish_emergency_power_usage(
+        &self,
+        usage: &EmergencyPowerUsage,
+        old: Option<&EmergencyPowerUsage>,
+    ) -> Result<(), MqttError> {
+        let topic = format!("{}/emergency_power", self.topics().status_sums);
+        self.group_context(&topic, self.topics().retain.status_sums)
+            .publish_changes(&usage.diff(old))
+    }
+
+    /// Publish the unit's network settings below `network`
+    fn publish_network_config(
+        &self,
+        network: &NetworkConfig,
+        old: Option<&NetworkConfig>,
+    ) -> Result<(), MqttError> {
+        self.context("network").publish_changes(&network.diff(old))
+    }
+
+    /// Publish the portal connection to `status/portal_online`, and the poll
+    /// time to `status/portal_last_online` while connected
+    fn publish_portal_status(&self, online: bool, time: DateTime<Utc>) -> Result<(), MqttError> {
+        let context = self.group_context(&self.topics().status, self.topics().retain.status);
+        context.publish("portal_online", &online)?;
+        if online {
+            context.publish("portal_last_online", &time)?;
+        }
         Ok(())
     }
 
+    /// Publish the unit's clock below `clock`
+    fn publish_device_clock(
+        &self,
+        clock: &DeviceClock,
+        old: Option<&DeviceClock>,
+    ) -> Result<(), MqttError> {
+        self.context("clock").publish_changes(&clock.diff(old))
+    }
+
+    /// Publish the PV forecast below `forecast`
+    fn publish_forecast(
+        &self,
+        forecast: &PvForecast,
+        old: Option<&PvForecast>,
+    ) -> Result<(), MqttError> {
+        self.context("forecast")
+            .publish_changes(&forecast.diff(old))
+    }
+
+    /// Publish the expected power (W) per period as JSON document to `forecast/power`
+    fn publish_forecast_power(
+        &self,
+        power: &BTreeMap<NaiveDateTime, f64>,
+    ) -> Result<(), MqttError> {
+        let power: BTreeMap<String, f64> = power
+            .iter()
+            .map(|(time, watts)| (time.format("%Y-%m-%dT%H:%M:%S").to_string(), *watts))
+            .collect();
+        self.context("forecast").publish_document("power", &power)
+    }
+
+    /// Publish whether the schedule skips grid charging because of the forecast
+    /// to `forecast/grid_charge_skipped`
+    fn publish_grid_charge_skipped(&self, skipped: bool) -> Result<(), MqttError> {
+        self.context("forecast")
+            .publish("grid_charge_skipped", &skipped)
+    }
+
+    /// Publish the seconds since the bridge started to `bridge/uptime`
+    fn publish_uptime(&self, seconds: u64) -> Result<(), MqttError> {
+        self.context("bridge").publish("uptime", &seconds)
+    }
+
+    /// Publish the lifecycle state to `bridge/state`
+    fn publish_bridge_state(&self, state: BridgeState) -> Result<(), MqttError> {
+        self.context("bridge").publish("state", &state)
+    }
+
+    /// Publish the completion time of a successful `cycle` to `bridge/last_poll/{cycle}`
+    fn publish_last_poll(&self, cycle: &str, time: DateTime<Utc>) -> Result<(), MqttError> {
+        self.context("bridge/last_poll").publish(cycle, &time)
+    }
+
+    /// Publish the number of restarts recorded in the state file to `bridge/restarts`
+    fn publish_restarts(&self, restarts: u64) -> Result<(), MqttError> {
+        self.context("bridge").publish("restarts", &restarts)
+    }
+
+    /// Publish the publish counters of the last cycle to `bridge/publish_attempts`,
+    /// `bridge/publish_failures`, `bridge/publish_retries` and `bridge/queue_depth`
+    fn publish_metrics(&self, stats: &PublishStats) -> Result<(), MqttError> {
+        let context = self.context("bridge");
+        context.publish("publish_attempts", &stats.attempts)?;
+        context.publish("publish_failures", &stats.failures)?;
+        context.publish("publish_retries", &stats.retries)?;
+        context.publish("queue_depth", &stats.queue_depth)
+    }
+
+    /// Publish the bridge's build information to `bridge/version`
+    fn publish_version(&self, info: &BuildInfo) -> Result<(), MqttError> {
+        self.context("bridge").publish_json("version", info)
+    }
+
+    /// Publish the outcome of a command to `command_ack/{name}` (not retained)
+    fn publish_command_ack(&self, ack: &CommandAck) -> Result<(), MqttError>
+    where
+        Self: Sized,
+    {
+        let mut context = self.context("command_ack");
+        context.retain = false;
+        context.publish_json(&ack.command, &JsonDocument::new(ack))
+    }
+
+    /// Publish emergency power test progress to `events/emergency_power_test`
+    ///
+    /// Object safe, for command handlers.
+    fn publish_emergency_power_test(
+        &self,
+        event: &EmergencyPowerTestEvent,
+    ) -> Result<(), MqttError> {
+        let mut context = self.context("events");
+        context.retain = false;
+        context.publish_json("emergency_power_test", &JsonDocument::new(event))
+    }
+
+    /// Publish a discrete event as JSON document to `events/{name}` (not retained)
+    fn publish_event<T: Serialize + Timestamped>(
+        &self,
+        name: &str,
+        event: &T,
+    ) -> Result<(), MqttError>
+    where
+        Self: Sized,
+    {
+        let mut context = self.context("events");
+        context.retain = false;
+        context.publish_json(name, &JsonDocument::new(event))
+    }
+
     /// Publish daily statistics (status_sums)
-    pub fn publish_daily_statistics(
+    fn publish_daily_statistics(
         &self,
         stats: &DailyStatistics,
-        old: Option<DailyStatistics>,
+        old: Option<&DailyStatistics>,
     ) -> Result<(), MqttError> {
-        let context = self.context("status_sums");
-
-        publish_if_changed!(context, stats, old, time);
-        publish_if_changed!(context, stats, old, autarky_today);
-        publish_if_changed!(context, stats, old, self_consumption_today);
-        publish_if_changed!(context, stats, old, solar_production_today);
-        publish_if_changed!(context, stats, old, house_consumption_today);
-        publish_if_changed!(context, stats, old, battery_charge_today);
-        publish_if_changed!(context, stats, old, battery_discharge_today);
-        publish_if_changed!(context, stats, old, export_to_grid_today);
-        publish_if_changed!(context, stats, old, consumption_from_grid_today);
-        publish_if_changed!(context, stats, old, state_of_charge_today);
-        publish_if_changed!(context, stats, old, start);
-        publish_if_changed!(context, stats, old, timespan);
+        self.group_context(&self.topics().status_sums, self.topics().retain.status_sums)
+            .publish_changes(&stats.diff(old))
+    }
 
-        Ok(())
+    /// Publish the energy integrated by the bridge next to the daily statistics
+    fn publish_integrated_energy(
+        &self,
+        energy: &IntegratedEnergy,
+        old: Option<&IntegratedEnergy>,
+    ) -> Result<(), MqttError> {
+        self.group_context(&self.topics().status_sums, self.topics().retain.status_sums)
+            .publish_changes(&energy.diff(old))
     }
 
-    pub fn publish_battery_data(
+    /// Publish battery and DCB data with change detection against `old`
+    fn publish_battery_data(
         &self,
         batteries: &[BatteryData],
         old: &[BatteryData],
     ) -> Result<(), MqttError> {
         for battery in batteries {
             let old_bat = old.iter().find(|b| b.index == battery.index);
-            self.publish_battery_data_item(battery, old_bat)?;
+            let battery_topic = self.topics().battery(battery.index);
+            let context = self.group_context(&battery_topic, self.topics().retain.battery);
+            context.publish_changes(&battery.diff(old_bat))?;
+            for dcb in &battery.dcbs {
+                let old_dcb = old_bat.and_then(|b| b.dcbs.iter().find(|d| d.index == dcb.index));
+                self.group_context(
+                    &self.topics().dcb(&battery_topic, dcb.index),
+                    self.topics().retain.battery,
+                )
+                .publish_changes(&dcb.diff(old_dcb))?;
+            }
         }
         Ok(())
     }
-    /// Publish battery data (all fields, no change detection - kept for compatibility)
-    fn publish_battery_data_item(
+
+    /// Publish the data of every PVI and its strings
+    fn publish_pvi_data(&self, pvis: &[PviData], old: &[PviData]) -> Result<(), MqttError> {
+        for pvi in pvis {
+            let old_pvi = old.iter().find(|p| p.index == pvi.index);
+            let pvi_topic = self.topics().pvi(pvi.index);
+            self.group_context(&pvi_topic, self.topics().retain.status)
+                .publish_changes(&pvi.diff(old_pvi))?;
+            for string in &pvi.strings {
+                let old_string =
+                    old_pvi.and_then(|p| p.strings.iter().find(|s| s.index == string.index));
+                self.group_context(
+                    &self.topics().pvi_string(&pvi_topic, string.index),
+                    self.topics().retain.status,
+                )
+                .publish_changes(&string.diff(old_string))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Publish the DCDC converter data with change detection against `old`
+    fn publish_dcdc_data(&self, dcdcs: &[DcdcData], old: &[DcdcData]) -> Result<(), MqttError> {
+        for dcdc in dcdcs {
+            let old_dcdc = old.iter().find(|d| d.index == dcdc.index);
+            self.group_context(&self.topics().dcdc(dcdc.index), self.topics().retain.status)
+                .publish_changes(&dcdc.diff(old_dcdc))?;
+        }
+        Ok(())
+    }
+
+    /// Publish the charge cycle trend below the battery topics
+    fn publish_cycle_trends(
         &self,
-        battery: &BatteryData,
-        old: Option<&BatteryData>,
+        trends: &[CycleTrend],
+        old: &[CycleTrend],
     ) -> Result<(), MqttError> {
-        let context = self.context(format!("status/battery:{}", battery.index).as_str());
-        publish_if_changed!(context, battery, old, time);
-        publish_if_changed!(context, battery, old, asoc);
-        publish_if_changed!(context, battery, old, charge_cycles);
-        publish_if_changed!(context, battery, old, current);
-        publish_if_changed!(context, battery, old, dcb_count);
-        for dcb in &battery.dcbs {
-            let old_dcb = old
-                .as_ref()
-                .and_then(|b| b.dcbs.iter().find(|d| d.index == dcb.index));
-            self.publish_dcb_data(dcb, old_dcb, battery.index)?;
+        for trend in trends {
+            let old_trend = old.iter().find(|t| t.index == trend.index);
+            self.group_context(
+                &self.topics().battery(trend.index),
+                self.topics().retain.battery,
+            )
+            .publish_changes(&trend.diff(old_trend))?;
         }
-        publish_if_changed!(context, battery, old, design_capacity);
-        publish_if_changed!(context, battery, old, device_name);
-        publish_if_changed!(context, battery, old, eod_voltage);
-        publish_if_changed!(context, battery, old, error_code);
-        publish_if_changed!(context, battery, old, fcc);
-        publish_if_changed!(context, battery, old, index);
-        publish_if_changed!(context, battery, old, max_battery_voltage);
-        publish_if_changed!(context, battery, old, max_charge_current);
-        publish_if_changed!(context, battery, old, max_discharge_current);
-        publish_if_changed!(context, battery, old, max_dcb_cell_temp);
-        publish_if_changed!(context, battery, old, min_dcb_cell_temp);
-        publish_if_changed!(context, battery, old, module_voltage);
-        publish_if_changed!(context, battery, old, rc);
-        publish_if_changed!(context, battery, old, ready_for_shutdown);
-        publish_if_changed!(context, battery, old, rsoc);
-        publish_if_changed!(context, battery, old, rsoc_real);
-        publish_if_changed!(context, battery, old, status_code);
-        publish_if_changed!(context, battery, old, terminal_voltage);
-        publish_if_changed!(context, battery, old, total_use_time);
-        publish_if_changed!(context, battery, old, total_discharge_time);
-        publish_if_changed!(context, battery, old, training_mode);
-        publish_if_changed!(context, battery, old, usable_capacity);
-        publish_if_changed!(context, battery, old, usable_remaining_capacity);
+        Ok(())
+    }
 
+    /// Publish the alert topics of batteries and DCBs whose state changed
+    fn publish_battery_alerts(
+        &self,
+        alerts: &[BatteryAlert],
+        old: &[BatteryAlert],
+    ) -> Result<(), MqttError> {
+        for alert in alerts {
+            if old.contains(alert) {
+                continue;
+            }
+            let battery_topic = self.topics().battery(alert.battery);
+            let topic = match alert.dcb {
+                Some(dcb) => self.topics().dcb(&battery_topic, dcb),
+                None => battery_topic,
+            };
+            self.group_context(&topic, self.topics().retain.battery)
+                .publish(alert.name, &alert.active)?;
+        }
         Ok(())
     }
 
-    fn publish_dcb_data(
+    /// Clear the retained topics of batteries and DCBs that are missing from
+    /// `batteries` but were published in `old`
+    ///
+    /// Returns the cleared sub-trees relative to the root topic.
+    fn clear_removed_batteries(
         &self,
-        data: &DcbData,
-        old: Option<&DcbData>,
-        bat_index: u64,
+        batteries: &[BatteryData],
+        old: &[BatteryData],
+    ) -> Result<Vec<String>, MqttError> {
+        let mut removed = Vec::new();
+        for old_bat in old {
+            let battery = batteries.iter().find(|b| b.index == old_bat.index);
+            let battery_topic = self.topics().battery(old_bat.index);
+            for old_dcb in &old_bat.dcbs {
+                if battery.is_some_and(|b| b.dcbs.iter().any(|d| d.index == old_dcb.index)) {
+                    continue;
+                }
+                let dcb_topic = self.topics().dcb(&battery_topic, old_dcb.index);
+                let context = self.context(&dcb_topic);
+                for name in old_dcb.diff(None).names() {
+                    context.clear(name)?;
+                }
+                removed.push(dcb_topic);
+            }
+            if battery.is_none() {
+                let context = self.context(&battery_topic);
+                for name in old_bat.diff(None).names() {
+                    context.clear(name)?;
+                }
+                removed.push(battery_topic);
+            }
+        }
+        Ok(removed)
+    }
+}
+
+/// Broker connection options shared by all MQTT clients of the bridge
+///
+/// Fails if the SOCKS5 relay of `[mqtt.socks5]` can't be started.
+pub(crate) fn mqtt_options(config: &Config, client_id: String) -> Result<MqttOptions, MqttError> {
+    let host = &config.mqtt.host;
+    tracing::info!("Connecting to MQTT broker at {}:{} with client ID '{}'", host, config.mqtt.port, client_id);
+    let (address, port) = match &config.mqtt.socks5 {
+        Some(proxy) => {
+            tracing::info!("Connecting via SOCKS5 proxy {}:{}", proxy.host, proxy.port);
+            let relay = socks::spawn_relay(
+                proxy,
+                host,
+                config.mqtt.port,
+                config.mqtt.connect_timeout,
+                config.mqtt.bind_interface.as_deref(),
+            )
+            .map_err(|e| MqttError::ConnectFailed {
+                broker: format!("{}:{}", host, config.mqtt.port),
+                reason: format!("SOCKS5 relay via {}:{}: {}", proxy.host, proxy.port, e),
+            })?;
+            (relay.ip().to_string(), relay.port())
+        }
+        None => {
+            // rumqttc resolves names itself on every (re)connect, only IPv6
+            // literals need their brackets
+            let address = match net::unbracket(host).parse() {
+                Ok(ip) => net::host_string(ip),
+                Err(_) => host.clone(),
+            };
+            (address, config.mqtt.port)
+        }
+    };
+    let mut mqtt_options = MqttOptions::new(client_id, address, port);
+
+    if !config.mqtt.username.is_empty() {
+        mqtt_options.set_credentials(&config.mqtt.username, &config.mqtt.password);
+    }
+
+    mqtt_options.set_keep_alive(Duration::from_secs(60));
+    Ok(mqtt_options)
+}
+
+/// Client and connection for `options`, bound to `[mqtt] bind_interface` if set
+///
+/// `[mqtt] connect_timeout` limits each connect, rounded up to whole seconds.
+/// With a SOCKS5 proxy the relay binds its connection to the proxy instead,
+/// rumqttc only connects to the loopback relay.
+pub(crate) fn mqtt_client(
+    config: &Config,
+    options: MqttOptions,
+    cap: usize,
+) -> (Client, Connection) {
+    let (client, mut connection) = Client::new(options, cap);
+    let mut network_options = NetworkOptions::new();
+    network_options.set_connection_timeout(connect_timeout_secs(config.mqtt.connect_timeout));
+    if let Some(interface) = config
+        .mqtt
+        .bind_interface
+        .as_ref()
+        .filter(|_| config.mqtt.socks5.is_none())
+    {
+        network_options.set_bind_device(interface);
+    }
+    connection.eventloop.set_network_options(network_options);
+    (client, connection)
+}
+
+fn connect_timeout_secs(timeout: Duration) -> u64 {
+    timeout.as_secs_f64().ceil().max(1.0) as u64
+}
+
+/// MQTT client counting its publishes in [`PublishMetrics`]
+///
+/// A publish that finds the event loop queue full is counted as a retry and
+/// waits for room instead of failing, unless the broker connection is lost.
+struct MeteredClient {
+    client: Client,
+    metrics: Arc<PublishMetrics>,
+    link: Link,
+}
+
+impl Transport for MeteredClient {
+    fn send(&self, topic: &str, payload: String, qos: QoS, retain: bool) -> Result<(), MqttError> {
+        self.send_binary(topic, payload.into_bytes(), qos, retain)
+    }
+
+    fn send_binary(
+        &self,
+        topic: &str,
+        payload: Vec<u8>,
+        qos: QoS,
+        retain: bool,
     ) -> Result<(), MqttError> {
-        let context =
-            self.context(format!("status/battery:{}/dcb:{}", bat_index, data.index).as_str());
-        publish_if_changed!(context, data, old, current);
-        publish_if_changed!(context, data, old, current_avg_30s);
-        publish_if_changed!(context, data, old, cycle_count);
-        publish_if_changed!(context, data, old, design_capacity);
-        publish_if_changed!(context, data, old, design_voltage);
-        publish_if_changed!(context, data, old, device_name);
-        publish_if_changed!(context, data, old, end_of_discharge);
-        publish_if_changed!(context, data, old, error);
-        publish_if_changed!(context, data, old, full_charge_capacity);
-        publish_if_changed!(context, data, old, fw_version);
-        publish_if_changed!(context, data, old, manufacture_date);
-        publish_if_changed!(context, data, old, manufacture_name);
-        publish_if_changed!(context, data, old, max_charge_current);
-        publish_if_changed!(context, data, old, max_charge_temperature);
-        publish_if_changed!(context, data, old, max_charge_voltage);
-        publish_if_changed!(context, data, old, max_discharge_current);
-        publish_if_changed!(context, data, old, min_charge_temperature);
-        publish_if_changed!(context, data, old, parallel_cell_count);
-        publish_if_changed!(context, data, old, sensor_count);
-        publish_if_changed!(context, data, old, series_cell_count);
-        publish_if_changed!(context, data, old, pcb_version);
-        publish_if_changed!(context, data, old, protocol_version);
-        publish_if_changed!(context, data, old, remaining_capacity);
-        publish_if_changed!(context, data, old, serial_no);
-        publish_if_changed!(context, data, old, serial_code);
-        publish_if_changed!(context, data, old, soc);
-        publish_if_changed!(context, data, old, soh);
-        publish_if_changed!(context, data, old, status);
-        publish_if_changed!(context, data, old, temperatures);
-        publish_if_changed!(context, data, old, voltage);
-        publish_if_changed!(context, data, old, voltage_avg_30s);
-        publish_if_changed!(context, data, old, voltages);
-        publish_if_changed!(context, data, old, warning);
+        self.metrics.attempt();
+        let result = match self.client.try_publish(topic, qos, retain, payload) {
+            Err(ClientError::TryRequest(Request::Publish(publish))) if !self.link.is_lost() => {
+                self.metrics.retry();
+                self.client
+                    .publish(topic, qos, retain, publish.payload.to_vec())
+            }
+            result => result,
+        };
+        match result {
+            Ok(()) => {
+                self.metrics.queued();
+                Ok(())
+            }
+            Err(e) => {
+                let reason = e.to_string();
+                self.metrics.failure(&format!("{}: {}", topic, reason));
+                Err(MqttError::PublishFailed {
+                    topic: topic.to_string(),
+                    reason,
+                })
+            }
+        }
+    }
+}
 
-        Ok(())
+/// Notifications of the event loop thread of [`MqttPublisher`]
+struct EventLoop<S> {
+    reporter: LinkReporter,
+    metrics: Arc<PublishMetrics>,
+    /// `{root}/command/`, None with commands disabled
+    command_prefix: Option<String>,
+    commands: Sender<IncomingCommand>,
+    /// Queue a subscription without waiting for room (the event loop empties the queue)
+    subscribe: S,
+}
+
+impl<S: FnMut(&str) -> Result<(), ClientError>> EventLoop<S> {
+    /// Handle one notification, returns whether it was a connection error
+    ///
+    /// The command subscription is requested again with every CONNACK: the
+    /// session is clean, the broker forgets it on each reconnect.
+    fn handle(&mut self, notification: Result<Event, ConnectionError>) -> bool {
+        if self.reporter.observe(&notification) {
+            return true;
+        }
+        match notification {
+            Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                if let Some(prefix) = &self.command_prefix {
+                    let topic = format!("{}#", prefix);
+                    if let Err(e) = (self.subscribe)(&topic) {
+                        tracing::error!("Failed to subscribe to {}: {}", topic, e);
+                    }
+                }
+            }
+            Ok(Event::Outgoing(Outgoing::Publish(_))) => self.metrics.written(),
+            Ok(Event::Incoming(Packet::Publish(publish))) => {
+                let Some(prefix) = &self.command_prefix else {
+                    return false;
+                };
+                if let Some(command) = subscriber::incoming_command(
+                    prefix,
+                    &publish.topic,
+                    &publish.payload,
+                    publish.retain,
+                ) {
+                    // Receiver is gone only during shutdown
+                    let _ = self.commands.send(command);
+                }
+            }
+            _ => {}
+        }
+        false
+    }
+}
+
+impl MqttPublisher {
+    pub fn new(config: &Config, device_id: String) -> Result<Self, MqttError> {
+        // Use custom client_id if provided, otherwise default to e3dc-mqtt-rs-{device_id}
+        let client_id = config
+            .mqtt
+            .client_id
+            .clone()
+            .unwrap_or_else(|| format!("e3dc-mqtt-rs-{}", device_id));
+
+        let mut mqtt_options = mqtt_options(config, client_id)?;
+
+        // Set Last Will and Testament - publish "false" to online topic when connection is lost
+        let online_topic = format!("{}/{}/online", config.mqtt.root, device_id);
+        mqtt_options.set_last_will(rumqttc::LastWill {
+            topic: online_topic.clone(),
+            message: b"false".to_vec().into(),
+            qos: QoS::AtLeastOnce,
+            retain: true,
+        });
+
+        // Create blocking client (no async!)
+        let (client, mut connection) = mqtt_client(config, mqtt_options, 10);
+        let root_topic = format!("{}/{}", config.mqtt.root, device_id);
+        let metrics = Arc::new(PublishMetrics::default());
+
+        // Commands are forwarded from the event loop to the main loop
+        let (command_tx, commands) = mpsc::channel();
+
+        // Spawn event loop in background thread (not tokio task!), it ends
+        // once the client is dropped. Connection errors go to `connection_events`.
+        let (reporter, link) = link::link("MQTT");
+        // Read-only bridge: never subscribe, so nothing can reach the E3DC
+        let command_prefix = config
+            .commands
+            .enabled
+            .then(|| format!("{}/command/", root_topic));
+        let subscriber = client.clone();
+        let mut event_loop = EventLoop {
+            reporter,
+            metrics: Arc::clone(&metrics),
+            command_prefix,
+            commands: command_tx,
+            subscribe: move |topic: &str| subscriber.try_subscribe(topic, QoS::AtLeastOnce),
+        };
+        thread::Builder::new()
+            .name("mqtt-event-loop".to_string())
+            .spawn(move || {
+                for notification in connection.iter() {
+                    if event_loop.handle(notification) {
+                        // rumqttc reconnects on the next poll
+                        thread::sleep(link::RECONNECT_DELAY);
+                    }
+                }
+            })
+            .map_err(|e| MqttError::ConnectFailed {
+                broker: format!("{}:{}", config.mqtt.host, config.mqtt.port),
+                reason: format!("failed to spawn the event loop thread: {}", e),
+            })?;
+
+        Ok(Self {
+            client: MeteredClient {
+                client,
+                metrics,
+                link,
+            },
+            root_topic,
+            device_id,
+            commands,
+            payload_format: PayloadFormat::from_config(&config.payload),
+            topics: config.topics.clone(),
+            renames: config.mqtt.rename.clone(),
+        })
+    }
+
+    /// Sink publishing the RSCP debug log to `bridge/debug` (not retained)
+    pub fn debug_sink(&self) -> DebugSink {
+        let client = self.client.client.clone();
+        let topic = format!("{}/bridge/debug", self.root_topic);
+        Box::new(move |text| {
+            // Best effort, debug output must never fail a query
+            let _ = client.send(&topic, text, QoS::AtMostOnce, false);
+        })
+    }
+
+    /// Connection changes of the event loop since the last call
+    pub fn connection_events(&self) -> Vec<ConnectionEvent> {
+        self.client.link.events()
+    }
+
+    /// Whether the event loop is currently without a broker connection
+    pub fn connection_lost(&self) -> bool {
+        self.client.link.is_lost()
+    }
+
+    /// Publish counters since the last call
+    pub fn take_publish_stats(&self) -> PublishStats {
+        self.client.metrics.take()
+    }
+
+    /// Wait up to `timeout` for the next command received on `command/#`
+    pub fn wait_for_command(&self, timeout: Duration) -> Option<IncomingCommand> {
+        match self.commands.recv_timeout(timeout) {
+            Ok(command) => Some(command),
+            Err(RecvTimeoutError::Timeout) => None,
+            Err(RecvTimeoutError::Disconnected) => {
+                // Event loop is gone, behave like a plain sleep
+                thread::sleep(timeout);
+                None
+            }
+        }
+    }
+}
+
+impl Publisher for MqttPublisher {
+    fn transport(&self) -> &dyn Transport {
+        &self.client
+    }
+
+    fn root_topic(&self) -> &str {
+        &self.root_topic
+    }
+
+    fn device_id(&self) -> &str {
+        &self.device_id
+    }
+
+    fn payload_format(&self) -> &PayloadFormat {
+        &self.payload_format
+    }
+
+    fn topics(&self) -> &TopicsConfig {
+        &self.topics
+    }
+
+    fn topic_renames(&self) -> &BTreeMap<String, String> {
+        &self.renames
     }
 }
 
@@ -260,3 +877,77 @@ impl Drop for MqttPublisher {
         tracing::info!("MQTT client disconnected");
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rumqttc::{ConnAck, ConnectReturnCode, Publish};
+
+    #[test]
+    fn test_event_loop_resubscribes_after_reconnect() {
+        let (reporter, link) = link::link("MQTT");
+        let (commands_tx, commands) = mpsc::channel();
+        let mut subscriptions = Vec::new();
+        let mut event_loop = EventLoop {
+            reporter,
+            metrics: Arc::new(PublishMetrics::default()),
+            command_prefix: Some("e3dc/S10E-123/command/".to_string()),
+            commands: commands_tx,
+            subscribe: |topic: &str| {
+                subscriptions.push(topic.to_string());
+                Ok(())
+            },
+        };
+        let connack = || {
+            Event::Incoming(Packet::ConnAck(ConnAck::new(
+                ConnectReturnCode::Success,
+                false,
+            )))
+        };
+        let command = || {
+            Event::Incoming(Packet::Publish(Publish::new(
+                "e3dc/S10E-123/command/refresh",
+                QoS::AtLeastOnce,
+                "1",
+            )))
+        };
+
+        assert!(!event_loop.handle(Ok(connack())));
+        // Broker restarted: the clean session lost the subscription
+        assert!(event_loop.handle(Err(ConnectionError::NetworkTimeout)));
+        assert!(link.is_lost());
+        assert!(!event_loop.handle(Ok(connack())));
+        assert!(!event_loop.handle(Ok(command())));
+        drop(event_loop);
+
+        assert_eq!(subscriptions, ["e3dc/S10E-123/command/#"; 2]);
+        assert_eq!(
+            commands.try_iter().collect::<Vec<_>>(),
+            [IncomingCommand {
+                name: "refresh".to_string(),
+                payload: "1".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_mqtt_options_keep_broker_name() {
+        let config = |host: &str| -> Config {
+            toml::from_str(&format!(
+                "[e3dc]\nhost = \"e3dc\"\nusername = \"u\"\npassword = \"p\"\nkey = \"k\"\n\n\
+                 [mqtt]\nhost = \"{}\"\nusername = \"\"\npassword = \"\"\n",
+                host
+            ))
+            .unwrap()
+        };
+        let options = mqtt_options(&config("broker.invalid"), "id".to_string()).unwrap();
+        assert_eq!(
+            options.broker_address(),
+            ("broker.invalid".to_string(), 1883)
+        );
+        let options = mqtt_options(&config("fd00::10"), "id".to_string()).unwrap();
+        assert_eq!(options.broker_address(), ("[fd00::10]".to_string(), 1883));
+        assert_eq!(connect_timeout_secs(Duration::from_millis(1500)), 2);
+        assert_eq!(connect_timeout_secs(Duration::ZERO), 1);
+    }
+}
@@ -0,0 +1,274 @@
+//! Broker self-test (`selftest` subcommand)
+//!
+//! Checks what the bridge needs from the broker: connecting, subscribing,
+//! publishing with the QoS levels the bridge uses and a working last will.
+//! MQTT 3.1.1 brokers drop publishes denied by an ACL silently, so every
+//! probe is published below `{root}/selftest/` and must arrive back on the
+//! test's own subscription to pass.
+
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use rumqttc::{Connection, ConnectionError, Event, LastWill, Packet, QoS, SubscribeReasonCode};
+
+use crate::config::Config;
+use crate::mqtt::publisher::{mqtt_client, mqtt_options};
+
+/// Outcome of one check
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Check {
+    pub name: &'static str,
+    pub passed: bool,
+    pub detail: String,
+}
+
+impl Check {
+    fn pass(name: &'static str, detail: impl Into<String>) -> Self {
+        Self {
+            name,
+            passed: true,
+            detail: detail.into(),
+        }
+    }
+
+    fn fail(name: &'static str, detail: impl Into<String>) -> Self {
+        Self {
+            name,
+            passed: false,
+            detail: detail.into(),
+        }
+    }
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mark = if self.passed { "✓" } else { "✗" };
+        write!(f, "{} {}: {}", mark, self.name, self.detail)
+    }
+}
+
+/// Probes published by the test, as the bridge publishes its topics
+const PROBES: [(&str, &str, QoS, bool); 2] = [
+    // bridge/debug
+    ("publish QoS 0", "qos0", QoS::AtMostOnce, false),
+    // State topics, events and the online status
+    ("publish QoS 1 retained", "qos1", QoS::AtLeastOnce, true),
+];
+
+/// Run all checks, waiting up to `timeout` for each broker response
+///
+/// Stops after the first failed connect or subscribe, the remaining checks
+/// can't be verified without them.
+pub fn run(config: &Config, timeout: Duration) -> Vec<Check> {
+    let id = std::process::id();
+    let base = format!("{}/selftest/{}", config.mqtt.root, id);
+    let client_id = format!("e3dc-mqtt-rs-selftest-{}", id);
+    let broker = format!("{}:{}", config.mqtt.host, config.mqtt.port);
+    let mut checks = Vec::new();
+
+    let options = match mqtt_options(config, client_id.clone()) {
+        Ok(options) => options,
+        Err(e) => {
+            checks.push(Check::fail("connect", e.to_string()));
+            return checks;
+        }
+    };
+    let (client, mut connection) = mqtt_client(config, options, 10);
+    let connect = connect_check(&mut connection, &broker, timeout);
+    let connected = connect.passed;
+    checks.push(connect);
+    if !connected {
+        return checks;
+    }
+
+    let filter = format!("{}/#", base);
+    if let Err(e) = client.subscribe(&filter, QoS::AtLeastOnce) {
+        checks.push(Check::fail("subscribe", e.to_string()));
+        return checks;
+    }
+    let subscribe = match wait_for(&mut connection, timeout, |packet| match packet {
+        Packet::SubAck(ack) => ack.return_codes.first().cloned(),
+        _ => None,
+    }) {
+        Ok(Some(code)) => subscribe_check(&filter, &code),
+        Ok(None) => Check::fail("subscribe", format!("no SUBACK within {:?}", timeout)),
+        Err(e) => Check::fail("subscribe", e.to_string()),
+    };
+    let subscribed = subscribe.passed;
+    checks.push(subscribe);
+    if !subscribed {
+        return checks;
+    }
+
+    for (name, suffix, qos, retain) in PROBES {
+        let topic = format!("{}/{}", base, suffix);
+        let token = format!("probe-{}", suffix);
+        let start = Instant::now();
+        if let Err(e) = client.publish(&topic, qos, retain, token.clone()) {
+            checks.push(Check::fail(name, e.to_string()));
+            continue;
+        }
+        let delivered = wait_for(&mut connection, timeout, |packet| match packet {
+            Packet::Publish(p) if p.topic == topic && p.payload == token.as_bytes() => Some(()),
+            _ => None,
+        });
+        checks.push(match delivered {
+            Ok(Some(())) => Check::pass(
+                name,
+                format!("delivered back in {} ms", start.elapsed().as_millis()),
+            ),
+            Ok(None) => Check::fail(name, not_delivered(config, &topic, timeout)),
+            Err(e) => Check::fail(name, e.to_string()),
+        });
+        if retain {
+            // Don't leave the probe behind
+            let _ = client.publish(&topic, qos, true, Vec::new());
+        }
+    }
+
+    checks.push(last_will_check(
+        config,
+        &mut connection,
+        &format!("{}/lwt", base),
+        format!("{}-lwt", client_id),
+        timeout,
+    ));
+
+    let _ = client.disconnect();
+    let _ = connection.recv_timeout(timeout);
+    checks
+}
+
+fn connect_check(connection: &mut Connection, broker: &str, timeout: Duration) -> Check {
+    match wait_for(connection, timeout, |packet| {
+        matches!(packet, Packet::ConnAck(_)).then_some(())
+    }) {
+        Ok(Some(())) => Check::pass("connect", format!("connected to {}", broker)),
+        Ok(None) => Check::fail(
+            "connect",
+            format!("no CONNACK from {} within {:?}", broker, timeout),
+        ),
+        Err(e) => match *e {
+            ConnectionError::ConnectionRefused(code) => Check::fail(
+                "connect",
+                format!(
+                    "{} refused the connection ({:?}), check username and password",
+                    broker, code
+                ),
+            ),
+            e => Check::fail("connect", format!("{}: {}", broker, e)),
+        },
+    }
+}
+
+fn subscribe_check(filter: &str, code: &SubscribeReasonCode) -> Check {
+    match code {
+        SubscribeReasonCode::Success(QoS::AtMostOnce) => Check::fail(
+            "subscribe",
+            format!(
+                "{} granted with QoS 0 only, commands may get lost",
+                filter
+            ),
+        ),
+        SubscribeReasonCode::Success(qos) => {
+            Check::pass("subscribe", format!("{} granted with {:?}", filter, qos))
+        }
+        SubscribeReasonCode::Failure => Check::fail(
+            "subscribe",
+            format!(
+                "{} denied by the broker, the ACL must allow subscribing below the root (needed for commands)",
+                filter
+            ),
+        ),
+    }
+}
+
+fn not_delivered(config: &Config, topic: &str, timeout: Duration) -> String {
+    format!(
+        "{} not delivered back within {:?}, the ACL probably denies publishing to {}/# (denied publishes are dropped silently)",
+        topic, timeout, config.mqtt.root
+    )
+}
+
+/// Connect a second client with a last will and drop it without DISCONNECT
+fn last_will_check(
+    config: &Config,
+    connection: &mut Connection,
+    topic: &str,
+    client_id: String,
+    timeout: Duration,
+) -> Check {
+    const NAME: &str = "last will";
+    let mut options = match mqtt_options(config, client_id) {
+        Ok(options) => options,
+        Err(e) => return Check::fail(NAME, e.to_string()),
+    };
+    options.set_last_will(LastWill::new(topic, "false", QoS::AtLeastOnce, false));
+    let (will_client, mut will_connection) = mqtt_client(config, options, 10);
+    match wait_for(&mut will_connection, timeout, |packet| {
+        matches!(packet, Packet::ConnAck(_)).then_some(())
+    }) {
+        Ok(Some(())) => {}
+        Ok(None) => return Check::fail(NAME, format!("no CONNACK within {:?}", timeout)),
+        Err(e) => return Check::fail(NAME, e.to_string()),
+    }
+    // Closing the socket without DISCONNECT makes the broker send the will
+    drop(will_connection);
+    drop(will_client);
+
+    match wait_for(connection, timeout, |packet| match packet {
+        Packet::Publish(p) if p.topic == topic => Some(()),
+        _ => None,
+    }) {
+        Ok(Some(())) => Check::pass(NAME, "delivered after an unclean disconnect"),
+        Ok(None) => Check::fail(
+            NAME,
+            format!(
+                "not delivered within {:?}, online would stay true when the bridge dies",
+                timeout
+            ),
+        ),
+        Err(e) => Check::fail(NAME, e.to_string()),
+    }
+}
+
+/// Drive the event loop until `matches` accepts an incoming packet
+///
+/// Returns None on timeout.
+fn wait_for<T>(
+    connection: &mut Connection,
+    timeout: Duration,
+    mut matches: impl FnMut(&Packet) -> Option<T>,
+) -> Result<Option<T>, Box<ConnectionError>> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        match connection.recv_timeout(remaining) {
+            Ok(Ok(Event::Incoming(packet))) => {
+                if let Some(value) = matches(&packet) {
+                    return Ok(Some(value));
+                }
+            }
+            Ok(Ok(Event::Outgoing(_))) => {}
+            Ok(Err(e)) => return Err(Box::new(e)),
+            Err(_) => return Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_subscribe_check() {
+        let filter = "e3dc/selftest/1/#";
+        assert!(subscribe_check(filter, &SubscribeReasonCode::Success(QoS::AtLeastOnce)).passed);
+        assert!(!subscribe_check(filter, &SubscribeReasonCode::Success(QoS::AtMostOnce)).passed);
+        let denied = subscribe_check(filter, &SubscribeReasonCode::Failure);
+        assert!(!denied.passed);
+        assert!(denied
+            .to_string()
+            .starts_with("✗ subscribe: e3dc/selftest/1/# denied"));
+    }
+}
@@ -0,0 +1,196 @@
+//! Command subscription and dispatch
+//!
+//! Commands arrive on `{root}/{device-id}/command/{name}`. The [`Subscriber`]
+//! checks them against the `[commands]` ACL, hands them to the registered
+//! [`CommandHandler`] for the name and publishes the outcome as a
+//! [`CommandAck`] to `command_ack/{name}`, so senders don't have to guess
+//! whether a write went through:
+//!
+//! ```json
+//! {"time": "...", "command": "max_charge_power", "payload": "3000", "status": "applied", "message": "..."}
+//! ```
+//!
+//! Rejected commands are additionally published as `events/rejected_command`.
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tracing::{info, warn};
+
+use crate::config::CommandsConfig;
+use crate::errors::{CommandError, MqttError};
+use crate::events::RejectedCommandEvent;
+use crate::mqtt::commands::IncomingCommand;
+use crate::mqtt::Publisher;
+
+/// Executes the commands of one or more names on a target (e.g. the E3DC client)
+pub trait CommandHandler<C> {
+    /// Command names (topic suffix after `command/`) this handler accepts
+    fn names(&self) -> &[&'static str];
+
+    /// Validate and execute `command`, returns a short result message
+    fn handle(
+        &mut self,
+        command: &IncomingCommand,
+        target: &mut C,
+        publisher: &dyn Publisher,
+    ) -> Result<String, CommandError>;
+}
+
+/// Outcome of a command
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CommandStatus {
+    Applied,
+    /// Not allowed, unknown or invalid, nothing was written
+    Rejected,
+    /// Valid, but executing it failed
+    Failed,
+}
+
+/// Acknowledgement published to `command_ack/{name}` (not retained)
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CommandAck {
+    pub time: DateTime<Utc>,
+    pub command: String,
+    pub payload: String,
+    pub status: CommandStatus,
+    pub message: String,
+}
+
+/// Parse a publish below `prefix` (`{root}/command/`) into a command
+///
+/// Retained commands are ignored, they would be replayed on every restart.
+pub fn incoming_command(
+    prefix: &str,
+    topic: &str,
+    payload: &[u8],
+    retain: bool,
+) -> Option<IncomingCommand> {
+    let name = topic.strip_prefix(prefix)?;
+    if retain {
+        warn!("Ignoring retained command on {}", topic);
+        return None;
+    }
+    Some(IncomingCommand {
+        name: name.to_string(),
+        payload: String::from_utf8_lossy(payload).to_string(),
+    })
+}
+
+/// Routes incoming commands to the registered handlers
+pub struct Subscriber<C> {
+    acl: CommandsConfig,
+    handlers: Vec<Box<dyn CommandHandler<C>>>,
+}
+
+impl<C> Subscriber<C> {
+    pub fn new(acl: CommandsConfig) -> Self {
+        Self {
+            acl,
+            handlers: Vec::new(),
+        }
+    }
+
+    /// Register a handler, the first registered handler of a name wins
+    pub fn register(&mut self, handler: Box<dyn CommandHandler<C>>) {
+        self.handlers.push(handler);
+    }
+
+    /// Check, execute and acknowledge one command
+    ///
+    /// Rejected and failed commands are logged and acknowledged, they never
+    /// stop the bridge; only publishing the acknowledgement can fail.
+    pub fn dispatch<P: Publisher>(
+        &mut self,
+        command: IncomingCommand,
+        target: &mut C,
+        publisher: &P,
+    ) -> Result<CommandAck, MqttError> {
+        let result = if !self.acl.is_allowed(&command.name) {
+            Err(CommandError::NotAllowed(command.name.clone()))
+        } else {
+            match self
+                .handlers
+                .iter_mut()
+                .find(|handler| handler.names().contains(&command.name.as_str()))
+            {
+                Some(handler) => handler.handle(&command, target, publisher),
+                None => Err(CommandError::UnknownCommand(command.name.clone())),
+            }
+        };
+
+        let (status, message) = match result {
+            Ok(message) => {
+                info!("✓ Command '{}' applied", command.name);
+                (CommandStatus::Applied, message)
+            }
+            Err(e @ CommandError::Failed { .. }) => {
+                warn!("{}", e);
+                (CommandStatus::Failed, e.to_string())
+            }
+            Err(e) => {
+                warn!("Rejected command: {}", e);
+                publisher.publish_event(
+                    "rejected_command",
+                    &RejectedCommandEvent {
+                        time: Utc::now(),
+                        command: command.name.clone(),
+                        payload: command.payload.clone(),
+                        reason: e.to_string(),
+                    },
+                )?;
+                (CommandStatus::Rejected, e.to_string())
+            }
+        };
+
+        let ack = CommandAck {
+            time: Utc::now(),
+            command: command.name,
+            payload: command.payload,
+            status,
+            message,
+        };
+        publisher.publish_command_ack(&ack)?;
+        Ok(ack)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_incoming_command() {
+        let prefix = "e3dc/S10-123/command/";
+        assert_eq!(
+            incoming_command(
+                prefix,
+                "e3dc/S10-123/command/max_charge_power",
+                b"3000",
+                false
+            ),
+            Some(IncomingCommand {
+                name: "max_charge_power".to_string(),
+                payload: "3000".to_string(),
+            })
+        );
+        assert_eq!(
+            incoming_command(
+                prefix,
+                "e3dc/S10-123/command/max_charge_power",
+                b"3000",
+                true
+            ),
+            None
+        );
+        assert_eq!(
+            incoming_command(
+                prefix,
+                "e3dc/S10-123/command_ack/max_charge_power",
+                b"{}",
+                false
+            ),
+            None
+        );
+    }
+}
@@ -0,0 +1,86 @@
+//! Night-time suppression of zero PV noise
+//!
+//! Once PV production has been exactly zero for a configured period, the PV
+//! topics are frozen at their last published value until the first non-zero
+//! reading. `solar_production_excess` would otherwise follow the house
+//! consumption all night.
+
+use chrono::{DateTime, Duration, Utc};
+
+use crate::mqtt::Status;
+
+/// Tracks how long PV production has been zero
+#[derive(Debug)]
+pub struct ZeroPvSuppression {
+    after: Duration,
+    zero_since: Option<DateTime<Utc>>,
+    suppressed: bool,
+}
+
+impl ZeroPvSuppression {
+    pub fn new(after: Duration) -> Self {
+        Self {
+            after,
+            zero_since: None,
+            suppressed: false,
+        }
+    }
+
+    /// Feed the current PV production, returns whether PV topics are suppressed
+    pub fn update(&mut self, solar_production: f64, time: DateTime<Utc>) -> bool {
+        if solar_production != 0.0 {
+            if self.suppressed {
+                tracing::info!("PV production resumed, publishing PV topics again");
+            }
+            self.zero_since = None;
+            self.suppressed = false;
+            return false;
+        }
+        let since = *self.zero_since.get_or_insert(time);
+        if !self.suppressed && time - since >= self.after {
+            tracing::info!("PV production zero since {}, suppressing PV topics", since);
+            self.suppressed = true;
+        }
+        self.suppressed
+    }
+
+    /// Whether PV topics (and PV-only queries) are currently skipped
+    pub fn is_suppressed(&self) -> bool {
+        self.suppressed
+    }
+
+    /// Freeze the PV fields of `status` at the last published values while suppressed
+    pub fn apply(&mut self, status: &mut Status, last: Option<&Status>) {
+        if !status.is_known("solar_production") {
+            return;
+        }
+        let suppressed = self.update(status.solar_production, status.time);
+        if let (true, Some(last)) = (suppressed, last) {
+            status.solar_production = last.solar_production;
+            status.solar_production_excess = last.solar_production_excess;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_zero_pv_suppression() {
+        let mut suppression = ZeroPvSuppression::new(Duration::minutes(30));
+        let start = Utc.with_ymd_and_hms(2024, 1, 15, 17, 0, 0).unwrap();
+
+        assert!(!suppression.update(120.0, start));
+        assert!(!suppression.update(0.0, start + Duration::minutes(1)));
+        assert!(!suppression.update(0.0, start + Duration::minutes(30)));
+        assert!(suppression.update(0.0, start + Duration::minutes(31)));
+        assert!(suppression.update(0.0, start + Duration::hours(5)));
+        assert!(suppression.is_suppressed());
+
+        // First non-zero reading resumes immediately
+        assert!(!suppression.update(3.0, start + Duration::hours(14)));
+        assert!(!suppression.update(0.0, start + Duration::hours(15)));
+    }
+}
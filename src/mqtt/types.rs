@@ -1,13 +1,37 @@
-use chrono::{DateTime, Duration, Utc};
-use serde::Serialize;
+use chrono::{DateTime, Duration, NaiveDate, NaiveTime, Utc, Weekday};
+use serde::{Deserialize, Serialize};
 
-use crate::e3dc;
+use crate::e3dc::{self, EmergencyPowerState, IdlePeriodType};
+use crate::energy;
+use crate::events;
+use crate::forecast;
+use crate::rates;
 
-fn round(value: f64, decimals: i32) -> f64 {
+pub(crate) fn round(value: f64, decimals: i32) -> f64 {
     let multiplier = 10_f64.powi(decimals);
     (value * multiplier).round() / multiplier
 }
 
+/// `value`, else the placeholder default with `name` recorded in `unknown`
+fn known<T: Default>(unknown: &mut Vec<&'static str>, name: &'static str, value: Option<T>) -> T {
+    value.unwrap_or_else(|| {
+        unknown.push(name);
+        T::default()
+    })
+}
+
+/// Highest minus lowest cell voltage in mV, 0 without cells
+fn cell_imbalance(voltages: &[f64]) -> f64 {
+    let max = voltages.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    let min = voltages.iter().copied().fold(f64::INFINITY, f64::min);
+    if voltages.is_empty() {
+        0.0
+    } else {
+        round((max - min) * 1000.0, 1)
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct Status {
     pub time: DateTime<Utc>,
     pub additional: f64,
@@ -24,6 +48,9 @@ pub struct Status {
     pub solar_production_excess: f64,
     pub state_of_charge: f64,
     pub wb_consumption: f64,
+    pub emergency_power: EmergencyPowerState,
+    /// Fields without a value from the E3DC, they hold 0 and aren't published
+    pub unknown: Vec<&'static str>,
 }
 
 /// Splits a signed value into positive and negative parts.
@@ -40,34 +67,58 @@ fn split_val(value: f64) -> (f64, f64) {
 
 impl Status {
     pub fn from_e3dc(status: &e3dc::Status) -> Self {
-        let additional = -status.power_add;
+        let mut unknown = Vec::new();
+        let additional = -known(&mut unknown, "additional", status.power_add);
         // Split power_battery into charge/discharge (Python compatibility)
-        let (battery_charge, battery_discharge) = split_val(status.power_battery);
+        if status.power_battery.is_none() {
+            unknown.extend(["battery_charge", "battery_discharge"]);
+        }
+        let power_battery = known(&mut unknown, "battery_consumption", status.power_battery);
+        let (battery_charge, battery_discharge) = split_val(power_battery);
 
         // Split power_grid into from_grid/to_grid (Python compatibility)
         // power_grid > 0: consuming from grid, power_grid < 0: exporting to grid
-        let (consumption_from_grid, export_to_grid) = split_val(status.power_grid);
+        if status.power_grid.is_none() {
+            unknown.extend(["consumption_from_grid", "export_to_grid"]);
+        }
+        let power_grid = known(&mut unknown, "grid_production", status.power_grid);
+        let (consumption_from_grid, export_to_grid) = split_val(power_grid);
 
-        let solar_production_excess = status.power_pv - status.power_home;
+        if status.power_pv.is_none() || status.power_home.is_none() {
+            unknown.push("solar_production_excess");
+        }
+        let power_pv = known(&mut unknown, "solar_production", status.power_pv);
+        let power_home = known(&mut unknown, "house_consumption", status.power_home);
+        let solar_production_excess = power_pv - power_home;
 
         Status {
             time: status.time_stamp,
             additional,
-            autarky: round(status.autarky, 1),
+            autarky: round(known(&mut unknown, "autarky", status.autarky), 1),
             battery_charge,
             battery_discharge,
-            battery_consumption: status.power_battery,
+            battery_consumption: power_battery,
             consumption_from_grid,
             export_to_grid,
-            grid_production: status.power_grid,
-            house_consumption: status.power_home,
-            self_consumption: round(status.self_consumption, 1),
-            solar_production: status.power_pv,
+            grid_production: power_grid,
+            house_consumption: power_home,
+            self_consumption: round(
+                known(&mut unknown, "self_consumption", status.self_consumption),
+                1,
+            ),
+            solar_production: power_pv,
             solar_production_excess,
-            state_of_charge: status.battery_soc,
-            wb_consumption: status.power_wb,
+            state_of_charge: known(&mut unknown, "state_of_charge", status.battery_soc),
+            wb_consumption: known(&mut unknown, "wb_consumption", status.power_wb),
+            emergency_power: status.emergency_power_state,
+            unknown,
         }
     }
+
+    /// Whether `field` holds a value from the E3DC
+    pub fn is_known(&self, field: &str) -> bool {
+        !self.unknown.contains(&field)
+    }
 }
 
 #[derive(Serialize)]
@@ -84,7 +135,7 @@ pub struct SystemInfo<'a> {
     pub mac_address: &'a String,
     pub max_battery_charge_power: Option<u64>,    // W
     pub max_battery_discharge_power: Option<u64>, // W
-    pub model: &'static str,
+    pub model: &'a str,
     pub release: &'a String,
     pub serial: &'a String,
 
@@ -97,6 +148,10 @@ pub struct SystemInfo<'a> {
     // Weather regulation
     pub weather_forecast_mode: u64,
     pub weather_regulated_charge_enabled: bool,
+    // Device metadata, null if the firmware doesn't answer
+    pub production_date: Option<&'a str>,
+    pub platform_type: Option<u64>,
+    pub assembly_serial_number: Option<&'a str>,
 }
 
 impl<'a> SystemInfo<'a> {
@@ -111,12 +166,12 @@ impl<'a> SystemInfo<'a> {
             installed_peak_power: info.installed_peak_power,
             ip_address: &info.ip_address,
             max_ac_power: info.max_ac_power,
-            mac_address: info.mac_address,
+            mac_address: &info.mac_address,
             max_battery_charge_power: info.max_battery_charge_power,
             max_battery_discharge_power: info.max_battery_discharge_power,
-            model: info.model,
+            model: &info.model,
             release: &info.software_release,
-            serial: info.serial_number,
+            serial: &info.serial_number,
             discharge_start_power: info.discharge_start_power,
             max_charge_power: info.max_charge_power,
             max_discharge_power: info.max_discharge_power,
@@ -124,10 +179,14 @@ impl<'a> SystemInfo<'a> {
             power_save_enabled: info.power_save_enabled,
             weather_forecast_mode: info.weather_forecast_mode,
             weather_regulated_charge_enabled: info.weather_regulated_charge_enabled,
+            production_date: info.hardware.production_date.as_deref(),
+            platform_type: info.hardware.platform_type,
+            assembly_serial_number: info.hardware.assembly_serial_number.as_deref(),
         }
     }
 }
 
+#[derive(Debug, Clone)]
 pub struct DcbData {
     pub index: u64,
     // Current measurements
@@ -173,6 +232,7 @@ pub struct DcbData {
     // Cell data
     pub temperatures: Vec<f64>, // °C (from BAT::DCB_ALL_CELL_TEMPERATURES)
     pub voltages: Vec<f64>,     // V (from BAT::DCB_ALL_CELL_VOLTAGES)
+    pub cell_imbalance: f64,    // mV, highest minus lowest cell voltage
 }
 
 impl DcbData {
@@ -216,10 +276,12 @@ impl DcbData {
             voltage_avg_30s: round(data.voltage_avg_30s, 2),
             voltages: data.cell_voltages.iter().map(|v| round(*v, 2)).collect(),
             warning: data.warning,
+            cell_imbalance: cell_imbalance(&data.cell_voltages),
         }
     }
 }
 
+#[derive(Debug, Clone)]
 pub struct BatteryData {
     pub index: u64,
     pub time: DateTime<Utc>,
@@ -251,6 +313,9 @@ pub struct BatteryData {
     pub max_dcb_cell_temp: f64, // °C
     pub min_dcb_cell_temp: f64, // °C
 
+    // Highest cell imbalance of all DCBs
+    pub cell_imbalance: f64, // mV
+
     // Status and errors
     pub status_code: f64,
     pub error_code: f64,
@@ -270,43 +335,80 @@ pub struct BatteryData {
     // Operational state
     pub ready_for_shutdown: bool,
     pub training_mode: bool,
+
+    /// Fields without a value from the E3DC, they hold 0 and aren't published
+    pub unknown: Vec<&'static str>,
 }
 impl BatteryData {
     pub fn from_e3dc(data: &e3dc::BatteryData) -> Self {
+        let mut unknown = Vec::new();
+        let unknown = &mut unknown;
         Self {
             time: data.time_stamp,
-            asoc: data.asoc,
-            charge_cycles: data.charge_cycles,
-            current: round(data.current, 2),
+            asoc: known(unknown, "asoc", data.asoc),
+            cell_imbalance: data
+                .dcbs
+                .iter()
+                .map(|dcb| cell_imbalance(&dcb.cell_voltages))
+                .fold(0.0, f64::max),
+            charge_cycles: known(unknown, "charge_cycles", data.charge_cycles),
+            current: round(known(unknown, "current", data.current), 2),
             dcb_count: data.dcb_count,
             dcbs: data.dcbs.iter().map(DcbData::from_e3dc).collect(),
-            design_capacity: data.design_capacity,
+            design_capacity: known(unknown, "design_capacity", data.design_capacity),
             device_name: data.device_name.clone(),
-            eod_voltage: data.eod_voltage,
-            error_code: data.error_code,
-            fcc: data.fcc,
+            eod_voltage: known(unknown, "eod_voltage", data.eod_voltage),
+            error_code: known(unknown, "error_code", data.error_code),
+            fcc: known(unknown, "fcc", data.fcc),
             index: data.index,
-            max_battery_voltage: round(data.max_bat_voltage, 2),
-            max_charge_current: data.max_charge_current,
-            max_discharge_current: data.max_discharge_current,
-            max_dcb_cell_temp: round(data.max_dcb_cell_temp, 2),
-            min_dcb_cell_temp: round(data.min_dcb_cell_temp, 2),
-            module_voltage: round(data.module_voltage, 2),
-            rc: round(data.rc, 2),
-            ready_for_shutdown: data.ready_for_shutdown,
-            rsoc: round(data.rsoc, 2),
-            rsoc_real: round(data.rsoc_real, 2),
-            status_code: data.status_code,
-            terminal_voltage: round(data.terminal_voltage, 2),
-            total_use_time: data.total_use_time,
-            total_discharge_time: data.total_discharge_time,
-            training_mode: data.training_mode,
-            usable_capacity: round(data.usable_capacity, 2),
-            usable_remaining_capacity: round(data.usable_remaining_capacity, 2),
+            max_battery_voltage: round(
+                known(unknown, "max_battery_voltage", data.max_bat_voltage),
+                2,
+            ),
+            max_charge_current: known(unknown, "max_charge_current", data.max_charge_current),
+            max_discharge_current: known(
+                unknown,
+                "max_discharge_current",
+                data.max_discharge_current,
+            ),
+            max_dcb_cell_temp: round(
+                known(unknown, "max_dcb_cell_temp", data.max_dcb_cell_temp),
+                2,
+            ),
+            min_dcb_cell_temp: round(
+                known(unknown, "min_dcb_cell_temp", data.min_dcb_cell_temp),
+                2,
+            ),
+            module_voltage: round(known(unknown, "module_voltage", data.module_voltage), 2),
+            rc: round(known(unknown, "rc", data.rc), 2),
+            ready_for_shutdown: known(unknown, "ready_for_shutdown", data.ready_for_shutdown),
+            rsoc: round(known(unknown, "rsoc", data.rsoc), 2),
+            rsoc_real: round(known(unknown, "rsoc_real", data.rsoc_real), 2),
+            status_code: known(unknown, "status_code", data.status_code),
+            terminal_voltage: round(known(unknown, "terminal_voltage", data.terminal_voltage), 2),
+            total_use_time: known(unknown, "total_use_time", data.total_use_time),
+            total_discharge_time: known(unknown, "total_discharge_time", data.total_discharge_time),
+            training_mode: known(unknown, "training_mode", data.training_mode),
+            usable_capacity: round(known(unknown, "usable_capacity", data.usable_capacity), 2),
+            usable_remaining_capacity: round(
+                known(
+                    unknown,
+                    "usable_remaining_capacity",
+                    data.usable_remaining_capacity,
+                ),
+                2,
+            ),
+            unknown: std::mem::take(unknown),
         }
     }
+
+    /// Whether `field` holds a value from the E3DC
+    pub fn is_known(&self, field: &str) -> bool {
+        !self.unknown.contains(&field)
+    }
 }
 
+#[derive(Debug, Clone)]
 pub struct DailyStatistics {
     pub time: DateTime<Utc>,
     pub autarky_today: f64,               // %
@@ -340,3 +442,557 @@ impl DailyStatistics {
         }
     }
 }
+
+/// Currently applied charge limits, published to `{status}/limits`
+pub struct ChargeLimits {
+    pub time: DateTime<Utc>,
+    pub used_charge_limit: f64,             // W
+    pub bat_charge_limit: f64,              // W
+    pub dcdc_charge_limit: f64,             // W
+    pub user_charge_limit: f64,             // W
+    pub used_discharge_limit: f64,          // W
+    pub bat_discharge_limit: f64,           // W
+    pub dcdc_discharge_limit: f64,          // W
+    pub user_discharge_limit: f64,          // W
+    pub remaining_bat_charge_power: f64,    // W
+    pub remaining_bat_discharge_power: f64, // W
+    /// The effective limit is below what the battery could take
+    pub charge_limited: bool,
+    pub discharge_limited: bool,
+    /// Fields the firmware doesn't answer, they hold 0 and aren't published
+    pub unknown: Vec<&'static str>,
+}
+
+impl ChargeLimits {
+    pub fn from_e3dc(limits: &e3dc::ChargeLimits) -> Self {
+        let mut unknown = Vec::new();
+        let limited = |used: Option<f64>, battery: Option<f64>| Some(used? < battery?);
+        let charge_limited = limited(limits.used_charge_limit, limits.bat_charge_limit);
+        let discharge_limited = limited(limits.used_discharge_limit, limits.bat_discharge_limit);
+        let unknown = &mut unknown;
+        Self {
+            time: limits.time_stamp,
+            used_charge_limit: known(unknown, "used_charge_limit", limits.used_charge_limit),
+            bat_charge_limit: known(unknown, "bat_charge_limit", limits.bat_charge_limit),
+            dcdc_charge_limit: known(unknown, "dcdc_charge_limit", limits.dcdc_charge_limit),
+            user_charge_limit: known(unknown, "user_charge_limit", limits.user_charge_limit),
+            used_discharge_limit: known(
+                unknown,
+                "used_discharge_limit",
+                limits.used_discharge_limit,
+            ),
+            bat_discharge_limit: known(unknown, "bat_discharge_limit", limits.bat_discharge_limit),
+            dcdc_discharge_limit: known(
+                unknown,
+                "dcdc_discharge_limit",
+                limits.dcdc_discharge_limit,
+            ),
+            user_discharge_limit: known(
+                unknown,
+                "user_discharge_limit",
+                limits.user_discharge_limit,
+            ),
+            remaining_bat_charge_power: known(
+                unknown,
+                "remaining_bat_charge_power",
+                limits.remaining_bat_charge_power,
+            ),
+            remaining_bat_discharge_power: known(
+                unknown,
+                "remaining_bat_discharge_power",
+                limits.remaining_bat_discharge_power,
+            ),
+            charge_limited: known(unknown, "charge_limited", charge_limited),
+            discharge_limited: known(unknown, "discharge_limited", discharge_limited),
+            unknown: std::mem::take(unknown),
+        }
+    }
+}
+
+/// Live data of one PVI (inverter), published to `{status}/pvi:{index}`
+pub struct PviData {
+    pub index: u64,
+    pub time: DateTime<Utc>,
+    pub on_grid: bool,
+    pub ac_power: f64, // W
+    pub dc_power: f64, // W
+    pub strings: Vec<PviString>,
+}
+
+pub struct PviString {
+    pub index: u64,
+    pub dc_power: f64,   // W
+    pub dc_voltage: f64, // V
+    pub dc_current: f64, // A
+}
+
+impl PviData {
+    pub fn from_e3dc(pvi: &e3dc::PviData) -> Self {
+        Self {
+            index: pvi.index,
+            time: pvi.time_stamp,
+            on_grid: pvi.on_grid,
+            ac_power: pvi.ac_power,
+            dc_power: pvi.dc_power,
+            strings: pvi
+                .strings
+                .iter()
+                .map(|string| PviString {
+                    index: string.index,
+                    dc_power: string.dc_power,
+                    dc_voltage: string.dc_voltage,
+                    dc_current: string.dc_current,
+                })
+                .collect(),
+        }
+    }
+}
+
+/// DCDC converter data, published to `{status}/dcdc:{index}`
+pub struct DcdcData {
+    pub index: u64,
+    pub time: DateTime<Utc>,
+    pub battery_current: f64, // A
+    pub battery_voltage: f64, // V
+    pub battery_power: f64,   // W
+    pub dcl_current: f64,     // A
+    pub dcl_voltage: f64,     // V
+    pub dcl_power: f64,       // W
+    pub status: String,
+    pub state: u64,
+    pub substate: u64,
+    pub error_code: u64,
+    pub warning_code: u64,
+}
+
+impl DcdcData {
+    pub fn from_e3dc(dcdc: &e3dc::DcdcData) -> Self {
+        Self {
+            index: dcdc.index,
+            time: dcdc.time_stamp,
+            battery_current: round(dcdc.battery_current, 2),
+            battery_voltage: round(dcdc.battery_voltage, 2),
+            battery_power: round(dcdc.battery_power, 0),
+            dcl_current: round(dcdc.dcl_current, 2),
+            dcl_voltage: round(dcdc.dcl_voltage, 2),
+            dcl_power: round(dcdc.dcl_power, 0),
+            status: dcdc.status.clone(),
+            state: dcdc.state,
+            substate: dcdc.substate,
+            error_code: dcdc.error_code,
+            warning_code: dcdc.warning_code,
+        }
+    }
+}
+
+/// Phase balancing state, published to `{status}/phases`
+pub struct PhaseBalance {
+    pub time: DateTime<Utc>,
+    pub l1_balanced: bool,
+    pub l2_balanced: bool,
+    pub l3_balanced: bool,
+    pub power_l1: f64, // W
+    pub power_l2: f64, // W
+    pub power_l3: f64, // W
+}
+
+impl PhaseBalance {
+    pub fn from_e3dc(balance: &e3dc::PhaseBalance) -> Self {
+        Self {
+            time: balance.time_stamp,
+            l1_balanced: balance.is_balanced(1),
+            l2_balanced: balance.is_balanced(2),
+            l3_balanced: balance.is_balanced(3),
+            power_l1: balance.power_l1,
+            power_l2: balance.power_l2,
+            power_l3: balance.power_l3,
+        }
+    }
+}
+
+/// Grid export against the configured limit, published below the status topic
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExportLimitUsage {
+    pub export_limit: f64,       // W
+    pub export_limit_usage: f64, // % of the limit
+}
+
+impl ExportLimitUsage {
+    pub fn new(export: f64, limit: f64) -> Self {
+        Self {
+            export_limit: limit,
+            export_limit_usage: if limit > 0.0 {
+                round(export / limit * 100.0, 1)
+            } else {
+                0.0
+            },
+        }
+    }
+}
+
+/// Derived battery rates, published below the status topic
+#[derive(Debug, Clone, PartialEq)]
+pub struct BatteryRates {
+    pub soc_rate: f64,      // %/h
+    pub time_to_full: f64,  // min, 0 while not charging
+    pub time_to_empty: f64, // min, 0 while not discharging
+}
+
+impl BatteryRates {
+    /// None without SOC or battery power
+    pub fn new(soc_rate: f64, status: &e3dc::Status, capacity_wh: f64) -> Option<Self> {
+        let (soc, power) = (status.battery_soc?, status.power_battery?);
+        Some(Self {
+            soc_rate: round(soc_rate, 1),
+            time_to_full: round(rates::time_to_full(soc, power, capacity_wh), 0),
+            time_to_empty: round(rates::time_to_empty(soc, power, capacity_wh), 0),
+        })
+    }
+}
+
+/// Energy integrated by the bridge, published next to the `*_today` DB sums
+#[derive(Debug, Clone, PartialEq)]
+pub struct IntegratedEnergy {
+    pub solar_production_energy_integrated: f64,      // Wh
+    pub house_consumption_energy_integrated: f64,     // Wh
+    pub battery_charge_energy_integrated: f64,        // Wh
+    pub battery_discharge_energy_integrated: f64,     // Wh
+    pub export_to_grid_energy_integrated: f64,        // Wh
+    pub consumption_from_grid_energy_integrated: f64, // Wh
+}
+
+impl IntegratedEnergy {
+    pub fn new(totals: &energy::EnergyTotals) -> Self {
+        Self {
+            solar_production_energy_integrated: round(totals.solar_production, 0),
+            house_consumption_energy_integrated: round(totals.house_consumption, 0),
+            battery_charge_energy_integrated: round(totals.battery_charge, 0),
+            battery_discharge_energy_integrated: round(totals.battery_discharge, 0),
+            export_to_grid_energy_integrated: round(totals.export_to_grid, 0),
+            consumption_from_grid_energy_integrated: round(totals.consumption_from_grid, 0),
+        }
+    }
+}
+
+/// Emergency power usage, published below `status_sums/emergency_power`
+#[derive(Debug, Clone, PartialEq)]
+pub struct EmergencyPowerUsage {
+    pub switchovers: u64,
+    pub island_time: u64,   // s
+    pub island_energy: f64, // Wh
+}
+
+impl EmergencyPowerUsage {
+    pub fn new(usage: &events::EmergencyPowerUsage) -> Self {
+        Self {
+            switchovers: usage.switchovers,
+            island_time: usage.island_seconds as u64,
+            island_energy: round(usage.island_energy, 0),
+        }
+    }
+}
+
+/// Emergency power capabilities, published below `emergency_power`
+#[derive(Debug, Clone, PartialEq)]
+pub struct EmergencyPowerCapabilities {
+    pub possible: bool,
+    pub ready_for_switch: bool,
+    pub power_off_possible: bool,
+    pub reserve_configured: bool,
+    pub reserve_percent: f64,     // %
+    pub reserve_energy: f64,      // Wh
+    pub reserve_held_energy: f64, // Wh
+
+    /// Fields without a value from the E3DC, they hold 0 and aren't published
+    pub unknown: Vec<&'static str>,
+}
+
+impl EmergencyPowerCapabilities {
+    pub fn from_e3dc(capabilities: &e3dc::EmergencyPowerCapabilities) -> Self {
+        let mut unknown = Vec::new();
+        let unknown = &mut unknown;
+        Self {
+            possible: known(unknown, "possible", capabilities.possible),
+            ready_for_switch: known(unknown, "ready_for_switch", capabilities.ready_for_switch),
+            power_off_possible: known(
+                unknown,
+                "power_off_possible",
+                capabilities.power_off_possible,
+            ),
+            reserve_configured: known(
+                unknown,
+                "reserve_configured",
+                capabilities.reserve_configured,
+            ),
+            reserve_percent: round(
+                known(unknown, "reserve_percent", capabilities.reserve_percent),
+                1,
+            ),
+            reserve_energy: round(
+                known(unknown, "reserve_energy", capabilities.reserve_energy),
+                0,
+            ),
+            reserve_held_energy: round(
+                known(
+                    unknown,
+                    "reserve_held_energy",
+                    capabilities.reserve_held_energy,
+                ),
+                0,
+            ),
+            unknown: std::mem::take(unknown),
+        }
+    }
+}
+
+/// Network settings of the unit, published below `network`
+#[derive(Debug, Clone, PartialEq)]
+pub struct NetworkConfig {
+    pub dhcp: bool,
+    pub ip_address: String,
+    pub subnet_mask: String,
+    pub gateway: String,
+    pub dns: String,
+}
+
+impl NetworkConfig {
+    pub fn from_e3dc(network: &e3dc::NetworkConfig) -> Self {
+        Self {
+            dhcp: network.dhcp,
+            ip_address: network.ip_address.clone(),
+            subnet_mask: network.subnet_mask.clone(),
+            gateway: network.gateway.clone(),
+            dns: network.dns.clone(),
+        }
+    }
+}
+
+/// PV forecast, published below `forecast`
+#[derive(Debug, Clone, PartialEq)]
+pub struct PvForecast {
+    pub energy_today: f64,    // Wh
+    pub energy_tomorrow: f64, // Wh
+}
+
+impl PvForecast {
+    /// Energy of `today` and the day after, 0 for days the forecast doesn't cover
+    pub fn new(forecast: &forecast::PvForecast, today: NaiveDate) -> Self {
+        let energy = |day: Option<NaiveDate>| {
+            day.and_then(|day| forecast.energy(day))
+                .unwrap_or_default()
+                .round()
+        };
+        Self {
+            energy_today: energy(Some(today)),
+            energy_tomorrow: energy(today.succ_opt()),
+        }
+    }
+}
+
+/// Clock of the unit, published below `clock`
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeviceClock {
+    pub utc_time: DateTime<Utc>,
+    /// Wall clock time in `time_zone`, without offset (`2024-03-01T13:00:00`)
+    pub local_time: String,
+    pub time_zone: String,
+    pub offset: f64, // s, unit ahead of the host if positive
+}
+
+impl DeviceClock {
+    pub fn from_e3dc(clock: &e3dc::DeviceClock) -> Self {
+        Self {
+            utc_time: clock.utc_time,
+            local_time: clock.local_time.format("%Y-%m-%dT%H:%M:%S").to_string(),
+            time_zone: clock.time_zone.clone(),
+            offset: round(clock.offset().num_milliseconds() as f64 / 1000.0, 0),
+        }
+    }
+}
+
+/// Charge cycle trend of one battery, published below its battery topic
+#[derive(Debug, Clone, PartialEq)]
+pub struct CycleTrend {
+    pub index: u64,
+    pub cycles_per_day: f64,
+    pub cycles_per_month: f64,
+}
+
+impl CycleTrend {
+    pub fn new(index: u64, (per_day, per_month): (f64, f64)) -> Self {
+        Self {
+            index,
+            cycles_per_day: round(per_day, 3),
+            cycles_per_month: round(per_month, 2),
+        }
+    }
+}
+
+/// Month-, year-to-date or lifetime totals (`status_sums_month`/`_year`/`_lifetime`)
+pub struct PeriodStatistics {
+    pub time: DateTime<Utc>,
+    pub autarky: f64,               // %
+    pub self_consumption: f64,      // %
+    pub solar_production: f64,      // Wh
+    pub house_consumption: f64,     // Wh
+    pub battery_charge: f64,        // Wh
+    pub battery_discharge: f64,     // Wh
+    pub battery_throughput: f64,    // Wh, charge + discharge
+    pub export_to_grid: f64,        // Wh
+    pub consumption_from_grid: f64, // Wh
+    pub start: DateTime<Utc>,
+}
+
+impl PeriodStatistics {
+    pub fn from_e3dc(stat: &e3dc::DailyStatistics) -> Self {
+        Self {
+            time: stat.time_stamp,
+            autarky: round(stat.autarky, 1),
+            self_consumption: round(stat.consumed_production, 1),
+            solar_production: stat.solar_production,
+            house_consumption: stat.consumption,
+            battery_charge: stat.bat_power_in,
+            battery_discharge: stat.bat_power_out,
+            battery_throughput: stat.bat_power_in + stat.bat_power_out,
+            export_to_grid: stat.grid_power_in,
+            consumption_from_grid: stat.grid_power_out,
+            start: stat.start,
+        }
+    }
+}
+
+/// Statistics of one complete day, element of the `{status_sums}/history` array
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DayStatistics {
+    pub date: NaiveDate,
+    pub autarky: f64,               // %
+    pub self_consumption: f64,      // %
+    pub solar_production: f64,      // Wh
+    pub house_consumption: f64,     // Wh
+    pub battery_charge: f64,        // Wh
+    pub battery_discharge: f64,     // Wh
+    pub export_to_grid: f64,        // Wh
+    pub consumption_from_grid: f64, // Wh
+    pub state_of_charge: f64,       // %
+}
+
+impl DayStatistics {
+    pub fn from_e3dc(stat: &e3dc::DailyStatistics) -> Self {
+        Self {
+            date: stat.start.date_naive(),
+            autarky: round(stat.autarky, 1),
+            self_consumption: round(stat.consumed_production, 1),
+            solar_production: stat.solar_production,
+            house_consumption: stat.consumption,
+            battery_charge: stat.bat_power_in,
+            battery_discharge: stat.bat_power_out,
+            export_to_grid: stat.grid_power_in,
+            consumption_from_grid: stat.grid_power_out,
+            state_of_charge: round(stat.state_of_charge, 1),
+        }
+    }
+}
+
+/// Today's curve in mean power per slot, published to `{status_sums}/curve`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DayCurve {
+    pub start: DateTime<Utc>,
+    pub slot_seconds: i64,
+    /// W per slot from `start` on, null for a slot without data
+    pub solar_production: Vec<Option<f64>>,
+    pub house_consumption: Vec<Option<f64>>,
+}
+
+impl DayCurve {
+    /// Converts the slot energies (Wh) to the mean power of each slot
+    pub fn from_e3dc(curve: &e3dc::DayCurve) -> Self {
+        let slot_seconds = curve.slot.num_seconds().max(1);
+        let per_hour = 3600.0 / slot_seconds as f64;
+        let len = curve
+            .slots
+            .iter()
+            .map(|slot| slot.index + 1)
+            .max()
+            .unwrap_or(0);
+        let mut solar_production = vec![None; len as usize];
+        let mut house_consumption = vec![None; len as usize];
+        for slot in &curve.slots {
+            let index = slot.index as usize;
+            solar_production[index] = Some(round(slot.solar_production * per_hour, 1));
+            house_consumption[index] = Some(round(slot.consumption * per_hour, 1));
+        }
+        Self {
+            start: curve.start,
+            slot_seconds,
+            solar_production,
+            house_consumption,
+        }
+    }
+}
+
+/// Idle period window as JSON, e.g.
+/// `{"type": "charge", "day": "monday", "start": "08:00", "end": "12:00"}`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IdlePeriod {
+    #[serde(rename = "type")]
+    pub period_type: String,
+    pub day: String,
+    pub start: String,
+    pub end: String,
+    #[serde(default = "default_active")]
+    pub active: bool,
+}
+
+fn default_active() -> bool {
+    true
+}
+
+const WEEKDAYS: [&str; 7] = [
+    "monday",
+    "tuesday",
+    "wednesday",
+    "thursday",
+    "friday",
+    "saturday",
+    "sunday",
+];
+
+impl IdlePeriod {
+    pub fn from_e3dc(period: &e3dc::IdlePeriod) -> Self {
+        Self {
+            period_type: match period.period_type {
+                IdlePeriodType::Charge => "charge",
+                IdlePeriodType::Discharge => "discharge",
+            }
+            .to_string(),
+            day: WEEKDAYS[period.day.num_days_from_monday() as usize].to_string(),
+            start: period.start.format("%H:%M").to_string(),
+            end: period.end.format("%H:%M").to_string(),
+            active: period.active,
+        }
+    }
+
+    /// Convert to the E3DC representation, validating type, day and times
+    pub fn to_e3dc(&self) -> Result<e3dc::IdlePeriod, String> {
+        let period_type = match self.period_type.to_lowercase().as_str() {
+            "charge" => IdlePeriodType::Charge,
+            "discharge" => IdlePeriodType::Discharge,
+            other => return Err(format!("invalid type '{}'", other)),
+        };
+        let day = self
+            .day
+            .parse::<Weekday>()
+            .map_err(|_| format!("invalid day '{}'", self.day))?;
+        let parse_time = |value: &str| {
+            NaiveTime::parse_from_str(value, "%H:%M")
+                .map_err(|_| format!("invalid time '{}', expected HH:MM", value))
+        };
+        Ok(e3dc::IdlePeriod {
+            period_type,
+            day,
+            start: parse_time(&self.start)?,
+            end: parse_time(&self.end)?,
+            active: self.active,
+        })
+    }
+}
@@ -0,0 +1,187 @@
+//! Host resolution for the E3DC and the MQTT broker
+//!
+//! Hosts are names or IP literals, IPv6 with or without brackets (`fd00::10`,
+//! `[fd00::10]`). The addresses of a dual-stack name are tried alternating
+//! between the families, starting with the one the resolver prefers (the
+//! ordering of RFC 8305 "happy eyeballs", tried one after another instead of
+//! racing), each with its own connect timeout: an unroutable address costs
+//! one timeout instead of the system's TCP timeout. rscp connects by host
+//! string, it gets the first address that accepted a connection. rumqttc gets
+//! the broker name and resolves it again on every reconnect, so a changed
+//! address is picked up; its addresses share one connect timeout.
+//!
+//! Connections can be bound to a network interface (Linux `SO_BINDTODEVICE`),
+//! for brokers only reachable through one interface of a multi-homed host.
+
+use std::io;
+use std::net::{IpAddr, SocketAddr, TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use socket2::{Domain, Protocol, Socket, Type};
+
+/// Host without the brackets of an IPv6 literal
+pub fn unbracket(host: &str) -> &str {
+    host.strip_prefix('[')
+        .and_then(|host| host.strip_suffix(']'))
+        .unwrap_or(host)
+}
+
+/// Host string of an address as the client libraries expect it, IPv6 in brackets
+pub fn host_string(ip: IpAddr) -> String {
+    match ip {
+        IpAddr::V4(ip) => ip.to_string(),
+        IpAddr::V6(ip) => format!("[{}]", ip),
+    }
+}
+
+/// Addresses of `host` in connection order
+pub fn resolve(host: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+    let host = unbracket(host);
+    let addresses = match host.parse::<IpAddr>() {
+        Ok(ip) => vec![SocketAddr::new(ip, port)],
+        Err(_) => (host, port).to_socket_addrs()?.collect(),
+    };
+    Ok(interleave(addresses))
+}
+
+/// Alternate the address families, keeping the order within each family
+fn interleave(addresses: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let Some(first) = addresses.first() else {
+        return addresses;
+    };
+    let first_v6 = first.is_ipv6();
+    let (mut preferred, mut other): (Vec<_>, Vec<_>) = addresses
+        .into_iter()
+        .partition(|address| address.is_ipv6() == first_v6);
+    let mut ordered = Vec::with_capacity(preferred.len() + other.len());
+    preferred.reverse();
+    other.reverse();
+    while !preferred.is_empty() || !other.is_empty() {
+        ordered.extend(preferred.pop());
+        ordered.extend(other.pop());
+    }
+    ordered
+}
+
+/// Connect to the first address of `host` that answers within `timeout`,
+/// through `interface` if set
+pub fn connect(
+    host: &str,
+    port: u16,
+    timeout: Duration,
+    interface: Option<&str>,
+) -> io::Result<TcpStream> {
+    let mut last_error = None;
+    for address in resolve(host, port)? {
+        match connect_address(&address, timeout, interface) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => {
+                tracing::debug!("{} not reachable: {}", address, e);
+                last_error = Some(e);
+            }
+        }
+    }
+    Err(last_error.unwrap_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} has no addresses", host),
+        )
+    }))
+}
+
+fn connect_address(
+    address: &SocketAddr,
+    timeout: Duration,
+    interface: Option<&str>,
+) -> io::Result<TcpStream> {
+    let Some(interface) = interface else {
+        return TcpStream::connect_timeout(address, timeout);
+    };
+    let socket = Socket::new(
+        Domain::for_address(*address),
+        Type::STREAM,
+        Some(Protocol::TCP),
+    )?;
+    bind_device(&socket, interface)?;
+    socket.connect_timeout(&(*address).into(), timeout)?;
+    Ok(socket.into())
+}
+
+#[cfg(target_os = "linux")]
+fn bind_device(socket: &Socket, interface: &str) -> io::Result<()> {
+    socket.bind_device(Some(interface.as_bytes()))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn bind_device(_socket: &Socket, _interface: &str) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "binding to an interface is only supported on Linux",
+    ))
+}
+
+/// Host string of the first address of `host` accepting a connection on `port`
+pub fn reachable_host(
+    host: &str,
+    port: u16,
+    timeout: Duration,
+    interface: Option<&str>,
+) -> io::Result<String> {
+    let stream = connect(host, port, timeout, interface)?;
+    Ok(host_string(stream.peer_addr()?.ip()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_resolve_order() {
+        assert_eq!(unbracket("[fd00::10]"), "fd00::10");
+        assert_eq!(unbracket("broker.local"), "broker.local");
+        assert_eq!(
+            resolve("[fd00::10]", 1883).unwrap(),
+            vec!["[fd00::10]:1883".parse().unwrap()]
+        );
+        assert_eq!(host_string("fd00::10".parse().unwrap()), "[fd00::10]");
+
+        let addresses: Vec<SocketAddr> = [
+            "[fd00::1]:1883",
+            "[fd00::2]:1883",
+            "10.0.0.1:1883",
+            "[fd00::3]:1883",
+        ]
+        .iter()
+        .map(|a| a.parse().unwrap())
+        .collect();
+        let ordered: Vec<String> = interleave(addresses)
+            .iter()
+            .map(|a| a.to_string())
+            .collect();
+        assert_eq!(
+            ordered,
+            vec![
+                "[fd00::1]:1883",
+                "10.0.0.1:1883",
+                "[fd00::2]:1883",
+                "[fd00::3]:1883"
+            ]
+        );
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        assert_eq!(
+            reachable_host("127.0.0.1", port, Duration::from_secs(1), None).unwrap(),
+            "127.0.0.1"
+        );
+        #[cfg(target_os = "linux")]
+        assert!(connect(
+            "127.0.0.1",
+            port,
+            Duration::from_secs(1),
+            Some("no-such-interface0")
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,200 @@
+//! Parquet files of the history export
+//!
+//! One row group per file with a column chunk per metric, written with the
+//! `parquet` crate built without its codecs and Arrow: the pages are
+//! uncompressed, which DuckDB, pandas and Spark read as they are.
+
+use std::fs::{self, File};
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{bail, Context};
+use parquet::basic::{LogicalType, Repetition, TimeUnit, Type as PhysicalType};
+use parquet::column::writer::ColumnWriter;
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::SerializedFileWriter;
+use parquet::format::MilliSeconds;
+use parquet::schema::types::Type;
+
+/// Values of one column, all columns of a table have the same length
+#[derive(Debug, Clone, PartialEq)]
+pub enum Column {
+    /// Milliseconds since the epoch (UTC), required
+    TimestampMillis(Vec<i64>),
+    Int64(Vec<Option<i64>>),
+    Double(Vec<Option<f64>>),
+}
+
+impl Column {
+    fn len(&self) -> usize {
+        match self {
+            Column::TimestampMillis(values) => values.len(),
+            Column::Int64(values) => values.len(),
+            Column::Double(values) => values.len(),
+        }
+    }
+
+    fn schema(&self, name: &str) -> parquet::errors::Result<Type> {
+        match self {
+            Column::TimestampMillis(_) => Type::primitive_type_builder(name, PhysicalType::INT64)
+                .with_repetition(Repetition::REQUIRED)
+                .with_logical_type(Some(LogicalType::Timestamp {
+                    is_adjusted_to_u_t_c: true,
+                    unit: TimeUnit::MILLIS(MilliSeconds {}),
+                }))
+                .build(),
+            Column::Int64(_) => Type::primitive_type_builder(name, PhysicalType::INT64)
+                .with_repetition(Repetition::OPTIONAL)
+                .build(),
+            Column::Double(_) => Type::primitive_type_builder(name, PhysicalType::DOUBLE)
+                .with_repetition(Repetition::OPTIONAL)
+                .build(),
+        }
+    }
+
+    /// Write the values, nulls of optional columns as definition level 0
+    fn write_to(&self, writer: &mut ColumnWriter<'_>) -> parquet::errors::Result<()> {
+        match (self, writer) {
+            (Column::TimestampMillis(values), ColumnWriter::Int64ColumnWriter(writer)) => {
+                writer.write_batch(values, None, None)?;
+            }
+            (Column::Int64(values), ColumnWriter::Int64ColumnWriter(writer)) => {
+                let defined: Vec<i64> = values.iter().flatten().copied().collect();
+                writer.write_batch(&defined, Some(&definition_levels(values)), None)?;
+            }
+            (Column::Double(values), ColumnWriter::DoubleColumnWriter(writer)) => {
+                let defined: Vec<f64> = values.iter().flatten().copied().collect();
+                writer.write_batch(&defined, Some(&definition_levels(values)), None)?;
+            }
+            _ => unreachable!("column writers follow the schema"),
+        }
+        Ok(())
+    }
+}
+
+fn definition_levels<T>(values: &[Option<T>]) -> Vec<i16> {
+    values.iter().map(|value| value.is_some() as i16).collect()
+}
+
+/// Write a Parquet file with the named columns atomically (temporary file,
+/// synced to disk before the rename)
+///
+/// Fails without touching `path` if the columns differ in length.
+pub fn write(path: &Path, columns: &[(String, Column)]) -> anyhow::Result<()> {
+    let rows = columns.first().map_or(0, |(_, column)| column.len());
+    if let Some((name, column)) = columns.iter().find(|(_, column)| column.len() != rows) {
+        bail!(
+            "Column {} has {} values, expected {}",
+            name,
+            column.len(),
+            rows
+        );
+    }
+    let fields = columns
+        .iter()
+        .map(|(name, column)| column.schema(name).map(Arc::new))
+        .collect::<parquet::errors::Result<Vec<_>>>()?;
+    let schema = Type::group_type_builder("schema")
+        .with_fields(fields)
+        .build()?;
+    let properties = WriterProperties::builder()
+        .set_created_by(concat!("e3dc-mqtt-rs version ", env!("CARGO_PKG_VERSION")).to_string())
+        .build();
+
+    let tmp = path.with_extension("tmp");
+    let mut file =
+        File::create(&tmp).with_context(|| format!("Failed to create {}", tmp.display()))?;
+    let mut writer = SerializedFileWriter::new(&mut file, Arc::new(schema), Arc::new(properties))?;
+    let mut row_group = writer.next_row_group()?;
+    for (_, column) in columns {
+        let mut column_writer = row_group
+            .next_column()?
+            .context("Fewer column writers than columns")?;
+        column.write_to(column_writer.untyped())?;
+        column_writer.close()?;
+    }
+    row_group.close()?;
+    writer.close()?;
+    file.sync_all()?;
+    fs::rename(&tmp, path).with_context(|| format!("Failed to rename {}", tmp.display()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+    use parquet::record::Field;
+
+    #[test]
+    fn test_read_back() {
+        let path =
+            std::env::temp_dir().join(format!("e3dc-parquet-{}.parquet", std::process::id()));
+        write(
+            &path,
+            &[
+                (
+                    "time".to_string(),
+                    Column::TimestampMillis(vec![1_709_294_400_000, 1_709_294_460_000, 0]),
+                ),
+                (
+                    "index".to_string(),
+                    Column::Int64(vec![Some(0), None, Some(1)]),
+                ),
+                (
+                    "soc".to_string(),
+                    Column::Double(vec![Some(50.5), Some(-1.25), None]),
+                ),
+            ],
+        )
+        .unwrap();
+
+        let reader = SerializedFileReader::new(File::open(&path).unwrap()).unwrap();
+        assert_eq!(reader.metadata().file_metadata().num_rows(), 3);
+        let rows: Vec<Vec<(String, Field)>> = reader
+            .get_row_iter(None)
+            .unwrap()
+            .map(|row| row.unwrap().into_columns())
+            .collect();
+        let named = |values: [Field; 3]| -> Vec<(String, Field)> {
+            ["time", "index", "soc"]
+                .into_iter()
+                .map(String::from)
+                .zip(values)
+                .collect()
+        };
+        assert_eq!(
+            rows,
+            vec![
+                named([
+                    Field::TimestampMillis(1_709_294_400_000),
+                    Field::Long(0),
+                    Field::Double(50.5),
+                ]),
+                named([
+                    Field::TimestampMillis(1_709_294_460_000),
+                    Field::Null,
+                    Field::Double(-1.25),
+                ]),
+                named([Field::TimestampMillis(0), Field::Long(1), Field::Null]),
+            ]
+        );
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_column_length_mismatch() {
+        let path =
+            std::env::temp_dir().join(format!("e3dc-parquet-short-{}.parquet", std::process::id()));
+        let error = write(
+            &path,
+            &[
+                ("time".to_string(), Column::TimestampMillis(vec![0, 1])),
+                ("soc".to_string(), Column::Double(vec![Some(50.5)])),
+            ],
+        )
+        .unwrap_err();
+        assert_eq!(error.to_string(), "Column soc has 1 values, expected 2");
+        assert!(!path.exists() && !path.with_extension("tmp").exists());
+    }
+}
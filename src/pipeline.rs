@@ -0,0 +1,135 @@
+//! Poller to sink pipeline
+//!
+//! The main loop polls the E3DC and hands every reading as a typed
+//! [`Message`] to the sinks, which run on their own thread behind a bounded
+//! channel. A slow sink (a disk that stalls, an export of a whole day)
+//! never delays a poll: while the queue is full new messages are dropped
+//! and counted instead of blocking the poller.
+//!
+//! The local history store is a sink; further consumers (exporters,
+//! buffering or rate-limited forwarders) implement [`Sink`] and are added
+//! to the pipeline without touching the poll loop.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::thread;
+
+use crate::mqtt::{BatteryData, DailyStatistics, Status};
+
+/// Reading of one poll
+#[derive(Debug, Clone)]
+pub enum Message {
+    Status(Status),
+    Statistics(DailyStatistics),
+    Batteries(Vec<BatteryData>),
+}
+
+impl Message {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Message::Status(_) => "status",
+            Message::Statistics(_) => "statistics",
+            Message::Batteries(_) => "batteries",
+        }
+    }
+}
+
+/// Consumer of the poll messages, runs on the pipeline thread
+pub trait Sink: Send {
+    fn name(&self) -> &'static str;
+
+    /// Handle a message, a failure is logged and the next message handled
+    fn consume(&mut self, message: &Message) -> anyhow::Result<()>;
+}
+
+/// Sending end of the pipeline
+pub struct Pipeline {
+    sender: SyncSender<Message>,
+    dropped: AtomicU64,
+}
+
+impl Pipeline {
+    /// Start the sink thread with room for `capacity` queued messages
+    pub fn spawn(mut sinks: Vec<Box<dyn Sink>>, capacity: usize) -> std::io::Result<Self> {
+        let (sender, receiver) = mpsc::sync_channel::<Message>(capacity);
+        thread::Builder::new()
+            .name("sinks".to_string())
+            .spawn(move || {
+                for message in receiver {
+                    for sink in &mut sinks {
+                        if let Err(e) = sink.consume(&message) {
+                            tracing::warn!(
+                                "{} sink failed on {}: {:#}",
+                                sink.name(),
+                                message.kind(),
+                                e
+                            );
+                        }
+                    }
+                }
+            })?;
+        Ok(Self {
+            sender,
+            dropped: AtomicU64::new(0),
+        })
+    }
+
+    /// Queue a message for the sinks, returns false if it was dropped
+    pub fn send(&self, message: Message) -> bool {
+        match self.sender.try_send(message) {
+            Ok(()) => true,
+            Err(TrySendError::Full(message)) => {
+                let dropped = self.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+                tracing::warn!(
+                    "Sinks are behind, dropped {} message ({} so far)",
+                    message.kind(),
+                    dropped
+                );
+                false
+            }
+            Err(TrySendError::Disconnected(message)) => {
+                tracing::warn!("Sink thread stopped, dropped {} message", message.kind());
+                false
+            }
+        }
+    }
+
+    /// Messages dropped because the queue was full
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::Sender;
+    use std::time::Duration;
+
+    struct Forward(Sender<&'static str>);
+
+    impl Sink for Forward {
+        fn name(&self) -> &'static str {
+            "forward"
+        }
+
+        fn consume(&mut self, message: &Message) -> anyhow::Result<()> {
+            self.0.send(message.kind())?;
+            anyhow::ensure!(!matches!(message, Message::Batteries(_)), "no batteries");
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_pipeline() {
+        let (sender, received) = mpsc::channel();
+        let pipeline = Pipeline::spawn(vec![Box::new(Forward(sender))], 4).unwrap();
+        // A failing message doesn't stop the sink
+        assert!(pipeline.send(Message::Batteries(Vec::new())));
+        assert!(pipeline.send(Message::Batteries(Vec::new())));
+        let timeout = Duration::from_secs(5);
+        assert_eq!(received.recv_timeout(timeout), Ok("batteries"));
+        assert_eq!(received.recv_timeout(timeout), Ok("batteries"));
+        assert_eq!(pipeline.dropped(), 0);
+    }
+}
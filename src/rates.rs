@@ -0,0 +1,151 @@
+//! Derived battery rates
+//!
+//! The SOC change per hour over a sliding window, the time until the
+//! battery is full or empty at the current battery power and a smoothed SOC,
+//! values dashboards otherwise approximate from noisy single readings.
+
+use std::collections::VecDeque;
+
+use chrono::{DateTime, Duration, Utc};
+
+/// SOC change per hour over a sliding window of status readings
+pub struct SocRate {
+    window: Duration,
+    samples: VecDeque<(DateTime<Utc>, f64)>,
+}
+
+impl SocRate {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            samples: VecDeque::new(),
+        }
+    }
+
+    /// Feed a reading, returns the change in %/h
+    ///
+    /// None until the readings span half the window, a single SOC step over a
+    /// few seconds would otherwise show as a huge rate.
+    pub fn update(&mut self, time: DateTime<Utc>, soc: f64) -> Option<f64> {
+        self.samples.push_back((time, soc));
+        while self
+            .samples
+            .front()
+            .is_some_and(|(t, _)| time - *t > self.window)
+        {
+            self.samples.pop_front();
+        }
+        let (first_time, first_soc) = *self.samples.front()?;
+        let span = time - first_time;
+        if span.is_zero() || span < self.window / 2 {
+            return None;
+        }
+        Some((soc - first_soc) / (span.num_milliseconds() as f64 / 3_600_000.0))
+    }
+}
+
+/// SOC for display and automation thresholds: the median over a sliding
+/// window, only followed once it moved by the hysteresis
+///
+/// The raw SOC bounces by 1 % between polls, a threshold at 80 % would
+/// otherwise trigger several times while the battery passes it.
+pub struct SmoothedSoc {
+    window: Duration,
+    hysteresis: f64,
+    samples: VecDeque<(DateTime<Utc>, f64)>,
+    value: Option<f64>,
+}
+
+impl SmoothedSoc {
+    pub fn new(window: Duration, hysteresis: f64) -> Self {
+        Self {
+            window,
+            hysteresis,
+            samples: VecDeque::new(),
+            value: None,
+        }
+    }
+
+    /// Feed a reading, returns the smoothed SOC
+    pub fn update(&mut self, time: DateTime<Utc>, soc: f64) -> f64 {
+        self.samples.push_back((time, soc));
+        while self
+            .samples
+            .front()
+            .is_some_and(|(t, _)| time - *t > self.window)
+        {
+            self.samples.pop_front();
+        }
+        let mut values: Vec<f64> = self.samples.iter().map(|(_, soc)| *soc).collect();
+        values.sort_by(f64::total_cmp);
+        // Mean of the two middle values for an even count
+        let median = (values[(values.len() - 1) / 2] + values[values.len() / 2]) / 2.0;
+        match self.value {
+            Some(value) if (median - value).abs() < self.hysteresis => value,
+            _ => {
+                self.value = Some(median);
+                median
+            }
+        }
+    }
+}
+
+/// Minutes until the battery is full at `power` W (charging), 0 otherwise
+pub fn time_to_full(soc: f64, power: f64, capacity_wh: f64) -> f64 {
+    if power <= 0.0 || capacity_wh <= 0.0 {
+        return 0.0;
+    }
+    capacity_wh * (100.0 - soc).max(0.0) / 100.0 / power * 60.0
+}
+
+/// Minutes until the battery is empty at `power` W (discharging, negative), 0 otherwise
+pub fn time_to_empty(soc: f64, power: f64, capacity_wh: f64) -> f64 {
+    if power >= 0.0 || capacity_wh <= 0.0 {
+        return 0.0;
+    }
+    capacity_wh * soc.max(0.0) / 100.0 / -power * 60.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_soc_rate() {
+        let start = Utc.with_ymd_and_hms(2024, 3, 1, 12, 0, 0).unwrap();
+        let mut rate = SocRate::new(Duration::minutes(30));
+        assert_eq!(rate.update(start, 50.0), None);
+        assert_eq!(rate.update(start + Duration::minutes(10), 52.0), None);
+        assert_eq!(rate.update(start + Duration::minutes(15), 53.0), Some(12.0));
+        // Readings older than the window drop out
+        assert_eq!(rate.update(start + Duration::minutes(40), 53.0), Some(2.0));
+    }
+
+    #[test]
+    fn test_smoothed_soc() {
+        let start = Utc.with_ymd_and_hms(2024, 3, 1, 12, 0, 0).unwrap();
+        let mut soc = SmoothedSoc::new(Duration::minutes(5), 1.0);
+        let at = |minutes| start + Duration::minutes(minutes);
+        assert_eq!(soc.update(at(0), 80.0), 80.0);
+        // Readings bouncing by 1 % don't move it
+        assert_eq!(soc.update(at(1), 81.0), 80.0);
+        assert_eq!(soc.update(at(2), 80.0), 80.0);
+        assert_eq!(soc.update(at(3), 79.0), 80.0);
+        assert_eq!(soc.update(at(4), 78.0), 80.0);
+        assert_eq!(soc.update(at(5), 78.0), 80.0);
+        // The first reading drops out of the window, the median moved by 1.5 %
+        assert_eq!(soc.update(at(6), 78.0), 78.5);
+        assert_eq!(soc.update(at(12), 78.0), 78.5);
+        assert_eq!(soc.update(at(13), 77.0), 77.5);
+    }
+
+    #[test]
+    fn test_time_to_full_and_empty() {
+        // 10 kWh, 50% left, 2.5 kW
+        assert_eq!(time_to_full(50.0, 2500.0, 10000.0), 120.0);
+        assert_eq!(time_to_empty(50.0, -2500.0, 10000.0), 120.0);
+        assert_eq!(time_to_full(50.0, -2500.0, 10000.0), 0.0);
+        assert_eq!(time_to_empty(50.0, 0.0, 10000.0), 0.0);
+    }
+}
@@ -0,0 +1,234 @@
+//! Time-based charge schedule (`[schedule]`)
+//!
+//! Translates the configured windows into EMS writes: a grid charge window
+//! starts a manual charge for the energy missing to the target SOC, a lock
+//! window limits the discharge power to 0 W and restores the previous limit
+//! when it ends. The EMS state is checked every status cycle while a window
+//! is active, so settings changed in the portal or app meanwhile are
+//! overwritten again.
+
+use chrono::NaiveTime;
+
+use crate::config::{ScheduleAction, ScheduleWindow};
+use crate::e3dc::{EmsControlState, PowerSettingsUpdate};
+use crate::errors::E3dcError;
+use crate::E3dcClient;
+
+/// EMS write decided by the [`Scheduler`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ScheduleCommand {
+    /// Charge this many Wh from the grid
+    StartManualCharge(u64),
+    StopManualCharge,
+    /// Power limits on, discharge power 0 W
+    LockDischarge,
+    /// Previous discharge limit, None if it is unknown (limits are switched off)
+    RestoreDischarge {
+        max_discharge_power: Option<u64>,
+        power_limits_used: bool,
+    },
+}
+
+impl ScheduleCommand {
+    /// Write the command to the E3DC
+    pub fn execute(&self, client: &mut E3dcClient) -> Result<(), E3dcError> {
+        match self {
+            ScheduleCommand::StartManualCharge(energy) => client.start_manual_charge(*energy),
+            ScheduleCommand::StopManualCharge => client.start_manual_charge(0),
+            ScheduleCommand::LockDischarge => client.set_power_settings(&PowerSettingsUpdate {
+                power_limits_used: Some(true),
+                max_discharge_power: Some(0),
+                ..Default::default()
+            }),
+            ScheduleCommand::RestoreDischarge {
+                max_discharge_power,
+                power_limits_used,
+            } => client.set_power_settings(&PowerSettingsUpdate {
+                power_limits_used: Some(*power_limits_used),
+                max_discharge_power: *max_discharge_power,
+                ..Default::default()
+            }),
+        }
+    }
+}
+
+pub struct Scheduler {
+    windows: Vec<ScheduleWindow>,
+    /// A manual charge was started by the schedule
+    charging: bool,
+    /// Discharge limit before the lock: (max_discharge_power, power_limits_used)
+    locked: Option<(Option<u64>, bool)>,
+    /// Grid charge windows do nothing (enough PV expected)
+    grid_charge_skipped: bool,
+}
+
+impl Scheduler {
+    pub fn new(windows: Vec<ScheduleWindow>) -> Self {
+        Self {
+            windows,
+            charging: false,
+            locked: None,
+            grid_charge_skipped: false,
+        }
+    }
+
+    /// Skip the grid charge windows, a running schedule charge is stopped
+    pub fn set_grid_charge_skipped(&mut self, skipped: bool) {
+        self.grid_charge_skipped = skipped;
+    }
+
+    /// Highest target SOC of the grid charge windows active at `time`
+    pub fn grid_charge_target(&self, time: NaiveTime) -> Option<f64> {
+        if self.grid_charge_skipped {
+            return None;
+        }
+        self.windows
+            .iter()
+            .filter(|window| window.contains(time))
+            .filter_map(|window| match window.action {
+                ScheduleAction::GridCharge { target_soc } => Some(target_soc),
+                ScheduleAction::LockDischarge => None,
+            })
+            .reduce(f64::max)
+    }
+
+    /// Whether a lock discharge window is active at `time`
+    pub fn discharge_locked(&self, time: NaiveTime) -> bool {
+        self.windows
+            .iter()
+            .any(|window| window.action == ScheduleAction::LockDischarge && window.contains(time))
+    }
+
+    /// Whether [`Scheduler::plan`] needs the EMS state at `time`
+    ///
+    /// Outside of all windows only after a window ended, to undo its writes.
+    pub fn is_active(&self, time: NaiveTime) -> bool {
+        self.charging || self.locked.is_some() || self.windows.iter().any(|w| w.contains(time))
+    }
+
+    /// Writes needed to bring the EMS in line with the windows active at `time`
+    ///
+    /// Assumes the returned commands are executed; a failed write is simply
+    /// retried with the next plan.
+    pub fn plan(
+        &mut self,
+        time: NaiveTime,
+        soc: f64,
+        capacity_wh: f64,
+        ems: &EmsControlState,
+    ) -> Vec<ScheduleCommand> {
+        let mut commands = Vec::new();
+
+        match self.grid_charge_target(time) {
+            Some(target) if soc < target => {
+                if !ems.manual_charge_active {
+                    let energy = (capacity_wh * (target - soc) / 100.0).round() as u64;
+                    if energy > 0 {
+                        commands.push(ScheduleCommand::StartManualCharge(energy));
+                        self.charging = true;
+                    }
+                }
+            }
+            _ => {
+                if self.charging && ems.manual_charge_active {
+                    commands.push(ScheduleCommand::StopManualCharge);
+                }
+                self.charging = false;
+            }
+        }
+
+        if self.discharge_locked(time) {
+            if self.locked.is_none() {
+                // Already locked when the window started (e.g. after a restart
+                // within the window): the previous limit is unknown
+                self.locked = Some(if ems.power_limits_used && ems.max_discharge_power == 0 {
+                    (None, false)
+                } else {
+                    (Some(ems.max_discharge_power), ems.power_limits_used)
+                });
+            }
+            if !ems.power_limits_used || ems.max_discharge_power != 0 {
+                commands.push(ScheduleCommand::LockDischarge);
+            }
+        } else if let Some((max_discharge_power, power_limits_used)) = self.locked.take() {
+            commands.push(ScheduleCommand::RestoreDischarge {
+                max_discharge_power,
+                power_limits_used,
+            });
+        }
+
+        commands
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(hour: u32, minute: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(hour, minute, 0).unwrap()
+    }
+
+    fn ems(manual_charge_active: bool, max_discharge_power: u64) -> EmsControlState {
+        EmsControlState {
+            manual_charge_active,
+            max_discharge_power,
+            power_limits_used: true,
+        }
+    }
+
+    #[test]
+    fn test_schedule_plan() {
+        let mut scheduler = Scheduler::new(vec![
+            ScheduleWindow {
+                start: time(23, 0),
+                end: time(5, 0),
+                action: ScheduleAction::GridCharge { target_soc: 80.0 },
+            },
+            ScheduleWindow {
+                start: time(17, 0),
+                end: time(20, 0),
+                action: ScheduleAction::LockDischarge,
+            },
+        ]);
+        assert!(!scheduler.is_active(time(12, 0)));
+        assert!(scheduler.is_active(time(2, 0)));
+
+        // 10 kWh battery at 30%: 5 kWh missing to 80%
+        assert_eq!(
+            scheduler.plan(time(23, 30), 30.0, 10000.0, &ems(false, 3000)),
+            vec![ScheduleCommand::StartManualCharge(5000)]
+        );
+        assert_eq!(
+            scheduler.plan(time(1, 0), 50.0, 10000.0, &ems(true, 3000)),
+            vec![]
+        );
+        // Window over before the target was reached
+        assert_eq!(
+            scheduler.plan(time(5, 0), 70.0, 10000.0, &ems(true, 3000)),
+            vec![ScheduleCommand::StopManualCharge]
+        );
+        assert!(!scheduler.is_active(time(5, 1)));
+
+        assert_eq!(
+            scheduler.plan(time(17, 0), 70.0, 10000.0, &ems(false, 3000)),
+            vec![ScheduleCommand::LockDischarge]
+        );
+        assert_eq!(
+            scheduler.plan(time(18, 0), 70.0, 10000.0, &ems(false, 0)),
+            vec![]
+        );
+        // Changed in the app meanwhile
+        assert_eq!(
+            scheduler.plan(time(19, 0), 70.0, 10000.0, &ems(false, 1500)),
+            vec![ScheduleCommand::LockDischarge]
+        );
+        assert_eq!(
+            scheduler.plan(time(20, 0), 70.0, 10000.0, &ems(false, 0)),
+            vec![ScheduleCommand::RestoreDischarge {
+                max_discharge_power: Some(3000),
+                power_limits_used: true,
+            }]
+        );
+    }
+}
@@ -0,0 +1,252 @@
+//! SOCKS5 relay for the MQTT connection (`[mqtt.socks5]`)
+//!
+//! rumqttc only speaks HTTP CONNECT to proxies, so the bridge listens on an
+//! ephemeral loopback port instead and points rumqttc at it. Every
+//! connection accepted there is forwarded through the SOCKS5 proxy to the
+//! configured broker, the broker's name is resolved by the proxy (RFC 1928,
+//! username/password authentication per RFC 1929). The target is fixed, the
+//! relay can't be used to reach anything but the broker.
+
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+use crate::config::Socks5Config;
+use crate::net;
+
+const VERSION: u8 = 5;
+const NO_AUTHENTICATION: u8 = 0x00;
+const USERNAME_PASSWORD: u8 = 0x02;
+const CONNECT: u8 = 0x01;
+const ADDRESS_IPV4: u8 = 0x01;
+const ADDRESS_DOMAIN: u8 = 0x03;
+const ADDRESS_IPV6: u8 = 0x04;
+
+fn proxy_error(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::ConnectionRefused, message)
+}
+
+/// Text of a CONNECT reply code
+fn reply_message(code: u8) -> &'static str {
+    match code {
+        0x01 => "general failure",
+        0x02 => "connection not allowed by ruleset",
+        0x03 => "network unreachable",
+        0x04 => "host unreachable",
+        0x05 => "connection refused",
+        0x06 => "TTL expired",
+        0x07 => "command not supported",
+        0x08 => "address type not supported",
+        _ => "unknown error",
+    }
+}
+
+/// Ask the proxy on `stream` to connect to `host:port`
+///
+/// Returns once the proxy reports the connection as established, the
+/// stream then carries the broker connection.
+pub fn handshake(
+    stream: &mut TcpStream,
+    host: &str,
+    port: u16,
+    credentials: Option<(&str, &str)>,
+) -> io::Result<()> {
+    let method = if credentials.is_some() {
+        USERNAME_PASSWORD
+    } else {
+        NO_AUTHENTICATION
+    };
+    stream.write_all(&[VERSION, 1, method])?;
+    let mut reply = [0u8; 2];
+    stream.read_exact(&mut reply)?;
+    if reply != [VERSION, method] {
+        return Err(proxy_error(format!(
+            "proxy refused authentication method {}",
+            method
+        )));
+    }
+
+    if let Some((username, password)) = credentials {
+        let mut request = vec![1, username.len() as u8];
+        request.extend_from_slice(username.as_bytes());
+        request.push(password.len() as u8);
+        request.extend_from_slice(password.as_bytes());
+        stream.write_all(&request)?;
+        stream.read_exact(&mut reply)?;
+        if reply[1] != 0 {
+            return Err(proxy_error("proxy authentication failed".to_string()));
+        }
+    }
+
+    let mut request = vec![VERSION, CONNECT, 0];
+    match net::unbracket(host).parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) => {
+            request.push(ADDRESS_IPV4);
+            request.extend_from_slice(&ip.octets());
+        }
+        Ok(IpAddr::V6(ip)) => {
+            request.push(ADDRESS_IPV6);
+            request.extend_from_slice(&ip.octets());
+        }
+        Err(_) => {
+            request.push(ADDRESS_DOMAIN);
+            request.push(host.len() as u8);
+            request.extend_from_slice(host.as_bytes());
+        }
+    }
+    request.extend_from_slice(&port.to_be_bytes());
+    stream.write_all(&request)?;
+
+    let mut header = [0u8; 4];
+    stream.read_exact(&mut header)?;
+    if header[1] != 0 {
+        return Err(proxy_error(format!(
+            "proxy could not connect to {}:{}: {}",
+            host,
+            port,
+            reply_message(header[1])
+        )));
+    }
+    // Skip the bound address of the reply
+    let address_len = match header[3] {
+        ADDRESS_IPV4 => 4,
+        ADDRESS_IPV6 => 16,
+        ADDRESS_DOMAIN => {
+            let mut len = [0u8; 1];
+            stream.read_exact(&mut len)?;
+            len[0] as usize
+        }
+        other => {
+            return Err(proxy_error(format!(
+                "invalid address type {} in proxy reply",
+                other
+            )))
+        }
+    };
+    let mut bound = vec![0u8; address_len + 2];
+    stream.read_exact(&mut bound)
+}
+
+/// Forward connections to a loopback port through the proxy to `host:port`,
+/// returns the address to connect to
+///
+/// The connection to the proxy uses `timeout` per address and `interface` if set.
+pub fn spawn_relay(
+    proxy: &Socks5Config,
+    host: &str,
+    port: u16,
+    timeout: Duration,
+    interface: Option<&str>,
+) -> io::Result<SocketAddr> {
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let local = listener.local_addr()?;
+    let proxy = proxy.clone();
+    let host = host.to_string();
+    let interface = interface.map(str::to_string);
+    thread::Builder::new()
+        .name("mqtt-socks5".to_string())
+        .spawn(move || {
+            for client in listener.incoming() {
+                let client = match client {
+                    Ok(client) => client,
+                    Err(e) => {
+                        tracing::warn!("SOCKS5 relay accept failed: {}", e);
+                        continue;
+                    }
+                };
+                let connected =
+                    net::connect(&proxy.host, proxy.port, timeout, interface.as_deref()).and_then(
+                        |mut upstream| {
+                            upstream.set_read_timeout(Some(timeout))?;
+                            handshake(&mut upstream, &host, port, proxy.credentials())?;
+                            upstream.set_read_timeout(None)?;
+                            Ok(upstream)
+                        },
+                    );
+                match connected {
+                    Ok(upstream) => {
+                        tracing::debug!("Connected to {}:{} via SOCKS5 {}", host, port, proxy.host);
+                        if let Err(e) = relay(client, upstream) {
+                            tracing::warn!("SOCKS5 relay failed: {}", e);
+                        }
+                    }
+                    // Dropping the client fails rumqttc's connect, which reports it
+                    Err(e) => tracing::error!("SOCKS5 proxy {}: {}", proxy.host, e),
+                }
+            }
+        })?;
+    Ok(local)
+}
+
+/// Copy both directions between `client` and `upstream` on their own threads
+fn relay(client: TcpStream, upstream: TcpStream) -> io::Result<()> {
+    let pipe = |mut from: TcpStream, mut to: TcpStream| {
+        move || {
+            let _ = io::copy(&mut from, &mut to);
+            let _ = to.shutdown(Shutdown::Write);
+        }
+    };
+    thread::Builder::new()
+        .name("mqtt-socks5-up".to_string())
+        .spawn(pipe(client.try_clone()?, upstream.try_clone()?))?;
+    thread::Builder::new()
+        .name("mqtt-socks5-down".to_string())
+        .spawn(pipe(upstream, client))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_socks5_handshake() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let proxy = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut greeting = [0u8; 3];
+            stream.read_exact(&mut greeting).unwrap();
+            assert_eq!(greeting, [5, 1, USERNAME_PASSWORD]);
+            stream.write_all(&[5, USERNAME_PASSWORD]).unwrap();
+
+            let mut auth = [0u8; 11];
+            stream.read_exact(&mut auth).unwrap();
+            assert_eq!(&auth, b"\x01\x04user\x04pass");
+            stream.write_all(&[1, 0]).unwrap();
+
+            let mut request = [0u8; 5 + 15 + 2];
+            stream.read_exact(&mut request).unwrap();
+            assert_eq!(&request[..5], &[5, CONNECT, 0, ADDRESS_DOMAIN, 15]);
+            assert_eq!(&request[5..20], b"mqtt.cloud.test");
+            assert_eq!(&request[20..], &8883u16.to_be_bytes());
+            stream
+                .write_all(&[5, 0, 0, ADDRESS_IPV4, 10, 0, 0, 1, 0x22, 0xb3])
+                .unwrap();
+            stream.write_all(b"CONNACK").unwrap();
+
+            // Second connection: the proxy can't reach the broker
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.read_exact(&mut greeting).unwrap();
+            stream.write_all(&[5, NO_AUTHENTICATION]).unwrap();
+            let mut request = [0u8; 4 + 4 + 2];
+            stream.read_exact(&mut request).unwrap();
+            assert_eq!(&request[3..8], &[ADDRESS_IPV4, 192, 0, 2, 1]);
+            stream
+                .write_all(&[5, 0x04, 0, ADDRESS_IPV4, 0, 0, 0, 0, 0, 0])
+                .unwrap();
+        });
+
+        let mut stream = TcpStream::connect(address).unwrap();
+        handshake(&mut stream, "mqtt.cloud.test", 8883, Some(("user", "pass"))).unwrap();
+        let mut payload = [0u8; 7];
+        stream.read_exact(&mut payload).unwrap();
+        assert_eq!(&payload, b"CONNACK");
+
+        let mut stream = TcpStream::connect(address).unwrap();
+        let error = handshake(&mut stream, "192.0.2.1", 1883, None).unwrap_err();
+        assert!(error.to_string().contains("host unreachable"));
+        proxy.join().unwrap();
+    }
+}
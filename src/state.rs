@@ -0,0 +1,239 @@
+//! Persisted bridge state (`[default] state_dir`)
+//!
+//! A directory with one JSON file per kind of state, `{dir}/{name}.json`:
+//! the charge cycle history (`cycles.json`) and the counters behind
+//! `bridge/restarts` (`counters.json`). Every file carries the schema version
+//! of its content,
+//!
+//! ```json
+//! {"version":1,"data":{"starts":12}}
+//! ```
+//!
+//! so a type can migrate files written by older versions and a downgraded
+//! bridge refuses newer ones instead of misreading them. Writes go to a
+//! temporary file that is renamed, a crash never leaves a file half written.
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::cycles::CycleHistory;
+
+/// Content of one state file
+pub trait StateFile: Serialize + DeserializeOwned + Default {
+    /// File name without the `.json` extension
+    const NAME: &'static str;
+    /// Schema version written with the data
+    const VERSION: u32;
+
+    /// Convert data written with an older schema `version`, None if that isn't possible
+    fn migrate(version: u32, data: serde_json::Value) -> Option<Self> {
+        let _ = (version, data);
+        None
+    }
+}
+
+#[derive(Serialize)]
+struct Versioned<'a, T> {
+    version: u32,
+    data: &'a T,
+}
+
+#[derive(Deserialize)]
+struct VersionedValue {
+    version: u32,
+    data: serde_json::Value,
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Directory of state files
+#[derive(Debug)]
+pub struct StateDir {
+    dir: PathBuf,
+}
+
+impl StateDir {
+    /// Open the directory, it is created if missing
+    pub fn open(dir: &Path) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        Ok(Self {
+            dir: dir.to_path_buf(),
+        })
+    }
+
+    /// Path of the file holding `T`
+    pub fn path<T: StateFile>(&self) -> PathBuf {
+        self.dir.join(format!("{}.json", T::NAME))
+    }
+
+    /// Load `T`, a missing file is the default value
+    pub fn load<T: StateFile>(&self) -> io::Result<T> {
+        let path = self.path::<T>();
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(T::default()),
+            Err(e) => return Err(e),
+        };
+        let file: VersionedValue = serde_json::from_str(&content)
+            .map_err(|e| invalid_data(format!("{}: {}", path.display(), e)))?;
+        if file.version == T::VERSION {
+            serde_json::from_value(file.data)
+                .map_err(|e| invalid_data(format!("{}: {}", path.display(), e)))
+        } else if file.version < T::VERSION {
+            T::migrate(file.version, file.data).ok_or_else(|| {
+                invalid_data(format!(
+                    "{}: cannot migrate schema version {}",
+                    path.display(),
+                    file.version
+                ))
+            })
+        } else {
+            Err(invalid_data(format!(
+                "{}: schema version {} is newer than the supported {}",
+                path.display(),
+                file.version,
+                T::VERSION
+            )))
+        }
+    }
+
+    /// Write `T` atomically (temporary file, synced to disk before the rename)
+    pub fn save<T: StateFile>(&self, state: &T) -> io::Result<()> {
+        let content = serde_json::to_string(&Versioned {
+            version: T::VERSION,
+            data: state,
+        })?;
+        let path = self.path::<T>();
+        let tmp = path.with_extension("tmp");
+        let mut file = fs::File::create(&tmp)?;
+        file.write_all(content.as_bytes())?;
+        file.sync_all()?;
+        fs::rename(tmp, path)
+    }
+
+    /// Import a `[default] state_file` written by older versions
+    ///
+    /// Only state without a file in the directory yet is taken over, so the
+    /// import runs once. Returns whether anything was imported.
+    pub fn import_legacy(&self, path: &Path) -> io::Result<bool> {
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e),
+        };
+        let invalid = |e: serde_json::Error| invalid_data(format!("{}: {}", path.display(), e));
+        let value: serde_json::Value = serde_json::from_str(&content).map_err(invalid)?;
+        // Files written before the start counter hold the cycle history only
+        let legacy = if value.get("batteries").is_some() {
+            LegacyState {
+                cycles: serde_json::from_value(value).map_err(invalid)?,
+                starts: 0,
+            }
+        } else {
+            serde_json::from_value(value).map_err(invalid)?
+        };
+
+        let mut imported = false;
+        if !self.path::<CycleHistory>().exists() {
+            self.save(&legacy.cycles)?;
+            imported = true;
+        }
+        if !self.path::<Counters>().exists() {
+            self.save(&Counters {
+                starts: legacy.starts,
+            })?;
+            imported = true;
+        }
+        Ok(imported)
+    }
+}
+
+/// Single file state written before `[default] state_dir`
+#[derive(Deserialize)]
+struct LegacyState {
+    #[serde(default)]
+    cycles: CycleHistory,
+    #[serde(default)]
+    starts: u64,
+}
+
+/// Counters kept across restarts
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Counters {
+    /// Bridge starts recorded, including the current one
+    #[serde(default)]
+    pub starts: u64,
+}
+
+impl Counters {
+    /// Count the current start, returns the number of restarts before it
+    pub fn record_start(&mut self) -> u64 {
+        self.starts += 1;
+        self.starts - 1
+    }
+}
+
+impl StateFile for Counters {
+    const NAME: &'static str = "counters";
+    const VERSION: u32 = 1;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    #[test]
+    fn test_state_persistence() {
+        let dir = std::env::temp_dir().join(format!("e3dc-state-{}", std::process::id()));
+        let legacy = dir.join("state.json");
+        let day = NaiveDate::from_ymd_opt(2024, 3, 1).unwrap();
+        let state = StateDir::open(&dir).unwrap();
+
+        // Cycle history only, as written by older versions
+        fs::write(
+            &legacy,
+            r#"{"batteries":{"0":[{"date":"2024-03-01","cycles":100.0}]}}"#,
+        )
+        .unwrap();
+        assert!(state.import_legacy(&legacy).unwrap());
+        assert!(!state.import_legacy(&legacy).unwrap());
+
+        let mut counters: Counters = state.load().unwrap();
+        assert_eq!(counters.record_start(), 0);
+        state.save(&counters).unwrap();
+        let mut cycles: CycleHistory = state.load().unwrap();
+        assert!(!cycles.record(0, day, 100.5));
+
+        let mut counters: Counters = state.load().unwrap();
+        assert_eq!(counters.record_start(), 1);
+        assert_eq!(
+            fs::read_to_string(state.path::<Counters>()).unwrap(),
+            r#"{"version":1,"data":{"starts":1}}"#
+        );
+
+        fs::write(
+            state.path::<Counters>(),
+            r#"{"version":2,"data":{"starts":1}}"#,
+        )
+        .unwrap();
+        assert!(state.load::<Counters>().is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            StateDir::open(&dir)
+                .unwrap()
+                .load::<Counters>()
+                .unwrap()
+                .starts,
+            0
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
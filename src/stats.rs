@@ -0,0 +1,224 @@
+//! Date-range statistics export (`stats` subcommand)
+//!
+//! Splits a range of UTC days into spans of the requested granularity and
+//! turns the E3DC's DB sums of each span into a row, written as CSV or as a
+//! JSON array. One DB request per span, so an hourly export of a year takes
+//! almost 9000 requests.
+
+use std::fmt;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+use chrono::{DateTime, Datelike, Duration, Months, NaiveDate, NaiveTime, Utc};
+use serde::Serialize;
+
+use crate::e3dc;
+use crate::mqtt::types::round;
+
+/// Length of the exported spans
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Granularity {
+    QuarterHour,
+    Hour,
+    Day,
+    /// Calendar months, clipped to the range
+    Month,
+}
+
+impl FromStr for Granularity {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "quarter_hour" | "15m" => Ok(Granularity::QuarterHour),
+            "hour" => Ok(Granularity::Hour),
+            "day" => Ok(Granularity::Day),
+            "month" => Ok(Granularity::Month),
+            _ => Err(format!(
+                "unknown granularity '{}', expected quarter_hour, hour, day or month",
+                value
+            )),
+        }
+    }
+}
+
+/// Output format of the rows
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatsFormat {
+    Csv,
+    Json,
+}
+
+impl FromStr for StatsFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "csv" => Ok(StatsFormat::Csv),
+            "json" => Ok(StatsFormat::Json),
+            _ => Err(format!("unknown format '{}', expected csv or json", value)),
+        }
+    }
+}
+
+/// Spans covering the UTC days `from` to `to` (inclusive), oldest first
+pub fn stats_spans(
+    from: NaiveDate,
+    to: NaiveDate,
+    granularity: Granularity,
+) -> Vec<(DateTime<Utc>, Duration)> {
+    let midnight = |date: NaiveDate| date.and_time(NaiveTime::MIN).and_utc();
+    let end = midnight(to) + Duration::days(1);
+    let mut start = midnight(from);
+    let mut spans = Vec::new();
+    while start < end {
+        let next = match granularity {
+            Granularity::QuarterHour => start + Duration::minutes(15),
+            Granularity::Hour => start + Duration::hours(1),
+            Granularity::Day => start + Duration::days(1),
+            Granularity::Month => {
+                let first = start.date_naive().with_day(1).unwrap_or(start.date_naive());
+                midnight(first + Months::new(1))
+            }
+        }
+        .min(end);
+        spans.push((start, next - start));
+        start = next;
+    }
+    spans
+}
+
+/// Sums of one span
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StatsRow {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub autarky: f64,               // %
+    pub self_consumption: f64,      // %
+    pub solar_production: f64,      // Wh
+    pub house_consumption: f64,     // Wh
+    pub battery_charge: f64,        // Wh
+    pub battery_discharge: f64,     // Wh
+    pub export_to_grid: f64,        // Wh
+    pub consumption_from_grid: f64, // Wh
+    pub state_of_charge: f64,       // %
+}
+
+impl StatsRow {
+    const HEADER: &'static str = "start,end,autarky,self_consumption,solar_production,\
+        house_consumption,battery_charge,battery_discharge,export_to_grid,\
+        consumption_from_grid,state_of_charge";
+
+    /// Row of the span `start` to `start + span`
+    pub fn from_e3dc(start: DateTime<Utc>, span: Duration, stat: &e3dc::DailyStatistics) -> Self {
+        Self {
+            start,
+            end: start + span,
+            autarky: round(stat.autarky, 1),
+            self_consumption: round(stat.consumed_production, 1),
+            solar_production: stat.solar_production,
+            house_consumption: stat.consumption,
+            battery_charge: stat.bat_power_in,
+            battery_discharge: stat.bat_power_out,
+            export_to_grid: stat.grid_power_in,
+            consumption_from_grid: stat.grid_power_out,
+            state_of_charge: round(stat.state_of_charge, 1),
+        }
+    }
+}
+
+impl fmt::Display for StatsRow {
+    /// CSV line without the line break
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{},{},{},{},{},{},{},{},{},{},{}",
+            self.start.to_rfc3339(),
+            self.end.to_rfc3339(),
+            self.autarky,
+            self.self_consumption,
+            self.solar_production,
+            self.house_consumption,
+            self.battery_charge,
+            self.battery_discharge,
+            self.export_to_grid,
+            self.consumption_from_grid,
+            self.state_of_charge
+        )
+    }
+}
+
+/// Write `rows` in `format`, CSV with a header line
+pub fn write_rows(out: &mut impl Write, rows: &[StatsRow], format: StatsFormat) -> io::Result<()> {
+    match format {
+        StatsFormat::Csv => {
+            writeln!(out, "{}", StatsRow::HEADER)?;
+            for row in rows {
+                writeln!(out, "{}", row)?;
+            }
+        }
+        StatsFormat::Json => {
+            serde_json::to_writer_pretty(&mut *out, rows)?;
+            writeln!(out)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_stats_spans() {
+        let date = |month, day| NaiveDate::from_ymd_opt(2024, month, day).unwrap();
+        let days = stats_spans(date(1, 30), date(2, 1), Granularity::Day);
+        assert_eq!(days.len(), 3);
+        assert_eq!(
+            days[0].0,
+            Utc.with_ymd_and_hms(2024, 1, 30, 0, 0, 0).unwrap()
+        );
+        assert!(days.iter().all(|(_, span)| *span == Duration::days(1)));
+        assert_eq!(
+            stats_spans(date(1, 1), date(1, 1), Granularity::QuarterHour).len(),
+            96
+        );
+        assert!(stats_spans(date(1, 2), date(1, 1), Granularity::Day).is_empty());
+
+        // Months are clipped to the range, February 2024 has 29 days
+        let months = stats_spans(date(1, 15), date(3, 10), Granularity::Month);
+        let lengths: Vec<i64> = months.iter().map(|(_, span)| span.num_days()).collect();
+        assert_eq!(lengths, vec![17, 29, 10]);
+        assert_eq!(
+            months[1].0,
+            Utc.with_ymd_and_hms(2024, 2, 1, 0, 0, 0).unwrap()
+        );
+
+        let start = Utc.with_ymd_and_hms(2024, 1, 30, 0, 0, 0).unwrap();
+        let stat = e3dc::DailyStatistics {
+            time_stamp: start,
+            autarky: 81.234,
+            consumption: 9000.0,
+            solar_production: 12000.0,
+            consumed_production: 60.0,
+            bat_power_in: 3000.0,
+            bat_power_out: 2500.0,
+            grid_power_in: 4800.0,
+            grid_power_out: 1700.0,
+            state_of_charge: 55.55,
+            start,
+            timespan: Duration::days(1),
+        };
+        let row = StatsRow::from_e3dc(start, Duration::days(1), &stat);
+        let mut csv = Vec::new();
+        write_rows(&mut csv, &[row], StatsFormat::Csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines[0], StatsRow::HEADER);
+        assert_eq!(
+            lines[1],
+            "2024-01-30T00:00:00+00:00,2024-01-31T00:00:00+00:00,81.2,60,12000,9000,3000,2500,4800,1700,55.6"
+        );
+    }
+}
@@ -0,0 +1,575 @@
+//! Periodic tasks of the main loop
+//!
+//! Every periodic job registers a named task with a trigger, either a fixed
+//! interval aligned to its multiples (the status poll every 5 s runs at
+//! :00, :05, ..., or shifted per `[e3dc] align`/`align_offset`) or a cron
+//! expression in the host's local time
+//! (`"*/15 * * * *"`, `"5 0 * * *"`). The main loop asks which tasks are
+//! due and sleeps until the earliest next run.
+//!
+//! A task may be given a jitter, every run is then delayed by a random
+//! amount below it, so a fleet of bridges doesn't publish in lockstep.
+//!
+//! When the loop notices a run late (a slow E3DC, a suspended host), the
+//! task's [`CatchUp`] policy decides what happens to the runs it missed.
+
+use std::fmt;
+use std::str::FromStr;
+
+use chrono::{
+    DateTime, Datelike, Duration, Local, NaiveDate, NaiveDateTime, TimeZone, Timelike, Utc,
+};
+use serde::Deserialize;
+
+use crate::e3dc::{next_aligned, next_interval};
+
+/// Cron expression: minute, hour, day of month, month and day of week
+///
+/// Each field is `*`, a number, a range `a-b`, a step `*/n` or `a-b/n`, or
+/// a comma separated list of these. Days of week run from 0 (Sunday) to 7
+/// (Sunday again). As in cron, a day matches either restricted day field
+/// if both are restricted.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Cron {
+    expression: String,
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    days_restricted: bool,
+    weekdays_restricted: bool,
+}
+
+/// Bits of the values a field matches, whether it is restricted (not `*`)
+fn parse_field(field: &str, min: u32, max: u32) -> Result<(u64, bool), String> {
+    let mut bits = 0u64;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => {
+                let step: u32 = step
+                    .parse()
+                    .ok()
+                    .filter(|step| *step > 0)
+                    .ok_or_else(|| format!("invalid step '{}'", step))?;
+                (range, step)
+            }
+            None => (part, 1),
+        };
+        let (start, end) = if range == "*" {
+            (min, max)
+        } else {
+            let value = |value: &str| {
+                value
+                    .parse::<u32>()
+                    .ok()
+                    .filter(|value| (min..=max).contains(value))
+                    .ok_or_else(|| format!("'{}' is not in {}-{}", value, min, max))
+            };
+            match range.split_once('-') {
+                Some((start, end)) => (value(start)?, value(end)?),
+                // A single value with a step runs up to the maximum, as in cron
+                None if part.contains('/') => (value(range)?, max),
+                None => {
+                    let value = value(range)?;
+                    (value, value)
+                }
+            }
+        };
+        if start > end {
+            return Err(format!("empty range '{}'", range));
+        }
+        for value in (start..=end).step_by(step as usize) {
+            bits |= 1 << value;
+        }
+    }
+    Ok((bits, field != "*"))
+}
+
+impl FromStr for Cron {
+    type Err = String;
+
+    fn from_str(expression: &str) -> Result<Self, Self::Err> {
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            return Err(format!(
+                "cron expression '{}' must have 5 fields",
+                expression
+            ));
+        };
+        let field = |name: &str, field: &str, min, max| {
+            parse_field(field, min, max)
+                .map_err(|e| format!("cron expression '{}', {}: {}", expression, name, e))
+        };
+        let (minutes, _) = field("minute", minute, 0, 59)?;
+        let (hours, _) = field("hour", hour, 0, 23)?;
+        let (days, days_restricted) = field("day of month", day, 1, 31)?;
+        let (months, _) = field("month", month, 1, 12)?;
+        let (mut weekdays, weekdays_restricted) = field("day of week", weekday, 0, 7)?;
+        // 7 is Sunday too
+        if weekdays & (1 << 7) != 0 {
+            weekdays |= 1;
+        }
+        Ok(Self {
+            expression: expression.to_string(),
+            minutes,
+            hours,
+            days,
+            months,
+            weekdays,
+            days_restricted,
+            weekdays_restricted,
+        })
+    }
+}
+
+impl TryFrom<String> for Cron {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl fmt::Display for Cron {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.expression)
+    }
+}
+
+impl Cron {
+    fn matches_day(&self, date: NaiveDate) -> bool {
+        let day = self.days & (1 << date.day()) != 0;
+        let weekday = self.weekdays & (1 << date.weekday().num_days_from_sunday()) != 0;
+        match (self.days_restricted, self.weekdays_restricted) {
+            (true, true) => day || weekday,
+            (true, false) => day,
+            (false, true) => weekday,
+            (false, false) => true,
+        }
+    }
+
+    /// First matching minute after `time`, None if there is none within 5 years
+    pub fn next_after_naive(&self, time: NaiveDateTime) -> Option<NaiveDateTime> {
+        let mut time = time.with_second(0)?.with_nanosecond(0)? + Duration::minutes(1);
+        let limit = time + Duration::days(5 * 366);
+        while time < limit {
+            let date = time.date();
+            if self.months & (1 << date.month()) == 0 {
+                let (year, month) = match date.month() {
+                    12 => (date.year() + 1, 1),
+                    month => (date.year(), month + 1),
+                };
+                time = NaiveDate::from_ymd_opt(year, month, 1)?.and_hms_opt(0, 0, 0)?;
+            } else if !self.matches_day(date) {
+                time = date.succ_opt()?.and_hms_opt(0, 0, 0)?;
+            } else if self.hours & (1 << time.hour()) == 0 {
+                time = date.and_hms_opt(time.hour(), 0, 0)? + Duration::hours(1);
+            } else if self.minutes & (1 << time.minute()) == 0 {
+                time += Duration::minutes(1);
+            } else {
+                return Some(time);
+            }
+        }
+        None
+    }
+
+    /// First matching minute after `time` in the time zone of `time`
+    ///
+    /// Minutes skipped by a daylight saving change don't exist and never
+    /// match, the first of two repeated minutes does.
+    pub fn next_after<Tz: TimeZone>(&self, time: &DateTime<Tz>) -> Option<DateTime<Tz>> {
+        let zone = time.timezone();
+        let mut naive = time.naive_local();
+        loop {
+            naive = self.next_after_naive(naive)?;
+            if let Some(next) = zone.from_local_datetime(&naive).earliest() {
+                if next > *time {
+                    return Some(next);
+                }
+            }
+        }
+    }
+}
+
+/// Random duration below `max`, zero for a zero `max`
+///
+/// Drawn from the randomly keyed std hasher, good enough to spread polls
+/// without a random number generator dependency.
+pub fn random_below(max: Duration) -> Duration {
+    use std::hash::{BuildHasher, Hasher};
+    let Ok(millis) = u64::try_from(max.num_milliseconds()) else {
+        return Duration::zero();
+    };
+    if millis == 0 {
+        return Duration::zero();
+    }
+    let random = std::collections::hash_map::RandomState::new()
+        .build_hasher()
+        .finish();
+    Duration::milliseconds((random % millis) as i64)
+}
+
+/// What the multiples of an interval are counted from (`[e3dc] align`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Alignment {
+    /// The Unix epoch, every bridge polls at the same seconds
+    #[default]
+    Epoch,
+    /// Midnight of the host's local clock, restarting every day
+    LocalClock,
+    /// The start of the bridge (free-running)
+    Start,
+}
+
+/// Fixed interval with its alignment and a phase offset
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Cadence {
+    pub interval: Duration,
+    pub alignment: Alignment,
+    /// Runs this long after every aligned boundary
+    pub offset: Duration,
+    /// Anchor of [`Alignment::Start`]
+    pub started: DateTime<Utc>,
+}
+
+impl Cadence {
+    /// Next run after `time`
+    pub fn next_after(&self, time: DateTime<Utc>) -> DateTime<Utc> {
+        let shifted = time - self.offset;
+        let next = match self.alignment {
+            Alignment::Epoch => next_interval(shifted, self.interval),
+            Alignment::LocalClock => next_on_clock(shifted, self.interval, &Local),
+            Alignment::Start => next_aligned(shifted, self.interval, self.started),
+        };
+        next + self.offset
+    }
+}
+
+/// Next multiple of `interval` since midnight in `tz` after `time`
+///
+/// The multiples restart at every midnight, the last one of a day that
+/// doesn't divide by the interval is cut short.
+fn next_on_clock<Tz: TimeZone>(time: DateTime<Utc>, interval: Duration, tz: &Tz) -> DateTime<Utc> {
+    let day = time.with_timezone(tz).date_naive();
+    let midnight = |day: NaiveDate| {
+        day.and_hms_opt(0, 0, 0)
+            .and_then(|midnight| midnight.and_local_timezone(tz.clone()).earliest())
+            .map(|midnight| midnight.with_timezone(&Utc))
+    };
+    match (midnight(day), day.succ_opt().and_then(midnight)) {
+        (Some(today), Some(tomorrow)) => next_aligned(time, interval, today).min(tomorrow),
+        // Midnight skipped by a DST switch
+        _ => next_interval(time, interval),
+    }
+}
+
+/// When a task runs
+#[derive(Debug, Clone, PartialEq)]
+pub enum Trigger {
+    /// At the multiples of the interval (since the epoch)
+    Every(Duration),
+    /// At the multiples of the interval with the configured alignment
+    Cadence(Cadence),
+    /// At the minutes matching the expression, in local time
+    Cron(Cron),
+}
+
+impl Trigger {
+    /// Next run after `time`, None for a cron expression that never matches
+    pub fn next_after(&self, time: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        match self {
+            Trigger::Every(interval) => Some(next_interval(time, *interval)),
+            Trigger::Cadence(cadence) => Some(cadence.next_after(time)),
+            Trigger::Cron(cron) => cron
+                .next_after(&time.with_timezone(&Local))
+                .map(|next| next.with_timezone(&Utc)),
+        }
+    }
+}
+
+/// What happens to runs the loop noticed late
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CatchUp {
+    /// Run once for any number of missed runs, then continue from now
+    #[default]
+    Once,
+    /// Run once for every missed run, oldest first
+    All,
+    /// Drop a run noticed after the following one was already due
+    Skip,
+}
+
+/// Handle of a registered task
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TaskId(usize);
+
+#[derive(Debug)]
+struct Task {
+    name: &'static str,
+    trigger: Trigger,
+    catch_up: CatchUp,
+    /// Upper bound of the random delay of every run
+    jitter: Duration,
+    next: Option<DateTime<Utc>>,
+}
+
+impl Task {
+    /// Next run of the trigger after `time`, delayed by the jitter
+    fn next_after(&self, time: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        self.trigger
+            .next_after(time)
+            .map(|next| next + random_below(self.jitter))
+    }
+}
+
+/// Named periodic tasks of the main loop
+#[derive(Debug, Default)]
+pub struct Tasks {
+    tasks: Vec<Task>,
+}
+
+impl Tasks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a task, its first run is the trigger's next time after `now`
+    pub fn register(
+        &mut self,
+        name: &'static str,
+        trigger: Trigger,
+        catch_up: CatchUp,
+        now: DateTime<Utc>,
+    ) -> TaskId {
+        let next = trigger.next_after(now);
+        if next.is_none() {
+            tracing::warn!("Task {} never runs", name);
+        }
+        self.tasks.push(Task {
+            name,
+            trigger,
+            catch_up,
+            jitter: Duration::zero(),
+            next,
+        });
+        TaskId(self.tasks.len() - 1)
+    }
+
+    /// Delay every following run of the task by a random amount below `jitter`
+    ///
+    /// The jitter should stay below the trigger's interval, a run is
+    /// otherwise skipped now and then.
+    pub fn set_jitter(&mut self, id: TaskId, jitter: Duration) {
+        self.tasks[id.0].jitter = jitter;
+    }
+
+    pub fn name(&self, id: TaskId) -> &'static str {
+        self.tasks[id.0].name
+    }
+
+    /// Whether the task is due, without taking the run
+    pub fn is_due(&self, id: TaskId, now: DateTime<Utc>) -> bool {
+        self.tasks[id.0].next.is_some_and(|next| next <= now)
+    }
+
+    /// Take the due run of the task, returns the time it was scheduled for
+    ///
+    /// Runs missed in the meantime are handled by the task's [`CatchUp`]
+    /// policy, with [`CatchUp::All`] the task stays due until it caught up.
+    pub fn due(&mut self, id: TaskId, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let task = &mut self.tasks[id.0];
+        let scheduled = task.next.filter(|next| *next <= now)?;
+        let following = task.next_after(scheduled);
+        match task.catch_up {
+            CatchUp::Once => task.next = task.next_after(now),
+            CatchUp::All => task.next = following,
+            CatchUp::Skip if following.is_some_and(|following| following <= now) => {
+                tracing::debug!("Task {} skipped the run of {}", task.name, scheduled);
+                task.next = task.next_after(now);
+                return None;
+            }
+            CatchUp::Skip => task.next = following,
+        }
+        Some(scheduled)
+    }
+
+    /// Make the task due at `now`, e.g. for a refresh on request
+    pub fn run_now(&mut self, id: TaskId, now: DateTime<Utc>) {
+        self.tasks[id.0].next = Some(now);
+    }
+
+    /// Make every task due at `now`
+    pub fn run_all_now(&mut self, now: DateTime<Utc>) {
+        for task in &mut self.tasks {
+            task.next = Some(now);
+        }
+    }
+
+    /// Earliest next run of all tasks
+    pub fn next_run(&self) -> Option<DateTime<Utc>> {
+        self.tasks.iter().filter_map(|task| task.next).min()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(time: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(time, "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    #[test]
+    fn test_cron() {
+        let cron: Cron = "*/15 * * * *".parse().unwrap();
+        assert_eq!(
+            cron.next_after_naive(at("2024-03-01 12:07")),
+            Some(at("2024-03-01 12:15"))
+        );
+        assert_eq!(
+            cron.next_after_naive(at("2024-03-01 23:45")),
+            Some(at("2024-03-02 00:00"))
+        );
+
+        let rollup: Cron = "5 0 * * *".parse().unwrap();
+        assert_eq!(
+            rollup.next_after_naive(at("2024-03-01 00:05")),
+            Some(at("2024-03-02 00:05"))
+        );
+
+        // Weekdays at 7:00 and 18:00, Sunday as 7
+        let cron: Cron = "0 7,18 * * 1-5".parse().unwrap();
+        assert_eq!(
+            cron.next_after_naive(at("2024-03-01 18:00")),
+            Some(at("2024-03-04 07:00"))
+        );
+        let sunday: Cron = "0 12 * * 7".parse().unwrap();
+        assert_eq!(
+            sunday.next_after_naive(at("2024-03-01 12:00")),
+            Some(at("2024-03-03 12:00"))
+        );
+        // Both day fields restricted: either matches
+        let cron: Cron = "0 0 15 * 1".parse().unwrap();
+        assert_eq!(
+            cron.next_after_naive(at("2024-03-05 00:00")),
+            Some(at("2024-03-11 00:00"))
+        );
+        assert_eq!(
+            "0 0 29 2 *"
+                .parse::<Cron>()
+                .unwrap()
+                .next_after_naive(at("2024-03-01 00:00")),
+            Some(at("2028-02-29 00:00"))
+        );
+        assert_eq!(
+            "0 0 31 2 *"
+                .parse::<Cron>()
+                .unwrap()
+                .next_after_naive(at("2024-03-01 00:00")),
+            None
+        );
+
+        assert!("* * * *".parse::<Cron>().is_err());
+        assert!("60 * * * *".parse::<Cron>().is_err());
+        assert!("*/0 * * * *".parse::<Cron>().is_err());
+        assert!("5-1 * * * *".parse::<Cron>().is_err());
+    }
+
+    #[test]
+    fn test_cadence() {
+        let started = Utc.with_ymd_and_hms(2024, 3, 1, 12, 0, 1).unwrap();
+        let time = Utc.with_ymd_and_hms(2024, 3, 1, 12, 0, 4).unwrap();
+        let cadence = |alignment, offset| Cadence {
+            interval: Duration::seconds(5),
+            alignment,
+            offset: Duration::seconds(offset),
+            started,
+        };
+        assert_eq!(
+            cadence(Alignment::Epoch, 0).next_after(time),
+            time + Duration::seconds(1)
+        );
+        assert_eq!(
+            cadence(Alignment::Epoch, 2).next_after(time),
+            time + Duration::seconds(3)
+        );
+        assert_eq!(
+            cadence(Alignment::Start, 0).next_after(time),
+            time + Duration::seconds(2)
+        );
+        assert_eq!(
+            cadence(Alignment::Start, 1).next_after(time),
+            time + Duration::seconds(3)
+        );
+
+        // 7 s multiples since midnight in UTC+1, cut short at the next midnight
+        let tz = chrono::FixedOffset::east_opt(3600).unwrap();
+        let midnight = Utc.with_ymd_and_hms(2024, 3, 1, 23, 0, 0).unwrap();
+        let interval = Duration::seconds(7);
+        assert_eq!(
+            next_on_clock(midnight + Duration::seconds(8), interval, &tz),
+            midnight + Duration::seconds(14)
+        );
+        assert_eq!(
+            next_on_clock(midnight - Duration::seconds(3), interval, &tz),
+            midnight
+        );
+    }
+
+    #[test]
+    fn test_tasks() {
+        let start = Utc.with_ymd_and_hms(2024, 3, 1, 12, 0, 2).unwrap();
+        let seconds = |s| start + Duration::seconds(s);
+        let mut tasks = Tasks::new();
+        let status = tasks.register(
+            "status",
+            Trigger::Every(Duration::seconds(5)),
+            CatchUp::Once,
+            start,
+        );
+        let all = tasks.register(
+            "all",
+            Trigger::Every(Duration::seconds(10)),
+            CatchUp::All,
+            start,
+        );
+        let skip = tasks.register(
+            "skip",
+            Trigger::Every(Duration::seconds(10)),
+            CatchUp::Skip,
+            start,
+        );
+        assert_eq!(tasks.next_run(), Some(seconds(3)));
+        assert_eq!(tasks.due(status, seconds(2)), None);
+        assert_eq!(tasks.due(status, seconds(3)), Some(seconds(3)));
+        assert!(!tasks.is_due(status, seconds(3)));
+
+        // The loop was blocked for 30 s
+        let late = seconds(31);
+        assert_eq!(tasks.due(status, late), Some(seconds(8)));
+        assert_eq!(tasks.due(status, late), None);
+        assert_eq!(tasks.next_run(), Some(seconds(8)));
+        let caught_up: Vec<_> = std::iter::from_fn(|| tasks.due(all, late)).collect();
+        assert_eq!(caught_up, vec![seconds(8), seconds(18), seconds(28)]);
+        assert_eq!(tasks.due(skip, late), None);
+        assert!(!tasks.is_due(skip, late));
+        assert_eq!(tasks.due(skip, seconds(38)), Some(seconds(38)));
+
+        tasks.run_now(status, late);
+        assert_eq!(tasks.due(status, late), Some(late));
+        assert_eq!(tasks.name(skip), "skip");
+
+        // Jittered runs stay between the boundary and the boundary plus the jitter
+        tasks.set_jitter(all, Duration::seconds(2));
+        for _ in 0..20 {
+            tasks.run_now(all, late);
+            tasks.due(all, late);
+            let next = tasks.tasks[all.0].next.unwrap();
+            assert!(next >= seconds(38) && next < seconds(40));
+        }
+        assert_eq!(random_below(Duration::zero()), Duration::zero());
+    }
+}
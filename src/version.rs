@@ -0,0 +1,74 @@
+//! Build information of the bridge itself
+//!
+//! Crate version, git commit and build time, published retained to
+//! `bridge/version` and as `origin` in the Home Assistant discovery configs
+//! to tell outdated installs apart.
+
+use std::fmt;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BuildInfo {
+    pub version: &'static str,
+    /// Short commit hash, "unknown" when built outside a git checkout
+    pub git_hash: &'static str,
+    pub build_time: Option<DateTime<Utc>>,
+}
+
+impl BuildInfo {
+    /// Build information of the running binary
+    pub fn current() -> Self {
+        Self {
+            version: env!("CARGO_PKG_VERSION"),
+            git_hash: env!("E3DC_MQTT_GIT_HASH"),
+            build_time: env!("E3DC_MQTT_BUILD_TIME")
+                .parse()
+                .ok()
+                .and_then(|secs| DateTime::from_timestamp(secs, 0))
+                .filter(|time| time.timestamp() > 0),
+        }
+    }
+}
+
+impl fmt::Display for BuildInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({}", self.version, self.git_hash)?;
+        if let Some(time) = self.build_time {
+            write!(f, ", built {}", time.format("%Y-%m-%d %H:%M:%S UTC"))?;
+        }
+        write!(f, ")")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_build_info() {
+        let current = BuildInfo::current();
+        assert_eq!(current.version, env!("CARGO_PKG_VERSION"));
+        assert!(!current.git_hash.is_empty());
+
+        let info = BuildInfo {
+            version: "0.1.3",
+            git_hash: "0209f0c",
+            build_time: Some(Utc.with_ymd_and_hms(2024, 3, 1, 12, 0, 0).unwrap()),
+        };
+        assert_eq!(
+            info.to_string(),
+            "0.1.3 (0209f0c, built 2024-03-01 12:00:00 UTC)"
+        );
+        assert_eq!(
+            serde_json::to_value(&info).unwrap(),
+            serde_json::json!({
+                "version": "0.1.3",
+                "git_hash": "0209f0c",
+                "build_time": "2024-03-01T12:00:00Z",
+            })
+        );
+    }
+}
@@ -2,11 +2,20 @@
 //!
 //! These tests verify the core functionality without requiring actual E3DC hardware.
 
-use e3dc_mqtt_rs::config::{E3dcConfig, MqttConfig};
-use e3dc_mqtt_rs::mqtt::context::MqttPayload;
-use e3dc_mqtt_rs::errors::{E3dcError, MqttError};
+use e3dc_mqtt_rs::config::{CommandsConfig, E3dcConfig, MqttConfig, PayloadEncoding, Timezone};
+use e3dc_mqtt_rs::mqtt::context::{FloatFormat, MqttPayload, PayloadFormat, Transport};
+use e3dc_mqtt_rs::errors::{CommandError, E3dcError, MqttError};
 use std::time::Duration;
 use chrono::{Utc, TimeZone};
+use e3dc_mqtt_rs::bridge::Shutdown;
+use e3dc_mqtt_rs::e3dc::{self, DailyStatistics, EmergencyPowerState};
+use e3dc_mqtt_rs::events::RejectedCommandEvent;
+use e3dc_mqtt_rs::mqtt::commands::IncomingCommand;
+use e3dc_mqtt_rs::mqtt::subscriber::{CommandHandler, CommandStatus, Subscriber};
+use e3dc_mqtt_rs::mqtt::{self, Publisher};
+use rumqttc::QoS;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
 
 #[test]
 fn test_mqtt_config_debug_redacts_password() {
@@ -14,6 +23,10 @@ fn test_mqtt_config_debug_redacts_password() {
         root: "e3dc".to_string(),
         host: "mqtt.example.com".to_string(),
         port: 1883,
+        connect_timeout: Duration::from_secs(5),
+        bind_interface: None,
+        socks5: None,
+        rename: BTreeMap::new(),
         client_id: None,
         username: "test-user".to_string(),
         password: "secret-password".to_string(),
@@ -36,8 +49,33 @@ fn test_e3dc_config_debug_redacts_credentials() {
         username: "user@example.com".to_string(),
         password: "secret-password".to_string(),
         key: "secret-key".to_string(),
+        connect_timeout: Duration::from_secs(5),
         interval: Duration::from_secs(5),
         statistic_update_interval: Duration::from_secs(60),
+        statistic_schedule: None,
+        align: Default::default(),
+        align_offset: Duration::ZERO,
+        startup_delay: None,
+        jitter: None,
+        rediscover_batteries: false,
+        suppress_zero_pv_after: None,
+        poll_charge_limits: false,
+        poll_phases: false,
+        soc_rate_window: None,
+        soc_smoothing_window: None,
+        soc_smoothing_hysteresis: 1.0,
+        poll_dcbs: true,
+        model: None,
+        models: Default::default(),
+        poll_dcdc: false,
+        poll_wb_phases: false,
+        wallbox_index: 0,
+        integrate_energy: false,
+        poll_batteries: true,
+        battery_worker: false,
+        poll_clock: false,
+        poll_portal: false,
+        poll_network: false,
     };
 
     let debug_output = format!("{:?}", config);
@@ -68,6 +106,68 @@ fn test_mqtt_payload_f64() {
     assert_eq!(value.to_payload(), "-123.456");
 }
 
+#[test]
+fn test_mqtt_payload_f64_never_scientific() {
+    assert_eq!(1e-7_f64.to_payload(), "0");
+    assert_eq!(1e21_f64.to_payload(), "1000000000000000000000");
+    assert_eq!((0.1_f64 + 0.2).to_payload(), "0.3");
+    // Rounds to zero without a sign
+    assert_eq!((-0.0001_f64).to_payload(), "0");
+}
+
+#[test]
+fn test_float_format_policy() {
+    let fixed = FloatFormat {
+        decimals: 2,
+        trim_trailing_zeros: false,
+    };
+    assert_eq!(12.5_f64.to_payload_with(fixed), "12.50");
+    assert_eq!(3.0_f64.to_payload_with(fixed), "3.00");
+    assert_eq!(vec![1.0, 2.345].to_payload_with(fixed), "[1.00,2.35]");
+
+    let mut format = PayloadFormat::default();
+    format.field_decimals.insert("voltages".to_string(), 1);
+    assert_eq!(format.for_field("voltages").decimals, 1);
+    assert_eq!(format.for_field("current"), FloatFormat::DEFAULT);
+}
+
+#[test]
+fn test_cbor_cell_arrays() {
+    let voltages = vec![3.3124, 3.3, 3.2987];
+    let bytes = voltages.to_cbor(FloatFormat::DEFAULT).unwrap().unwrap();
+    let decoded: Vec<f32> = ciborium::from_reader(bytes.as_slice()).unwrap();
+    assert_eq!(decoded, vec![3.312_f32, 3.3, 3.299]);
+    // Much smaller than the text form
+    assert!(bytes.len() < voltages.to_payload().len());
+
+    // Scalars have no binary form
+    assert!(12.5_f64.to_cbor(FloatFormat::DEFAULT).is_none());
+}
+
+#[test]
+fn test_timestamp_timezone() {
+    let winter = Utc.with_ymd_and_hms(2024, 1, 15, 12, 0, 0).unwrap();
+    let summer = Utc.with_ymd_and_hms(2024, 7, 15, 12, 0, 0).unwrap();
+    let format = PayloadFormat::default();
+    assert_eq!(
+        winter.to_payload_for(&format, "time"),
+        "2024-01-15T12:00:00+00:00"
+    );
+
+    let format = PayloadFormat {
+        timezone: "Europe/Berlin".parse::<Timezone>().unwrap(),
+        ..Default::default()
+    };
+    assert_eq!(
+        winter.to_payload_for(&format, "time"),
+        "2024-01-15T13:00:00+01:00"
+    );
+    assert_eq!(
+        summer.to_payload_for(&format, "time"),
+        "2024-07-15T14:00:00+02:00"
+    );
+}
+
 #[test]
 fn test_mqtt_payload_u64() {
     let value = 12345_u64;
@@ -96,6 +196,7 @@ fn test_mqtt_payload_string() {
 }
 
 #[test]
+#[allow(clippy::approx_constant)]
 fn test_mqtt_payload_vec_f64() {
     let values = vec![1.0, 2.5, 3.14];
     assert_eq!(values.to_payload(), "[1,2.5,3.14]");
@@ -117,6 +218,533 @@ fn test_mqtt_payload_datetime() {
     assert!(payload.contains("Z") || payload.contains("+") || payload.contains("-"));
 }
 
+// ============================================================================
+// Publisher Tests
+// ============================================================================
+
+/// Captures (topic, payload, retain) instead of talking to a broker
+#[derive(Default)]
+struct RecordingTransport {
+    messages: RefCell<Vec<(String, String, bool)>>,
+    /// Topic suffix whose publishes fail
+    failing: Option<&'static str>,
+}
+
+impl Transport for RecordingTransport {
+    fn send(&self, topic: &str, payload: String, _qos: QoS, retain: bool) -> Result<(), MqttError> {
+        if self.failing.is_some_and(|suffix| topic.ends_with(suffix)) {
+            return Err(MqttError::PublishFailed {
+                topic: topic.to_string(),
+                reason: "queue closed".to_string(),
+            });
+        }
+        self.messages
+            .borrow_mut()
+            .push((topic.to_string(), payload, retain));
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct RecordingPublisher {
+    transport: RecordingTransport,
+    /// `[mqtt.rename]` entries
+    renames: BTreeMap<String, String>,
+    format: PayloadFormat,
+}
+
+impl Publisher for RecordingPublisher {
+    fn transport(&self) -> &dyn Transport {
+        &self.transport
+    }
+
+    fn root_topic(&self) -> &str {
+        "e3dc/S10E-123"
+    }
+
+    fn device_id(&self) -> &str {
+        "S10E-123"
+    }
+
+    fn topic_renames(&self) -> &BTreeMap<String, String> {
+        &self.renames
+    }
+
+    fn payload_format(&self) -> &PayloadFormat {
+        &self.format
+    }
+}
+
+fn test_status(solar_production: f64) -> mqtt::Status {
+    mqtt::Status {
+        time: Utc.with_ymd_and_hms(2024, 1, 15, 12, 0, 0).unwrap(),
+        additional: 0.0,
+        autarky: 100.0,
+        battery_charge: 500.0,
+        battery_discharge: 0.0,
+        battery_consumption: 500.0,
+        consumption_from_grid: 0.0,
+        export_to_grid: 0.0,
+        grid_production: 0.0,
+        house_consumption: 700.0,
+        self_consumption: 100.0,
+        solar_production,
+        solar_production_excess: solar_production - 700.0,
+        state_of_charge: 55.5,
+        wb_consumption: 0.0,
+        emergency_power: EmergencyPowerState::NotActive,
+        unknown: Vec::new(),
+    }
+}
+
+#[test]
+fn test_publisher_topic_renames() {
+    let publisher = RecordingPublisher {
+        renames: BTreeMap::from([("house_consumption".to_string(), "load_power".to_string())]),
+        ..Default::default()
+    };
+    publisher
+        .publish_status(&test_status(1200.0), None)
+        .unwrap();
+    let messages = publisher.transport.messages.borrow();
+    assert!(messages.contains(&(
+        "e3dc/S10E-123/status/load_power".to_string(),
+        "700".to_string(),
+        false
+    )));
+    assert!(!messages
+        .iter()
+        .any(|(topic, _, _)| topic.ends_with("/house_consumption")));
+}
+
+#[test]
+fn test_publisher_status_change_detection() {
+    let publisher = RecordingPublisher::default();
+
+    let first = test_status(1200.0);
+    publisher.publish_status(&first, None).unwrap();
+    {
+        let messages = publisher.transport.messages.borrow();
+        assert_eq!(messages.len(), 16);
+        // Real-time values are not retained by default
+        assert!(messages.contains(&(
+            "e3dc/S10E-123/status/state_of_charge".to_string(),
+            "55.5".to_string(),
+            false
+        )));
+        assert!(messages.contains(&(
+            "e3dc/S10E-123/status/emergency_power".to_string(),
+            "grid".to_string(),
+            false
+        )));
+    }
+
+    // Only the changed fields are published again
+    publisher.transport.messages.borrow_mut().clear();
+    let second = test_status(1300.0);
+    publisher.publish_status(&second, Some(&first)).unwrap();
+    let topics: Vec<String> = publisher
+        .transport
+        .messages
+        .borrow()
+        .iter()
+        .map(|(topic, _, _)| topic.clone())
+        .collect();
+    assert_eq!(
+        topics,
+        vec![
+            "e3dc/S10E-123/status/solar_production",
+            "e3dc/S10E-123/status/solar_production_excess",
+        ]
+    );
+}
+
+#[test]
+fn test_publisher_skips_unknown_fields() {
+    let publisher = RecordingPublisher::default();
+    let status = |power_wb| e3dc::Status {
+        time_stamp: Utc.with_ymd_and_hms(2024, 1, 15, 12, 0, 0).unwrap(),
+        power_battery: Some(500.0),
+        power_wb,
+        power_wb_solar: None,
+        power_home: Some(700.0),
+        power_pv: Some(1200.0),
+        power_grid: None,
+        power_add: Some(0.0),
+        battery_soc: Some(55.5),
+        autarky: Some(100.0),
+        self_consumption: Some(100.0),
+        emergency_power_state: EmergencyPowerState::NotActive,
+        missing: Vec::new(),
+    };
+    let topics = |publisher: &RecordingPublisher| -> Vec<String> {
+        publisher
+            .transport
+            .messages
+            .borrow()
+            .iter()
+            .map(|(topic, _, _)| topic.clone())
+            .collect()
+    };
+
+    // Never answered: no made up 0 W for the grid and the wallbox
+    let first = mqtt::Status::from_e3dc(&status(None));
+    publisher.publish_status(&first, None).unwrap();
+    let published = topics(&publisher);
+    assert_eq!(published.len(), 12);
+    for field in [
+        "grid_production",
+        "consumption_from_grid",
+        "export_to_grid",
+        "wb_consumption",
+    ] {
+        assert!(!published.iter().any(|topic| topic.ends_with(field)));
+    }
+
+    // The first value is published even though it equals the placeholder
+    publisher.transport.messages.borrow_mut().clear();
+    let second = mqtt::Status::from_e3dc(&status(Some(0.0)));
+    publisher.publish_status(&second, Some(&first)).unwrap();
+    assert!(topics(&publisher).contains(&"e3dc/S10E-123/status/wb_consumption".to_string()));
+}
+
+#[test]
+fn test_publisher_partial_failure() {
+    let publisher = RecordingPublisher {
+        transport: RecordingTransport {
+            failing: Some("/autarky"),
+            ..RecordingTransport::default()
+        },
+        ..Default::default()
+    };
+
+    // The other fields still go out, the error sums up the failures
+    let error = publisher
+        .publish_status(&test_status(1200.0), None)
+        .unwrap_err()
+        .to_string();
+    assert_eq!(publisher.transport.messages.borrow().len(), 15);
+    assert!(error.contains("1 of 16 fields failed"), "{}", error);
+    assert!(error.contains("status/autarky"), "{}", error);
+}
+
+#[test]
+fn test_publisher_events_not_retained() {
+    let publisher = RecordingPublisher::default();
+    let event = RejectedCommandEvent {
+        time: Utc.with_ymd_and_hms(2024, 1, 15, 12, 0, 0).unwrap(),
+        command: "self_destruct".to_string(),
+        payload: "1".to_string(),
+        reason: "Unknown command".to_string(),
+    };
+    publisher.publish_event("rejected_command", &event).unwrap();
+    let messages = publisher.transport.messages.borrow();
+    let (topic, payload, retain) = &messages[0];
+    assert_eq!(topic, "e3dc/S10E-123/events/rejected_command");
+    assert!(!retain);
+
+    // Events are JSON documents with source and publish time
+    let json: serde_json::Value = serde_json::from_str(payload).unwrap();
+    assert_eq!(json["command"], "self_destruct");
+    assert_eq!(json["time_stamp"], "2024-01-15T12:00:00Z");
+    assert!(json["published_at"].is_string());
+}
+
+#[test]
+fn test_publisher_events_json_with_cbor() {
+    // The recording transport rejects binary payloads
+    let publisher = RecordingPublisher {
+        format: PayloadFormat {
+            encoding: PayloadEncoding::Cbor,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let event = RejectedCommandEvent {
+        time: Utc.with_ymd_and_hms(2024, 1, 15, 12, 0, 0).unwrap(),
+        command: "self_destruct".to_string(),
+        payload: "1".to_string(),
+        reason: "Unknown command".to_string(),
+    };
+    publisher.publish_event("rejected_command", &event).unwrap();
+    publisher.publish_idle_periods(&[]).unwrap();
+    let messages = publisher.transport.messages.borrow();
+    let json: serde_json::Value = serde_json::from_str(&messages[0].1).unwrap();
+    assert_eq!(json["command"], "self_destruct");
+    assert_eq!(messages[1], ("e3dc/S10E-123/idle_periods".to_string(), "[]".to_string(), true));
+}
+
+/// Stores the payload of `set_value`, fails on `broken`
+struct ValueHandler;
+
+impl CommandHandler<Vec<u64>> for ValueHandler {
+    fn names(&self) -> &[&'static str] {
+        &["set_value", "broken"]
+    }
+
+    fn handle(
+        &mut self,
+        command: &IncomingCommand,
+        target: &mut Vec<u64>,
+        _publisher: &dyn Publisher,
+    ) -> Result<String, CommandError> {
+        if command.name == "broken" {
+            return Err(CommandError::Failed {
+                command: command.name.clone(),
+                reason: "write rejected".to_string(),
+            });
+        }
+        let value = command
+            .payload
+            .parse()
+            .map_err(|_| CommandError::InvalidPayload {
+                command: command.name.clone(),
+                reason: "not a number".to_string(),
+            })?;
+        target.push(value);
+        Ok(format!("set to {}", value))
+    }
+}
+
+#[test]
+fn test_subscriber_dispatch() {
+    let publisher = RecordingPublisher::default();
+    let mut subscriber = Subscriber::new(CommandsConfig {
+        enabled: true,
+        allow: Vec::new(),
+        deny: vec!["denied".to_string()],
+    });
+    subscriber.register(Box::new(ValueHandler));
+    let command = |name: &str, payload: &str| IncomingCommand {
+        name: name.to_string(),
+        payload: payload.to_string(),
+    };
+    let mut values = Vec::new();
+
+    let ack = subscriber
+        .dispatch(command("set_value", "42"), &mut values, &publisher)
+        .unwrap();
+    assert_eq!(ack.status, CommandStatus::Applied);
+    assert_eq!(ack.message, "set to 42");
+    assert_eq!(values, [42]);
+
+    for (name, payload, status) in [
+        ("set_value", "x", CommandStatus::Rejected),
+        ("denied", "1", CommandStatus::Rejected),
+        ("unknown", "1", CommandStatus::Rejected),
+        ("broken", "1", CommandStatus::Failed),
+    ] {
+        let ack = subscriber
+            .dispatch(command(name, payload), &mut values, &publisher)
+            .unwrap();
+        assert_eq!(ack.status, status, "{}", name);
+    }
+    assert_eq!(values, [42]);
+
+    let messages = publisher.transport.messages.borrow();
+    let topics: Vec<&str> = messages
+        .iter()
+        .map(|(topic, _, _)| topic.as_str())
+        .collect();
+    assert_eq!(topics[0], "e3dc/S10E-123/command_ack/set_value");
+    // Rejections are also events, failures are not
+    assert_eq!(
+        topics
+            .iter()
+            .filter(|topic| topic.ends_with("events/rejected_command"))
+            .count(),
+        3
+    );
+    assert!(messages.iter().all(|(_, _, retain)| !retain));
+    let json: serde_json::Value = serde_json::from_str(&messages.last().unwrap().1).unwrap();
+    assert_eq!(json["status"], "failed");
+    assert_eq!(json["command"], "broken");
+}
+
+#[test]
+fn test_publisher_clears_removed_dcbs() {
+    let publisher = RecordingPublisher::default();
+    let dcb = |index| e3dc::DcbData {
+        index,
+        ..Default::default()
+    };
+    let battery = |index, dcbs| {
+        mqtt::BatteryData::from_e3dc(&e3dc::BatteryData {
+            index,
+            dcbs,
+            ..Default::default()
+        })
+    };
+    let old = vec![battery(0, vec![dcb(0), dcb(1)]), battery(1, vec![dcb(0)])];
+    let new = vec![battery(0, vec![dcb(0)])];
+
+    let removed = publisher.clear_removed_batteries(&new, &old).unwrap();
+    assert_eq!(
+        removed,
+        vec![
+            "status/battery:0/dcb:1",
+            "status/battery:1/dcb:0",
+            "status/battery:1"
+        ]
+    );
+    let messages = publisher.transport.messages.borrow();
+    assert!(messages
+        .iter()
+        .all(|(_, payload, retain)| payload.is_empty() && *retain));
+    assert!(messages.contains(&(
+        "e3dc/S10E-123/status/battery:0/dcb:1/voltages".to_string(),
+        String::new(),
+        true
+    )));
+    assert!(!messages
+        .iter()
+        .any(|(topic, _, _)| topic.starts_with("e3dc/S10E-123/status/battery:0/dcb:0")));
+
+    // Nothing vanished, nothing cleared
+    drop(messages);
+    assert!(publisher
+        .clear_removed_batteries(&old, &old)
+        .unwrap()
+        .is_empty());
+}
+
+#[test]
+fn test_publisher_statistics_history() {
+    let publisher = RecordingPublisher::default();
+    let day = |day| DailyStatistics {
+        time_stamp: Utc.with_ymd_and_hms(2024, 1, 16, 0, 0, 5).unwrap(),
+        autarky: 81.25,
+        consumed_production: 64.0,
+        solar_production: 5000.0,
+        consumption: 8000.0,
+        bat_power_in: 2000.0,
+        bat_power_out: 1800.0,
+        grid_power_in: 300.0,
+        grid_power_out: 1500.0,
+        state_of_charge: 40.0,
+        start: Utc.with_ymd_and_hms(2024, 1, day, 0, 0, 0).unwrap(),
+        timespan: chrono::Duration::days(1),
+    };
+    let history: Vec<mqtt::DayStatistics> = [day(14), day(15)]
+        .iter()
+        .map(mqtt::DayStatistics::from_e3dc)
+        .collect();
+    publisher.publish_statistics_history(&history).unwrap();
+
+    let messages = publisher.transport.messages.borrow();
+    let (topic, payload, retain) = &messages[0];
+    assert_eq!(topic, "e3dc/S10E-123/status_sums/history");
+    assert!(retain);
+    let json: serde_json::Value = serde_json::from_str(payload).unwrap();
+    assert_eq!(json[0]["date"], "2024-01-14");
+    assert_eq!(json[1]["date"], "2024-01-15");
+    assert_eq!(json[1]["autarky"], 81.3);
+    assert_eq!(json[1]["consumption_from_grid"], 1500.0);
+}
+
+#[test]
+fn test_publisher_day_curve() {
+    let publisher = RecordingPublisher::default();
+    let slot = |index, solar_production, consumption| e3dc::CurveSlot {
+        index,
+        solar_production,
+        consumption,
+    };
+    let curve = e3dc::DayCurve {
+        time_stamp: Utc.with_ymd_and_hms(2024, 1, 15, 1, 0, 5).unwrap(),
+        start: Utc.with_ymd_and_hms(2024, 1, 15, 0, 0, 0).unwrap(),
+        slot: chrono::Duration::minutes(15),
+        // Slot 2 without data
+        slots: vec![
+            slot(0, 0.0, 100.0),
+            slot(1, 25.0, 112.5),
+            slot(3, 50.0, 75.0),
+        ],
+    };
+    publisher
+        .publish_day_curve(&mqtt::DayCurve::from_e3dc(&curve))
+        .unwrap();
+
+    let messages = publisher.transport.messages.borrow();
+    let (topic, payload, _) = &messages[0];
+    assert_eq!(topic, "e3dc/S10E-123/status_sums/curve");
+    let json: serde_json::Value = serde_json::from_str(payload).unwrap();
+    assert_eq!(json["slot_seconds"], 900);
+    assert_eq!(
+        json["solar_production"],
+        serde_json::json!([0.0, 100.0, null, 200.0])
+    );
+    assert_eq!(json["house_consumption"][1], 450.0);
+}
+
+#[test]
+fn test_publisher_pvi_data() {
+    let publisher = RecordingPublisher::default();
+    let pvi = |index, dc_power| mqtt::PviData {
+        index,
+        time: Utc.with_ymd_and_hms(2024, 1, 15, 12, 0, 0).unwrap(),
+        on_grid: true,
+        ac_power: 2900.0,
+        dc_power,
+        strings: vec![mqtt::PviString {
+            index: 0,
+            dc_power,
+            dc_voltage: 420.0,
+            dc_current: dc_power / 420.0,
+        }],
+    };
+    let old = [pvi(0, 3000.0), pvi(1, 1500.0)];
+    publisher.publish_pvi_data(&old, &[]).unwrap();
+    publisher.transport.messages.borrow_mut().clear();
+
+    // Only the changed inverter and string are published
+    publisher
+        .publish_pvi_data(&[pvi(0, 3000.0), pvi(1, 1680.0)], &old)
+        .unwrap();
+    let messages = publisher.transport.messages.borrow();
+    let topics: Vec<&str> = messages.iter().map(|(t, _, _)| t.as_str()).collect();
+    assert_eq!(
+        topics,
+        [
+            "e3dc/S10E-123/status/pvi:1/dc_power",
+            "e3dc/S10E-123/status/pvi:1/string:0/dc_power",
+            "e3dc/S10E-123/status/pvi:1/string:0/dc_current",
+        ]
+    );
+}
+
+#[test]
+fn test_publisher_device_clock() {
+    let publisher = RecordingPublisher::default();
+    let utc_time = Utc.with_ymd_and_hms(2024, 3, 1, 12, 0, 0).unwrap();
+    let clock = e3dc::DeviceClock {
+        time_stamp: utc_time + chrono::Duration::seconds(-90),
+        utc_time,
+        local_time: Utc
+            .with_ymd_and_hms(2024, 3, 1, 13, 0, 0)
+            .unwrap()
+            .naive_utc(),
+        time_zone: "Europe/Berlin".to_string(),
+    };
+    let clock = mqtt::DeviceClock::from_e3dc(&clock);
+    assert_eq!(clock.offset, 90.0);
+    publisher.publish_device_clock(&clock, None).unwrap();
+
+    let messages = publisher.transport.messages.borrow();
+    let payloads: Vec<(&str, &str)> = messages
+        .iter()
+        .map(|(t, p, _)| (t.as_str(), p.as_str()))
+        .collect();
+    assert_eq!(
+        payloads[1..],
+        [
+            ("e3dc/S10E-123/clock/local_time", "2024-03-01T13:00:00"),
+            ("e3dc/S10E-123/clock/time_zone", "Europe/Berlin"),
+            ("e3dc/S10E-123/clock/offset", "90"),
+        ]
+    );
+}
+
 // ============================================================================
 // Error Type Tests
 // ============================================================================
@@ -171,6 +799,40 @@ fn test_duration_formats() {
     assert_eq!(duration.as_secs(), 5);
 }
 
+// ============================================================================
+// Serde Tests
+// ============================================================================
+
+#[test]
+fn test_e3dc_types_serde_roundtrip() {
+    let stats = DailyStatistics {
+        time_stamp: Utc.with_ymd_and_hms(2024, 1, 15, 12, 0, 0).unwrap(),
+        autarky: 85.5,
+        consumption: 12000.0,
+        solar_production: 18000.0,
+        consumed_production: 60.0,
+        bat_power_in: 5000.0,
+        bat_power_out: 4000.0,
+        grid_power_in: 2000.0,
+        grid_power_out: 7000.0,
+        state_of_charge: 80.0,
+        start: Utc.with_ymd_and_hms(2024, 1, 15, 0, 0, 0).unwrap(),
+        timespan: chrono::Duration::hours(12),
+    };
+
+    let json = serde_json::to_value(&stats).unwrap();
+    // chrono::Duration is stored as seconds
+    assert_eq!(json["timespan"], 43200);
+
+    let parsed: DailyStatistics = serde_json::from_value(json).unwrap();
+    assert_eq!(parsed.timespan, stats.timespan);
+    assert_eq!(parsed.start, stats.start);
+    assert_eq!(parsed.autarky, stats.autarky);
+
+    let state: EmergencyPowerState = serde_json::from_str("\"not_active\"").unwrap();
+    assert_eq!(state, EmergencyPowerState::NotActive);
+}
+
 // ============================================================================
 // Edge Case Tests
 // ============================================================================
@@ -222,6 +884,10 @@ fn test_config_empty_strings() {
         root: "".to_string(),  // Empty root should be allowed
         host: "mqtt.example.com".to_string(),
         port: 1883,
+        connect_timeout: Duration::from_secs(5),
+        bind_interface: None,
+        socks5: None,
+        rename: BTreeMap::new(),
         client_id: None,
         username: "".to_string(),
         password: "".to_string(),
@@ -239,6 +905,10 @@ fn test_config_port_ranges() {
         root: "e3dc".to_string(),
         host: "mqtt.example.com".to_string(),
         port: 1, // Minimum valid port
+        connect_timeout: Duration::from_secs(5),
+        bind_interface: None,
+        socks5: None,
+        rename: BTreeMap::new(),
         client_id: None,
         username: "test".to_string(),
         password: "test".to_string(),
@@ -249,6 +919,10 @@ fn test_config_port_ranges() {
         root: "e3dc".to_string(),
         host: "mqtt.example.com".to_string(),
         port: 65535, // Maximum valid port
+        connect_timeout: Duration::from_secs(5),
+        bind_interface: None,
+        socks5: None,
+        rename: BTreeMap::new(),
         client_id: None,
         username: "test".to_string(),
         password: "test".to_string(),
@@ -259,6 +933,10 @@ fn test_config_port_ranges() {
         root: "e3dc".to_string(),
         host: "mqtt.example.com".to_string(),
         port: 8883, // Common TLS port
+        connect_timeout: Duration::from_secs(5),
+        bind_interface: None,
+        socks5: None,
+        rename: BTreeMap::new(),
         client_id: Some("custom-id".to_string()),
         username: "test".to_string(),
         password: "test".to_string(),
@@ -266,3 +944,97 @@ fn test_config_port_ranges() {
     assert_eq!(config.port, 8883);
     assert_eq!(config.client_id, Some("custom-id".to_string()));
 }
+
+#[test]
+fn test_bridge_shutdown() {
+    let shutdown = Shutdown::new();
+    assert!(!shutdown.wait(Duration::from_millis(10)));
+
+    let waiting = shutdown.clone();
+    let waiter = std::thread::spawn(move || waiting.wait(Duration::from_secs(60)));
+    shutdown.request();
+    assert!(waiter.join().unwrap());
+    assert!(shutdown.is_requested());
+}
+
+#[test]
+fn test_publisher_emergency_power_reserve() {
+    // 20 % of 10 kWh usable, the SOC below the reserve holds back only its charge
+    assert_eq!(e3dc::reserve_held_energy(20.0, 2000.0, 55.0), 2000.0);
+    assert_eq!(e3dc::reserve_held_energy(20.0, 2000.0, 5.0), 500.0);
+    assert_eq!(e3dc::reserve_held_energy(0.0, 0.0, 55.0), 0.0);
+
+    let capabilities =
+        mqtt::EmergencyPowerCapabilities::from_e3dc(&e3dc::EmergencyPowerCapabilities {
+            possible: Some(true),
+            ready_for_switch: Some(true),
+            power_off_possible: Some(false),
+            reserve_configured: Some(true),
+            reserve_percent: Some(20.0),
+            reserve_energy: Some(2000.0),
+            reserve_held_energy: Some(500.0),
+        });
+    let publisher = RecordingPublisher::default();
+    publisher
+        .publish_emergency_power_capabilities(&capabilities, None)
+        .unwrap();
+    let messages = publisher.transport.messages.borrow();
+    for (topic, payload) in [
+        ("reserve_percent", "20"),
+        ("reserve_energy", "2000"),
+        ("reserve_held_energy", "500"),
+    ] {
+        assert!(messages.contains(&(
+            format!("e3dc/S10E-123/emergency_power/{}", topic),
+            payload.to_string(),
+            true
+        )));
+    }
+}
+
+#[test]
+fn test_publisher_emergency_power_reserve_without_capacity() {
+    // Without EP::PARAM_EP_RESERVE_ENERGY the Wh follow from the installed capacity
+    assert_eq!(
+        e3dc::reserve_energy(Some(20.0), None, Some(10000.0)),
+        Some(2000.0)
+    );
+    assert_eq!(
+        e3dc::reserve_energy(Some(20.0), Some(1800.0), Some(10000.0)),
+        Some(1800.0)
+    );
+    // ... and stay unknown without the capacity instead of 0 Wh
+    assert_eq!(e3dc::reserve_energy(Some(20.0), None, None), None);
+    assert_eq!(e3dc::reserve_energy(None, None, Some(10000.0)), None);
+
+    let capabilities =
+        mqtt::EmergencyPowerCapabilities::from_e3dc(&e3dc::EmergencyPowerCapabilities {
+            possible: Some(true),
+            ready_for_switch: None,
+            power_off_possible: Some(false),
+            reserve_configured: None,
+            reserve_percent: Some(20.0),
+            reserve_energy: None,
+            reserve_held_energy: None,
+        });
+    let publisher = RecordingPublisher::default();
+    publisher
+        .publish_emergency_power_capabilities(&capabilities, None)
+        .unwrap();
+    let topics: Vec<String> = publisher
+        .transport
+        .messages
+        .borrow()
+        .iter()
+        .map(|(topic, _, _)| topic.clone())
+        .collect();
+    assert!(topics.contains(&"e3dc/S10E-123/emergency_power/reserve_percent".to_string()));
+    for field in [
+        "ready_for_switch",
+        "reserve_configured",
+        "reserve_energy",
+        "reserve_held_energy",
+    ] {
+        assert!(!topics.contains(&format!("e3dc/S10E-123/emergency_power/{}", field)));
+    }
+}